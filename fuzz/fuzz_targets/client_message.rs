@@ -0,0 +1,14 @@
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use postgres_shim::ClientMessage;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let mut stream = Cursor::new(data);
+    while ClientMessage::from_stream(&mut stream).is_ok() {}
+
+    let mut buffer = BytesMut::from(data);
+    while let Ok(Some(_)) = ClientMessage::decode(&mut buffer) {}
+});
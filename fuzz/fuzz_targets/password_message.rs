@@ -0,0 +1,11 @@
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use postgres_shim::PasswordMessage;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = PasswordMessage::from_stream(&mut Cursor::new(data));
+    let _ = PasswordMessage::decode(&mut BytesMut::from(data));
+});
@@ -0,0 +1,54 @@
+#![no_main]
+
+//! Runs a whole session, startup included, on bytes from the fuzzer against
+//! a router without routes.
+
+use libfuzzer_sys::fuzz_target;
+use postgres_shim::{DefaultServerParameters, PostgressIntermediary, Router};
+use std::io::{Cursor, Read, Result, Write};
+
+/// Reads the fuzzer's bytes and discards whatever the server writes.
+struct Client<'a> {
+    input: Cursor<&'a [u8]>,
+}
+
+impl Read for Client<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Client<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn parameters() -> DefaultServerParameters {
+    DefaultServerParameters {
+        server_version: "14".to_string(),
+        server_encoding: "UTF8".to_string(),
+        client_encoding: "UTF8".to_string(),
+        application_name: String::new(),
+        default_transaction_read_only: "off".to_string(),
+        in_hot_standby: "off".to_string(),
+        is_superuser: "off".to_string(),
+        session_authorization: "postgres".to_string(),
+        date_style: "ISO, MDY".to_string(),
+        interval_style: "postgres".to_string(),
+        time_zone: "UTC".to_string(),
+        integer_datetimes: "on".to_string(),
+        standard_conforming_strings: "on".to_string(),
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let client = Client {
+        input: Cursor::new(data),
+    };
+    let _ = PostgressIntermediary::new(Router::new(parameters()), client).run();
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use postgres_shim::StartupMessage;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = StartupMessage::from_stream(&mut Cursor::new(data));
+    let _ = StartupMessage::decode(&mut BytesMut::from(data));
+});
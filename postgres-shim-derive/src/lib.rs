@@ -0,0 +1,146 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Expr, Fields, LitStr, Type};
+
+/// Derives `postgres_shim::PgRow` for a struct with named fields.
+///
+/// Field attributes:
+/// - `#[pg(rename = "name")]` uses a different column name.
+/// - `#[pg(column_type = Type::VARCHAR)]` overrides the type inferred from the field.
+/// - `#[pg(nullable)]` marks the column nullable.
+/// - `#[pg(skip)]` leaves the field out of the row.
+///
+/// Field types implement `postgres_shim::PgType`, which also tells whether
+/// the column is nullable, unless the column type is overridden. Those only
+/// need `postgres_shim::ToSqlValue`, and are nullable when they are an
+/// `Option` or marked `#[pg(nullable)]`.
+#[proc_macro_derive(PgRow, attributes(pg))]
+pub fn derive_pg_row(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match pg_row(input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+struct FieldAttributes {
+    rename: Option<String>,
+    column_type: Option<Expr>,
+    nullable: bool,
+    skip: bool,
+}
+
+fn field_attributes(field: &syn::Field) -> syn::Result<FieldAttributes> {
+    let mut attributes = FieldAttributes {
+        rename: None,
+        column_type: None,
+        nullable: false,
+        skip: false,
+    };
+    for attribute in field.attrs.iter().filter(|attr| attr.path().is_ident("pg")) {
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                attributes.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("column_type") {
+                attributes.column_type = Some(meta.value()?.parse::<Expr>()?);
+            } else if meta.path.is_ident("nullable") {
+                attributes.nullable = true;
+            } else if meta.path.is_ident("skip") {
+                attributes.skip = true;
+            } else {
+                return Err(meta.error("unsupported pg attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(attributes)
+}
+
+fn pg_row(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "PgRow can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "PgRow can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut columns = Vec::new();
+    let mut values = Vec::new();
+    for field in fields {
+        let attributes = field_attributes(field)?;
+        if attributes.skip {
+            continue;
+        }
+        let ident = field.ident.as_ref().expect("named field");
+        let ty = &field.ty;
+        let name = attributes
+            .rename
+            .unwrap_or_else(|| ident.to_string().trim_start_matches("r#").to_string());
+        let nullable = attributes.nullable;
+        let (column_type, nullable) = match attributes.column_type {
+            Some(column_type) => {
+                let nullable = nullable || is_option(ty);
+                let column_type = quote! {
+                    {
+                        use ::postgres_shim::Type;
+                        #column_type
+                    }
+                };
+                (column_type, quote! { #nullable })
+            }
+            None => (
+                quote! { <#ty as ::postgres_shim::PgType>::pg_type() },
+                match nullable {
+                    true => quote! { true },
+                    false => quote! { <#ty as ::postgres_shim::PgType>::NULLABLE },
+                },
+            ),
+        };
+        columns.push(quote! {
+            {
+                let mut column = ::postgres_shim::Column::new(#name, #column_type);
+                column.nullable = #nullable;
+                column
+            }
+        });
+        values.push(quote! { &self.#ident as &dyn ::postgres_shim::ToSqlValue });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::postgres_shim::PgRow for #ident #ty_generics #where_clause {
+            fn columns() -> ::std::vec::Vec<::postgres_shim::Column> {
+                ::std::vec![#(#columns),*]
+            }
+
+            fn values(&self) -> ::std::vec::Vec<&dyn ::postgres_shim::ToSqlValue> {
+                ::std::vec![#(#values),*]
+            }
+        }
+    })
+}
+
+/// Whether `ty` is written as `Option<_>`, with or without its path.
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Option"),
+        _ => false,
+    }
+}
@@ -0,0 +1,727 @@
+use bytes::{BufMut, BytesMut};
+use postgres_types::{Kind, Type};
+use std::io::{Cursor, Error, ErrorKind, Read, Result};
+
+use crate::client_message::ReadPostgresExt;
+use crate::{ErrorResponse, ParameterValue, ToSqlValue, Value};
+
+/// Postgres does not accept arrays with more dimensions.
+const MAX_DIMENSIONS: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArrayDimension {
+    pub len: i32,
+    pub lower_bound: i32,
+}
+
+/// A possibly multi-dimensional postgres array. Elements are stored flat in
+/// row-major order, NULL elements are represented by the element type itself
+/// (`Option<T>` or `Value::Null`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Array<T> {
+    dimensions: Vec<ArrayDimension>,
+    elements: Vec<T>,
+}
+
+impl<T> Array<T> {
+    pub fn new(dimensions: Vec<ArrayDimension>, elements: Vec<T>) -> Result<Self> {
+        if element_count(&dimensions)? != elements.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Array dimensions do not match the number of elements",
+            ));
+        }
+        Ok(Self {
+            dimensions,
+            elements,
+        })
+    }
+
+    pub fn from_elements(elements: Vec<T>) -> Self {
+        let dimensions = match elements.len() {
+            0 => vec![],
+            len => vec![ArrayDimension {
+                len: len as i32,
+                lower_bound: 1,
+            }],
+        };
+        Self {
+            dimensions,
+            elements,
+        }
+    }
+
+    pub fn dimensions(&self) -> &[ArrayDimension] {
+        &self.dimensions
+    }
+
+    pub fn elements(&self) -> &[T] {
+        &self.elements
+    }
+
+    pub fn into_elements(self) -> Vec<T> {
+        self.elements
+    }
+}
+
+impl Array<Value> {
+    /// Decodes an array bind parameter of the given array type.
+    pub fn from_parameter(parameter: ParameterValue, ty: &Type) -> Result<Self> {
+        let element_type = element_type(ty);
+        match parameter {
+            ParameterValue::Text(text) => decode_text(&text, &element_type)
+                .map_err(|error| malformed(error, "22P02", "malformed array literal")),
+            ParameterValue::Binary(data) => decode_binary(&data, &element_type).map_err(|error| {
+                malformed(error, "22P03", "incorrect binary data format in array")
+            }),
+            ParameterValue::Null => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "a NULL parameter is not an array",
+            )),
+        }
+    }
+
+    /// Converts nested `Value::Array`s into a flat array with dimensions.
+    pub fn from_nested(values: &[Value]) -> Result<Self> {
+        let mut dimensions = Vec::new();
+        let mut level = values;
+        loop {
+            if level.is_empty() {
+                if !dimensions.is_empty() {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "Multidimensional arrays cannot contain empty sub-arrays",
+                    ));
+                }
+                return Ok(Self::from_elements(vec![]));
+            }
+            dimensions.push(ArrayDimension {
+                len: level.len() as i32,
+                lower_bound: 1,
+            });
+            match &level[0] {
+                Value::Array(next) => level = next,
+                _ => break,
+            }
+        }
+        let mut elements = Vec::new();
+        flatten(values, &dimensions, &mut elements)?;
+        Self::new(dimensions, elements)
+    }
+
+    /// Converts back into nested `Value::Array`s, dropping lower bounds.
+    pub fn into_nested(self) -> Value {
+        fn nest(elements: &mut std::vec::IntoIter<Value>, dimensions: &[ArrayDimension]) -> Value {
+            match dimensions.split_first() {
+                None => elements.next().unwrap_or(Value::Null),
+                Some((dimension, rest)) => {
+                    Value::Array((0..dimension.len).map(|_| nest(elements, rest)).collect())
+                }
+            }
+        }
+        if self.dimensions.is_empty() {
+            return Value::Array(vec![]);
+        }
+        nest(&mut self.elements.into_iter(), &self.dimensions)
+    }
+}
+
+fn flatten(
+    values: &[Value],
+    dimensions: &[ArrayDimension],
+    elements: &mut Vec<Value>,
+) -> Result<()> {
+    let (dimension, rest) = dimensions.split_first().expect("at least one dimension");
+    if values.len() as i32 != dimension.len {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Multidimensional arrays must have sub-arrays with matching dimensions",
+        ));
+    }
+    for value in values {
+        match (value, rest.is_empty()) {
+            (Value::Array(next), false) => flatten(next, rest, elements)?,
+            (Value::Array(_), true) | (_, false) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Multidimensional arrays must have sub-arrays with matching dimensions",
+                ))
+            }
+            (value, true) => elements.push(value.clone()),
+        }
+    }
+    Ok(())
+}
+
+impl<T> ToSqlValue for Array<T>
+where
+    T: ToSqlValue,
+{
+    fn as_bin_value(&self, ty: &Type) -> Result<Option<BytesMut>> {
+        encode_binary(&self.dimensions, &self.elements, ty).map(Some)
+    }
+
+    fn as_str_value(&self, ty: &Type) -> Result<Option<BytesMut>> {
+        encode_text(&self.dimensions, &self.elements, ty).map(Some)
+    }
+}
+
+impl<T> ToSqlValue for [T]
+where
+    T: ToSqlValue,
+{
+    fn as_bin_value(&self, ty: &Type) -> Result<Option<BytesMut>> {
+        encode_binary(&one_dimension(self.len()), self, ty).map(Some)
+    }
+
+    fn as_str_value(&self, ty: &Type) -> Result<Option<BytesMut>> {
+        encode_text(&one_dimension(self.len()), self, ty).map(Some)
+    }
+}
+
+impl<T> ToSqlValue for Vec<T>
+where
+    T: ToSqlValue,
+{
+    fn as_bin_value(&self, ty: &Type) -> Result<Option<BytesMut>> {
+        self.as_slice().as_bin_value(ty)
+    }
+
+    fn as_str_value(&self, ty: &Type) -> Result<Option<BytesMut>> {
+        self.as_slice().as_str_value(ty)
+    }
+}
+
+/// The number of elements of an array with `dimensions`, checking that the
+/// lengths are not negative and neither the count nor an upper bound
+/// overflows.
+fn element_count(dimensions: &[ArrayDimension]) -> Result<usize> {
+    if dimensions.is_empty() {
+        return Ok(0);
+    }
+    dimensions
+        .iter()
+        .try_fold(1usize, |count, dimension| {
+            let len = usize::try_from(dimension.len).ok()?;
+            dimension.lower_bound.checked_add(dimension.len - 1)?;
+            count.checked_mul(len)
+        })
+        .ok_or_else(|| invalid_array("invalid dimensions"))
+}
+
+fn one_dimension(len: usize) -> Vec<ArrayDimension> {
+    match len {
+        0 => vec![],
+        len => vec![ArrayDimension {
+            len: len as i32,
+            lower_bound: 1,
+        }],
+    }
+}
+
+pub(crate) fn element_type(ty: &Type) -> Type {
+    match ty.kind() {
+        Kind::Array(element) => element.clone(),
+        _ => Type::TEXT,
+    }
+}
+
+pub(crate) fn encode_text<T>(
+    dimensions: &[ArrayDimension],
+    elements: &[T],
+    ty: &Type,
+) -> Result<BytesMut>
+where
+    T: ToSqlValue,
+{
+    let element_type = element_type(ty);
+    let mut buffer = BytesMut::new();
+    if dimensions
+        .iter()
+        .any(|dimension| dimension.lower_bound != 1)
+    {
+        for dimension in dimensions {
+            buffer.put_slice(
+                format!(
+                    "[{}:{}]",
+                    dimension.lower_bound,
+                    dimension.lower_bound + dimension.len - 1
+                )
+                .as_bytes(),
+            );
+        }
+        buffer.put_u8(b'=');
+    }
+    if dimensions.is_empty() {
+        buffer.put_slice(b"{}");
+        return Ok(buffer);
+    }
+    let mut elements = elements.iter();
+    write_text_level(&mut buffer, dimensions, &mut elements, &element_type)?;
+    Ok(buffer)
+}
+
+fn write_text_level<'a, T>(
+    buffer: &mut BytesMut,
+    dimensions: &[ArrayDimension],
+    elements: &mut impl Iterator<Item = &'a T>,
+    element_type: &Type,
+) -> Result<()>
+where
+    T: ToSqlValue + 'a,
+{
+    let (dimension, rest) = match dimensions.split_first() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+    buffer.put_u8(b'{');
+    for i in 0..dimension.len {
+        if i > 0 {
+            buffer.put_u8(b',');
+        }
+        if !rest.is_empty() {
+            write_text_level(buffer, rest, elements, element_type)?;
+            continue;
+        }
+        let text = match elements.next() {
+            Some(element) => element.as_str_value(element_type)?,
+            None => None,
+        };
+        match text {
+            None => buffer.put_slice(b"NULL"),
+            Some(text) => write_text_element(buffer, &text),
+        }
+    }
+    buffer.put_u8(b'}');
+    Ok(())
+}
+
+fn write_text_element(buffer: &mut BytesMut, text: &[u8]) {
+    let needs_quotes = text.is_empty()
+        || text.eq_ignore_ascii_case(b"NULL")
+        || text
+            .iter()
+            .any(|c| matches!(c, b'{' | b'}' | b',' | b'"' | b'\\') || c.is_ascii_whitespace());
+    if !needs_quotes {
+        buffer.put_slice(text);
+        return;
+    }
+    buffer.put_u8(b'"');
+    for c in text {
+        if matches!(c, b'"' | b'\\') {
+            buffer.put_u8(b'\\');
+        }
+        buffer.put_u8(*c);
+    }
+    buffer.put_u8(b'"');
+}
+
+pub(crate) fn encode_binary<T>(
+    dimensions: &[ArrayDimension],
+    elements: &[T],
+    ty: &Type,
+) -> Result<BytesMut>
+where
+    T: ToSqlValue,
+{
+    let element_type = element_type(ty);
+    let encoded = elements
+        .iter()
+        .map(|element| element.as_bin_value(&element_type))
+        .collect::<Result<Vec<Option<BytesMut>>>>()?;
+    let mut buffer = BytesMut::new();
+    buffer.put_i32(dimensions.len() as i32);
+    buffer.put_i32(encoded.iter().any(Option::is_none) as i32);
+    buffer.put_u32(element_type.oid());
+    for dimension in dimensions {
+        buffer.put_i32(dimension.len);
+        buffer.put_i32(dimension.lower_bound);
+    }
+    for element in encoded {
+        match element {
+            None => buffer.put_i32(-1),
+            Some(data) => {
+                buffer.put_i32(data.len() as i32);
+                buffer.put_slice(&data);
+            }
+        }
+    }
+    Ok(buffer)
+}
+
+fn invalid_array(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Array parameters that fail to decode fail the Bind with 22P02 in text
+/// and 22P03 in binary format, keeping the code of an element that failed.
+fn malformed(error: Error, code: &str, message: &str) -> Error {
+    match ErrorResponse::of(&error) {
+        Some(_) => error,
+        None => ErrorResponse::error(code, format!("{}: {}", message, error)),
+    }
+}
+
+fn decode_binary(data: &[u8], element_type: &Type) -> Result<Array<Value>> {
+    let mut cursor = Cursor::new(data);
+    let n_dimensions = cursor.read_int32()? as i32;
+    let _has_nulls = cursor.read_int32()?;
+    let _element_oid = cursor.read_int32()?;
+    if !(0..=MAX_DIMENSIONS as i32).contains(&n_dimensions) {
+        return Err(invalid_array("invalid number of dimensions"));
+    }
+    let mut dimensions = Vec::new();
+    for _ in 0..n_dimensions {
+        dimensions.push(ArrayDimension {
+            len: cursor.read_int32()? as i32,
+            lower_bound: cursor.read_int32()? as i32,
+        });
+    }
+    // Every element takes at least its length field.
+    let n_elements = element_count(&dimensions)?;
+    if n_elements > data.len() {
+        return Err(invalid_array("invalid dimensions"));
+    }
+    let mut elements = Vec::with_capacity(n_elements);
+    for _ in 0..n_elements {
+        let len = cursor.read_int32()? as i32;
+        if len < 0 {
+            elements.push(Value::Null);
+            continue;
+        }
+        if len as u64 > data.len() as u64 - cursor.position() {
+            return Err(invalid_array("element length out of bounds"));
+        }
+        let mut element = vec![0; len as usize];
+        cursor.read_exact(&mut element)?;
+        elements.push(Value::from_parameter(
+            ParameterValue::Binary(element),
+            element_type,
+        )?);
+    }
+    Array::new(dimensions, elements)
+}
+
+enum TextNode {
+    Element(Option<String>),
+    Nested(Vec<TextNode>),
+}
+
+fn decode_text(text: &str, element_type: &Type) -> Result<Array<Value>> {
+    let text = text.trim();
+    let (bounds, body) = match text.strip_prefix('[') {
+        Some(_) => {
+            let (bounds, body) = text
+                .split_once('=')
+                .ok_or_else(|| invalid_array("missing \"=\" after dimensions"))?;
+            (Some(parse_bounds(bounds)?), body.trim_start())
+        }
+        None => (None, text),
+    };
+    let mut chars = body.char_indices().peekable();
+    if chars.next().map(|(_, c)| c) != Some('{') {
+        return Err(invalid_array("array value must start with \"{\""));
+    }
+    let root = parse_level(&mut chars, 1)?;
+    if chars.any(|(_, c)| !c.is_whitespace()) {
+        return Err(invalid_array("junk after closing right brace"));
+    }
+
+    let mut dimensions = Vec::new();
+    let mut level = &root;
+    while let TextNode::Nested(children) = level {
+        if children.is_empty() {
+            break;
+        }
+        dimensions.push(ArrayDimension {
+            len: children.len() as i32,
+            lower_bound: 1,
+        });
+        level = &children[0];
+    }
+    if let Some(bounds) = bounds {
+        if bounds.len() != dimensions.len()
+            || bounds
+                .iter()
+                .zip(&dimensions)
+                .any(|(bound, dimension)| bound.len != dimension.len)
+        {
+            return Err(invalid_array(
+                "specified array dimensions do not match array contents",
+            ));
+        }
+        dimensions = bounds;
+    }
+
+    let mut elements = Vec::new();
+    collect_elements(&root, 0, &dimensions, &mut elements, element_type)?;
+    Array::new(dimensions, elements)
+}
+
+fn parse_bounds(text: &str) -> Result<Vec<ArrayDimension>> {
+    text.trim()
+        .split('[')
+        .skip(1)
+        .map(|bound| {
+            let bound = bound
+                .trim()
+                .strip_suffix(']')
+                .ok_or_else(|| invalid_array("missing \"]\" in array dimensions"))?;
+            let (lower, upper) = bound
+                .split_once(':')
+                .ok_or_else(|| invalid_array("missing \":\" in array dimensions"))?;
+            let lower: i32 = lower
+                .trim()
+                .parse()
+                .map_err(|_| invalid_array("invalid bound"))?;
+            let upper: i32 = upper
+                .trim()
+                .parse()
+                .map_err(|_| invalid_array("invalid bound"))?;
+            if upper < lower {
+                return Err(invalid_array("upper bound cannot be less than lower bound"));
+            }
+            let len = upper
+                .checked_sub(lower)
+                .and_then(|len| len.checked_add(1))
+                .ok_or_else(|| invalid_array("array size exceeds the maximum allowed"))?;
+            Ok(ArrayDimension {
+                len,
+                lower_bound: lower,
+            })
+        })
+        .collect()
+}
+
+/// Parses the elements of a `{...}` level `depth` levels deep, its opening
+/// brace already consumed.
+fn parse_level(
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+    depth: usize,
+) -> Result<TextNode> {
+    if depth > MAX_DIMENSIONS {
+        return Err(ErrorResponse::error(
+            "54000",
+            format!(
+                "number of array dimensions exceeds the maximum allowed ({})",
+                MAX_DIMENSIONS
+            ),
+        ));
+    }
+    let mut children = Vec::new();
+    loop {
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        match chars.peek().map(|(_, c)| *c) {
+            None => return Err(invalid_array("unexpected end of input")),
+            Some('}') if children.is_empty() => {
+                chars.next();
+                return Ok(TextNode::Nested(children));
+            }
+            Some('{') => {
+                chars.next();
+                children.push(parse_level(chars, depth + 1)?);
+            }
+            Some('"') => {
+                chars.next();
+                let mut element = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(invalid_array("unexpected end of input")),
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) => element.push(c),
+                            None => return Err(invalid_array("unexpected end of input")),
+                        },
+                        Some((_, c)) => element.push(c),
+                    }
+                }
+                children.push(TextNode::Element(Some(element)));
+            }
+            Some(_) => {
+                let mut element = String::new();
+                while let Some((_, c)) = chars.next_if(|(_, c)| !matches!(c, ',' | '}')) {
+                    match c {
+                        '{' | '"' => return Err(invalid_array("unexpected character")),
+                        '\\' => match chars.next() {
+                            Some((_, c)) => element.push(c),
+                            None => return Err(invalid_array("unexpected end of input")),
+                        },
+                        c => element.push(c),
+                    }
+                }
+                let element = element.trim_end();
+                children.push(TextNode::Element(if element.eq_ignore_ascii_case("NULL") {
+                    None
+                } else {
+                    Some(element.to_string())
+                }));
+            }
+        }
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        match chars.next() {
+            Some((_, ',')) => continue,
+            Some((_, '}')) => return Ok(TextNode::Nested(children)),
+            _ => return Err(invalid_array("unexpected character")),
+        }
+    }
+}
+
+fn collect_elements(
+    node: &TextNode,
+    depth: usize,
+    dimensions: &[ArrayDimension],
+    elements: &mut Vec<Value>,
+    element_type: &Type,
+) -> Result<()> {
+    match node {
+        TextNode::Nested(children) if depth < dimensions.len() => {
+            if children.len() as i32 != dimensions[depth].len {
+                return Err(invalid_array(
+                    "multidimensional arrays must have sub-arrays with matching dimensions",
+                ));
+            }
+            children.iter().try_for_each(|child| {
+                collect_elements(child, depth + 1, dimensions, elements, element_type)
+            })
+        }
+        TextNode::Nested(children) if children.is_empty() && dimensions.is_empty() => Ok(()),
+        TextNode::Element(element) if depth == dimensions.len() => {
+            elements.push(match element {
+                None => Value::Null,
+                Some(text) => {
+                    Value::from_parameter(ParameterValue::Text(text.clone()), element_type)?
+                }
+            });
+            Ok(())
+        }
+        _ => Err(invalid_array(
+            "multidimensional arrays must have sub-arrays with matching dimensions",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_round_trip_with_nulls_and_quotes() {
+        let array = Array::from_parameter(
+            ParameterValue::Text(r#"{{1,NULL},{3,4}}"#.to_string()),
+            &Type::INT4_ARRAY,
+        )
+        .unwrap();
+        assert_eq!(array.dimensions().len(), 2);
+        assert_eq!(
+            array.elements(),
+            &[Value::Int4(1), Value::Null, Value::Int4(3), Value::Int4(4)]
+        );
+        assert_eq!(
+            array.as_str_value(&Type::INT4_ARRAY).unwrap().unwrap(),
+            BytesMut::from(&b"{{1,NULL},{3,4}}"[..])
+        );
+
+        let array = Array::from_parameter(
+            ParameterValue::Text(r#"{"a \"b\"",c,"NULL",""}"#.to_string()),
+            &Type::TEXT_ARRAY,
+        )
+        .unwrap();
+        assert_eq!(
+            array.into_nested(),
+            Value::Array(vec![
+                Value::from(r#"a "b""#),
+                Value::from("c"),
+                Value::from("NULL"),
+                Value::from(""),
+            ])
+        );
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let encoded = vec![Some(1i32), None, Some(3)]
+            .as_bin_value(&Type::INT4_ARRAY)
+            .unwrap()
+            .unwrap();
+        let array =
+            Array::from_parameter(ParameterValue::Binary(encoded.to_vec()), &Type::INT4_ARRAY)
+                .unwrap();
+        assert_eq!(
+            array.elements(),
+            &[Value::Int4(1), Value::Null, Value::Int4(3)]
+        );
+    }
+
+    #[test]
+    fn rejects_overflowing_dimensions() {
+        let mut data = Vec::new();
+        data.extend(6i32.to_be_bytes());
+        data.extend(0i32.to_be_bytes());
+        data.extend(Type::INT4.oid().to_be_bytes());
+        for _ in 0..6 {
+            data.extend(i32::MAX.to_be_bytes());
+            data.extend(1i32.to_be_bytes());
+        }
+        let error = Array::from_parameter(ParameterValue::Binary(data), &Type::INT4_ARRAY);
+        assert_eq!(
+            ErrorResponse::of(&error.unwrap_err()).unwrap().code,
+            "22P03"
+        );
+
+        let dimension = |len, lower_bound| ArrayDimension { len, lower_bound };
+        assert!(Array::new(vec![dimension(i32::MAX, 1); 6], vec![Value::Null]).is_err());
+        assert!(Array::new(vec![dimension(-1, 1); 2], vec![Value::Null]).is_err());
+        assert!(Array::new(vec![dimension(2, i32::MAX)], vec![Value::Null; 2]).is_err());
+        assert!(Array::new(vec![dimension(1, i32::MAX)], vec![Value::Null]).is_ok());
+        assert!(Array::from_parameter(
+            ParameterValue::Text(format!("[{}:{}]={{1}}", i32::MIN, i32::MAX)),
+            &Type::INT4_ARRAY,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn malformed_parameters_fail_the_bind() {
+        let code = |parameter| {
+            let error = Array::from_parameter(parameter, &Type::INT4_ARRAY).unwrap_err();
+            ErrorResponse::of(&error).unwrap().code.clone()
+        };
+        assert_eq!(code(ParameterValue::Text("{1,2".to_string())), "22P02");
+        assert_eq!(code(ParameterValue::Text("{1,x}".to_string())), "22P02");
+        assert_eq!(code(ParameterValue::Binary(vec![0, 0, 0, 1])), "22P03");
+        let mut data = Vec::new();
+        for field in [1, 0, Type::INT4.oid() as i32, 1, 1, 2, 0] {
+            data.extend(field.to_be_bytes());
+        }
+        // An int4 element two bytes long.
+        data.extend([0, 1]);
+        assert_eq!(code(ParameterValue::Binary(data)), "22P03");
+
+        // Nesting is bounded before it can exhaust the stack.
+        assert_eq!(code(ParameterValue::Text("{".repeat(1 << 20))), "54000");
+        assert_eq!(
+            code(ParameterValue::Text("{{{{{{{1}}}}}}}".to_string())),
+            "54000"
+        );
+        let array = Array::from_parameter(
+            ParameterValue::Text("{{{{{{1}}}}}}".to_string()),
+            &Type::INT4_ARRAY,
+        )
+        .unwrap();
+        assert_eq!(array.dimensions().len(), 6);
+    }
+
+    #[test]
+    fn custom_lower_bounds_are_kept() {
+        let array = Array::from_parameter(
+            ParameterValue::Text("[0:1]={a,b}".to_string()),
+            &Type::TEXT_ARRAY,
+        )
+        .unwrap();
+        assert_eq!(array.dimensions()[0].lower_bound, 0);
+        assert_eq!(
+            array.as_str_value(&Type::TEXT_ARRAY).unwrap().unwrap(),
+            BytesMut::from(&b"[0:1]={a,b}"[..])
+        );
+    }
+}
@@ -0,0 +1,552 @@
+use bytes::BytesMut;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time;
+
+use crate::client_message::{report_fatal, ClientMessage, Close, Describe, ProtocolViolation};
+use crate::codec::{FrontendMessage, ServerCodec};
+use crate::protocol::{describe_columns, Prepare, Protocol};
+use crate::server_message::ServerMessage;
+use crate::timeout::StatementTimeout;
+use crate::{
+    Column, DefaultServerParameters, FormatCode, Limits, ParameterValue, ParsedStatement, Portal,
+    ResultWriter, SchemaDescription, Timeouts, Type, TypeRegistry,
+};
+
+/// The async counterpart of [`PostgresShim`](crate::PostgresShim). Methods
+/// that talk to the backing store are async, configuration stays blocking.
+pub trait AsyncPostgresShim<PortalData>: Send {
+    fn prepare(
+        &mut self,
+        query_name: String,
+        query: String,
+        parameter_types: Vec<Type>,
+    ) -> impl Future<Output = Result<()>> + Send;
+    fn bind(
+        &mut self,
+        query_name: String,
+        parameters: Vec<ParameterValue>,
+    ) -> impl Future<Output = Result<PortalData>> + Send;
+    fn describe(
+        &mut self,
+        portal: &PortalData,
+    ) -> impl Future<Output = Result<Option<Vec<Column>>>> + Send;
+    /// Rows written to `result_writer` are buffered and sent once the
+    /// future completes, use `max_rows` to bound them.
+    fn execute(
+        &mut self,
+        portal: PortalData,
+        max_rows: u32,
+        columns: Option<Vec<Column>>,
+        result_writer: ResultWriter<'_, Vec<u8>>,
+    ) -> impl Future<Output = Result<()>> + Send;
+    fn default_parameters(&mut self) -> DefaultServerParameters;
+    fn type_registry(&mut self) -> TypeRegistry {
+        TypeRegistry::new()
+    }
+    /// See [`PostgresShim::schema_description`](crate::PostgresShim::schema_description).
+    fn schema_description(&mut self) -> Option<&dyn SchemaDescription> {
+        None
+    }
+    /// See [`PostgresShim::driver_compatibility`](crate::PostgresShim::driver_compatibility).
+    fn driver_compatibility(&mut self) -> bool {
+        false
+    }
+    /// See [`PostgresShim::parse_queries`](crate::PostgresShim::parse_queries).
+    fn parse_queries(&mut self) -> bool {
+        false
+    }
+    fn prepare_parsed(
+        &mut self,
+        query_name: String,
+        statement: ParsedStatement,
+        parameter_types: Vec<Type>,
+    ) -> impl Future<Output = Result<()>> + Send {
+        self.prepare(query_name, statement.query, parameter_types)
+    }
+    /// See [`PostgresShim::close_statement`](crate::PostgresShim::close_statement).
+    fn close_statement(&mut self, _query_name: String) {}
+}
+
+/// Serves one connection over an async stream, so many idle sessions can
+/// share a Tokio runtime instead of a thread each. Messages are decoded and
+/// encoded with the same code as [`PostgressIntermediary`](crate::PostgressIntermediary).
+pub struct AsyncPostgressIntermediary<Stream, Shim, PortalData> {
+    stream: Stream,
+    shim: Shim,
+    portals: HashMap<String, Portal<PortalData>>,
+    protocol: Protocol,
+    codec: ServerCodec,
+    /// Bytes read from `stream` that do not form a whole message yet.
+    input: BytesMut,
+    /// Backend messages waiting to be written to `stream`.
+    out: Vec<u8>,
+}
+
+impl<Stream, Shim, PortalData> AsyncPostgressIntermediary<Stream, Shim, PortalData>
+where
+    Stream: AsyncRead + AsyncWrite + Unpin,
+    Shim: AsyncPostgresShim<PortalData>,
+{
+    pub fn new(shim: Shim, stream: Stream) -> Self {
+        Self {
+            shim,
+            stream,
+            portals: HashMap::new(),
+            protocol: Protocol::new(),
+            codec: ServerCodec::new(),
+            input: BytesMut::new(),
+            out: Vec::new(),
+        }
+    }
+
+    /// Bounds what the client may send, see [`Limits`].
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.protocol.limits = limits;
+        self.codec = self.codec.with_limits(limits);
+        self
+    }
+
+    /// Sets the default session timeouts, see [`Timeouts`].
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.protocol.timeouts = timeouts;
+        self
+    }
+
+    pub async fn run(mut self) -> Result<()> {
+        let result = self.session().await;
+        if let Err(error) = &result {
+            if report_fatal(error, &mut self.out).is_ok() {
+                let _ = self.flush().await;
+            }
+        }
+        result
+    }
+
+    async fn session(&mut self) -> Result<()> {
+        self.init().await?;
+        loop {
+            let message = match self.protocol.take_idle_timeout() {
+                Some(idle_timeout) => {
+                    match time::timeout(idle_timeout.duration, self.read_message()).await {
+                        Ok(message) => message?,
+                        Err(_) => {
+                            idle_timeout.terminate(&mut self.out)?;
+                            return self.flush().await;
+                        }
+                    }
+                }
+                None => self.read_message().await?,
+            };
+            let FrontendMessage::Client(message) = message else {
+                return Err(unexpected_message());
+            };
+            if self.protocol.skips(&message) {
+                continue;
+            }
+            match message {
+                ClientMessage::Parse {
+                    name,
+                    query,
+                    parameter_type_oids,
+                } => {
+                    let prepare = self.protocol.parse(
+                        &mut self.out,
+                        self.shim.schema_description(),
+                        name,
+                        query,
+                        parameter_type_oids,
+                    )?;
+                    if let Some(prepare) = prepare {
+                        let name = prepare.name().to_string();
+                        let result = self.prepare(prepare).await;
+                        if self.protocol.recover(&mut self.out, result)?.is_some() {
+                            self.protocol.prepared(&name);
+                            ServerMessage::ParseComplete.write(&mut self.out)?;
+                        }
+                    }
+                }
+                ClientMessage::Bind {
+                    portal,
+                    name,
+                    parameter_format_codes,
+                    parameters,
+                    result_format_codes,
+                } => {
+                    let bind = self.protocol.bind(
+                        &mut self.out,
+                        self.shim.schema_description(),
+                        portal,
+                        name,
+                        parameter_format_codes,
+                        parameters,
+                        result_format_codes,
+                    )?;
+                    if let Some(bind) = bind {
+                        let data = self.shim.bind(bind.name, bind.parameters).await;
+                        if let Some(data) = self.protocol.recover(&mut self.out, data)? {
+                            self.protocol.bound(&bind.portal);
+                            self.portals
+                                .insert(bind.portal, Portal::new(data, bind.result_format_codes));
+                            ServerMessage::BindComplete.write(&mut self.out)?;
+                        }
+                    }
+                }
+                ClientMessage::Execute { portal, max_rows } => match self.portals.remove(&portal) {
+                    Some(shim_portal) => {
+                        self.protocol.close_portal(&portal);
+                        let format_codes = shim_portal.result_format_codes.clone();
+                        let (data, columns) = shim_portal.data();
+                        let result = self.execute(data, max_rows, columns, format_codes).await;
+                        if self.protocol.recover(&mut self.out, result)?.is_some() {
+                            self.protocol.executed(&portal);
+                        }
+                    }
+                    None => self.protocol.execute(&mut self.out, &portal)?,
+                },
+                ClientMessage::Query { query } => {
+                    let query = self.protocol.simple_query(
+                        &mut self.out,
+                        self.shim.schema_description(),
+                        query,
+                    )?;
+                    if let Some(prepare) = query {
+                        self.simple_query(prepare).await?;
+                        self.protocol.sync(&mut self.out)?;
+                    }
+                }
+                ClientMessage::Describe(describe) => match describe {
+                    Describe::Portal { name } => {
+                        if !self
+                            .protocol
+                            .describe_builtin_portal(&mut self.out, &name)?
+                        {
+                            match self.portals.get_mut(&name) {
+                                Some(portal) => {
+                                    let columns = self.shim.describe(&portal.portal_data).await;
+                                    if let Some(columns) =
+                                        self.protocol.recover(&mut self.out, columns)?
+                                    {
+                                        describe_columns(
+                                            &mut self.out,
+                                            columns.as_deref(),
+                                            portal.result_format_codes.clone(),
+                                        )?;
+                                        portal.add_columns(columns);
+                                    }
+                                }
+                                None => self.protocol.unknown_portal(&mut self.out, &name)?,
+                            }
+                        }
+                    }
+                    Describe::Statement { name } => self.protocol.describe_statement(
+                        &mut self.out,
+                        self.shim.schema_description(),
+                        &name,
+                    )?,
+                },
+                ClientMessage::Close(close) => {
+                    match &close {
+                        Close::Statement { name } => self.shim.close_statement(name.clone()),
+                        Close::Portal { name } => {
+                            self.portals.remove(name);
+                        }
+                    }
+                    self.protocol.close(&mut self.out, &close)?;
+                }
+                ClientMessage::Sync => self.protocol.sync(&mut self.out)?,
+                ClientMessage::Terminate => {
+                    return Ok(());
+                }
+            }
+            self.flush().await?;
+        }
+    }
+
+    async fn prepare(&mut self, prepare: Prepare) -> Result<()> {
+        match prepare {
+            Prepare::Text {
+                name,
+                query,
+                parameter_types,
+            } => self.shim.prepare(name, query, parameter_types).await,
+            Prepare::Parsed {
+                name,
+                statement,
+                parameter_types,
+            } => {
+                self.shim
+                    .prepare_parsed(name, *statement, parameter_types)
+                    .await
+            }
+        }
+    }
+
+    /// Executes a portal of the shim, cancelled at the statement timeout.
+    async fn execute(
+        &mut self,
+        data: PortalData,
+        max_rows: u32,
+        columns: Option<Vec<Column>>,
+        format_codes: Vec<FormatCode>,
+    ) -> Result<()> {
+        let writer = ResultWriter::new(
+            format_codes,
+            &mut self.out,
+            self.protocol.session.client_encoding,
+        )
+        .with_deadline(self.protocol.statement_deadline());
+        let execution = self.shim.execute(data, max_rows, columns, writer);
+        match self.protocol.statement_timeout() {
+            Some(timeout) => time::timeout(timeout, execution)
+                .await
+                .unwrap_or_else(|_| Err(StatementTimeout::error())),
+            None => execution.await,
+        }
+    }
+
+    /// See [`PostgressIntermediary`](crate::PostgressIntermediary), simple
+    /// queries run through the shim as the unnamed statement and portal.
+    async fn simple_query(&mut self, prepare: Prepare) -> Result<()> {
+        self.portals.remove("");
+        self.protocol.close_portal("");
+        let prepared = self.prepare(prepare).await;
+        if self.protocol.recover(&mut self.out, prepared)?.is_none() {
+            return Ok(());
+        }
+        let data = self.shim.bind(String::new(), vec![]).await;
+        let Some(data) = self.protocol.recover(&mut self.out, data)? else {
+            return Ok(());
+        };
+        let columns = self.shim.describe(&data).await;
+        let Some(columns) = self.protocol.recover(&mut self.out, columns)? else {
+            return Ok(());
+        };
+        if let Some(columns) = &columns {
+            describe_columns(&mut self.out, Some(columns), vec![])?;
+        }
+        let result = self.execute(data, 0, columns, vec![]).await;
+        if self.protocol.recover(&mut self.out, result)?.is_some() {
+            self.protocol.executed("");
+        }
+        Ok(())
+    }
+
+    async fn init(&mut self) -> Result<()> {
+        let FrontendMessage::Startup(startup_message) = self.read_message().await? else {
+            return Err(unexpected_message());
+        };
+        ServerMessage::AuthenticationCleartextPassword.write(&mut self.out)?;
+        self.flush().await?;
+        let FrontendMessage::Password(_) = self.read_message().await? else {
+            return Err(unexpected_message());
+        };
+        self.protocol.types = self.shim.type_registry();
+        self.protocol.driver_compatibility = self.shim.driver_compatibility();
+        self.protocol.parse_queries = self.shim.parse_queries();
+        let default_parameters = self.shim.default_parameters();
+        let started = self
+            .protocol
+            .startup(&mut self.out, &startup_message, &default_parameters);
+        self.flush().await?;
+        started
+    }
+
+    async fn read_message(&mut self) -> Result<FrontendMessage> {
+        loop {
+            if let Some(message) = self.codec.decode(&mut self.input)? {
+                return Ok(message);
+            }
+            if self.stream.read_buf(&mut self.input).await? == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "connection closed mid-message",
+                ));
+            }
+        }
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        self.stream.write_all(&self.out).await?;
+        self.out.clear();
+        self.stream.flush().await
+    }
+}
+
+fn unexpected_message() -> Error {
+    ProtocolViolation::error("unexpected message")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Value;
+    use std::time::Duration;
+
+    struct Numbers;
+
+    impl AsyncPostgresShim<u32> for Numbers {
+        async fn prepare(&mut self, _: String, _: String, _: Vec<Type>) -> Result<()> {
+            Ok(())
+        }
+
+        async fn bind(&mut self, _: String, parameters: Vec<ParameterValue>) -> Result<u32> {
+            match parameters.first() {
+                Some(ParameterValue::Text(count)) => Ok(count.parse().unwrap_or(0)),
+                _ => Ok(0),
+            }
+        }
+
+        async fn describe(&mut self, _: &u32) -> Result<Option<Vec<Column>>> {
+            Ok(Some(vec![Column::new("n", Type::INT4)]))
+        }
+
+        async fn execute(
+            &mut self,
+            count: u32,
+            _: u32,
+            columns: Option<Vec<Column>>,
+            result_writer: ResultWriter<'_, Vec<u8>>,
+        ) -> Result<()> {
+            if count > 1000 {
+                time::sleep(Duration::from_secs(60)).await;
+            }
+            let columns = columns.unwrap_or_default();
+            let mut row_writer = result_writer.start_writing(&columns)?;
+            for n in 0..count {
+                row_writer.write_row([Value::Int4(n as i32)])?;
+            }
+            row_writer.finish()
+        }
+
+        fn default_parameters(&mut self) -> DefaultServerParameters {
+            DefaultServerParameters {
+                server_version: "14".to_string(),
+                server_encoding: "UTF8".to_string(),
+                client_encoding: "UTF8".to_string(),
+                application_name: String::new(),
+                default_transaction_read_only: "off".to_string(),
+                in_hot_standby: "off".to_string(),
+                is_superuser: "off".to_string(),
+                session_authorization: "alice".to_string(),
+                date_style: "ISO, MDY".to_string(),
+                interval_style: "postgres".to_string(),
+                time_zone: "UTC".to_string(),
+                integer_datetimes: "on".to_string(),
+                standard_conforming_strings: "on".to_string(),
+            }
+        }
+    }
+
+    fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![tag];
+        message.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+        message.extend_from_slice(body);
+        message
+    }
+
+    /// The backend message types in `data`, in order.
+    fn message_types(mut data: &[u8]) -> Vec<char> {
+        let mut types = vec![];
+        while data.len() >= 5 {
+            let length = i32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize;
+            types.push(data[0] as char);
+            data = &data[1 + length..];
+        }
+        types
+    }
+
+    /// A session selecting `limit` numbers with the extended protocol.
+    fn select_numbers(limit: Option<&str>) -> Vec<u8> {
+        let mut startup = 196608_i32.to_be_bytes().to_vec();
+        startup.extend_from_slice(b"user\0alice\0\0");
+        let mut input = ((startup.len() + 4) as i32).to_be_bytes().to_vec();
+        input.extend(startup);
+        input.extend(message(b'p', b"secret\0"));
+        input.extend(message(b'P', b"\0SELECT n FROM numbers LIMIT $1\0\0\0"));
+        let mut bind = b"\0\0\0\0\0\x01".to_vec();
+        match limit {
+            Some(limit) => {
+                bind.extend_from_slice(&(limit.len() as i32).to_be_bytes());
+                bind.extend_from_slice(limit.as_bytes());
+            }
+            None => bind.extend_from_slice(&(-1_i32).to_be_bytes()),
+        }
+        bind.extend_from_slice(b"\0\0");
+        input.extend(message(b'B', &bind));
+        input.extend(message(b'D', b"P\0"));
+        input.extend(message(b'E', b"\0\0\0\0\0"));
+        input.extend(message(b'S', b""));
+        input.extend(message(b'X', b""));
+        input
+    }
+
+    /// A session sending `queries` with the simple query protocol.
+    fn simple_queries(queries: &[&str]) -> Vec<u8> {
+        let mut startup = 196608_i32.to_be_bytes().to_vec();
+        startup.extend_from_slice(b"user\0alice\0\0");
+        let mut input = ((startup.len() + 4) as i32).to_be_bytes().to_vec();
+        input.extend(startup);
+        input.extend(message(b'p', b"secret\0"));
+        for query in queries {
+            input.extend(message(b'Q', format!("{}\0", query).as_bytes()));
+        }
+        input.extend(message(b'X', b""));
+        input
+    }
+
+    /// The message types the server sent after startup.
+    async fn run_session(input: Vec<u8>, timeouts: Timeouts) -> Vec<char> {
+        let (mut client, server) = tokio::io::duplex(4096);
+        let session = tokio::spawn(
+            AsyncPostgressIntermediary::new(Numbers, server)
+                .with_timeouts(timeouts)
+                .run(),
+        );
+        client.write_all(&input).await.unwrap();
+        session.await.unwrap().unwrap();
+        let mut received = vec![];
+        client.read_to_end(&mut received).await.unwrap();
+        let types = message_types(&received);
+        let after_startup = types.iter().position(|t| *t == 'Z').unwrap() + 1;
+        types[after_startup..].to_vec()
+    }
+
+    #[tokio::test]
+    async fn serves_a_session_over_an_async_stream() {
+        assert_eq!(
+            run_session(select_numbers(Some("3")), Timeouts::default()).await,
+            ['1', '2', 'T', 'D', 'D', 'D', 'C', 'Z']
+        );
+        assert_eq!(
+            run_session(select_numbers(None), Timeouts::default()).await,
+            ['1', '2', 'T', 'C', 'Z']
+        );
+    }
+
+    #[tokio::test]
+    async fn cancels_statements_after_the_timeout() {
+        let timeouts = Timeouts {
+            statement: Some(Duration::from_millis(20)),
+            ..Timeouts::default()
+        };
+        assert_eq!(
+            run_session(select_numbers(Some("1000000")), timeouts).await,
+            ['1', '2', 'T', 'E', 'Z']
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_simple_queries_through_the_shim() {
+        assert_eq!(
+            run_session(
+                simple_queries(&["SELECT n FROM numbers", "SET statement_timeout TO 0"]),
+                Timeouts::default()
+            )
+            .await,
+            ['T', 'C', 'Z', 'C', 'Z']
+        );
+    }
+}
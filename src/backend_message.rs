@@ -0,0 +1,204 @@
+use bytes::BytesMut;
+use std::fmt;
+use std::io::{Cursor, Error, Result};
+
+use crate::client_message::{read_body, split_frame, Body, ProtocolViolation, ReadPostgresExt};
+use crate::limits::Limits;
+use crate::FormatCode;
+
+/// A message sent by the server, as a client decodes it. The owned
+/// counterpart of [`ServerMessage`](crate::ServerMessage).
+#[derive(Debug, Clone)]
+pub enum BackendMessage {
+    AuthenticationOk,
+    AuthenticationCleartextPassword,
+    BackendKeyData { process_id: i32, secret_key: i32 },
+    BindComplete,
+    CloseComplete,
+    CommandComplete { tag: String },
+    DataRow { fields: Vec<Option<Vec<u8>>> },
+    ErrorResponse(ErrorResponse),
+    EmptyQueryResponse,
+    NoData,
+    ParameterDescription { type_oids: Vec<u32> },
+    ParameterStatus { name: String, value: String },
+    ParseComplete,
+    ReadyForQuery { transaction_status: u8 },
+    RowDescription { fields: Vec<FieldDescription> },
+}
+
+/// A column of a RowDescription.
+#[derive(Debug, Clone)]
+pub struct FieldDescription {
+    pub name: String,
+    pub table_oid: u32,
+    pub attribute_number: i16,
+    pub type_oid: u32,
+    pub type_size: i16,
+    pub type_modifier: i32,
+    pub format_code: FormatCode,
+}
+
+/// The fields of an ErrorResponse clients act on. A client returns it as
+/// the source of an [`Error`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorResponse {
+    pub severity: String,
+    /// The SQLSTATE, such as `42601`.
+    pub code: String,
+    pub message: String,
+    /// 1-based character position in the query the error refers to.
+    pub position: Option<usize>,
+}
+
+impl fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} ({})", self.severity, self.message, self.code)
+    }
+}
+
+impl std::error::Error for ErrorResponse {}
+
+impl ErrorResponse {
+    /// An ERROR with the SQLSTATE `code`. Returned from a shim or a row
+    /// writer, it fails the statement and the session goes on, where any
+    /// other error ends the session.
+    pub fn error(code: &str, message: impl Into<String>) -> Error {
+        Error::other(ErrorResponse {
+            severity: "ERROR".to_string(),
+            code: code.to_string(),
+            message: message.into(),
+            position: None,
+        })
+    }
+
+    /// The server error `error` was made from, if any.
+    pub fn of(error: &Error) -> Option<&ErrorResponse> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl BackendMessage {
+    /// Decodes a message from the front of `buffer`, or returns `None` when
+    /// it does not hold the whole message yet. Consumed bytes are removed.
+    pub fn decode(buffer: &mut BytesMut) -> Result<Option<Self>> {
+        match split_frame(buffer, 1, Limits::default().max_message_size)? {
+            Some(frame) => Self::from_stream(&mut Cursor::new(frame)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn from_stream(stream: &mut impl ReadPostgresExt) -> Result<Self> {
+        let type_identification = stream.read_byte()?;
+        let buffer = read_body(stream, Limits::default().max_message_size)?;
+        let mut body = Body::new(&buffer);
+        let message = match type_identification {
+            b'R' => match body.int32()? {
+                0 => Self::AuthenticationOk,
+                3 => Self::AuthenticationCleartextPassword,
+                request => {
+                    return Err(ProtocolViolation::error(format!(
+                        "unsupported authentication request {}",
+                        request
+                    )))
+                }
+            },
+            b'K' => Self::BackendKeyData {
+                process_id: body.int32()? as i32,
+                secret_key: body.int32()? as i32,
+            },
+            b'2' => Self::BindComplete,
+            b'3' => Self::CloseComplete,
+            b'C' => Self::CommandComplete {
+                tag: body.string()?,
+            },
+            b'D' => {
+                let n_fields = body.int16()?;
+                let fields = (0..n_fields)
+                    .map(|_| match body.int32()? as i32 {
+                        -1 => Ok(None),
+                        length if length < 0 => {
+                            Err(ProtocolViolation::error("invalid field length"))
+                        }
+                        length => Ok(Some(body.take(length as usize)?.to_vec())),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Self::DataRow { fields }
+            }
+            b'E' => {
+                let mut error = ErrorResponse {
+                    severity: String::new(),
+                    code: String::new(),
+                    message: String::new(),
+                    position: None,
+                };
+                loop {
+                    let field_type = body.byte()?;
+                    if field_type == 0 {
+                        break;
+                    }
+                    let value = body.string()?;
+                    match field_type {
+                        b'S' => error.severity = value,
+                        b'C' => error.code = value,
+                        b'M' => error.message = value,
+                        b'P' => error.position = value.parse().ok(),
+                        _ => {}
+                    }
+                }
+                Self::ErrorResponse(error)
+            }
+            b'I' => Self::EmptyQueryResponse,
+            b'n' => Self::NoData,
+            b't' => {
+                let n_types = body.int16()?;
+                let type_oids = (0..n_types)
+                    .map(|_| body.int32())
+                    .collect::<Result<Vec<_>>>()?;
+                Self::ParameterDescription { type_oids }
+            }
+            b'S' => Self::ParameterStatus {
+                name: body.string()?,
+                value: body.string()?,
+            },
+            b'1' => Self::ParseComplete,
+            b'Z' => Self::ReadyForQuery {
+                transaction_status: body.byte()?,
+            },
+            b'T' => {
+                let n_fields = body.int16()?;
+                let fields = (0..n_fields)
+                    .map(|_| {
+                        Ok(FieldDescription {
+                            name: body.string()?,
+                            table_oid: body.int32()?,
+                            attribute_number: body.int16()? as i16,
+                            type_oid: body.int32()?,
+                            type_size: body.int16()? as i16,
+                            type_modifier: body.int32()? as i32,
+                            format_code: match body.int16()? {
+                                0 => FormatCode::Text,
+                                1 => FormatCode::Binary,
+                                code => {
+                                    return Err(ProtocolViolation::error(format!(
+                                        "unsupported format code: {}",
+                                        code
+                                    )))
+                                }
+                            },
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Self::RowDescription { fields }
+            }
+            _ => {
+                return Err(ProtocolViolation::error(format!(
+                    "invalid backend message type {}",
+                    type_identification
+                )))
+            }
+        };
+        body.finish()?;
+        Ok(message)
+    }
+}
@@ -0,0 +1,747 @@
+use sqlparser::ast::{Expr, Query, Statement};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::catalog::{is_catalog_statement, is_session_statement, Catalog, INFORMATION_SCHEMA};
+use crate::client_message::FormatCode;
+use crate::encoding::{parse_set_client_encoding, ClientEncoding};
+use crate::evaluate;
+use crate::server_message::CommandCompleteTag;
+use crate::timeout::{format_timeout, parse_timeout, TIMEOUT_SETTINGS};
+use crate::{Column, DefaultServerParameters, ParameterValue, Type, Value};
+
+/// Statements the intermediary answers itself instead of handing them to the
+/// shim, because they change protocol level session state or read the
+/// emulated system catalogs.
+#[derive(Debug, Clone)]
+pub(crate) enum BuiltinStatement {
+    SetClientEncoding(Option<String>),
+    /// A query evaluated against the emulated catalogs and session.
+    Query {
+        query: Box<Query>,
+        parameter_types: Vec<Type>,
+    },
+    Show(String),
+    /// `SET name = value`, `None` resets the parameter to its default.
+    Set {
+        name: String,
+        value: Option<String>,
+    },
+}
+
+/// Which kinds of statements the intermediary answers.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct BuiltinOptions {
+    /// Queries that only read `pg_catalog` and `information_schema`.
+    pub catalog: bool,
+    /// The queries drivers send while connecting: `SHOW`, `SET` of session
+    /// parameters and selects of session functions such as `version()`.
+    pub bootstrap: bool,
+}
+
+#[derive(Debug)]
+pub(crate) struct BuiltinResult {
+    pub columns: Option<Vec<Column>>,
+    pub rows: Vec<Vec<Value>>,
+    pub tag: CommandCompleteTag,
+    pub action: Option<SessionAction>,
+}
+
+/// Session changes applied when the statement is executed.
+#[derive(Debug)]
+pub(crate) enum SessionAction {
+    SetClientEncoding(ClientEncoding),
+    Set { name: String, value: String },
+}
+
+pub(crate) struct BuiltinPortal {
+    pub result: BuiltinResult,
+    pub result_format_codes: Vec<FormatCode>,
+}
+
+/// Session parameters `SET` may change when bootstrap queries are answered.
+const SETTABLE: &[&str] = &[
+    "application_name",
+    "datestyle",
+    "intervalstyle",
+    "timezone",
+    "extra_float_digits",
+    "search_path",
+    "bytea_output",
+    "client_min_messages",
+    "default_transaction_isolation",
+    "default_transaction_read_only",
+];
+
+/// Session state builtin statements can read.
+pub(crate) struct SessionState {
+    pub client_encoding: ClientEncoding,
+    pub default_client_encoding: ClientEncoding,
+    pub user: String,
+    pub database: String,
+    /// Current values of the session parameters, keyed by lowercase name.
+    pub settings: HashMap<String, String>,
+    defaults: HashMap<String, String>,
+}
+
+impl SessionState {
+    pub fn new() -> Self {
+        SessionState {
+            client_encoding: ClientEncoding::Utf8,
+            default_client_encoding: ClientEncoding::Utf8,
+            user: String::new(),
+            database: String::new(),
+            settings: HashMap::new(),
+            defaults: HashMap::new(),
+        }
+    }
+
+    /// Resets the session parameters to the server defaults.
+    pub fn reset_settings(&mut self, parameters: &DefaultServerParameters) {
+        let version_number = {
+            let mut parts = parameters
+                .server_version
+                .split(|c: char| !c.is_ascii_digit())
+                .map(|part| part.parse::<u32>().unwrap_or(0));
+            let major = parts.next().unwrap_or(0);
+            let minor = parts.next().unwrap_or(0);
+            major * 10000 + minor
+        };
+        self.defaults = [
+            ("server_version", parameters.server_version.clone()),
+            ("server_version_num", version_number.to_string()),
+            ("server_encoding", parameters.server_encoding.clone()),
+            ("application_name", parameters.application_name.clone()),
+            (
+                "default_transaction_read_only",
+                parameters.default_transaction_read_only.clone(),
+            ),
+            ("in_hot_standby", parameters.in_hot_standby.clone()),
+            ("is_superuser", parameters.is_superuser.clone()),
+            (
+                "session_authorization",
+                parameters.session_authorization.clone(),
+            ),
+            ("datestyle", parameters.date_style.clone()),
+            ("intervalstyle", parameters.interval_style.clone()),
+            ("timezone", parameters.time_zone.clone()),
+            ("integer_datetimes", parameters.integer_datetimes.clone()),
+            (
+                "standard_conforming_strings",
+                parameters.standard_conforming_strings.clone(),
+            ),
+            ("transaction_isolation", "read committed".to_string()),
+            (
+                "default_transaction_isolation",
+                "read committed".to_string(),
+            ),
+            ("transaction_read_only", "off".to_string()),
+            ("search_path", "\"$user\", public".to_string()),
+            ("extra_float_digits", "1".to_string()),
+            ("max_identifier_length", "63".to_string()),
+            ("bytea_output", "hex".to_string()),
+            ("client_min_messages", "notice".to_string()),
+        ]
+        .into_iter()
+        .chain(TIMEOUT_SETTINGS.iter().map(|name| (*name, "0".to_string())))
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+        self.settings = self.defaults.clone();
+    }
+
+    /// Changes the server default of a parameter and its current value.
+    pub fn set_default(&mut self, name: &str, value: String) {
+        self.defaults.insert(name.to_string(), value.clone());
+        self.settings.insert(name.to_string(), value);
+    }
+
+    /// The current value of a timeout parameter, `None` when disabled.
+    pub fn timeout(&self, name: &str) -> Option<Duration> {
+        parse_timeout(self.settings.get(name)?).flatten()
+    }
+
+    /// The current value of a session parameter.
+    pub fn setting(&self, name: &str) -> Option<String> {
+        match setting_name(name).as_str() {
+            "client_encoding" => Some(self.client_encoding.name().to_string()),
+            name => self.settings.get(name).cloned(),
+        }
+    }
+}
+
+/// Normalizes a parameter name, including the multi word aliases `SHOW`
+/// accepts.
+fn setting_name(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.as_str() {
+        "transaction isolation level" => "transaction_isolation".to_string(),
+        "time zone" => "timezone".to_string(),
+        _ => name,
+    }
+}
+
+/// The name a parameter is reported with in `ParameterStatus`, for the
+/// settable parameters the server reports.
+pub(crate) fn reported_name(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "application_name" => "application_name",
+        "datestyle" => "DateStyle",
+        "intervalstyle" => "IntervalStyle",
+        "timezone" => "TimeZone",
+        "default_transaction_read_only" => "default_transaction_read_only",
+        _ => return None,
+    })
+}
+
+impl BuiltinStatement {
+    /// Recognizes a single builtin statement, as sent with `Parse`.
+    pub fn from_query(
+        query: &str,
+        parameter_types: &[Type],
+        options: BuiltinOptions,
+    ) -> Option<Self> {
+        if let Some(encoding) = parse_set_client_encoding(query) {
+            return Some(BuiltinStatement::SetClientEncoding(encoding));
+        }
+        // Parameters the client left out or sent as unknown are text, which
+        // is also how Describe reports them.
+        let parameter_types: Vec<Type> = (0..parameter_count(query).max(parameter_types.len()))
+            .map(|i| match parameter_types.get(i) {
+                None | Some(&Type::UNKNOWN) => Type::TEXT,
+                Some(ty) => ty.clone(),
+            })
+            .collect();
+        let mut statements = Self::parse(query, &parameter_types, options)?;
+        match statements.len() {
+            1 => statements.pop(),
+            _ => None,
+        }
+    }
+
+    /// Recognizes the statements of a simple query. All of them have to be
+    /// builtin, a query mixing builtin and shim statements goes to the shim.
+    pub fn from_simple_query(query: &str, options: BuiltinOptions) -> Option<Vec<Self>> {
+        if let Some(encoding) = parse_set_client_encoding(query) {
+            return Some(vec![BuiltinStatement::SetClientEncoding(encoding)]);
+        }
+        Self::parse(query, &[], options)
+    }
+
+    fn parse(query: &str, parameter_types: &[Type], options: BuiltinOptions) -> Option<Vec<Self>> {
+        if !options.bootstrap {
+            let lowercase = query.to_lowercase();
+            let catalog = options.catalog
+                && (lowercase.contains("pg_") || lowercase.contains(INFORMATION_SCHEMA));
+            if !catalog && !lowercase.contains("timeout") {
+                return None;
+            }
+        }
+        let statements = Parser::parse_sql(&PostgreSqlDialect {}, query).ok()?;
+        if statements.is_empty() {
+            return None;
+        }
+        statements
+            .into_iter()
+            .map(|statement| Self::from_statement(statement, parameter_types, options))
+            .collect()
+    }
+
+    fn from_statement(
+        statement: Statement,
+        parameter_types: &[Type],
+        options: BuiltinOptions,
+    ) -> Option<Self> {
+        let answered = match &statement {
+            Statement::Query(_) => {
+                is_catalog_statement(&statement)
+                    || (options.bootstrap && is_session_statement(&statement))
+            }
+            _ => options.bootstrap || is_timeout_statement(&statement),
+        };
+        if !answered {
+            return None;
+        }
+        match statement {
+            Statement::Query(query) => Some(BuiltinStatement::Query {
+                query,
+                parameter_types: parameter_types.to_vec(),
+            }),
+            Statement::ShowVariable { variable } => {
+                let name = variable
+                    .iter()
+                    .map(|part| part.value.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(" ");
+                // The parser collects every word up to the end of the query,
+                // so a following statement shows up as more words.
+                let name = setting_name(&name);
+                if name.contains(' ') {
+                    None
+                } else {
+                    Some(BuiltinStatement::Show(name))
+                }
+            }
+            Statement::SetVariable {
+                variables, value, ..
+            } => {
+                let name = match variables.iter().collect::<Vec<_>>().as_slice() {
+                    [name] => setting_name(&name.to_string()),
+                    _ => return None,
+                };
+                let value = setting_value(&value);
+                if name == "client_encoding" {
+                    Some(BuiltinStatement::SetClientEncoding(value))
+                } else if SETTABLE.contains(&name.as_str())
+                    || TIMEOUT_SETTINGS.contains(&name.as_str())
+                {
+                    Some(BuiltinStatement::Set { name, value })
+                } else {
+                    None
+                }
+            }
+            Statement::SetTimeZone { value, .. } => Some(BuiltinStatement::Set {
+                name: "timezone".to_string(),
+                value: setting_value(&[value]),
+            }),
+            Statement::SetNames { charset_name, .. } => {
+                Some(BuiltinStatement::SetClientEncoding(Some(charset_name)))
+            }
+            Statement::SetNamesDefault {} => Some(BuiltinStatement::SetClientEncoding(None)),
+            _ => None,
+        }
+    }
+
+    /// The types of the parameters the statement takes.
+    pub fn parameter_types(&self) -> &[Type] {
+        match self {
+            BuiltinStatement::Query {
+                parameter_types, ..
+            } => parameter_types,
+            _ => &[],
+        }
+    }
+
+    /// The columns the statement returns, `None` for statements without
+    /// rows. Queries are evaluated with NULL parameters, as the types of
+    /// their columns follow from the values.
+    pub fn describe(
+        &self,
+        session: &SessionState,
+        catalog: Option<&Catalog>,
+    ) -> std::result::Result<Option<Vec<Column>>, (&'static str, String)> {
+        match self {
+            BuiltinStatement::Query {
+                parameter_types, ..
+            } => {
+                let parameters = vec![ParameterValue::Null; parameter_types.len()];
+                Ok(self.bind(session, catalog, parameters)?.columns)
+            }
+            BuiltinStatement::Show(name) => Ok(Some(vec![Column::new(name.clone(), Type::TEXT)])),
+            BuiltinStatement::Set { .. } | BuiltinStatement::SetClientEncoding(_) => Ok(None),
+        }
+    }
+
+    /// Evaluates the statement. Errors are a SQLSTATE code and message.
+    pub fn bind(
+        &self,
+        session: &SessionState,
+        catalog: Option<&Catalog>,
+        parameters: Vec<ParameterValue>,
+    ) -> std::result::Result<BuiltinResult, (&'static str, String)> {
+        match self {
+            BuiltinStatement::Query {
+                query,
+                parameter_types,
+            } => {
+                let catalog = catalog.ok_or_else(|| {
+                    (
+                        "XX000",
+                        "the schema description is no longer available".to_string(),
+                    )
+                })?;
+                let parameters = parameters
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, parameter)| {
+                        let ty = parameter_types.get(i).unwrap_or(&Type::UNKNOWN);
+                        match (parameter, ty) {
+                            (ParameterValue::Null, _) => Ok(Value::Null),
+                            (ParameterValue::Text(text), &Type::UNKNOWN) => Ok(Value::Text(text)),
+                            (parameter, &Type::UNKNOWN) => Err((
+                                "22P03",
+                                format!("binary parameter {:?} needs a declared type", parameter),
+                            )),
+                            (parameter, ty) => Value::from_parameter(parameter, ty)
+                                .map_err(|error| ("22P02", error.to_string())),
+                        }
+                    })
+                    .collect::<std::result::Result<Vec<Value>, _>>()?;
+                let result = evaluate::execute(catalog, query, &parameters)?;
+                Ok(BuiltinResult {
+                    tag: CommandCompleteTag::Select {
+                        rows: result.rows.len() as u32,
+                    },
+                    columns: Some(result.columns),
+                    rows: result.rows,
+                    action: None,
+                })
+            }
+            BuiltinStatement::Show(name) => {
+                let value = session.setting(name).ok_or_else(|| {
+                    (
+                        "42704",
+                        format!("unrecognized configuration parameter \"{}\"", name),
+                    )
+                })?;
+                Ok(BuiltinResult {
+                    columns: Some(vec![Column::new(name.clone(), Type::TEXT)]),
+                    rows: vec![vec![Value::Text(value)]],
+                    tag: CommandCompleteTag::Show,
+                    action: None,
+                })
+            }
+            BuiltinStatement::Set { name, value } => {
+                let mut value = match value {
+                    Some(value) => value.clone(),
+                    None => session.defaults.get(name).cloned().unwrap_or_default(),
+                };
+                if TIMEOUT_SETTINGS.contains(&name.as_str()) {
+                    let timeout = parse_timeout(&value).ok_or_else(|| {
+                        (
+                            "22023",
+                            format!("invalid value for parameter \"{}\": \"{}\"", name, value),
+                        )
+                    })?;
+                    value = format_timeout(timeout);
+                }
+                Ok(BuiltinResult {
+                    columns: None,
+                    rows: vec![],
+                    tag: CommandCompleteTag::Set,
+                    action: Some(SessionAction::Set {
+                        name: name.clone(),
+                        value,
+                    }),
+                })
+            }
+            BuiltinStatement::SetClientEncoding(name) => {
+                let encoding = match name {
+                    None => session.default_client_encoding,
+                    Some(name) => ClientEncoding::from_name(name).ok_or_else(|| {
+                        (
+                            "22023",
+                            format!(
+                                "invalid value for parameter \"client_encoding\": \"{}\"",
+                                name
+                            ),
+                        )
+                    })?,
+                };
+                Ok(BuiltinResult {
+                    columns: None,
+                    rows: vec![],
+                    tag: CommandCompleteTag::Set,
+                    action: Some(SessionAction::SetClientEncoding(encoding)),
+                })
+            }
+        }
+    }
+}
+
+/// The number of parameters `query` takes, the highest `$n` used.
+fn parameter_count(query: &str) -> usize {
+    Tokenizer::new(&PostgreSqlDialect {}, query)
+        .tokenize()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|token| match token {
+            Token::Placeholder(name) => name.strip_prefix('$')?.parse().ok(),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Whether the statement shows or sets a timeout, which the intermediary
+/// enforces and so always answers.
+fn is_timeout_statement(statement: &Statement) -> bool {
+    let name = match statement {
+        Statement::ShowVariable { variable } => match variable.as_slice() {
+            [name] => name.value.to_lowercase(),
+            _ => return false,
+        },
+        Statement::SetVariable { variables, .. } => {
+            match variables.iter().collect::<Vec<_>>()[..] {
+                [name] => name.to_string().to_lowercase(),
+                _ => return false,
+            }
+        }
+        _ => return false,
+    };
+    TIMEOUT_SETTINGS.contains(&name.as_str())
+}
+
+/// The value of a `SET`, `None` for `DEFAULT`. Lists such as `search_path`
+/// are joined the way `SHOW` prints them.
+fn setting_value(value: &[Expr]) -> Option<String> {
+    let parts: Vec<String> = value
+        .iter()
+        .map(|expr| match expr {
+            Expr::Value(sqlparser::ast::Value::SingleQuotedString(text)) => text.clone(),
+            Expr::Identifier(ident) => ident.value.clone(),
+            expr => expr.to_string(),
+        })
+        .collect();
+    match parts.as_slice() {
+        [part] if part.eq_ignore_ascii_case("default") => None,
+        _ => Some(parts.join(", ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::NoTables;
+    use crate::{evaluate, TypeRegistry};
+
+    const BOOTSTRAP: BuiltinOptions = BuiltinOptions {
+        catalog: true,
+        bootstrap: true,
+    };
+
+    fn session() -> SessionState {
+        let mut session = SessionState::new();
+        session.user = "alice".to_string();
+        session.database = "shop".to_string();
+        session.reset_settings(&DefaultServerParameters {
+            server_version: "14.5".to_string(),
+            server_encoding: "UTF8".to_string(),
+            client_encoding: "UTF8".to_string(),
+            application_name: String::new(),
+            default_transaction_read_only: "off".to_string(),
+            in_hot_standby: "off".to_string(),
+            is_superuser: "on".to_string(),
+            session_authorization: "alice".to_string(),
+            date_style: "ISO, MDY".to_string(),
+            interval_style: "postgres".to_string(),
+            time_zone: "UTC".to_string(),
+            integer_datetimes: "on".to_string(),
+            standard_conforming_strings: "on".to_string(),
+        });
+        session
+    }
+
+    fn run(session: &SessionState, query: &str) -> Vec<Vec<Vec<Option<String>>>> {
+        let catalog = Catalog::new(&NoTables, &TypeRegistry::new(), session);
+        BuiltinStatement::from_simple_query(query, BOOTSTRAP)
+            .expect("builtin query")
+            .iter()
+            .map(|statement| {
+                statement
+                    .bind(session, Some(&catalog), vec![])
+                    .unwrap()
+                    .rows
+                    .iter()
+                    .map(|row| row.iter().map(evaluate::text).collect())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bootstrap_queries_are_opt_in() {
+        let catalog_only = BuiltinOptions {
+            catalog: true,
+            bootstrap: false,
+        };
+        for query in [
+            "SELECT version()",
+            "SHOW transaction_isolation",
+            "SET extra_float_digits = 3",
+        ] {
+            assert!(BuiltinStatement::from_query(query, &[], catalog_only).is_none());
+            assert!(BuiltinStatement::from_query(query, &[], BOOTSTRAP).is_some());
+        }
+        assert!(BuiltinStatement::from_query("SELECT now()", &[], BOOTSTRAP).is_none());
+        assert!(
+            BuiltinStatement::from_query("SELECT version() FROM orders", &[], BOOTSTRAP).is_none()
+        );
+        assert!(BuiltinStatement::from_query("SET work_mem = '4MB'", &[], BOOTSTRAP).is_none());
+        assert!(BuiltinStatement::from_simple_query(
+            "SHOW search_path; SELECT * FROM orders",
+            BOOTSTRAP
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn answers_session_queries() {
+        let session = session();
+        assert_eq!(
+            run(
+                &session,
+                "SELECT version(); SELECT current_schema(); SHOW TRANSACTION ISOLATION LEVEL"
+            ),
+            vec![
+                vec![vec![Some(
+                    "PostgreSQL 14.5 on x86_64-pc-linux-gnu, compiled by postgres-shim, 64-bit"
+                        .to_string()
+                )]],
+                vec![vec![Some("public".to_string())]],
+                vec![vec![Some("read committed".to_string())]],
+            ]
+        );
+        assert_eq!(
+            run(&session, "SELECT current_setting('server_version_num')"),
+            vec![vec![vec![Some("140005".to_string())]]]
+        );
+    }
+
+    #[test]
+    fn set_reports_the_new_value() {
+        let session = session();
+        let statement =
+            BuiltinStatement::from_query("SET extra_float_digits = 3", &[], BOOTSTRAP).unwrap();
+        match statement.bind(&session, None, vec![]).unwrap().action {
+            Some(SessionAction::Set { name, value }) => {
+                assert_eq!((name.as_str(), value.as_str()), ("extra_float_digits", "3"))
+            }
+            action => panic!("unexpected action {:?}", action),
+        }
+        let statement =
+            BuiltinStatement::from_query("SET TIME ZONE DEFAULT", &[], BOOTSTRAP).unwrap();
+        match statement.bind(&session, None, vec![]).unwrap().action {
+            Some(SessionAction::Set { name, value }) => {
+                assert_eq!((name.as_str(), value.as_str()), ("timezone", "UTC"))
+            }
+            action => panic!("unexpected action {:?}", action),
+        }
+    }
+
+    #[test]
+    fn always_answers_timeout_settings() {
+        let mut session = session();
+        let statement = BuiltinStatement::from_query(
+            "SET statement_timeout = '5000'",
+            &[],
+            BuiltinOptions::default(),
+        )
+        .unwrap();
+        match statement.bind(&session, None, vec![]).unwrap().action {
+            Some(SessionAction::Set { name, value }) => {
+                assert_eq!((name.as_str(), value.as_str()), ("statement_timeout", "5s"));
+                session.settings.insert(name, value);
+            }
+            action => panic!("unexpected action {:?}", action),
+        }
+        assert_eq!(
+            session.timeout("statement_timeout"),
+            Some(Duration::from_secs(5))
+        );
+        assert!(matches!(
+            BuiltinStatement::from_query(
+                "SHOW idle_session_timeout",
+                &[],
+                BuiltinOptions::default()
+            ),
+            Some(BuiltinStatement::Show(_))
+        ));
+        let error = BuiltinStatement::from_query(
+            "SET idle_session_timeout = 'later'",
+            &[],
+            BuiltinOptions::default(),
+        )
+        .unwrap()
+        .bind(&session, None, vec![])
+        .unwrap_err();
+        assert_eq!(error.0, "22023");
+    }
+
+    #[test]
+    fn answers_npgsql_type_loading() {
+        let results = run(
+            &session(),
+            r#"
+SELECT ns.nspname, t.oid, t.typname, t.typtype, t.typnotnull, t.elemtypoid
+FROM (
+    -- Arrays have typtype=b - this subquery identifies them by their typreceive and converts their typtype to a
+    SELECT
+        typ.oid, typ.typnamespace, typ.typname, typ.typtype, typ.typrelid, typ.typnotnull, typ.relkind,
+        elemtyp.oid AS elemtypoid, elemtyp.typname AS elemtypname, elemcls.relkind AS elemrelkind,
+        CASE WHEN elemproc.proname='array_recv' THEN 'a' ELSE elemtyp.typtype END AS elemtyptype
+    FROM (
+        SELECT typ.oid, typnamespace, typname, typrelid, typnotnull, relkind, typelem AS elemoid,
+            CASE WHEN proc.proname='array_recv' THEN 'a' ELSE typ.typtype END AS typtype,
+            CASE
+                WHEN proc.proname='array_recv' THEN typ.typelem
+                WHEN typ.typtype='r' THEN rngsubtype
+                WHEN typ.typtype='m' THEN (SELECT rngtypid FROM pg_range WHERE rngmultitypid = typ.oid)
+                WHEN typ.typtype='d' THEN typ.typbasetype
+            END AS elemtypoid
+        FROM pg_type AS typ
+        LEFT JOIN pg_class AS cls ON (cls.oid = typ.typrelid)
+        LEFT JOIN pg_proc AS proc ON proc.oid = typ.typreceive
+        LEFT JOIN pg_range ON (pg_range.rngtypid = typ.oid)
+    ) AS typ
+    LEFT JOIN pg_type AS elemtyp ON elemtyp.oid = elemtypoid
+    LEFT JOIN pg_class AS elemcls ON (elemcls.oid = elemtyp.typrelid)
+    LEFT JOIN pg_proc AS elemproc ON elemproc.oid = elemtyp.typreceive
+) AS t
+JOIN pg_namespace AS ns ON (ns.oid = typnamespace)
+WHERE
+    typtype IN ('b', 'r', 'm', 'e', 'd') OR -- Base, range, multirange, enum, domain
+    (typtype = 'c' AND relkind='c') OR -- User-defined free-standing composites (not table composites) by default
+    (typtype = 'p' AND typname IN ('record', 'void', 'unknown')) OR -- Some special supported pseudo-types
+    (typtype = 'a' AND (  -- Array of...
+        elemtyptype IN ('b', 'r', 'm', 'e', 'd') OR -- Array of base, range, multirange, enum, domain
+        (elemtyptype = 'p' AND elemtypname IN ('record', 'void')) OR -- Arrays of special supported pseudo-types
+        (elemtyptype = 'c' AND elemrelkind='c') -- Array of user-defined free-standing composites (not table composites) by default
+    ))
+ORDER BY CASE
+       WHEN typtype IN ('b', 'e', 'p') THEN 0           -- First base types, enums, pseudo-types
+       WHEN typtype = 'r' THEN 1                        -- Ranges after
+       WHEN typtype = 'm' THEN 2                        -- Multiranges after
+       WHEN typtype = 'c' THEN 3                        -- Composites after
+       WHEN typtype = 'd' AND elemtyptype <> 'a' THEN 4 -- Domains over non-arrays after
+       WHEN typtype = 'a' THEN 5                        -- Arrays after
+       WHEN typtype = 'd' AND elemtyptype = 'a' THEN 6  -- Domains over arrays last
+END;
+
+-- Load field definitions for (free-standing) composite types
+SELECT typ.oid, att.attname, att.atttypid
+FROM pg_type AS typ
+JOIN pg_namespace AS ns ON (ns.oid = typ.typnamespace)
+JOIN pg_class AS cls ON (cls.oid = typ.typrelid)
+JOIN pg_attribute AS att ON (att.attrelid = typ.typrelid)
+WHERE
+  (typ.typtype = 'c' AND cls.relkind='c') AND
+  attnum > 0 AND     -- Don't load system attributes
+  NOT attisdropped
+ORDER BY typ.oid, att.attnum;
+
+-- Load enum fields
+SELECT pg_type.oid, enumlabel
+FROM pg_enum
+JOIN pg_type ON pg_type.oid=enumtypid
+ORDER BY oid, enumsortorder;
+"#,
+        );
+        assert_eq!(results.len(), 3);
+        let int4 = results[0]
+            .iter()
+            .find(|row| row[2].as_deref() == Some("int4"))
+            .expect("int4 is loaded");
+        assert_eq!(int4[3].as_deref(), Some("b"));
+        let int4_array = results[0]
+            .iter()
+            .find(|row| row[2].as_deref() == Some("_int4"))
+            .expect("int4[] is loaded");
+        assert_eq!(int4_array[3].as_deref(), Some("a"));
+        assert_eq!(int4_array[5].as_deref(), Some("23"));
+        assert!(results[1].is_empty());
+        assert!(results[2].is_empty());
+    }
+}
@@ -0,0 +1,1169 @@
+use postgres_types::Kind;
+use sqlparser::ast::{visit_expressions, visit_relations, Expr, Statement};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+use crate::builtin::SessionState;
+use crate::evaluate::{self, Database, EvalResult, Relation};
+use crate::information_schema;
+use crate::{type_size, Column, Type, TypeRegistry, Value};
+
+/// Describes the tables a shim exposes. The intermediary uses it to answer
+/// queries against `pg_catalog` and `information_schema` itself, so psql
+/// meta-commands such as `\d`, GUI and BI tools can browse the shim without
+/// it handling catalog SQL.
+pub trait SchemaDescription {
+    fn tables(&self) -> Vec<Table>;
+
+    /// Schemas without tables that should still be listed. `public` is always
+    /// present, as are the schemas of the tables.
+    fn schemas(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Types listed in `pg_type` on top of the builtins, the type registry and
+    /// the types of the table columns.
+    fn types(&self) -> Vec<Type> {
+        vec![]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableKind {
+    Table,
+    View,
+}
+
+/// A table as listed in `pg_class`. Its columns are numbered from 1 in order
+/// unless they carry an attribute number of their own.
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub oid: u32,
+    pub schema: String,
+    pub name: String,
+    pub kind: TableKind,
+    pub columns: Vec<Column>,
+}
+
+impl Table {
+    /// A table in the `public` schema. User table OIDs start at 16384 in
+    /// postgres, staying above that keeps them apart from the builtins.
+    pub fn new(oid: u32, name: impl Into<String>) -> Self {
+        Self {
+            oid,
+            schema: PUBLIC_SCHEMA.to_string(),
+            name: name.into(),
+            kind: TableKind::Table,
+            columns: vec![],
+        }
+    }
+
+    pub fn in_schema(mut self, schema: impl Into<String>) -> Self {
+        self.schema = schema.into();
+        self
+    }
+
+    pub fn view(mut self) -> Self {
+        self.kind = TableKind::View;
+        self
+    }
+
+    pub fn with_column(mut self, column: Column) -> Self {
+        self.columns.push(column);
+        self
+    }
+
+    pub fn with_columns(mut self, columns: impl IntoIterator<Item = Column>) -> Self {
+        self.columns.extend(columns);
+        self
+    }
+
+    fn attribute_numbers(&self) -> impl Iterator<Item = (i16, &Column)> {
+        self.columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| (column.attribute_number.unwrap_or(i as i16 + 1), column))
+    }
+}
+
+pub(crate) const PUBLIC_SCHEMA: &str = "public";
+pub(crate) const CATALOG_SCHEMA: &str = "pg_catalog";
+pub(crate) const INFORMATION_SCHEMA: &str = "information_schema";
+
+const CATALOG_NAMESPACE_OID: u32 = 11;
+const PUBLIC_NAMESPACE_OID: u32 = 2200;
+const INFORMATION_SCHEMA_NAMESPACE_OID: u32 = 13_000;
+/// OIDs handed out to schemas declared by the shim.
+const FIRST_SCHEMA_OID: u32 = 16_000;
+const OWNER_OID: u32 = 10;
+const DEFAULT_COLLATION_OID: u32 = 100;
+
+/// Builtin types listed in `pg_type`.
+pub(crate) const BUILTIN_TYPES: &[Type] = &[
+    Type::BOOL,
+    Type::BYTEA,
+    Type::CHAR,
+    Type::NAME,
+    Type::INT8,
+    Type::INT2,
+    Type::INT4,
+    Type::REGPROC,
+    Type::TEXT,
+    Type::OID,
+    Type::JSON,
+    Type::FLOAT4,
+    Type::FLOAT8,
+    Type::UNKNOWN,
+    Type::BPCHAR,
+    Type::VARCHAR,
+    Type::DATE,
+    Type::TIME,
+    Type::TIMESTAMP,
+    Type::TIMESTAMPTZ,
+    Type::INTERVAL,
+    Type::NUMERIC,
+    Type::REGCLASS,
+    Type::REGTYPE,
+    Type::UUID,
+    Type::JSONB,
+    Type::BOOL_ARRAY,
+    Type::BYTEA_ARRAY,
+    Type::CHAR_ARRAY,
+    Type::NAME_ARRAY,
+    Type::INT8_ARRAY,
+    Type::INT2_ARRAY,
+    Type::INT4_ARRAY,
+    Type::TEXT_ARRAY,
+    Type::OID_ARRAY,
+    Type::JSON_ARRAY,
+    Type::FLOAT4_ARRAY,
+    Type::FLOAT8_ARRAY,
+    Type::BPCHAR_ARRAY,
+    Type::VARCHAR_ARRAY,
+    Type::DATE_ARRAY,
+    Type::TIME_ARRAY,
+    Type::TIMESTAMP_ARRAY,
+    Type::TIMESTAMPTZ_ARRAY,
+    Type::INTERVAL_ARRAY,
+    Type::NUMERIC_ARRAY,
+    Type::UUID_ARRAY,
+    Type::JSONB_ARRAY,
+];
+
+/// The system catalogs built from a [`SchemaDescription`], in the shape
+/// [`evaluate`] reads them.
+pub(crate) struct Catalog {
+    relations: HashMap<&'static str, Relation>,
+    information_schema: HashMap<&'static str, Relation>,
+    namespaces: Vec<(u32, String)>,
+    tables: Vec<Table>,
+    types: Vec<Type>,
+    user: String,
+    database: String,
+    settings: HashMap<String, String>,
+}
+
+impl Catalog {
+    pub fn new(
+        description: &dyn SchemaDescription,
+        registry: &TypeRegistry,
+        session: &SessionState,
+    ) -> Self {
+        let tables = description.tables();
+
+        let mut namespaces = vec![
+            (CATALOG_NAMESPACE_OID, CATALOG_SCHEMA.to_string()),
+            (PUBLIC_NAMESPACE_OID, PUBLIC_SCHEMA.to_string()),
+            (
+                INFORMATION_SCHEMA_NAMESPACE_OID,
+                INFORMATION_SCHEMA.to_string(),
+            ),
+        ];
+        let schemas = description
+            .schemas()
+            .into_iter()
+            .chain(tables.iter().map(|table| table.schema.clone()))
+            .chain(registry.types().map(|ty| ty.schema().to_string()));
+        for schema in schemas {
+            if !namespaces.iter().any(|(_, name)| *name == schema) {
+                let oid = FIRST_SCHEMA_OID + namespaces.len() as u32;
+                namespaces.push((oid, schema));
+            }
+        }
+
+        let mut types: Vec<Type> = BUILTIN_TYPES.to_vec();
+        let extra_types =
+            registry
+                .types()
+                .cloned()
+                .chain(description.types())
+                .chain(tables.iter().flat_map(|table| {
+                    table
+                        .columns
+                        .iter()
+                        .map(|column| column.column_type.clone())
+                }));
+        for ty in extra_types {
+            if !types.iter().any(|other| other.oid() == ty.oid()) {
+                types.push(ty);
+            }
+        }
+
+        let mut catalog = Catalog {
+            relations: HashMap::new(),
+            information_schema: HashMap::new(),
+            namespaces,
+            tables,
+            types,
+            user: session.user.clone(),
+            database: session.database.clone(),
+            settings: session.settings.clone(),
+        };
+        catalog.settings.insert(
+            "client_encoding".to_string(),
+            session.client_encoding.name().to_string(),
+        );
+        catalog.relations = HashMap::from([
+            ("pg_namespace", catalog.pg_namespace()),
+            ("pg_class", catalog.pg_class()),
+            ("pg_attribute", catalog.pg_attribute()),
+            ("pg_type", catalog.pg_type()),
+            ("pg_enum", catalog.pg_enum()),
+        ]);
+        let pg_proc = catalog.pg_proc();
+        catalog.relations.insert("pg_proc", pg_proc);
+        catalog.information_schema = information_schema::views(&catalog);
+        catalog
+    }
+
+    pub fn tables(&self) -> &[Table] {
+        &self.tables
+    }
+
+    pub fn schemas(&self) -> impl Iterator<Item = &str> {
+        self.namespaces.iter().map(|(_, name)| name.as_str())
+    }
+
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+
+    fn namespace_oid(&self, schema: &str) -> u32 {
+        self.namespaces
+            .iter()
+            .find(|(_, name)| name == schema)
+            .map(|(oid, _)| *oid)
+            .unwrap_or(PUBLIC_NAMESPACE_OID)
+    }
+
+    fn type_namespace_oid(&self, ty: &Type) -> u32 {
+        if BUILTIN_TYPES.contains(ty) {
+            CATALOG_NAMESPACE_OID
+        } else {
+            self.namespace_oid(ty.schema())
+        }
+    }
+
+    fn pg_namespace(&self) -> Relation {
+        relation(
+            &["oid", "nspname", "nspowner", "nspacl"],
+            self.namespaces
+                .iter()
+                .map(|(oid, name)| {
+                    vec![
+                        oid_value(*oid),
+                        text(name),
+                        oid_value(OWNER_OID),
+                        Value::Null,
+                    ]
+                })
+                .collect(),
+        )
+    }
+
+    fn pg_class(&self) -> Relation {
+        relation(
+            &[
+                "oid",
+                "relname",
+                "relnamespace",
+                "reltype",
+                "reloftype",
+                "relowner",
+                "relam",
+                "relfilenode",
+                "reltablespace",
+                "relpages",
+                "reltuples",
+                "relallvisible",
+                "reltoastrelid",
+                "relhasindex",
+                "relisshared",
+                "relpersistence",
+                "relkind",
+                "relnatts",
+                "relchecks",
+                "relhasrules",
+                "relhastriggers",
+                "relhassubclass",
+                "relrowsecurity",
+                "relforcerowsecurity",
+                "relispopulated",
+                "relreplident",
+                "relispartition",
+                "relrewrite",
+                "relacl",
+                "reloptions",
+                "relpartbound",
+            ],
+            self.tables
+                .iter()
+                .map(|table| {
+                    vec![
+                        oid_value(table.oid),
+                        text(&table.name),
+                        oid_value(self.namespace_oid(&table.schema)),
+                        oid_value(0),
+                        oid_value(0),
+                        oid_value(OWNER_OID),
+                        oid_value(0),
+                        oid_value(0),
+                        oid_value(0),
+                        Value::Int4(0),
+                        Value::Float4(-1.0),
+                        Value::Int4(0),
+                        oid_value(0),
+                        Value::Bool(false),
+                        Value::Bool(false),
+                        text("p"),
+                        text(match table.kind {
+                            TableKind::Table => "r",
+                            TableKind::View => "v",
+                        }),
+                        Value::Int2(table.columns.len() as i16),
+                        Value::Int2(0),
+                        Value::Bool(false),
+                        Value::Bool(false),
+                        Value::Bool(false),
+                        Value::Bool(false),
+                        Value::Bool(false),
+                        Value::Bool(true),
+                        text("d"),
+                        Value::Bool(false),
+                        oid_value(0),
+                        Value::Null,
+                        Value::Null,
+                        Value::Null,
+                    ]
+                })
+                .collect(),
+        )
+    }
+
+    fn pg_attribute(&self) -> Relation {
+        relation(
+            &[
+                "attrelid",
+                "attname",
+                "atttypid",
+                "attstattarget",
+                "attlen",
+                "attnum",
+                "attndims",
+                "attcacheoff",
+                "atttypmod",
+                "attbyval",
+                "attalign",
+                "attstorage",
+                "attcompression",
+                "attnotnull",
+                "atthasdef",
+                "atthasmissing",
+                "attidentity",
+                "attgenerated",
+                "attisdropped",
+                "attislocal",
+                "attinhcount",
+                "attcollation",
+                "attacl",
+                "attoptions",
+                "attfdwoptions",
+                "attmissingval",
+            ],
+            self.tables
+                .iter()
+                .flat_map(|table| {
+                    table.attribute_numbers().map(|(number, column)| {
+                        let ty = &column.column_type;
+                        let length = column.type_size();
+                        vec![
+                            oid_value(table.oid),
+                            text(&column.name),
+                            oid_value(ty.oid()),
+                            Value::Int2(-1),
+                            Value::Int2(length),
+                            Value::Int2(number),
+                            Value::Int4(matches!(ty.kind(), Kind::Array(_)) as i32),
+                            Value::Int4(-1),
+                            Value::Int4(column.type_modifier()),
+                            Value::Bool(by_value(length)),
+                            text(alignment(length)),
+                            text(storage(length)),
+                            text(""),
+                            Value::Bool(!column.nullable),
+                            Value::Bool(false),
+                            Value::Bool(false),
+                            text(""),
+                            text(""),
+                            Value::Bool(false),
+                            Value::Bool(true),
+                            Value::Int2(0),
+                            oid_value(collation(ty)),
+                            Value::Null,
+                            Value::Null,
+                            Value::Null,
+                            Value::Null,
+                        ]
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    fn pg_type(&self) -> Relation {
+        relation(
+            &[
+                "oid",
+                "typname",
+                "typnamespace",
+                "typowner",
+                "typlen",
+                "typbyval",
+                "typtype",
+                "typcategory",
+                "typispreferred",
+                "typisdefined",
+                "typdelim",
+                "typrelid",
+                "typsubscript",
+                "typelem",
+                "typarray",
+                "typinput",
+                "typoutput",
+                "typreceive",
+                "typsend",
+                "typmodin",
+                "typmodout",
+                "typanalyze",
+                "typalign",
+                "typstorage",
+                "typnotnull",
+                "typbasetype",
+                "typtypmod",
+                "typndims",
+                "typcollation",
+                "typdefaultbin",
+                "typdefault",
+                "typacl",
+            ],
+            self.types
+                .iter()
+                .map(|ty| {
+                    let length = type_size(ty);
+                    let element = match ty.kind() {
+                        Kind::Array(element) => element.oid(),
+                        _ => 0,
+                    };
+                    let array = self
+                        .types
+                        .iter()
+                        .find(|other| matches!(other.kind(), Kind::Array(element) if element == ty))
+                        .map(Type::oid)
+                        .unwrap_or(0);
+                    let base = match ty.kind() {
+                        Kind::Domain(base) => base.oid(),
+                        _ => 0,
+                    };
+                    let io_name = match ty.kind() {
+                        Kind::Array(_) => "array",
+                        Kind::Enum(_) => "enum",
+                        Kind::Composite(_) => "record",
+                        Kind::Domain(base) => base.name(),
+                        _ => ty.name(),
+                    };
+                    vec![
+                        oid_value(ty.oid()),
+                        text(ty.name()),
+                        oid_value(self.type_namespace_oid(ty)),
+                        oid_value(OWNER_OID),
+                        Value::Int2(length),
+                        Value::Bool(by_value(length)),
+                        text(match ty.kind() {
+                            Kind::Enum(_) => "e",
+                            Kind::Composite(_) => "c",
+                            Kind::Domain(_) => "d",
+                            Kind::Pseudo => "p",
+                            _ => "b",
+                        }),
+                        text(category(ty)),
+                        Value::Bool(matches!(
+                            *ty,
+                            Type::BOOL | Type::TEXT | Type::FLOAT8 | Type::OID | Type::TIMESTAMPTZ
+                        )),
+                        Value::Bool(true),
+                        text(","),
+                        oid_value(0),
+                        text(if element != 0 {
+                            "array_subscript_handler"
+                        } else {
+                            "-"
+                        }),
+                        oid_value(element),
+                        oid_value(array),
+                        text(&format!("{}_in", io_name)),
+                        text(&format!("{}_out", io_name)),
+                        text(&format!("{}_recv", io_name)),
+                        text(&format!("{}_send", io_name)),
+                        text("-"),
+                        text("-"),
+                        text("-"),
+                        text(alignment(length)),
+                        text(storage(length)),
+                        Value::Bool(false),
+                        oid_value(base),
+                        Value::Int4(-1),
+                        Value::Int4(0),
+                        oid_value(collation(ty)),
+                        Value::Null,
+                        Value::Null,
+                        Value::Null,
+                    ]
+                })
+                .collect(),
+        )
+    }
+
+    /// The type input, output and subscript functions. `regproc` columns
+    /// such as `pg_type.typreceive` hold function names, so `oid` here is the
+    /// name as well, which keeps joins like npgsql's `proc.oid = typreceive`
+    /// working.
+    fn pg_proc(&self) -> Relation {
+        let pg_type = &self.relations["pg_type"];
+        let mut names: Vec<String> = [
+            "typsubscript",
+            "typinput",
+            "typoutput",
+            "typreceive",
+            "typsend",
+        ]
+        .iter()
+        .filter_map(|column| pg_type.columns.iter().position(|name| name == column))
+        .flat_map(|index| {
+            pg_type
+                .rows
+                .iter()
+                .filter_map(move |row| evaluate::text(&row[index]))
+        })
+        .filter(|name| name != "-")
+        .collect();
+        names.sort();
+        names.dedup();
+        relation(
+            &[
+                "oid",
+                "proname",
+                "pronamespace",
+                "proowner",
+                "prokind",
+                "pronargs",
+            ],
+            names
+                .into_iter()
+                .map(|name| {
+                    vec![
+                        text(&name),
+                        Value::Text(name),
+                        oid_value(CATALOG_NAMESPACE_OID),
+                        oid_value(OWNER_OID),
+                        text("f"),
+                        Value::Int2(1),
+                    ]
+                })
+                .collect(),
+        )
+    }
+
+    fn pg_enum(&self) -> Relation {
+        relation(
+            &["oid", "enumtypid", "enumsortorder", "enumlabel"],
+            self.types
+                .iter()
+                .filter_map(|ty| match ty.kind() {
+                    Kind::Enum(labels) => Some((ty, labels)),
+                    _ => None,
+                })
+                .flat_map(|(ty, labels)| {
+                    labels.iter().enumerate().map(move |(i, label)| {
+                        vec![
+                            Value::Int8(ty.oid() as i64 * 1000 + i as i64),
+                            oid_value(ty.oid()),
+                            Value::Float4(i as f32 + 1.0),
+                            text(label),
+                        ]
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    fn find_type(&self, oid: u32) -> Option<&Type> {
+        self.types.iter().find(|ty| ty.oid() == oid)
+    }
+
+    /// The SQL spelling of a type, as returned by `format_type`.
+    pub fn format_type(&self, oid: u32, type_modifier: i32) -> Option<String> {
+        let ty = self.find_type(oid)?;
+        if let Kind::Array(element) = ty.kind() {
+            return Some(format!(
+                "{}[]",
+                self.format_type(element.oid(), type_modifier)
+                    .unwrap_or_else(|| element.name().to_string())
+            ));
+        }
+        let modifier = type_modifier - 4;
+        Some(match *ty {
+            Type::BOOL => "boolean".to_string(),
+            Type::INT2 => "smallint".to_string(),
+            Type::INT4 => "integer".to_string(),
+            Type::INT8 => "bigint".to_string(),
+            Type::FLOAT4 => "real".to_string(),
+            Type::FLOAT8 => "double precision".to_string(),
+            Type::CHAR => "\"char\"".to_string(),
+            Type::VARCHAR if type_modifier >= 4 => format!("character varying({})", modifier),
+            Type::VARCHAR => "character varying".to_string(),
+            Type::BPCHAR if type_modifier >= 4 => format!("character({})", modifier),
+            Type::BPCHAR => "character".to_string(),
+            Type::NUMERIC if type_modifier >= 4 => {
+                format!("numeric({},{})", modifier >> 16, modifier & 0xffff)
+            }
+            Type::TIME => "time without time zone".to_string(),
+            Type::TIMESTAMP => "timestamp without time zone".to_string(),
+            Type::TIMESTAMPTZ => "timestamp with time zone".to_string(),
+            _ if BUILTIN_TYPES.contains(ty) || ty.schema() == PUBLIC_SCHEMA => {
+                ty.name().to_string()
+            }
+            _ => format!("{}.{}", ty.schema(), ty.name()),
+        })
+    }
+
+    fn find_table(&self, name: &str) -> Option<&Table> {
+        let (schema, name) = split_qualified(name);
+        self.tables.iter().find(|table| {
+            table.name == name && schema.as_ref().is_none_or(|schema| table.schema == *schema)
+        })
+    }
+
+    fn find_type_by_name(&self, name: &str) -> Option<&Type> {
+        let (_, name) = split_qualified(name);
+        self.types.iter().find(|ty| {
+            ty.name() == name
+                || self
+                    .format_type(ty.oid(), -1)
+                    .is_some_and(|formatted| formatted == name)
+        })
+    }
+}
+
+impl Database for Catalog {
+    fn table(&self, schema: Option<&str>, name: &str) -> Option<Relation> {
+        match schema {
+            None | Some(CATALOG_SCHEMA) => self.relations.get(name).cloned(),
+            Some(INFORMATION_SCHEMA) => self.information_schema.get(name).cloned(),
+            Some(_) => None,
+        }
+    }
+
+    fn is_system_table(&self, schema: Option<&str>, name: &str) -> bool {
+        match schema {
+            Some(schema) => schema == CATALOG_SCHEMA || schema == INFORMATION_SCHEMA,
+            None => name.starts_with("pg_"),
+        }
+    }
+
+    fn function(&self, name: &str, args: &[Value]) -> EvalResult<Option<Value>> {
+        Ok(Some(match (name, args) {
+            ("pg_get_userbyid", _)
+            | ("current_user" | "session_user" | "user" | "current_role", []) => text(&self.user),
+            ("current_database", []) => text(&self.database),
+            ("version", []) => Value::Text(format!(
+                "PostgreSQL {} on x86_64-pc-linux-gnu, compiled by postgres-shim, 64-bit",
+                self.settings
+                    .get("server_version")
+                    .map(String::as_str)
+                    .unwrap_or_default()
+            )),
+            ("current_setting", [name, missing_ok @ ..]) => {
+                let name = evaluate::text(name).unwrap_or_default().to_lowercase();
+                match self.settings.get(&name) {
+                    Some(value) => text(value),
+                    None if matches!(missing_ok, [Value::Bool(true)]) => Value::Null,
+                    None => {
+                        return Err((
+                            "42704",
+                            format!("unrecognized configuration parameter \"{}\"", name),
+                        ))
+                    }
+                }
+            }
+            ("pg_backend_pid", []) => Value::Int4(0),
+            ("pg_is_in_recovery", []) => {
+                Value::Bool(self.settings.get("in_hot_standby").map(String::as_str) == Some("on"))
+            }
+            ("current_schema", []) => text(PUBLIC_SCHEMA),
+            ("current_schemas", _) => Value::Array(vec![text(CATALOG_SCHEMA), text(PUBLIC_SCHEMA)]),
+            (
+                "pg_table_is_visible"
+                | "pg_type_is_visible"
+                | "pg_function_is_visible"
+                | "has_table_privilege"
+                | "has_schema_privilege"
+                | "has_database_privilege"
+                | "has_column_privilege"
+                | "has_any_column_privilege",
+                _,
+            ) => Value::Bool(true),
+            ("pg_relation_is_publishable", _) => Value::Bool(false),
+            (
+                "pg_total_relation_size" | "pg_relation_size" | "pg_table_size" | "pg_indexes_size",
+                _,
+            ) => Value::Int8(0),
+            ("pg_encoding_to_char", _) => text("UTF8"),
+            ("format_type", [oid, type_modifier]) => {
+                match (as_oid(oid), evaluate::text(type_modifier)) {
+                    (Some(oid), type_modifier) => {
+                        let type_modifier = type_modifier
+                            .and_then(|type_modifier| type_modifier.parse().ok())
+                            .unwrap_or(-1);
+                        self.format_type(oid, type_modifier)
+                            .map(Value::Text)
+                            .unwrap_or_else(|| text("???"))
+                    }
+                    (None, _) => Value::Null,
+                }
+            }
+            ("quote_ident", [value]) => match evaluate::text(value) {
+                Some(name) => Value::Text(quote_ident(&name)),
+                None => Value::Null,
+            },
+            _ => return Ok(None),
+        }))
+    }
+
+    fn cast(&self, value: Value, type_name: &str) -> EvalResult<Option<Value>> {
+        // Reg types read as OIDs when cast from a name, and as names when
+        // cast from an OID, which is how each side is usually consumed.
+        Ok(Some(match (type_name, value) {
+            ("regclass", Value::Text(name)) => match self.find_table(&name) {
+                Some(table) => oid_value(table.oid),
+                None => return Err(("42P01", format!("relation \"{}\" does not exist", name))),
+            },
+            ("regclass", value) => {
+                let oid = as_oid(&value);
+                match self.tables.iter().find(|table| Some(table.oid) == oid) {
+                    Some(table) => text(&table.name),
+                    None => Value::Text(evaluate::text(&value).unwrap_or_default()),
+                }
+            }
+            ("regtype", Value::Text(name)) => match self.find_type_by_name(&name) {
+                Some(ty) => oid_value(ty.oid()),
+                None => return Err(("42704", format!("type \"{}\" does not exist", name))),
+            },
+            ("regtype", value) => match as_oid(&value).and_then(|oid| self.format_type(oid, -1)) {
+                Some(name) => Value::Text(name),
+                None => Value::Text(evaluate::text(&value).unwrap_or_default()),
+            },
+            ("regnamespace", Value::Text(name)) => oid_value(self.namespace_oid(&name)),
+            ("regnamespace", value) => {
+                let oid = as_oid(&value);
+                match self
+                    .namespaces
+                    .iter()
+                    .find(|(namespace, _)| Some(*namespace) == oid)
+                {
+                    Some((_, name)) => text(name),
+                    None => Value::Text(evaluate::text(&value).unwrap_or_default()),
+                }
+            }
+            (_, value) => value,
+        }))
+    }
+}
+
+pub(crate) fn relation(columns: &[&str], rows: Vec<Vec<Value>>) -> Relation {
+    Relation {
+        columns: columns.iter().map(|column| column.to_string()).collect(),
+        rows,
+    }
+}
+
+/// OIDs are unsigned 32 bit, they are carried as `int8` values.
+fn oid_value(oid: u32) -> Value {
+    Value::Int8(oid as i64)
+}
+
+fn as_oid(value: &Value) -> Option<u32> {
+    evaluate::text(value)?.trim().parse().ok()
+}
+
+fn text(value: &str) -> Value {
+    Value::Text(value.to_string())
+}
+
+fn split_qualified(name: &str) -> (Option<String>, String) {
+    let unquote = |part: &str| part.trim().trim_matches('"').to_string();
+    match name.rsplit_once('.') {
+        Some((schema, name)) => (Some(unquote(schema)), unquote(name)),
+        None => (None, unquote(name)),
+    }
+}
+
+fn quote_ident(name: &str) -> String {
+    let plain = name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && !name.is_empty();
+    if plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+fn by_value(length: i16) -> bool {
+    matches!(length, 1 | 2 | 4 | 8)
+}
+
+fn alignment(length: i16) -> &'static str {
+    match length {
+        1 => "c",
+        2 => "s",
+        8 => "d",
+        _ => "i",
+    }
+}
+
+fn storage(length: i16) -> &'static str {
+    if length == -1 {
+        "x"
+    } else {
+        "p"
+    }
+}
+
+fn collation(ty: &Type) -> u32 {
+    if crate::is_text_type(ty) && !matches!(ty.kind(), Kind::Enum(_)) {
+        DEFAULT_COLLATION_OID
+    } else {
+        0
+    }
+}
+
+fn category(ty: &Type) -> &'static str {
+    match ty.kind() {
+        Kind::Array(_) => return "A",
+        Kind::Enum(_) => return "E",
+        Kind::Composite(_) => return "C",
+        Kind::Domain(base) => return category(base),
+        Kind::Pseudo => return "P",
+        _ => {}
+    }
+    match *ty {
+        Type::BOOL => "B",
+        Type::INT2
+        | Type::INT4
+        | Type::INT8
+        | Type::FLOAT4
+        | Type::FLOAT8
+        | Type::NUMERIC
+        | Type::OID
+        | Type::REGPROC
+        | Type::REGCLASS
+        | Type::REGTYPE => "N",
+        Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::CHAR => "S",
+        Type::DATE | Type::TIME | Type::TIMESTAMP | Type::TIMESTAMPTZ => "D",
+        Type::INTERVAL => "T",
+        _ => "U",
+    }
+}
+
+/// Whether `statement` is a query whose relations are all system catalogs,
+/// or CTEs, and which reads at least one system catalog.
+pub(crate) fn is_catalog_statement(statement: &Statement) -> bool {
+    let ctes: Vec<String> = match statement {
+        Statement::Query(query) => query
+            .with
+            .iter()
+            .flat_map(|with| &with.cte_tables)
+            .map(|cte| cte.alias.name.value.to_lowercase())
+            .collect(),
+        _ => return false,
+    };
+    let mut system_tables = 0;
+    let flow = visit_relations(statement, |name| {
+        let parts: Vec<String> = name
+            .0
+            .iter()
+            .map(|part| part.value.to_lowercase())
+            .collect();
+        let system = match parts.as_slice() {
+            [.., schema, _] => schema == CATALOG_SCHEMA || schema == INFORMATION_SCHEMA,
+            [name] => name.starts_with("pg_"),
+            [] => false,
+        };
+        if system {
+            system_tables += 1;
+            ControlFlow::Continue(())
+        } else if parts.len() == 1 && ctes.contains(&parts[0]) {
+            ControlFlow::Continue(())
+        } else {
+            ControlFlow::Break(())
+        }
+    });
+    flow.is_continue() && system_tables > 0
+}
+
+/// Functions answered from the session state, as in `SELECT version()`.
+const SESSION_FUNCTIONS: &[&str] = &[
+    "version",
+    "current_setting",
+    "current_schema",
+    "current_schemas",
+    "current_database",
+    "current_user",
+    "session_user",
+    "current_role",
+    "user",
+    "pg_backend_pid",
+    "pg_is_in_recovery",
+];
+
+/// Whether `statement` is a query without relations that calls session
+/// functions and no others, such as the `SELECT current_schema()` drivers
+/// send while connecting.
+pub(crate) fn is_session_statement(statement: &Statement) -> bool {
+    if !matches!(statement, Statement::Query(_)) {
+        return false;
+    }
+    if visit_relations(statement, |_| ControlFlow::Break(())).is_break() {
+        return false;
+    }
+    let mut session_functions = 0;
+    let flow = visit_expressions(statement, |expr| match expr {
+        Expr::Function(function) => {
+            let name = function
+                .name
+                .0
+                .last()
+                .map(|part| part.value.to_lowercase())
+                .unwrap_or_default();
+            if SESSION_FUNCTIONS.contains(&name.as_str()) {
+                session_functions += 1;
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            }
+        }
+        _ => ControlFlow::Continue(()),
+    });
+    flow.is_continue() && session_functions > 0
+}
+
+/// The description used when the shim answers bootstrap queries but does not
+/// describe its schema, so the catalogs only list the builtin types.
+pub(crate) struct NoTables;
+
+impl SchemaDescription for NoTables {
+    fn tables(&self) -> Vec<Table> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlparser::ast::Query;
+    use sqlparser::dialect::PostgreSqlDialect;
+    use sqlparser::parser::Parser;
+
+    fn parse_catalog_query(query: &str) -> Option<Box<Query>> {
+        let mut statements = Parser::parse_sql(&PostgreSqlDialect {}, query).ok()?;
+        match statements.pop() {
+            Some(statement @ Statement::Query(_))
+                if statements.is_empty() && is_catalog_statement(&statement) =>
+            {
+                match statement {
+                    Statement::Query(query) => Some(query),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn session() -> SessionState {
+        let mut session = SessionState::new();
+        session.user = "alice".to_string();
+        session.database = "shop".to_string();
+        session
+    }
+
+    struct Shop;
+
+    impl SchemaDescription for Shop {
+        fn tables(&self) -> Vec<Table> {
+            vec![Table::new(16384, "orders")
+                .with_column(Column::new("id", Type::INT4).not_null())
+                .with_column(Column::new("note", Type::VARCHAR).with_max_length(20))]
+        }
+    }
+
+    fn query(sql: &str) -> Vec<Vec<Option<String>>> {
+        let catalog = Catalog::new(&Shop, &TypeRegistry::new(), &session());
+        let query = parse_catalog_query(sql).expect("catalog query");
+        evaluate::execute(&catalog, &query, &[])
+            .unwrap()
+            .rows
+            .iter()
+            .map(|row| row.iter().map(evaluate::text).collect())
+            .collect()
+    }
+
+    fn row(values: &[&str]) -> Vec<Option<String>> {
+        values.iter().map(|value| Some(value.to_string())).collect()
+    }
+
+    #[test]
+    fn only_catalog_queries_are_answered() {
+        assert!(parse_catalog_query("SELECT * FROM orders").is_none());
+        assert!(parse_catalog_query("SELECT * FROM orders JOIN pg_class ON true").is_none());
+        assert!(parse_catalog_query("SELECT oid FROM pg_catalog.pg_type").is_some());
+    }
+
+    #[test]
+    fn answers_psql_list_tables() {
+        let rows = query(
+            r#"SELECT n.nspname as "Schema",
+  c.relname as "Name",
+  CASE c.relkind WHEN 'r' THEN 'table' WHEN 'v' THEN 'view' WHEN 'm' THEN 'materialized view' WHEN 'i' THEN 'index' WHEN 'S' THEN 'sequence' WHEN 't' THEN 'TOAST table' WHEN 'f' THEN 'foreign table' WHEN 'p' THEN 'partitioned table' WHEN 'I' THEN 'partitioned index' END as "Type",
+  pg_catalog.pg_get_userbyid(c.relowner) as "Owner"
+FROM pg_catalog.pg_class c
+     LEFT JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+     LEFT JOIN pg_catalog.pg_am am ON am.oid = c.relam
+WHERE c.relkind IN ('r','p','')
+      AND n.nspname <> 'pg_catalog'
+      AND n.nspname !~ '^pg_toast'
+      AND n.nspname <> 'information_schema'
+  AND pg_catalog.pg_table_is_visible(c.oid)
+ORDER BY 1,2;"#,
+        );
+        assert_eq!(rows, vec![row(&["public", "orders", "table", "alice"])]);
+    }
+
+    #[test]
+    fn answers_psql_describe_table() {
+        let rows = query(
+            r#"SELECT c.oid,
+  n.nspname,
+  c.relname
+FROM pg_catalog.pg_class c
+     LEFT JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+WHERE c.relname OPERATOR(pg_catalog.~) '^(orders)$' COLLATE pg_catalog.default
+  AND pg_catalog.pg_table_is_visible(c.oid)
+ORDER BY 2, 3;"#,
+        );
+        assert_eq!(rows, vec![row(&["16384", "public", "orders"])]);
+
+        let rows = query(
+            r#"SELECT a.attname,
+  pg_catalog.format_type(a.atttypid, a.atttypmod),
+  (SELECT pg_catalog.pg_get_expr(d.adbin, d.adrelid, true)
+   FROM pg_catalog.pg_attrdef d
+   WHERE d.adrelid = a.attrelid AND d.adnum = a.attnum AND a.atthasdef),
+  a.attnotnull,
+  (SELECT c.collname FROM pg_catalog.pg_collation c, pg_catalog.pg_type t
+   WHERE c.oid = a.attcollation AND t.oid = a.atttypid AND a.attcollation <> t.typcollation) AS attcollation,
+  a.attidentity,
+  a.attgenerated
+FROM pg_catalog.pg_attribute a
+WHERE a.attrelid = '16384' AND a.attnum > 0 AND NOT a.attisdropped
+ORDER BY a.attnum;"#,
+        );
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    Some("id".to_string()),
+                    Some("integer".to_string()),
+                    None,
+                    Some("t".to_string()),
+                    None,
+                    Some(String::new()),
+                    Some(String::new()),
+                ],
+                vec![
+                    Some("note".to_string()),
+                    Some("character varying(20)".to_string()),
+                    None,
+                    Some("f".to_string()),
+                    None,
+                    Some(String::new()),
+                    Some(String::new()),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn answers_information_schema_queries() {
+        assert_eq!(
+            query(
+                "SELECT table_schema, table_name, table_type FROM information_schema.tables \
+                 WHERE table_schema NOT IN ('pg_catalog', 'information_schema')"
+            ),
+            vec![row(&["public", "orders", "BASE TABLE"])]
+        );
+        assert_eq!(
+            query(
+                "SELECT column_name, data_type, is_nullable, character_maximum_length \
+                 FROM information_schema.columns WHERE table_name = 'orders' \
+                 ORDER BY ordinal_position"
+            ),
+            vec![
+                vec![
+                    Some("id".to_string()),
+                    Some("integer".to_string()),
+                    Some("NO".to_string()),
+                    None,
+                ],
+                row(&["note", "character varying", "YES", "20"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_reg_casts_and_aggregates() {
+        assert_eq!(
+            query("SELECT count(*), max(attnum) FROM pg_attribute WHERE attrelid = 'orders'::regclass"),
+            vec![row(&["2", "2"])]
+        );
+        assert_eq!(
+            query("SELECT typname FROM pg_type WHERE oid = 'integer'::regtype"),
+            vec![row(&["int4"])]
+        );
+    }
+}
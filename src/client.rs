@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::io::{Error, Read, Result, Write};
+
+use crate::backend_message::{BackendMessage, FieldDescription};
+use crate::client_message::{ClientMessage, Close, Describe, PasswordMessage, StartupMessage};
+use crate::Type;
+
+/// A minimal blocking client speaking the extended query protocol, for
+/// testing shims in-process rather than through a full driver. Parameters
+/// are sent and results received in text format.
+///
+/// Server errors are returned as an [`Error`] whose source is an
+/// [`ErrorResponse`](crate::ErrorResponse), see
+/// [`ErrorResponse::of`](crate::ErrorResponse::of). The session stays usable
+/// after them unless they were fatal.
+pub struct Client<Stream> {
+    stream: Stream,
+    parameters: HashMap<String, String>,
+    transaction_status: u8,
+}
+
+/// The rows and command tag of one statement.
+#[derive(Debug, Clone, Default)]
+pub struct QueryResult {
+    /// Empty for statements without rows.
+    pub columns: Vec<FieldDescription>,
+    pub rows: Vec<Row>,
+    /// `None` for an empty query.
+    pub tag: Option<String>,
+}
+
+/// A row of text format values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub fields: Vec<Option<Vec<u8>>>,
+}
+
+impl Row {
+    /// The value of column `index`, `None` if it is NULL, out of range or not
+    /// UTF-8.
+    pub fn get(&self, index: usize) -> Option<&str> {
+        let field = self.fields.get(index)?.as_deref()?;
+        std::str::from_utf8(field).ok()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+impl QueryResult {
+    /// The values of the rows, NULL as `None`.
+    pub fn values(&self) -> Vec<Vec<Option<&str>>> {
+        self.rows
+            .iter()
+            .map(|row| (0..row.len()).map(|index| row.get(index)).collect())
+            .collect()
+    }
+}
+
+impl<Stream> Client<Stream>
+where
+    Stream: Read + Write,
+{
+    /// Starts a session, answering a password request with `password`.
+    pub fn connect(
+        mut stream: Stream,
+        startup_message: StartupMessage,
+        password: &str,
+    ) -> Result<Self> {
+        startup_message.write(&mut stream)?;
+        stream.flush()?;
+        let mut client = Client {
+            stream,
+            parameters: HashMap::new(),
+            transaction_status: b'I',
+        };
+        loop {
+            match client.read()? {
+                BackendMessage::AuthenticationCleartextPassword => {
+                    PasswordMessage {
+                        password: password.to_string(),
+                    }
+                    .write(&mut client.stream)?;
+                    client.stream.flush()?;
+                }
+                BackendMessage::ReadyForQuery { transaction_status } => {
+                    client.transaction_status = transaction_status;
+                    return Ok(client);
+                }
+                BackendMessage::ErrorResponse(error) => return Err(Error::other(error)),
+                _ => {}
+            }
+        }
+    }
+
+    /// A parameter the server reported with ParameterStatus.
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters.get(name).map(String::as_str)
+    }
+
+    /// `I`, `T` or `E`, as of the last ReadyForQuery.
+    pub fn transaction_status(&self) -> u8 {
+        self.transaction_status
+    }
+
+    /// Runs `query` with the simple query protocol, returning the result of
+    /// each statement.
+    pub fn simple_query(&mut self, query: &str) -> Result<Vec<QueryResult>> {
+        self.send([ClientMessage::Query {
+            query: query.as_bytes().to_vec(),
+        }])?;
+        self.results()
+    }
+
+    /// Prepares `query` as the statement `name`. Parameter types left out
+    /// are inferred by the server.
+    pub fn prepare(&mut self, name: &str, query: &str, parameter_types: &[Type]) -> Result<()> {
+        self.send([
+            ClientMessage::Parse {
+                name: name.to_string(),
+                query: query.as_bytes().to_vec(),
+                parameter_type_oids: parameter_types.iter().map(Type::oid).collect(),
+            },
+            ClientMessage::Sync,
+        ])?;
+        self.results().map(|_| ())
+    }
+
+    /// Binds the prepared statement `name` to `parameters` and runs it.
+    pub fn execute(&mut self, name: &str, parameters: &[&str]) -> Result<QueryResult> {
+        let mut messages = bind_and_execute(name, parameters);
+        messages.push(ClientMessage::Sync);
+        self.send(messages)?;
+        Ok(self.results()?.pop().unwrap_or_default())
+    }
+
+    /// Prepares and runs `query` as the unnamed statement in one round trip.
+    pub fn query(&mut self, query: &str, parameters: &[&str]) -> Result<QueryResult> {
+        let mut messages = vec![ClientMessage::Parse {
+            name: String::new(),
+            query: query.as_bytes().to_vec(),
+            parameter_type_oids: vec![],
+        }];
+        messages.extend(bind_and_execute("", parameters));
+        messages.push(ClientMessage::Sync);
+        self.send(messages)?;
+        Ok(self.results()?.pop().unwrap_or_default())
+    }
+
+    /// Closes the prepared statement `name`.
+    pub fn close(&mut self, name: &str) -> Result<()> {
+        self.send([
+            ClientMessage::Close(Close::Statement {
+                name: name.to_string(),
+            }),
+            ClientMessage::Sync,
+        ])?;
+        self.results().map(|_| ())
+    }
+
+    /// Ends the session.
+    pub fn terminate(mut self) -> Result<()> {
+        self.send([ClientMessage::Terminate])
+    }
+
+    fn send(&mut self, messages: impl IntoIterator<Item = ClientMessage>) -> Result<()> {
+        let mut buffer = Vec::new();
+        for message in messages {
+            message.write(&mut buffer)?;
+        }
+        self.stream.write_all(&buffer)?;
+        self.stream.flush()
+    }
+
+    fn read(&mut self) -> Result<BackendMessage> {
+        let message = BackendMessage::from_stream(&mut self.stream)?;
+        if let BackendMessage::ParameterStatus { name, value } = &message {
+            self.parameters.insert(name.clone(), value.clone());
+        }
+        Ok(message)
+    }
+
+    /// Collects results until ReadyForQuery. The first error, if any, is
+    /// returned instead.
+    fn results(&mut self) -> Result<Vec<QueryResult>> {
+        let mut results = vec![];
+        let mut current = QueryResult::default();
+        let mut error = None;
+        loop {
+            match self.read()? {
+                BackendMessage::RowDescription { fields } => current.columns = fields,
+                BackendMessage::DataRow { fields } => current.rows.push(Row { fields }),
+                BackendMessage::CommandComplete { tag } => {
+                    current.tag = Some(tag);
+                    results.push(std::mem::take(&mut current));
+                }
+                BackendMessage::EmptyQueryResponse => results.push(std::mem::take(&mut current)),
+                BackendMessage::ErrorResponse(response) => {
+                    let fatal = response.severity == "FATAL";
+                    let response = Error::other(response);
+                    if fatal {
+                        return Err(response);
+                    }
+                    error.get_or_insert(response);
+                }
+                BackendMessage::ReadyForQuery { transaction_status } => {
+                    self.transaction_status = transaction_status;
+                    return match error {
+                        Some(error) => Err(error),
+                        None => Ok(results),
+                    };
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn bind_and_execute(name: &str, parameters: &[&str]) -> Vec<ClientMessage> {
+    vec![
+        ClientMessage::Bind {
+            portal: String::new(),
+            name: name.to_string(),
+            parameter_format_codes: vec![],
+            parameters: parameters
+                .iter()
+                .map(|parameter| Some(parameter.as_bytes().to_vec()))
+                .collect(),
+            result_format_codes: vec![],
+        },
+        ClientMessage::Describe(Describe::Portal {
+            name: String::new(),
+        }),
+        ClientMessage::Execute {
+            portal: String::new(),
+            max_rows: 0,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Column, DefaultServerParameters, ErrorResponse, Response, Router, Server, Value};
+    use std::net::TcpStream;
+    use std::thread;
+
+    fn parameters() -> DefaultServerParameters {
+        DefaultServerParameters {
+            server_version: "14".to_string(),
+            server_encoding: "UTF8".to_string(),
+            client_encoding: "UTF8".to_string(),
+            application_name: String::new(),
+            default_transaction_read_only: "off".to_string(),
+            in_hot_standby: "off".to_string(),
+            is_superuser: "off".to_string(),
+            session_authorization: "postgres".to_string(),
+            date_style: "ISO, MDY".to_string(),
+            interval_style: "postgres".to_string(),
+            time_zone: "UTC".to_string(),
+            integer_datetimes: "on".to_string(),
+            standard_conforming_strings: "on".to_string(),
+        }
+    }
+
+    #[test]
+    fn queries_a_shim() {
+        let server = Server::bind("127.0.0.1:0", |_| {
+            Ok(Router::new(parameters()).select("users", |request| {
+                let id = request.capture("id").unwrap_or_default().to_string();
+                Ok(Response::rows(
+                    vec![
+                        Column::new("id", Type::TEXT),
+                        Column::new("name", Type::TEXT),
+                    ],
+                    vec![vec![Value::Text(id), Value::Null]],
+                ))
+            }))
+        })
+        .unwrap();
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let stream = TcpStream::connect(address).unwrap();
+        let mut client = Client::connect(stream, StartupMessage::new("alice"), "secret").unwrap();
+        assert_eq!(client.parameter("server_version"), Some("14"));
+
+        let query = "SELECT id, name FROM users WHERE id = $1";
+        let result = client.query(query, &["7"]).unwrap();
+        assert_eq!(result.columns[0].name, "id");
+        assert_eq!(result.columns[1].type_oid, Type::TEXT.oid());
+        assert_eq!(result.values(), vec![vec![Some("7"), None]]);
+        assert_eq!(result.tag.as_deref(), Some("SELECT 1"));
+
+        client.prepare("by_id", query, &[Type::INT4]).unwrap();
+        let result = client.execute("by_id", &["8"]).unwrap();
+        assert_eq!(result.values(), vec![vec![Some("8"), None]]);
+
+        let error = client
+            .query("SET statement_timeout TO 'soon'", &[])
+            .unwrap_err();
+        assert_eq!(ErrorResponse::of(&error).unwrap().code, "22023");
+        let results = client.simple_query("SHOW statement_timeout").unwrap();
+        assert_eq!(results[0].values(), vec![vec![Some("0")]]);
+        assert_eq!(client.transaction_status(), b'I');
+        client.terminate().unwrap();
+    }
+}
@@ -1,7 +1,25 @@
-use postgres_types::Type;
+use bytes::{BufMut, BytesMut};
 use std::collections::HashMap;
-use std::io::{Cursor, Read, Result};
+use std::fmt;
+use std::io::{Cursor, Error, ErrorKind, Read, Result, Write};
 
+use crate::limits::{LimitExceeded, Limits};
+use crate::server_message::{ServerMessage, WritePostgresExt};
+
+/// Postgres does not accept longer startup messages.
+const MAX_STARTUP_LENGTH: u32 = 10000;
+/// Postgres does not accept longer passwords.
+const MAX_PASSWORD_LENGTH: usize = 65535;
+/// Protocol 3.0, sent by clients in the startup message.
+pub(crate) const PROTOCOL_VERSION: u32 = 196608;
+/// Sent in place of a protocol version to cancel the query of a session.
+const CANCEL_REQUEST_CODE: u32 = 80877102;
+/// Sent in place of a protocol version to ask for TLS.
+const SSL_REQUEST_CODE: u32 = 80877103;
+/// Sent in place of a protocol version to ask for GSSAPI encryption.
+const GSSENC_REQUEST_CODE: u32 = 80877104;
+
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct StartupMessage {
     pub protocol_version: u32,
@@ -12,6 +30,18 @@ pub struct StartupMessage {
     pub parameters: HashMap<String, String>,
 }
 
+/// What a client may send before its startup message.
+#[derive(Debug)]
+pub(crate) enum StartupRequest {
+    Startup(StartupMessage),
+    /// An SSLRequest or GSSENCRequest, answered with `N` before the client
+    /// sends its startup message on the unencrypted connection.
+    Encryption,
+    /// A CancelRequest, sent on a connection of its own.
+    Cancel,
+}
+
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct PasswordMessage {
     pub password: String,
@@ -20,18 +50,19 @@ pub struct PasswordMessage {
 #[derive(Debug)]
 pub enum ClientMessage {
     Query {
-        query: String,
+        query: Vec<u8>,
     },
     Parse {
         name: String,
-        query: String,
-        parameters_types: Vec<Type>,
+        query: Vec<u8>,
+        parameter_type_oids: Vec<u32>,
     },
     Bind {
         portal: String,
         name: String,
         parameter_format_codes: Vec<FormatCode>,
-        parameters: Vec<Vec<u8>>,
+        /// `None` for NULL.
+        parameters: Vec<Option<Vec<u8>>>,
         result_format_codes: Vec<FormatCode>,
     },
     Execute {
@@ -39,16 +70,25 @@ pub enum ClientMessage {
         max_rows: u32,
     },
     Describe(Describe),
+    Close(Close),
     Sync,
     Terminate,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum Describe {
     Statement { name: String },
     Portal { name: String },
 }
 
+/// Frees a prepared statement or a portal.
+#[derive(Debug, Clone)]
+pub enum Close {
+    Statement { name: String },
+    Portal { name: String },
+}
+
 #[derive(Debug, Clone)]
 pub enum FormatCode {
     Text,
@@ -56,39 +96,133 @@ pub enum FormatCode {
 }
 
 impl PasswordMessage {
+    /// Appends the message to `buffer`, as a client sends it.
+    pub fn encode(self, buffer: &mut BytesMut) -> Result<()> {
+        self.write(&mut buffer.writer())
+    }
+
+    pub fn write(self, stream: &mut impl WritePostgresExt) -> Result<()> {
+        let mut body = Vec::new();
+        body.write_string(&self.password)?;
+        write_message(stream, Some(b'p'), &body)
+    }
+
+    /// Decodes a password message from the front of `buffer`, or returns
+    /// `None` when it does not hold the whole message yet.
+    pub fn decode(buffer: &mut BytesMut) -> Result<Option<Self>> {
+        match split_frame(buffer, 1, MAX_PASSWORD_LENGTH + 5)? {
+            Some(frame) => Self::from_stream(&mut Cursor::new(frame)).map(Some),
+            None => Ok(None),
+        }
+    }
+
     pub fn from_stream(stream: &mut impl ReadPostgresExt) -> Result<Self> {
         let header = stream.read_byte()?;
-        if header != 'p' as u8 {
-            panic!("Invalid message");
+        if header != b'p' {
+            return Err(ProtocolViolation::error(format!(
+                "expected password response, got message type {}",
+                header
+            )));
         }
-        let lenght_of_bytes = stream.read_int32()?;
-        let mut buffer = vec![0; lenght_of_bytes as usize - 4];
-        stream.read_exact(&mut buffer)?;
-        Ok(PasswordMessage {
-            password: String::from_utf8_lossy(&buffer[..buffer.len() - 1]).to_string(),
-        })
+        let buffer = read_body(stream, MAX_PASSWORD_LENGTH + 5)?;
+        let mut body = Body::new(&buffer);
+        let password = body.string()?;
+        body.finish()?;
+        Ok(PasswordMessage { password })
     }
 }
 
 impl StartupMessage {
+    /// A protocol 3.0 startup message for `user`.
+    pub fn new(user: impl Into<String>) -> Self {
+        StartupMessage {
+            protocol_version: PROTOCOL_VERSION,
+            user: user.into(),
+            database: None,
+            options: None,
+            replication: None,
+            parameters: HashMap::new(),
+        }
+    }
+
+    /// Appends the message to `buffer`, as a client sends it.
+    pub fn encode(self, buffer: &mut BytesMut) -> Result<()> {
+        self.write(&mut buffer.writer())
+    }
+
+    pub fn write(self, stream: &mut impl WritePostgresExt) -> Result<()> {
+        let mut body = Vec::new();
+        body.write_int32(self.protocol_version as i32)?;
+        let parameters = [
+            ("user", Some(self.user)),
+            ("database", self.database),
+            ("options", self.options),
+            ("replication", self.replication),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value?)))
+        .chain(self.parameters);
+        for (name, value) in parameters {
+            body.write_string(&name)?;
+            body.write_string(&value)?;
+        }
+        body.write_byte(0)?;
+        write_message(stream, None, &body)
+    }
+
+    /// Decodes a startup message, which has no type byte, from the front of
+    /// `buffer`, or returns `None` when it does not hold the whole message yet.
+    pub fn decode(buffer: &mut BytesMut) -> Result<Option<Self>> {
+        match split_frame(buffer, 0, MAX_STARTUP_LENGTH as usize)? {
+            Some(frame) => Self::from_stream(&mut Cursor::new(frame)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn from_stream(stream: &mut impl ReadPostgresExt) -> Result<Self> {
+        match StartupRequest::from_stream(stream)? {
+            StartupRequest::Startup(message) => Ok(message),
+            StartupRequest::Encryption | StartupRequest::Cancel => {
+                Err(ProtocolViolation::error("expected startup message"))
+            }
+        }
+    }
+}
+
+impl StartupRequest {
     pub fn from_stream(stream: &mut impl ReadPostgresExt) -> Result<Self> {
-        let lenght_of_bytes = stream.read_int32()?;
+        let length = stream.read_int32()?;
+        if length < 8 {
+            return Err(ProtocolViolation::error("invalid length of startup packet"));
+        }
+        LimitExceeded::check(
+            "startup packet length",
+            length as usize,
+            MAX_STARTUP_LENGTH as usize,
+        )?;
         let protocol_version = stream.read_int32()?;
-        let mut buffer = vec![0; lenght_of_bytes as usize - 4 - 4];
+        let mut buffer = vec![0; length as usize - 8];
         stream.read_exact(&mut buffer)?;
-        let mut i = 0;
+        match protocol_version {
+            SSL_REQUEST_CODE | GSSENC_REQUEST_CODE if buffer.is_empty() => {
+                return Ok(Self::Encryption)
+            }
+            CANCEL_REQUEST_CODE if buffer.len() == 8 => return Ok(Self::Cancel),
+            SSL_REQUEST_CODE | GSSENC_REQUEST_CODE | CANCEL_REQUEST_CODE => {
+                return Err(ProtocolViolation::error("invalid length of startup packet"))
+            }
+            _ => {}
+        }
+        let mut body = Body::new(&buffer);
         let mut parameters = HashMap::new();
         let mut user = String::new();
         let mut database = None;
         let mut options = None;
         let mut replication = None;
 
-        while match buffer.get(i) {
-            Some(0) | None => false,
-            _ => true,
-        } {
-            let parameter_name = read_string(&buffer, &mut i);
-            let parameter_value = read_string(&buffer, &mut i);
+        while !matches!(body.peek(), Some(0) | None) {
+            let parameter_name = body.string()?;
+            let parameter_value = body.string()?;
             match parameter_name.as_str() {
                 "user" => user = parameter_value,
                 "database" => database = Some(parameter_value),
@@ -99,124 +233,268 @@ impl StartupMessage {
                 }
             };
         }
-        Ok(Self {
+        Ok(Self::Startup(StartupMessage {
             protocol_version,
             user,
             database,
             options,
             replication,
             parameters,
-        })
+        }))
     }
 }
 
 impl ClientMessage {
-    pub fn from_stream(stream: &mut impl ReadPostgresExt) -> Result<Self> {
-        let type_identification = stream.read_byte()?;
-        match type_identification as char {
-            'Q' => {
-                let lenght = stream.read_int32()?;
-                let mut buffer = vec![0; lenght as usize - 4];
-                stream.read_exact(&mut buffer)?;
-                Ok(Self::Query {
-                    query: String::from_utf8_lossy(&buffer[..buffer.len() - 1]).to_string(),
-                })
+    /// Appends the message to `buffer`, as a client sends it.
+    pub fn encode(self, buffer: &mut BytesMut) -> Result<()> {
+        self.write(&mut buffer.writer())
+    }
+
+    pub fn write(self, stream: &mut impl WritePostgresExt) -> Result<()> {
+        let mut body = Vec::new();
+        let type_identification = match self {
+            Self::Query { query } => {
+                body.write_all(&query)?;
+                body.write_byte(0)?;
+                b'Q'
             }
-            'P' => {
-                let lenght = stream.read_int32()?;
-                let mut buffer = vec![0; lenght as usize - 4];
-                stream.read_exact(&mut buffer)?;
-                let mut i = 0;
-                let name = read_string(&buffer, &mut i);
-                let query = read_string(&buffer, &mut i);
-                let mut cursor = Cursor::new(&buffer[i..]);
-                let n_parameters = cursor.read_int16()?;
-                let mut parameters_types: Vec<Type> = Vec::new();
-                for _ in 0..n_parameters {
-                    parameters_types.push(Type::from_oid(cursor.read_int32()?).unwrap());
+            Self::Parse {
+                name,
+                query,
+                parameter_type_oids,
+            } => {
+                body.write_string(&name)?;
+                body.write_all(&query)?;
+                body.write_byte(0)?;
+                body.write_int16(parameter_type_oids.len() as u16)?;
+                for oid in parameter_type_oids {
+                    body.write_int32(oid as i32)?;
                 }
-                Ok(Self::Parse {
+                b'P'
+            }
+            Self::Bind {
+                portal,
+                name,
+                parameter_format_codes,
+                parameters,
+                result_format_codes,
+            } => {
+                body.write_string(&portal)?;
+                body.write_string(&name)?;
+                write_format_codes(&mut body, &parameter_format_codes)?;
+                body.write_int16(parameters.len() as u16)?;
+                for parameter in parameters {
+                    match parameter {
+                        Some(parameter) => {
+                            body.write_int32(parameter.len() as i32)?;
+                            body.write_all(&parameter)?;
+                        }
+                        None => body.write_int32(-1)?,
+                    }
+                }
+                write_format_codes(&mut body, &result_format_codes)?;
+                b'B'
+            }
+            Self::Execute { portal, max_rows } => {
+                body.write_string(&portal)?;
+                body.write_int32(max_rows as i32)?;
+                b'E'
+            }
+            Self::Describe(describe) => {
+                let (describe_type, name) = match describe {
+                    Describe::Statement { name } => (b'S', name),
+                    Describe::Portal { name } => (b'P', name),
+                };
+                body.write_byte(describe_type)?;
+                body.write_string(&name)?;
+                b'D'
+            }
+            Self::Close(close) => {
+                let (close_type, name) = match close {
+                    Close::Statement { name } => (b'S', name),
+                    Close::Portal { name } => (b'P', name),
+                };
+                body.write_byte(close_type)?;
+                body.write_string(&name)?;
+                b'C'
+            }
+            Self::Sync => b'S',
+            Self::Terminate => b'X',
+        };
+        write_message(stream, Some(type_identification), &body)
+    }
+
+    /// Decodes a message from the front of `buffer`, or returns `None` when
+    /// it does not hold the whole message yet. Consumed bytes are removed.
+    pub fn decode(buffer: &mut BytesMut) -> Result<Option<Self>> {
+        Self::decode_with_limits(buffer, &Limits::default())
+    }
+
+    /// Like [`ClientMessage::decode`], failing on messages beyond `limits`.
+    pub fn decode_with_limits(buffer: &mut BytesMut, limits: &Limits) -> Result<Option<Self>> {
+        match split_frame(buffer, 1, limits.max_message_size)? {
+            Some(frame) => Self::from_stream_with_limits(&mut Cursor::new(frame), limits).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn from_stream(stream: &mut impl ReadPostgresExt) -> Result<Self> {
+        Self::from_stream_with_limits(stream, &Limits::default())
+    }
+
+    /// Like [`ClientMessage::from_stream`], failing on messages beyond
+    /// `limits` before allocating anything for them.
+    pub fn from_stream_with_limits(
+        stream: &mut impl ReadPostgresExt,
+        limits: &Limits,
+    ) -> Result<Self> {
+        let type_identification = stream.read_byte()?;
+        let buffer = read_body(stream, limits.max_message_size)?;
+        let mut body = Body::new(&buffer);
+        let message = match type_identification {
+            b'Q' => Self::Query {
+                query: body.query(limits)?.to_vec(),
+            },
+            b'P' => {
+                let name = body.string()?;
+                let query = body.query(limits)?.to_vec();
+                let n_parameters = body.int16()?;
+                LimitExceeded::check(
+                    "number of parameters",
+                    n_parameters as usize,
+                    limits.max_parameters,
+                )?;
+                let parameter_type_oids = (0..n_parameters)
+                    .map(|_| body.int32())
+                    .collect::<Result<Vec<u32>>>()?;
+                Self::Parse {
                     name,
                     query,
-                    parameters_types,
-                })
-            }
-            'B' => {
-                let lenght = stream.read_int32()?;
-                let mut buffer = vec![0; lenght as usize - 4];
-                stream.read_exact(&mut buffer)?;
-                let mut i = 0;
-                let portal = read_string(&buffer, &mut i);
-                let name = read_string(&buffer, &mut i);
-                let mut cursor = Cursor::new(&buffer[i..]);
-                let n_format_codes = cursor.read_int16()?;
-                let parameter_format_codes = (0..n_format_codes)
-                    .into_iter()
-                    .map(|_| match cursor.read_int16() {
-                        Ok(0) => FormatCode::Text,
-                        Ok(1) => FormatCode::Binary,
-                        _ => panic!("Invalid format"),
-                    })
-                    .collect::<Vec<FormatCode>>();
-                let n_parameters = cursor.read_int16()?;
-                let mut parameters = Vec::new();
-                for _ in 0..n_parameters {
-                    let parameter_size = cursor.read_int32()?;
-                    let mut buffer = vec![0; parameter_size as usize];
-                    cursor.read_exact(&mut buffer)?;
-                    parameters.push(buffer);
+                    parameter_type_oids,
                 }
-                let n_result_format_codes = cursor.read_int16()?;
-                let result_format_codes = (0..n_result_format_codes)
-                    .into_iter()
-                    .map(|_| match cursor.read_int16() {
-                        Ok(0) => FormatCode::Text,
-                        Ok(1) => FormatCode::Binary,
-                        _ => panic!("Invalid format"),
+            }
+            b'B' => {
+                let portal = body.string()?;
+                let name = body.string()?;
+                let parameter_format_codes = body.format_codes()?;
+                let n_parameters = body.int16()?;
+                LimitExceeded::check(
+                    "number of parameters",
+                    n_parameters as usize,
+                    limits.max_parameters,
+                )?;
+                let parameters = (0..n_parameters)
+                    .map(|_| {
+                        let parameter_size = match body.int32()? as i32 {
+                            -1 => return Ok(None),
+                            size if size < 0 => {
+                                return Err(ProtocolViolation::error("invalid parameter length"))
+                            }
+                            size => size as usize,
+                        };
+                        LimitExceeded::check(
+                            "parameter size",
+                            parameter_size,
+                            limits.max_parameter_size,
+                        )?;
+                        Ok(Some(body.take(parameter_size)?.to_vec()))
                     })
-                    .collect::<Vec<FormatCode>>();
-
-                Ok(Self::Bind {
+                    .collect::<Result<Vec<Option<Vec<u8>>>>>()?;
+                let result_format_codes = body.format_codes()?;
+                Self::Bind {
                     portal,
                     name,
                     parameter_format_codes,
                     parameters,
                     result_format_codes,
-                })
-            }
-            'E' => {
-                let lenght = stream.read_int32()?;
-                let mut buffer = vec![0; lenght as usize - 4];
-                stream.read_exact(&mut buffer)?;
-                let mut i = 0;
-                let portal = read_string(&buffer, &mut i);
-                let mut cursor = Cursor::new(&buffer[i..]);
-                let max_rows = cursor.read_int32()?;
-                Ok(Self::Execute { portal, max_rows })
+                }
             }
-            'D' => {
-                let lenght = stream.read_int32()?;
-                let describe_type = stream.read_byte()?;
-                let mut buffer = vec![0; lenght as usize - 4 - 1];
-                stream.read_exact(&mut buffer)?;
-                let name = String::from_utf8_lossy(&buffer[..buffer.len() - 1]).to_string();
-                Ok(Self::Describe(match describe_type as char {
-                    'S' => Describe::Statement { name },
-                    'P' => Describe::Portal { name },
-                    _ => panic!("Invalid descripe type"),
-                }))
+            b'E' => Self::Execute {
+                portal: body.string()?,
+                max_rows: body.int32()?,
+            },
+            b'D' => {
+                let describe_type = body.byte()?;
+                let name = body.string()?;
+                Self::Describe(match describe_type {
+                    b'S' => Describe::Statement { name },
+                    b'P' => Describe::Portal { name },
+                    _ => {
+                        return Err(ProtocolViolation::error(format!(
+                            "invalid DESCRIBE message subtype {}",
+                            describe_type
+                        )))
+                    }
+                })
             }
-            'S' => {
-                let _ = stream.read_int32()?;
-                Ok(Self::Sync)
+            b'C' => {
+                let close_type = body.byte()?;
+                let name = body.string()?;
+                Self::Close(match close_type {
+                    b'S' => Close::Statement { name },
+                    b'P' => Close::Portal { name },
+                    _ => {
+                        return Err(ProtocolViolation::error(format!(
+                            "invalid CLOSE message subtype {}",
+                            close_type
+                        )))
+                    }
+                })
             }
-            'X' => {
-                let _ = stream.read_int32()?;
-                Ok(Self::Terminate)
+            b'S' => Self::Sync,
+            b'X' => Self::Terminate,
+            _ => {
+                return Err(ProtocolViolation::error(format!(
+                    "invalid frontend message type {}",
+                    type_identification
+                )))
             }
-            _ => panic!("Cannot process {}", type_identification as char),
-        }
+        };
+        body.finish()?;
+        Ok(message)
+    }
+}
+
+/// A client that broke the protocol. The intermediary answers with FATAL
+/// 08P01 `protocol_violation` and closes the session.
+#[derive(Debug)]
+pub(crate) struct ProtocolViolation(String);
+
+impl fmt::Display for ProtocolViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ProtocolViolation {}
+
+impl ProtocolViolation {
+    pub fn error(message: impl Into<String>) -> Error {
+        Error::new(ErrorKind::InvalidData, ProtocolViolation(message.into()))
+    }
+}
+
+/// Tells the client why the session ends if `error` is about a message it
+/// sent: a protocol violation or a message beyond the limits.
+pub(crate) fn report_fatal(error: &Error, out: &mut impl Write) -> Result<()> {
+    let Some(inner) = error.get_ref() else {
+        return Ok(());
+    };
+    let code = if inner.is::<ProtocolViolation>() {
+        "08P01"
+    } else if inner.is::<LimitExceeded>() {
+        "54000"
+    } else {
+        return Ok(());
+    };
+    ServerMessage::ErrorResponse {
+        severity: "FATAL",
+        code,
+        message: inner.to_string(),
+        position: None,
     }
+    .write(out)?;
+    out.flush()
 }
 
 pub trait ReadPostgresExt: Read {
@@ -240,14 +518,288 @@ pub trait ReadPostgresExt: Read {
 }
 impl<T> ReadPostgresExt for T where T: Read {}
 
-fn read_string(buffer: &[u8], start: &mut usize) -> String {
-    let (mut end_of_string, _) = buffer[*start..]
-        .iter()
-        .enumerate()
-        .find(|c| *c.1 == 0)
-        .unwrap();
-    end_of_string = *start + end_of_string;
-    let result = String::from_utf8_lossy(&buffer[*start..end_of_string]).to_string();
-    *start = end_of_string + 1;
-    result
+/// Writes a message of type `type_identification`, or a startup message
+/// when it is `None`, with its length.
+fn write_message(
+    stream: &mut impl WritePostgresExt,
+    type_identification: Option<u8>,
+    body: &[u8],
+) -> Result<()> {
+    if let Some(type_identification) = type_identification {
+        stream.write_byte(type_identification)?;
+    }
+    stream.write_int32(body.len() as i32 + 4)?;
+    stream.write_all(body)
+}
+
+fn write_format_codes(
+    stream: &mut impl WritePostgresExt,
+    format_codes: &[FormatCode],
+) -> Result<()> {
+    stream.write_int16(format_codes.len() as u16)?;
+    for format_code in format_codes {
+        stream.write_int16(match format_code {
+            FormatCode::Text => 0,
+            FormatCode::Binary => 1,
+        })?;
+    }
+    Ok(())
+}
+
+/// Reads the length of a message and, unless it exceeds `max_length`, the
+/// rest of it.
+pub(crate) fn read_body(stream: &mut impl ReadPostgresExt, max_length: usize) -> Result<Vec<u8>> {
+    let length = stream.read_int32()? as i32;
+    if length < 4 {
+        return Err(ProtocolViolation::error(format!(
+            "invalid message length {}",
+            length
+        )));
+    }
+    LimitExceeded::check("message length", length as usize, max_length)?;
+    let mut buffer = vec![0; length as usize - 4];
+    stream.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Splits the first message off `buffer` once it is complete. `type_length`
+/// is 1 for messages starting with a type byte and 0 for the startup message.
+/// Messages longer than `max_length` fail before any room is reserved.
+pub(crate) fn split_frame(
+    buffer: &mut BytesMut,
+    type_length: usize,
+    max_length: usize,
+) -> Result<Option<BytesMut>> {
+    let Some(length) = buffer.get(type_length..type_length + 4) else {
+        return Ok(None);
+    };
+    let length = i32::from_be_bytes([length[0], length[1], length[2], length[3]]);
+    if length < 4 {
+        return Err(ProtocolViolation::error(format!(
+            "invalid message length {}",
+            length
+        )));
+    }
+    LimitExceeded::check("message length", length as usize, max_length)?;
+    let frame_length = type_length + length as usize;
+    if buffer.len() < frame_length {
+        buffer.reserve(frame_length - buffer.len());
+        return Ok(None);
+    }
+    Ok(Some(buffer.split_to(frame_length)))
+}
+
+/// Reads the fields of a message body. Running out of bytes is a protocol
+/// violation rather than an unexpected end of the stream.
+pub(crate) struct Body<'a> {
+    buffer: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Body<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Body {
+            buffer,
+            position: 0,
+        }
+    }
+
+    pub fn peek(&self) -> Option<u8> {
+        self.buffer.get(self.position).copied()
+    }
+
+    pub fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.buffer.len())
+            .ok_or_else(|| ProtocolViolation::error("insufficient data left in message"))?;
+        let bytes = &self.buffer[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn int16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn int32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a null terminated string without decoding it.
+    pub fn bytes(&mut self) -> Result<&'a [u8]> {
+        let length = self.buffer[self.position..]
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or_else(|| ProtocolViolation::error("invalid string in message"))?;
+        let bytes = self.take(length)?;
+        self.position += 1;
+        Ok(bytes)
+    }
+
+    pub fn query(&mut self, limits: &Limits) -> Result<&'a [u8]> {
+        let query = self.bytes()?;
+        LimitExceeded::check("query length", query.len(), limits.max_query_length)?;
+        Ok(query)
+    }
+
+    pub fn string(&mut self) -> Result<String> {
+        Ok(String::from_utf8_lossy(self.bytes()?).to_string())
+    }
+
+    pub fn format_codes(&mut self) -> Result<Vec<FormatCode>> {
+        let n_format_codes = self.int16()?;
+        (0..n_format_codes)
+            .map(|_| match self.int16()? {
+                0 => Ok(FormatCode::Text),
+                1 => Ok(FormatCode::Binary),
+                code => Err(ProtocolViolation::error(format!(
+                    "unsupported format code: {}",
+                    code
+                ))),
+            })
+            .collect()
+    }
+
+    /// Checks that the whole message was read.
+    pub fn finish(&self) -> Result<()> {
+        match self.position == self.buffer.len() {
+            true => Ok(()),
+            false => Err(ProtocolViolation::error("invalid message format")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![tag];
+        message.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+        message.extend_from_slice(body);
+        message
+    }
+
+    fn is_violation(input: &[u8]) -> bool {
+        match ClientMessage::from_stream(&mut Cursor::new(input)) {
+            Err(error) => error
+                .get_ref()
+                .is_some_and(|error| error.is::<ProtocolViolation>()),
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        assert!(is_violation(&message(b'z', b"")));
+        assert!(is_violation(b"Q\0\0\0\0"));
+        assert!(is_violation(b"Q\xff\xff\xff\xff"));
+        assert!(is_violation(&message(b'Q', b"SELECT 1")));
+        assert!(is_violation(&message(b'D', b"X\0")));
+        assert!(is_violation(&message(b'E', b"\0\0\0")));
+        assert!(is_violation(&message(b'S', b"\0")));
+        // A Bind with a format code of 2.
+        assert!(is_violation(&message(b'B', b"\0\0\0\x01\0\x02\0\0\0\0")));
+        // A Bind with a parameter length of -2.
+        assert!(is_violation(&message(
+            b'B',
+            b"\0\0\0\0\0\x01\xff\xff\xff\xfe\0\0"
+        )));
+        // A Bind whose parameter claims more bytes than the message holds.
+        assert!(is_violation(&message(
+            b'B',
+            b"\0\0\0\0\0\x01\0\0\x01\0\0\0"
+        )));
+        assert!(is_violation(&message(
+            b'P',
+            b"\0SELECT $1\0\0\x02\0\0\0\x17"
+        )));
+
+        let mut password = Cursor::new(message(b'Q', b"secret\0"));
+        assert!(PasswordMessage::from_stream(&mut password).is_err());
+        let mut startup = Cursor::new(b"\0\0\0\x02".to_vec());
+        assert!(StartupMessage::from_stream(&mut startup).is_err());
+    }
+
+    #[test]
+    fn decodes_close() {
+        let close = |body: &[u8]| {
+            let input = message(b'C', body);
+            let decode = || ClientMessage::from_stream(&mut Cursor::new(&input)).unwrap();
+            let mut encoded = vec![];
+            decode().write(&mut encoded).unwrap();
+            assert_eq!(encoded, input);
+            decode()
+        };
+        assert!(matches!(
+            close(b"Ss1\0"),
+            ClientMessage::Close(Close::Statement { name }) if name == "s1"
+        ));
+        assert!(matches!(
+            close(b"P\0"),
+            ClientMessage::Close(Close::Portal { name }) if name.is_empty()
+        ));
+        assert!(is_violation(&message(b'C', b"X\0")));
+    }
+
+    #[test]
+    fn decodes_null_parameters() {
+        let bind = message(b'B', b"\0s\0\0\0\0\x02\xff\xff\xff\xff\0\0\0\x01a\0\0");
+        let message = ClientMessage::from_stream(&mut Cursor::new(bind.clone())).unwrap();
+        assert!(matches!(
+            &message,
+            ClientMessage::Bind { parameters, .. }
+                if parameters == &[None, Some(b"a".to_vec())]
+        ));
+        let mut encoded = vec![];
+        message.write(&mut encoded).unwrap();
+        assert_eq!(encoded, bind);
+    }
+
+    #[test]
+    fn rejects_messages_beyond_the_limits() {
+        let limits = Limits {
+            max_message_size: 64,
+            max_parameters: 1,
+            max_parameter_size: 4,
+            max_query_length: 8,
+            ..Limits::default()
+        };
+        let exceeds = |input: &[u8]| {
+            let mut buffer = BytesMut::from(input);
+            match ClientMessage::decode_with_limits(&mut buffer, &limits) {
+                Err(error) => error
+                    .get_ref()
+                    .is_some_and(|error| error.is::<LimitExceeded>()),
+                Ok(_) => false,
+            }
+        };
+        // Rejected from the length alone, before the body arrives.
+        assert!(exceeds(b"Q\x7f\xff\xff\xff"));
+        assert!(exceeds(&message(b'Q', b"SELECT 1, 2\0")));
+        assert!(exceeds(&message(
+            b'P',
+            b"\0SELECT $1\0\0\x02\0\0\0\x17\0\0\0\x17"
+        )));
+        assert!(exceeds(&message(
+            b'B',
+            b"\0\0\0\0\0\x01\0\0\0\x05hello\0\0"
+        )));
+        assert!(!exceeds(&message(
+            b'B',
+            b"\0\0\0\0\0\x01\0\0\0\x04hell\0\0"
+        )));
+
+        let mut startup = Cursor::new(b"\x7f\xff\xff\xff".to_vec());
+        let error = StartupMessage::from_stream(&mut startup).unwrap_err();
+        assert!(error.get_ref().unwrap().is::<LimitExceeded>());
+    }
 }
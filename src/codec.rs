@@ -0,0 +1,282 @@
+use bytes::BytesMut;
+use std::io::Result;
+
+use crate::backend_message::BackendMessage;
+use crate::client_message::{ClientMessage, PasswordMessage, StartupMessage};
+use crate::limits::Limits;
+use crate::server_message::ServerMessage;
+
+/// A message sent by the frontend, as decoded by [`ServerCodec`].
+#[derive(Debug)]
+pub enum FrontendMessage {
+    Startup(StartupMessage),
+    Password(PasswordMessage),
+    Client(ClientMessage),
+}
+
+impl FrontendMessage {
+    /// Appends the message to `buffer`, as a client sends it.
+    pub fn encode(self, buffer: &mut BytesMut) -> Result<()> {
+        match self {
+            Self::Startup(message) => message.encode(buffer),
+            Self::Password(message) => message.encode(buffer),
+            Self::Client(message) => message.encode(buffer),
+        }
+    }
+}
+
+/// Decodes frontend messages from and encodes backend messages into byte
+/// buffers without doing any IO, for event loops that read and write the
+/// socket themselves. Decoding follows the session: the startup message,
+/// then the password, then regular messages.
+#[derive(Debug, Default)]
+pub struct ServerCodec {
+    phase: Phase,
+    limits: Limits,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    #[default]
+    Startup,
+    Password,
+    Messages,
+}
+
+impl ServerCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bounds the messages accepted from the client, see [`Limits`].
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Decodes the next message from the front of `buffer`, or returns
+    /// `None` when more bytes are needed. Consumed bytes are removed.
+    pub fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<FrontendMessage>> {
+        let message = match self.phase {
+            Phase::Startup => StartupMessage::decode(buffer)?.map(FrontendMessage::Startup),
+            Phase::Password => PasswordMessage::decode(buffer)?.map(FrontendMessage::Password),
+            Phase::Messages => ClientMessage::decode_with_limits(buffer, &self.limits)?
+                .map(FrontendMessage::Client),
+        };
+        if message.is_some() {
+            self.phase = match self.phase {
+                Phase::Startup => Phase::Password,
+                Phase::Password | Phase::Messages => Phase::Messages,
+            };
+        }
+        Ok(message)
+    }
+
+    pub fn encode(&mut self, message: ServerMessage<'_>, buffer: &mut BytesMut) -> Result<()> {
+        message.encode(buffer)
+    }
+}
+
+/// The client side of [`ServerCodec`]: encodes frontend messages and decodes
+/// backend messages.
+#[derive(Debug, Default)]
+pub struct ClientCodec;
+
+impl ClientCodec {
+    pub fn new() -> Self {
+        ClientCodec
+    }
+
+    /// Decodes the next message from the front of `buffer`, or returns
+    /// `None` when more bytes are needed. Consumed bytes are removed.
+    pub fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<BackendMessage>> {
+        BackendMessage::decode(buffer)
+    }
+
+    pub fn encode(&mut self, message: FrontendMessage, buffer: &mut BytesMut) -> Result<()> {
+        message.encode(buffer)
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl tokio_util::codec::Decoder for ServerCodec {
+    type Item = FrontendMessage;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<FrontendMessage>> {
+        ServerCodec::decode(self, buffer)
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl<'a> tokio_util::codec::Encoder<ServerMessage<'a>> for ServerCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, message: ServerMessage<'a>, buffer: &mut BytesMut) -> Result<()> {
+        ServerCodec::encode(self, message, buffer)
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl tokio_util::codec::Decoder for ClientCodec {
+    type Item = BackendMessage;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<BackendMessage>> {
+        ClientCodec::decode(self, buffer)
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl tokio_util::codec::Encoder<FrontendMessage> for ClientCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, message: FrontendMessage, buffer: &mut BytesMut) -> Result<()> {
+        ClientCodec::encode(self, message, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Column, FormatCode, Type};
+
+    fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![tag];
+        message.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+        message.extend_from_slice(body);
+        message
+    }
+
+    #[test]
+    fn decodes_messages_split_across_reads() {
+        let mut startup = 196608_i32.to_be_bytes().to_vec();
+        startup.extend_from_slice(b"user\0alice\0\0");
+        let mut input = ((startup.len() + 4) as i32).to_be_bytes().to_vec();
+        input.extend(startup);
+        input.extend(message(b'p', b"secret\0"));
+        input.extend(message(b'Q', b"SELECT 1\0"));
+        input.extend(message(b'S', b""));
+
+        let mut codec = ServerCodec::new();
+        let mut buffer = BytesMut::new();
+        let mut messages = vec![];
+        for byte in input {
+            buffer.extend_from_slice(&[byte]);
+            while let Some(message) = codec.decode(&mut buffer).unwrap() {
+                messages.push(message);
+            }
+        }
+        assert!(buffer.is_empty());
+        assert!(matches!(
+            &messages[..],
+            [
+                FrontendMessage::Startup(StartupMessage { user, .. }),
+                FrontendMessage::Password(PasswordMessage { password }),
+                FrontendMessage::Client(ClientMessage::Query { query }),
+                FrontendMessage::Client(ClientMessage::Sync),
+            ] if user == "alice" && password == "secret" && query == b"SELECT 1"
+        ));
+    }
+
+    #[test]
+    fn encodes_backend_messages() {
+        let mut codec = ServerCodec::new();
+        let mut buffer = BytesMut::new();
+        codec
+            .encode(ServerMessage::ParseComplete, &mut buffer)
+            .unwrap();
+        codec
+            .encode(
+                ServerMessage::ReadyForQuery {
+                    transaction_status: b'I',
+                },
+                &mut buffer,
+            )
+            .unwrap();
+        assert_eq!(&buffer[..], b"1\0\0\0\x04Z\0\0\0\x05I");
+    }
+
+    #[test]
+    fn rejects_invalid_lengths() {
+        let mut buffer = BytesMut::from(&b"Q\0\0\0\x02"[..]);
+        assert!(ClientMessage::decode(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn client_codec_round_trips_with_the_server() {
+        let mut client = ClientCodec::new();
+        let mut buffer = BytesMut::new();
+        for message in [
+            ClientMessage::Parse {
+                name: "s".to_string(),
+                query: b"SELECT $1".to_vec(),
+                parameter_type_oids: vec![23],
+            },
+            ClientMessage::Bind {
+                portal: String::new(),
+                name: "s".to_string(),
+                parameter_format_codes: vec![FormatCode::Text],
+                parameters: vec![Some(b"42".to_vec()), None],
+                result_format_codes: vec![FormatCode::Binary],
+            },
+            ClientMessage::Execute {
+                portal: String::new(),
+                max_rows: 0,
+            },
+        ] {
+            client
+                .encode(FrontendMessage::Client(message), &mut buffer)
+                .unwrap();
+        }
+        let mut messages = vec![];
+        while let Some(message) = ClientMessage::decode(&mut buffer).unwrap() {
+            messages.push(message);
+        }
+        assert!(matches!(
+            &messages[..],
+            [
+                ClientMessage::Parse { name, parameter_type_oids, .. },
+                ClientMessage::Bind { parameters, result_format_codes, .. },
+                ClientMessage::Execute { max_rows: 0, .. },
+            ] if name == "s"
+                && parameter_type_oids == &[23]
+                && parameters == &[Some(b"42".to_vec()), None]
+                && matches!(result_format_codes[..], [FormatCode::Binary])
+        ));
+
+        let mut server = ServerCodec::new();
+        for message in [
+            ServerMessage::RowDescription {
+                fields: vec![(Column::new("n", Type::INT4), FormatCode::Text)],
+            },
+            ServerMessage::DataRow {
+                fields: vec![Some(BytesMut::from(&b"42"[..])), None],
+            },
+            ServerMessage::ErrorResponse {
+                severity: "ERROR",
+                code: "42601",
+                message: "syntax error".to_string(),
+                position: Some(8),
+            },
+        ] {
+            server.encode(message, &mut buffer).unwrap();
+        }
+        let mut messages = vec![];
+        while let Some(message) = client.decode(&mut buffer).unwrap() {
+            messages.push(message);
+        }
+        assert!(matches!(
+            &messages[..],
+            [
+                BackendMessage::RowDescription { fields },
+                BackendMessage::DataRow { fields: values },
+                BackendMessage::ErrorResponse(error),
+            ] if fields[0].name == "n"
+                && fields[0].type_oid == Type::INT4.oid()
+                && values == &[Some(b"42".to_vec()), None]
+                && error.code == "42601"
+                && error.position == Some(8)
+        ));
+    }
+}
@@ -0,0 +1,309 @@
+use encoding_rs::Encoding;
+use std::borrow::Cow;
+
+/// Character set used by the client, as set through the `client_encoding`
+/// startup parameter or `SET client_encoding`. Strings are always UTF-8 on
+/// the shim side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientEncoding {
+    Utf8,
+    /// No conversion, rows are sent as they are. Client text must still be
+    /// UTF-8 since shims get strings, as postgres checks it against a UTF8
+    /// server encoding.
+    SqlAscii,
+    Latin1,
+    Other {
+        name: &'static str,
+        encoding: &'static Encoding,
+    },
+}
+
+const OTHER_ENCODINGS: &[(&str, &Encoding)] = &[
+    ("LATIN2", encoding_rs::ISO_8859_2),
+    ("LATIN3", encoding_rs::ISO_8859_3),
+    ("LATIN4", encoding_rs::ISO_8859_4),
+    ("LATIN6", encoding_rs::ISO_8859_10),
+    ("LATIN7", encoding_rs::ISO_8859_13),
+    ("LATIN8", encoding_rs::ISO_8859_14),
+    ("LATIN9", encoding_rs::ISO_8859_15),
+    ("LATIN10", encoding_rs::ISO_8859_16),
+    ("ISO_8859_5", encoding_rs::ISO_8859_5),
+    ("ISO_8859_6", encoding_rs::ISO_8859_6),
+    ("ISO_8859_7", encoding_rs::ISO_8859_7),
+    ("ISO_8859_8", encoding_rs::ISO_8859_8),
+    ("WIN866", encoding_rs::IBM866),
+    ("WIN874", encoding_rs::WINDOWS_874),
+    ("WIN1250", encoding_rs::WINDOWS_1250),
+    ("WIN1251", encoding_rs::WINDOWS_1251),
+    ("WIN1252", encoding_rs::WINDOWS_1252),
+    ("WIN1253", encoding_rs::WINDOWS_1253),
+    ("WIN1254", encoding_rs::WINDOWS_1254),
+    ("WIN1255", encoding_rs::WINDOWS_1255),
+    ("WIN1256", encoding_rs::WINDOWS_1256),
+    ("WIN1257", encoding_rs::WINDOWS_1257),
+    ("WIN1258", encoding_rs::WINDOWS_1258),
+    ("KOI8R", encoding_rs::KOI8_R),
+    ("KOI8U", encoding_rs::KOI8_U),
+    ("EUC_JP", encoding_rs::EUC_JP),
+    ("EUC_KR", encoding_rs::EUC_KR),
+    ("SJIS", encoding_rs::SHIFT_JIS),
+    ("BIG5", encoding_rs::BIG5),
+    ("GBK", encoding_rs::GBK),
+    ("GB18030", encoding_rs::GB18030),
+];
+
+const ALIASES: &[(&str, &str)] = &[
+    ("UNICODE", "UTF8"),
+    ("ISO88591", "LATIN1"),
+    ("ISO88592", "LATIN2"),
+    ("ISO88593", "LATIN3"),
+    ("ISO88594", "LATIN4"),
+    ("ISO885910", "LATIN6"),
+    ("ISO885913", "LATIN7"),
+    ("ISO885914", "LATIN8"),
+    ("ISO885915", "LATIN9"),
+    ("ISO885916", "LATIN10"),
+    ("ISO88595", "ISO_8859_5"),
+    ("ISO88596", "ISO_8859_6"),
+    ("ISO88597", "ISO_8859_7"),
+    ("ISO88598", "ISO_8859_8"),
+    ("WINDOWS1250", "WIN1250"),
+    ("WINDOWS1251", "WIN1251"),
+    ("WINDOWS1252", "WIN1252"),
+    ("SHIFTJIS", "SJIS"),
+    ("KOI8", "KOI8R"),
+];
+
+/// Postgres compares encoding names ignoring case and punctuation.
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+impl ClientEncoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let normalized = normalize(name);
+        let normalized = ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == normalized)
+            .map(|(_, name)| normalize(name))
+            .unwrap_or(normalized);
+        match normalized.as_str() {
+            "UTF8" => Some(ClientEncoding::Utf8),
+            "SQLASCII" => Some(ClientEncoding::SqlAscii),
+            "LATIN1" => Some(ClientEncoding::Latin1),
+            _ => OTHER_ENCODINGS
+                .iter()
+                .find(|(name, _)| normalize(name) == normalized)
+                .map(|(name, encoding)| ClientEncoding::Other { name, encoding }),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientEncoding::Utf8 => "UTF8",
+            ClientEncoding::SqlAscii => "SQL_ASCII",
+            ClientEncoding::Latin1 => "LATIN1",
+            ClientEncoding::Other { name, .. } => name,
+        }
+    }
+
+    /// Decodes client bytes. The error is the message of a 22021
+    /// `character_not_in_repertoire` error.
+    pub fn decode(&self, bytes: &[u8]) -> std::result::Result<String, String> {
+        match self {
+            ClientEncoding::Utf8 | ClientEncoding::SqlAscii => String::from_utf8(bytes.to_vec())
+                .map_err(|error| {
+                    let start = error.utf8_error().valid_up_to();
+                    let invalid = error.utf8_error().error_len().unwrap_or(1);
+                    invalid_byte_sequence("UTF8", &bytes[start..start + invalid])
+                }),
+            ClientEncoding::Latin1 => Ok(bytes.iter().map(|byte| *byte as char).collect()),
+            ClientEncoding::Other { name, encoding } => encoding
+                .decode_without_bom_handling_and_without_replacement(bytes)
+                .map(Cow::into_owned)
+                .ok_or_else(|| invalid_byte_sequence(name, &bytes[..bytes.len().min(2)])),
+        }
+    }
+
+    /// Encodes UTF-8 server text for the client. The error is the message of a
+    /// 22P05 `untranslatable_character` error.
+    pub fn encode<'a>(&self, text: &'a [u8]) -> std::result::Result<Cow<'a, [u8]>, String> {
+        match self {
+            ClientEncoding::Utf8 | ClientEncoding::SqlAscii => Ok(Cow::Borrowed(text)),
+            ClientEncoding::Latin1 => {
+                if text.is_ascii() {
+                    return Ok(Cow::Borrowed(text));
+                }
+                String::from_utf8_lossy(text)
+                    .chars()
+                    .map(|c| u8::try_from(c as u32).map_err(|_| untranslatable(c, "LATIN1")))
+                    .collect::<std::result::Result<Vec<u8>, String>>()
+                    .map(Cow::Owned)
+            }
+            ClientEncoding::Other { name, encoding } => {
+                if text.is_ascii() {
+                    return Ok(Cow::Borrowed(text));
+                }
+                let text = String::from_utf8_lossy(text);
+                let mut encoder = encoding.new_encoder();
+                let mut result = Vec::with_capacity(
+                    encoder
+                        .max_buffer_length_from_utf8_without_replacement(text.len())
+                        .unwrap_or(text.len() * 4),
+                );
+                let (status, read) =
+                    encoder.encode_from_utf8_to_vec_without_replacement(&text, &mut result, true);
+                match status {
+                    encoding_rs::EncoderResult::InputEmpty => Ok(Cow::Owned(result)),
+                    encoding_rs::EncoderResult::Unmappable(c) => Err(untranslatable(c, name)),
+                    encoding_rs::EncoderResult::OutputFull => Err(format!(
+                        "could not convert \"{}\" to encoding \"{}\"",
+                        &text[read..],
+                        name
+                    )),
+                }
+            }
+        }
+    }
+}
+
+fn invalid_byte_sequence(encoding: &str, bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+    format!(
+        "invalid byte sequence for encoding \"{}\": {}",
+        encoding,
+        bytes.join(" ")
+    )
+}
+
+fn untranslatable(c: char, encoding: &str) -> String {
+    format!(
+        "character with byte sequence {} in encoding \"UTF8\" has no equivalent in encoding \"{}\"",
+        c.to_string()
+            .bytes()
+            .map(|byte| format!("0x{:02x}", byte))
+            .collect::<Vec<String>>()
+            .join(" "),
+        encoding
+    )
+}
+
+/// Returns the requested encoding if `query` is `SET client_encoding ...` or
+/// `SET NAMES ...`. `DEFAULT` is returned as `None` inside the `Some`.
+pub(crate) fn parse_set_client_encoding(query: &str) -> Option<Option<String>> {
+    let query = query.trim().trim_end_matches(';').trim();
+    let mut words = query.split_whitespace();
+    if !words.next()?.eq_ignore_ascii_case("SET") {
+        return None;
+    }
+    let mut word = words.next()?;
+    if word.eq_ignore_ascii_case("SESSION") || word.eq_ignore_ascii_case("LOCAL") {
+        word = words.next()?;
+    }
+    let rest: Vec<&str> = if word.eq_ignore_ascii_case("NAMES") {
+        words.collect()
+    } else {
+        let (name, value) = match word.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (word, None),
+        };
+        if !name.eq_ignore_ascii_case("client_encoding") {
+            return None;
+        }
+        let mut rest: Vec<&str> = value.into_iter().filter(|v| !v.is_empty()).collect();
+        rest.extend(words);
+        match rest.first() {
+            Some(first) if first.eq_ignore_ascii_case("TO") || *first == "=" => {
+                rest.remove(0);
+            }
+            Some(first) if first.starts_with('=') => {
+                rest[0] = &first[1..];
+            }
+            _ if value.is_some() => {}
+            _ => return None,
+        }
+        rest
+    };
+    let value = rest.join(" ");
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("DEFAULT") {
+        return Some(None);
+    }
+    Some(Some(
+        value.trim_matches(|c| c == '\'' || c == '"').to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_postgres_names_and_aliases() {
+        assert_eq!(
+            ClientEncoding::from_name("utf-8"),
+            Some(ClientEncoding::Utf8)
+        );
+        assert_eq!(
+            ClientEncoding::from_name("latin1"),
+            Some(ClientEncoding::Latin1)
+        );
+        assert_eq!(
+            ClientEncoding::from_name("ISO_8859_1"),
+            Some(ClientEncoding::Latin1)
+        );
+        assert_eq!(
+            ClientEncoding::from_name("win1252").unwrap().name(),
+            "WIN1252"
+        );
+        assert_eq!(ClientEncoding::from_name("klingon"), None);
+    }
+
+    #[test]
+    fn transcodes_latin1_and_win1252() {
+        assert_eq!(ClientEncoding::Latin1.decode(&[0x63, 0xe9]).unwrap(), "cé");
+        assert_eq!(
+            ClientEncoding::Latin1
+                .encode("cé".as_bytes())
+                .unwrap()
+                .as_ref(),
+            &[0x63, 0xe9]
+        );
+        assert!(ClientEncoding::Latin1.encode("€".as_bytes()).is_err());
+        let win1252 = ClientEncoding::from_name("WIN1252").unwrap();
+        assert_eq!(win1252.decode(&[0x80]).unwrap(), "€");
+        assert_eq!(win1252.encode("€".as_bytes()).unwrap().as_ref(), &[0x80]);
+    }
+
+    #[test]
+    fn rejects_invalid_utf8() {
+        assert_eq!(
+            ClientEncoding::Utf8.decode(&[0x61, 0xff]).unwrap_err(),
+            "invalid byte sequence for encoding \"UTF8\": 0xff"
+        );
+    }
+
+    #[test]
+    fn parses_set_client_encoding() {
+        assert_eq!(
+            parse_set_client_encoding("SET client_encoding TO 'LATIN1';"),
+            Some(Some("LATIN1".to_string()))
+        );
+        assert_eq!(
+            parse_set_client_encoding("set client_encoding=win1252"),
+            Some(Some("win1252".to_string()))
+        );
+        assert_eq!(
+            parse_set_client_encoding("SET NAMES 'UTF8'"),
+            Some(Some("UTF8".to_string()))
+        );
+        assert_eq!(
+            parse_set_client_encoding("SET SESSION client_encoding = DEFAULT"),
+            Some(None)
+        );
+        assert_eq!(parse_set_client_encoding("SET search_path TO public"), None);
+    }
+}
@@ -0,0 +1,1937 @@
+//! A small evaluator for the SELECT statements clients send to read the
+//! system catalogs. It runs over in-memory tables and covers the subset of
+//! SQL used by psql, the JDBC and npgsql drivers and the usual GUI tools:
+//! joins, correlated subqueries, `CASE`, casts, regular expressions,
+//! aggregates, `UNION`, `ORDER BY` and `LIMIT`.
+
+use regex::{Regex, RegexBuilder};
+use sqlparser::ast::{
+    BinaryOperator, DataType, Distinct, Expr, Function, FunctionArg, FunctionArgExpr,
+    FunctionArguments, GroupByExpr, Ident, Join, JoinConstraint, JoinOperator, ObjectName,
+    OrderByExpr, Query, Select, SelectItem, SetExpr, SetOperator, SetQuantifier, Subscript,
+    TableFactor, TableWithJoins, UnaryOperator, Value as SqlValue,
+};
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use crate::{Column, ToSqlValue, Type, Value};
+
+/// A SQLSTATE code and message.
+pub(crate) type EvalError = (&'static str, String);
+pub(crate) type EvalResult<T> = std::result::Result<T, EvalError>;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Relation {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// The tables and functions a query is evaluated against.
+pub(crate) trait Database {
+    /// Looks up a table. `schema` is `None` when the name is not qualified.
+    fn table(&self, schema: Option<&str>, name: &str) -> Option<Relation>;
+    /// Whether references to an unknown table in `schema` should read as an
+    /// empty table rather than fail.
+    fn is_system_table(&self, schema: Option<&str>, name: &str) -> bool;
+    /// Evaluates a function the evaluator does not know. `None` means the
+    /// function is unknown and evaluates to NULL.
+    fn function(&self, name: &str, args: &[Value]) -> EvalResult<Option<Value>>;
+    /// Casts to a type the evaluator does not know, such as `regclass`.
+    fn cast(&self, value: Value, type_name: &str) -> EvalResult<Option<Value>>;
+}
+
+#[derive(Debug)]
+pub(crate) struct QueryResult {
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+pub(crate) fn execute(
+    database: &dyn Database,
+    query: &Query,
+    parameters: &[Value],
+) -> EvalResult<QueryResult> {
+    let evaluator = Evaluator {
+        database,
+        parameters,
+        ctes: Vec::new(),
+    };
+    let output = evaluator.query(query, None)?;
+    let columns = output
+        .names
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let ty = output
+                .rows
+                .iter()
+                .find_map(|row| row.get(i)?.sql_type())
+                .unwrap_or(Type::TEXT);
+            Column::new(name, ty)
+        })
+        .collect();
+    Ok(QueryResult {
+        columns,
+        rows: output.rows,
+    })
+}
+
+struct Evaluator<'a> {
+    database: &'a dyn Database,
+    parameters: &'a [Value],
+    ctes: Vec<(String, Rc<Relation>)>,
+}
+
+/// Where the columns of one FROM item live in a joined row.
+#[derive(Debug, Clone)]
+struct Binding {
+    alias: String,
+    columns: Vec<String>,
+    offset: usize,
+    /// Unknown system table: any column reads as NULL.
+    open: bool,
+}
+
+struct Rows {
+    bindings: Vec<Binding>,
+    width: usize,
+    rows: Vec<Vec<Value>>,
+}
+
+struct Scope<'s> {
+    bindings: &'s [Binding],
+    row: &'s [Value],
+    /// Rows of the current group when evaluating aggregates.
+    group: Option<&'s [Vec<Value>]>,
+    outer: Option<&'s Scope<'s>>,
+}
+
+/// The rows folded into one output row by aggregation, next to a
+/// representative row that plain column references read from.
+type Group = Vec<Vec<Value>>;
+
+struct Output {
+    names: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+fn unsupported(what: impl std::fmt::Display) -> EvalError {
+    ("0A000", format!("{} is not supported", what))
+}
+
+fn ident(ident: &Ident) -> String {
+    if ident.quote_style.is_some() {
+        ident.value.clone()
+    } else {
+        ident.value.to_lowercase()
+    }
+}
+
+fn object_name(name: &ObjectName) -> (Option<String>, String) {
+    let mut parts: Vec<String> = name.0.iter().map(ident).collect();
+    let last = parts.pop().unwrap_or_default();
+    (parts.pop(), last)
+}
+
+impl<'a> Evaluator<'a> {
+    fn query(&self, query: &Query, outer: Option<&Scope>) -> EvalResult<Output> {
+        let nested;
+        let evaluator = match &query.with {
+            None => self,
+            Some(with) => {
+                if with.recursive {
+                    return Err(unsupported("WITH RECURSIVE"));
+                }
+                let mut ctes = self.ctes.clone();
+                for cte in &with.cte_tables {
+                    let evaluator = Evaluator {
+                        database: self.database,
+                        parameters: self.parameters,
+                        ctes: ctes.clone(),
+                    };
+                    let output = evaluator.query(&cte.query, outer)?;
+                    let mut columns = output.names;
+                    for (column, alias) in columns.iter_mut().zip(&cte.alias.columns) {
+                        *column = ident(&alias.name);
+                    }
+                    ctes.push((
+                        ident(&cte.alias.name),
+                        Rc::new(Relation {
+                            columns,
+                            rows: output.rows,
+                        }),
+                    ));
+                }
+                nested = Evaluator {
+                    database: self.database,
+                    parameters: self.parameters,
+                    ctes,
+                };
+                &nested
+            }
+        };
+        let order_by = query
+            .order_by
+            .as_ref()
+            .map(|order_by| order_by.exprs.as_slice())
+            .unwrap_or_default();
+        let mut output = match query.body.as_ref() {
+            SetExpr::Select(select) => evaluator.select(select, order_by, outer)?,
+            body => {
+                let mut output = evaluator.set_expr(body, outer)?;
+                evaluator.sort_output(&mut output, order_by)?;
+                output
+            }
+        };
+        let empty = Scope {
+            bindings: &[],
+            row: &[],
+            group: None,
+            outer,
+        };
+        if let Some(offset) = &query.offset {
+            let offset = evaluator.count(&offset.value, &empty)?;
+            output.rows.drain(..offset.min(output.rows.len()));
+        }
+        if let Some(limit) = &query.limit {
+            if !matches!(limit, Expr::Value(SqlValue::Null)) {
+                let limit = evaluator.count(limit, &empty)?;
+                output.rows.truncate(limit);
+            }
+        }
+        Ok(output)
+    }
+
+    fn count(&self, expr: &Expr, scope: &Scope) -> EvalResult<usize> {
+        match as_number(&self.eval(expr, scope)?) {
+            Some(count) if count >= 0.0 => Ok(count as usize),
+            _ => Err((
+                "22023",
+                format!("invalid row count \"{}\" in LIMIT or OFFSET", expr),
+            )),
+        }
+    }
+
+    fn set_expr(&self, body: &SetExpr, outer: Option<&Scope>) -> EvalResult<Output> {
+        match body {
+            SetExpr::Select(select) => self.select(select, &[], outer),
+            SetExpr::Query(query) => self.query(query, outer),
+            SetExpr::Values(values) => {
+                let scope = Scope {
+                    bindings: &[],
+                    row: &[],
+                    group: None,
+                    outer,
+                };
+                let rows = values
+                    .rows
+                    .iter()
+                    .map(|row| row.iter().map(|expr| self.eval(expr, &scope)).collect())
+                    .collect::<EvalResult<Vec<Vec<Value>>>>()?;
+                let width = rows.first().map(Vec::len).unwrap_or_default();
+                if rows.iter().any(|row| row.len() != width) {
+                    return Err((
+                        "42601",
+                        "VALUES lists must all be the same length".to_string(),
+                    ));
+                }
+                Ok(Output {
+                    names: (1..=width).map(|i| format!("column{}", i)).collect(),
+                    rows,
+                })
+            }
+            SetExpr::SetOperation {
+                left,
+                op,
+                set_quantifier,
+                right,
+            } => {
+                let left = self.set_expr(left, outer)?;
+                let right = self.set_expr(right, outer)?;
+                if left.names.len() != right.names.len() {
+                    return Err((
+                        "42601",
+                        format!("each {} query must have the same number of columns", op),
+                    ));
+                }
+                let all = matches!(set_quantifier, SetQuantifier::All);
+                let contains = |rows: &[Vec<Value>], row: &[Value]| {
+                    rows.iter().any(|other| rows_equal(other, row))
+                };
+                let mut rows = match op {
+                    SetOperator::Union => {
+                        let mut rows = left.rows;
+                        rows.extend(right.rows);
+                        rows
+                    }
+                    SetOperator::Intersect => left
+                        .rows
+                        .into_iter()
+                        .filter(|row| contains(&right.rows, row))
+                        .collect(),
+                    SetOperator::Except => left
+                        .rows
+                        .into_iter()
+                        .filter(|row| !contains(&right.rows, row))
+                        .collect(),
+                };
+                if !all {
+                    rows = distinct(rows);
+                }
+                Ok(Output {
+                    names: left.names,
+                    rows,
+                })
+            }
+            body => Err(unsupported(body)),
+        }
+    }
+
+    fn select(
+        &self,
+        select: &Select,
+        order_by: &[OrderByExpr],
+        outer: Option<&Scope>,
+    ) -> EvalResult<Output> {
+        let input = self.from(&select.from, outer)?;
+        let mut rows = Vec::new();
+        for row in input.rows {
+            let keep = match &select.selection {
+                None => true,
+                Some(selection) => {
+                    let scope = Scope {
+                        bindings: &input.bindings,
+                        row: &row,
+                        group: None,
+                        outer,
+                    };
+                    as_bool(&self.eval(selection, &scope)?) == Some(true)
+                }
+            };
+            if keep {
+                rows.push(row);
+            }
+        }
+
+        let group_by = match &select.group_by {
+            GroupByExpr::Expressions(exprs, modifiers) if modifiers.is_empty() => exprs.as_slice(),
+            group_by => return Err(unsupported(group_by)),
+        };
+        let aggregated = !group_by.is_empty()
+            || select.having.is_some()
+            || select.projection.iter().any(|item| match item {
+                SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                    has_aggregate(expr)
+                }
+                _ => false,
+            });
+        let groups: Vec<(Vec<Value>, Option<Group>)> = if aggregated {
+            let mut groups: Vec<(Vec<Value>, Vec<Vec<Value>>)> = Vec::new();
+            for row in rows {
+                let scope = Scope {
+                    bindings: &input.bindings,
+                    row: &row,
+                    group: None,
+                    outer,
+                };
+                let key = group_by
+                    .iter()
+                    .map(|expr| self.eval(expr, &scope))
+                    .collect::<EvalResult<Vec<Value>>>()?;
+                match groups.iter_mut().find(|(other, _)| rows_equal(other, &key)) {
+                    Some((_, group)) => group.push(row),
+                    None => groups.push((key, vec![row])),
+                }
+            }
+            if groups.is_empty() && group_by.is_empty() {
+                groups.push((vec![], vec![]));
+            }
+            groups
+                .into_iter()
+                .map(|(_, group)| {
+                    let first = group
+                        .first()
+                        .cloned()
+                        .unwrap_or_else(|| vec![Value::Null; input.width]);
+                    (first, Some(group))
+                })
+                .collect()
+        } else {
+            rows.into_iter().map(|row| (row, None)).collect()
+        };
+
+        let names = self.projection_names(&select.projection, &input.bindings)?;
+        let mut output = Vec::new();
+        for (row, group) in &groups {
+            let scope = Scope {
+                bindings: &input.bindings,
+                row,
+                group: group.as_deref(),
+                outer,
+            };
+            if let Some(having) = &select.having {
+                if as_bool(&self.eval(having, &scope)?) != Some(true) {
+                    continue;
+                }
+            }
+            let values = self.project(&select.projection, &scope)?;
+            let keys = order_by
+                .iter()
+                .map(|order| match output_column(&order.expr, &names) {
+                    Some(i) => Ok(values[i].clone()),
+                    None => self.eval(&order.expr, &scope),
+                })
+                .collect::<EvalResult<Vec<Value>>>()?;
+            output.push((values, keys));
+        }
+        sort_rows(&mut output, order_by);
+        let mut rows: Vec<Vec<Value>> = output.into_iter().map(|(values, _)| values).collect();
+        match &select.distinct {
+            None => {}
+            Some(Distinct::Distinct) => rows = distinct(rows),
+            Some(distinct) => return Err(unsupported(distinct)),
+        }
+        Ok(Output { names, rows })
+    }
+
+    fn sort_output(&self, output: &mut Output, order_by: &[OrderByExpr]) -> EvalResult<()> {
+        if order_by.is_empty() {
+            return Ok(());
+        }
+        let columns = order_by
+            .iter()
+            .map(|order| {
+                output_column(&order.expr, &output.names).ok_or_else(|| {
+                    (
+                        "0A000",
+                        format!(
+                            "ORDER BY on a UNION result must use output columns, not \"{}\"",
+                            order.expr
+                        ),
+                    )
+                })
+            })
+            .collect::<EvalResult<Vec<usize>>>()?;
+        let mut rows: Vec<(Vec<Value>, Vec<Value>)> = output
+            .rows
+            .drain(..)
+            .map(|row| {
+                let keys = columns.iter().map(|i| row[*i].clone()).collect();
+                (row, keys)
+            })
+            .collect();
+        sort_rows(&mut rows, order_by);
+        output.rows = rows.into_iter().map(|(row, _)| row).collect();
+        Ok(())
+    }
+
+    fn projection_names(
+        &self,
+        projection: &[SelectItem],
+        bindings: &[Binding],
+    ) -> EvalResult<Vec<String>> {
+        let mut names = Vec::new();
+        for item in projection {
+            match item {
+                SelectItem::UnnamedExpr(expr) => names.push(expr_name(expr)),
+                SelectItem::ExprWithAlias { alias, .. } => names.push(ident(alias)),
+                SelectItem::Wildcard(_) => {
+                    for binding in bindings {
+                        names.extend(binding.columns.iter().cloned());
+                    }
+                }
+                SelectItem::QualifiedWildcard(name, _) => {
+                    names.extend(qualified_binding(bindings, name)?.columns.iter().cloned())
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    fn project(&self, projection: &[SelectItem], scope: &Scope) -> EvalResult<Vec<Value>> {
+        let mut values = Vec::new();
+        for item in projection {
+            match item {
+                SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                    values.push(self.eval(expr, scope)?)
+                }
+                SelectItem::Wildcard(_) => {
+                    for binding in scope.bindings {
+                        values.extend_from_slice(
+                            &scope.row[binding.offset..binding.offset + binding.columns.len()],
+                        );
+                    }
+                }
+                SelectItem::QualifiedWildcard(name, _) => {
+                    let binding = qualified_binding(scope.bindings, name)?;
+                    values.extend_from_slice(
+                        &scope.row[binding.offset..binding.offset + binding.columns.len()],
+                    );
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    fn from(&self, from: &[TableWithJoins], outer: Option<&Scope>) -> EvalResult<Rows> {
+        let mut rows = Rows {
+            bindings: vec![],
+            width: 0,
+            rows: vec![vec![]],
+        };
+        for table in from {
+            let relation = self.table_with_joins(table, outer)?;
+            rows = self.join(rows, relation, &JoinOperator::CrossJoin, outer)?;
+        }
+        Ok(rows)
+    }
+
+    fn table_with_joins(&self, table: &TableWithJoins, outer: Option<&Scope>) -> EvalResult<Rows> {
+        let mut rows = self.table_factor(&table.relation, outer)?;
+        for Join {
+            relation,
+            join_operator,
+            ..
+        } in &table.joins
+        {
+            let right = self.table_factor(relation, outer)?;
+            rows = self.join(rows, right, join_operator, outer)?;
+        }
+        Ok(rows)
+    }
+
+    fn table_factor(&self, factor: &TableFactor, outer: Option<&Scope>) -> EvalResult<Rows> {
+        let (alias, relation, open) = match factor {
+            TableFactor::Table {
+                name, alias, args, ..
+            } => {
+                let (schema, table) = object_name(name);
+                let alias_name = alias.as_ref().map(|alias| ident(&alias.name));
+                let (relation, open) = match args {
+                    Some(args) => {
+                        let scope = Scope {
+                            bindings: &[],
+                            row: &[],
+                            group: None,
+                            outer,
+                        };
+                        let args = args
+                            .args
+                            .iter()
+                            .map(|arg| self.function_arg(arg, &scope))
+                            .collect::<EvalResult<Vec<Value>>>()?;
+                        (table_function(&table, &args)?, false)
+                    }
+                    None => match self.lookup_table(schema.as_deref(), &table) {
+                        Some(relation) => (relation, false),
+                        None if self.database.is_system_table(schema.as_deref(), &table) => (
+                            Relation {
+                                columns: vec![],
+                                rows: vec![],
+                            },
+                            true,
+                        ),
+                        None => {
+                            return Err(("42P01", format!("relation \"{}\" does not exist", name)))
+                        }
+                    },
+                };
+                let mut relation = relation;
+                if let Some(alias) = alias {
+                    for (column, name) in relation.columns.iter_mut().zip(&alias.columns) {
+                        *column = ident(&name.name);
+                    }
+                }
+                (alias_name.unwrap_or(table), relation, open)
+            }
+            TableFactor::Derived {
+                subquery, alias, ..
+            } => {
+                let output = self.query(subquery, outer)?;
+                let mut columns = output.names;
+                let alias = match alias {
+                    Some(alias) => {
+                        for (column, name) in columns.iter_mut().zip(&alias.columns) {
+                            *column = ident(&name.name);
+                        }
+                        ident(&alias.name)
+                    }
+                    None => String::new(),
+                };
+                (
+                    alias,
+                    Relation {
+                        columns,
+                        rows: output.rows,
+                    },
+                    false,
+                )
+            }
+            TableFactor::NestedJoin {
+                table_with_joins,
+                alias,
+            } => {
+                let mut rows = self.table_with_joins(table_with_joins, outer)?;
+                if let Some(alias) = alias {
+                    let columns = rows
+                        .bindings
+                        .iter()
+                        .flat_map(|binding| binding.columns.iter().cloned())
+                        .collect();
+                    rows.bindings = vec![Binding {
+                        alias: ident(&alias.name),
+                        columns,
+                        offset: 0,
+                        open: false,
+                    }];
+                }
+                return Ok(rows);
+            }
+            factor => return Err(unsupported(factor)),
+        };
+        let width = relation.columns.len();
+        Ok(Rows {
+            bindings: vec![Binding {
+                alias,
+                columns: relation.columns,
+                offset: 0,
+                open,
+            }],
+            width,
+            rows: relation.rows,
+        })
+    }
+
+    fn lookup_table(&self, schema: Option<&str>, name: &str) -> Option<Relation> {
+        if schema.is_none() {
+            if let Some((_, relation)) = self.ctes.iter().rev().find(|(cte, _)| cte == name) {
+                return Some(relation.as_ref().clone());
+            }
+        }
+        self.database.table(schema, name)
+    }
+
+    fn join(
+        &self,
+        left: Rows,
+        right: Rows,
+        operator: &JoinOperator,
+        outer: Option<&Scope>,
+    ) -> EvalResult<Rows> {
+        let (constraint, keep_left, keep_right) = match operator {
+            JoinOperator::Inner(constraint) => (Some(constraint), false, false),
+            JoinOperator::LeftOuter(constraint) => (Some(constraint), true, false),
+            JoinOperator::RightOuter(constraint) => (Some(constraint), false, true),
+            JoinOperator::FullOuter(constraint) => (Some(constraint), true, true),
+            JoinOperator::CrossJoin => (None, false, false),
+            operator => return Err(unsupported(format!("{:?} join", operator))),
+        };
+        let mut bindings = left.bindings;
+        bindings.extend(right.bindings.into_iter().map(|mut binding| {
+            binding.offset += left.width;
+            binding
+        }));
+        let width = left.width + right.width;
+        let using: Vec<(usize, usize)> = match constraint {
+            Some(JoinConstraint::Using(columns)) => columns
+                .iter()
+                .map(|column| {
+                    let column = ident(column);
+                    let find = |bindings: &[Binding]| {
+                        bindings.iter().find_map(|binding| {
+                            binding
+                                .columns
+                                .iter()
+                                .position(|name| *name == column)
+                                .map(|i| binding.offset + i)
+                        })
+                    };
+                    let (left_bindings, right_bindings) = bindings.split_at(
+                        bindings
+                            .iter()
+                            .position(|binding| binding.offset >= left.width)
+                            .unwrap_or(bindings.len()),
+                    );
+                    match (find(left_bindings), find(right_bindings)) {
+                        (Some(left), Some(right)) => Ok((left, right)),
+                        _ => Err((
+                            "42703",
+                            format!(
+                                "column \"{}\" specified in USING clause does not exist",
+                                column
+                            ),
+                        )),
+                    }
+                })
+                .collect::<EvalResult<_>>()?,
+            Some(JoinConstraint::Natural) => return Err(unsupported("NATURAL JOIN")),
+            _ => vec![],
+        };
+        let mut rows = Vec::new();
+        let mut right_matched = vec![false; right.rows.len()];
+        for left_row in &left.rows {
+            let mut matched = false;
+            for (right_row, right_matched) in right.rows.iter().zip(right_matched.iter_mut()) {
+                let mut row = left_row.clone();
+                row.extend_from_slice(right_row);
+                let keep = match constraint {
+                    Some(JoinConstraint::On(on)) => {
+                        let scope = Scope {
+                            bindings: &bindings,
+                            row: &row,
+                            group: None,
+                            outer,
+                        };
+                        as_bool(&self.eval(on, &scope)?) == Some(true)
+                    }
+                    Some(JoinConstraint::Using(_)) => using.iter().all(|(left, right)| {
+                        compare(&row[*left], &row[*right]) == Some(Ordering::Equal)
+                    }),
+                    _ => true,
+                };
+                if keep {
+                    matched = true;
+                    *right_matched = true;
+                    rows.push(row);
+                }
+            }
+            if keep_left && !matched {
+                let mut row = left_row.clone();
+                row.resize(width, Value::Null);
+                rows.push(row);
+            }
+        }
+        if keep_right {
+            for (right_row, matched) in right.rows.iter().zip(right_matched) {
+                if !matched {
+                    let mut row = vec![Value::Null; left.width];
+                    row.extend_from_slice(right_row);
+                    rows.push(row);
+                }
+            }
+        }
+        Ok(Rows {
+            bindings,
+            width,
+            rows,
+        })
+    }
+
+    fn eval(&self, expr: &Expr, scope: &Scope) -> EvalResult<Value> {
+        Ok(match expr {
+            Expr::Identifier(name) => column(scope, None, &ident(name))?,
+            Expr::CompoundIdentifier(names) => {
+                let mut names: Vec<String> = names.iter().map(ident).collect();
+                let name = names.pop().unwrap_or_default();
+                column(scope, names.pop().as_deref(), &name)?
+            }
+            Expr::Value(value) => self.literal(value)?,
+            Expr::TypedString { data_type, value } => {
+                self.cast(Value::Text(value.clone()), data_type)?
+            }
+            Expr::Nested(expr) => self.eval(expr, scope)?,
+            Expr::Collate { expr, .. } => self.eval(expr, scope)?,
+            Expr::IsNull(expr) => Value::Bool(self.eval(expr, scope)?.is_null()),
+            Expr::IsNotNull(expr) => Value::Bool(!self.eval(expr, scope)?.is_null()),
+            Expr::IsTrue(expr) => Value::Bool(as_bool(&self.eval(expr, scope)?) == Some(true)),
+            Expr::IsNotTrue(expr) => Value::Bool(as_bool(&self.eval(expr, scope)?) != Some(true)),
+            Expr::IsFalse(expr) => Value::Bool(as_bool(&self.eval(expr, scope)?) == Some(false)),
+            Expr::IsNotFalse(expr) => Value::Bool(as_bool(&self.eval(expr, scope)?) != Some(false)),
+            Expr::IsDistinctFrom(left, right) | Expr::IsNotDistinctFrom(left, right) => {
+                let left = self.eval(left, scope)?;
+                let right = self.eval(right, scope)?;
+                let same = match (left.is_null(), right.is_null()) {
+                    (true, true) => true,
+                    (false, false) => compare(&left, &right) == Some(Ordering::Equal),
+                    _ => false,
+                };
+                Value::Bool(same == matches!(expr, Expr::IsNotDistinctFrom(_, _)))
+            }
+            Expr::UnaryOp { op, expr } => {
+                let value = self.eval(expr, scope)?;
+                match op {
+                    UnaryOperator::Not => bool_value(as_bool(&value).map(|value| !value)),
+                    UnaryOperator::Plus => value,
+                    UnaryOperator::Minus => match value {
+                        Value::Null => Value::Null,
+                        Value::Int2(value) => Value::Int2(-value),
+                        Value::Int4(value) => Value::Int4(-value),
+                        Value::Int8(value) => Value::Int8(-value),
+                        value => number_value(-as_number(&value).ok_or_else(|| {
+                            ("42883", format!("operator does not exist: - {:?}", value))
+                        })?),
+                    },
+                    op => return Err(unsupported(format!("operator {}", op))),
+                }
+            }
+            Expr::BinaryOp { left, op, right } => match op {
+                BinaryOperator::And => {
+                    let left = as_bool(&self.eval(left, scope)?);
+                    if left == Some(false) {
+                        return Ok(Value::Bool(false));
+                    }
+                    match (left, as_bool(&self.eval(right, scope)?)) {
+                        (_, Some(false)) => Value::Bool(false),
+                        (Some(true), Some(true)) => Value::Bool(true),
+                        _ => Value::Null,
+                    }
+                }
+                BinaryOperator::Or => {
+                    let left = as_bool(&self.eval(left, scope)?);
+                    if left == Some(true) {
+                        return Ok(Value::Bool(true));
+                    }
+                    match (left, as_bool(&self.eval(right, scope)?)) {
+                        (_, Some(true)) => Value::Bool(true),
+                        (Some(false), Some(false)) => Value::Bool(false),
+                        _ => Value::Null,
+                    }
+                }
+                op => {
+                    let left = self.eval(left, scope)?;
+                    let right = self.eval(right, scope)?;
+                    binary_op(&left, op, &right)?
+                }
+            },
+            like @ (Expr::Like {
+                negated,
+                expr,
+                pattern,
+                escape_char,
+                any: false,
+            }
+            | Expr::ILike {
+                negated,
+                expr,
+                pattern,
+                escape_char,
+                any: false,
+            }) => {
+                let value = self.eval(expr, scope)?;
+                let pattern = self.eval(pattern, scope)?;
+                match (text(&value), text(&pattern)) {
+                    (Some(value), Some(pattern)) => {
+                        let regex = like_regex(
+                            &pattern,
+                            escape_char.as_deref(),
+                            matches!(like, Expr::ILike { .. }),
+                        )?;
+                        Value::Bool(regex.is_match(&value) != *negated)
+                    }
+                    _ => Value::Null,
+                }
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let value = self.eval(expr, scope)?;
+                let list = list
+                    .iter()
+                    .map(|item| self.eval(item, scope))
+                    .collect::<EvalResult<Vec<Value>>>()?;
+                in_values(&value, &list, *negated)
+            }
+            Expr::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } => {
+                let value = self.eval(expr, scope)?;
+                let output = self.query(subquery, Some(scope))?;
+                let list: Vec<Value> = output
+                    .rows
+                    .into_iter()
+                    .filter_map(|row| row.into_iter().next())
+                    .collect();
+                in_values(&value, &list, *negated)
+            }
+            Expr::Between {
+                expr,
+                negated,
+                low,
+                high,
+            } => {
+                let value = self.eval(expr, scope)?;
+                let low = compare(&value, &self.eval(low, scope)?);
+                let high = compare(&value, &self.eval(high, scope)?);
+                match (low, high) {
+                    (Some(low), Some(high)) => Value::Bool(
+                        (low != Ordering::Less && high != Ordering::Greater) != *negated,
+                    ),
+                    _ => Value::Null,
+                }
+            }
+            Expr::AnyOp {
+                left,
+                compare_op,
+                right,
+                ..
+            }
+            | Expr::AllOp {
+                left,
+                compare_op,
+                right,
+            } => {
+                let value = self.eval(left, scope)?;
+                let elements = match right.as_ref() {
+                    Expr::Subquery(query) => self
+                        .query(query, Some(scope))?
+                        .rows
+                        .into_iter()
+                        .filter_map(|row| row.into_iter().next())
+                        .collect(),
+                    right => match self.eval(right, scope)? {
+                        Value::Null => return Ok(Value::Null),
+                        Value::Array(elements) => elements,
+                        Value::Text(text) => parse_text_array(&text),
+                        value => vec![value],
+                    },
+                };
+                let results = elements
+                    .iter()
+                    .map(|element| {
+                        binary_op(&value, compare_op, element).map(|value| as_bool(&value))
+                    })
+                    .collect::<EvalResult<Vec<Option<bool>>>>()?;
+                if matches!(expr, Expr::AllOp { .. }) {
+                    if results.contains(&Some(false)) {
+                        Value::Bool(false)
+                    } else if results.contains(&None) {
+                        Value::Null
+                    } else {
+                        Value::Bool(true)
+                    }
+                } else if results.contains(&Some(true)) {
+                    Value::Bool(true)
+                } else if results.contains(&None) {
+                    Value::Null
+                } else {
+                    Value::Bool(false)
+                }
+            }
+            Expr::Exists { subquery, negated } => {
+                let output = self.query(subquery, Some(scope))?;
+                Value::Bool(output.rows.is_empty() == *negated)
+            }
+            Expr::Subquery(query) => {
+                let output = self.query(query, Some(scope))?;
+                if output.rows.len() > 1 {
+                    return Err((
+                        "21000",
+                        "more than one row returned by a subquery used as an expression"
+                            .to_string(),
+                    ));
+                }
+                output
+                    .rows
+                    .into_iter()
+                    .next()
+                    .and_then(|row| row.into_iter().next())
+                    .unwrap_or(Value::Null)
+            }
+            Expr::Case {
+                operand,
+                conditions,
+                results,
+                else_result,
+            } => {
+                let operand = match operand {
+                    Some(operand) => Some(self.eval(operand, scope)?),
+                    None => None,
+                };
+                for (condition, result) in conditions.iter().zip(results) {
+                    let condition = self.eval(condition, scope)?;
+                    let matched = match &operand {
+                        Some(operand) => compare(operand, &condition) == Some(Ordering::Equal),
+                        None => as_bool(&condition) == Some(true),
+                    };
+                    if matched {
+                        return self.eval(result, scope);
+                    }
+                }
+                match else_result {
+                    Some(result) => self.eval(result, scope)?,
+                    None => Value::Null,
+                }
+            }
+            Expr::Cast {
+                expr, data_type, ..
+            } => {
+                let value = self.eval(expr, scope)?;
+                self.cast(value, data_type)?
+            }
+            Expr::Array(array) => Value::Array(
+                array
+                    .elem
+                    .iter()
+                    .map(|element| self.eval(element, scope))
+                    .collect::<EvalResult<_>>()?,
+            ),
+            Expr::Subscript { expr, subscript } => {
+                let value = self.eval(expr, scope)?;
+                match subscript.as_ref() {
+                    Subscript::Index { index } => {
+                        let index = as_number(&self.eval(index, scope)?);
+                        match (value, index) {
+                            (Value::Array(elements), Some(index)) if index >= 1.0 => elements
+                                .into_iter()
+                                .nth(index as usize - 1)
+                                .unwrap_or(Value::Null),
+                            _ => Value::Null,
+                        }
+                    }
+                    subscript => return Err(unsupported(subscript)),
+                }
+            }
+            Expr::Function(function) => self.function(function, scope)?,
+            Expr::Trim {
+                expr,
+                trim_where: None,
+                trim_what: None,
+                ..
+            } => match text(&self.eval(expr, scope)?) {
+                Some(value) => Value::Text(value.trim_matches(' ').to_string()),
+                None => Value::Null,
+            },
+            expr => return Err(unsupported(format!("expression \"{}\"", expr))),
+        })
+    }
+
+    fn literal(&self, value: &SqlValue) -> EvalResult<Value> {
+        Ok(match value {
+            SqlValue::Number(number, _) => match number.parse::<i64>() {
+                Ok(number) => match i32::try_from(number) {
+                    Ok(number) => Value::Int4(number),
+                    Err(_) => Value::Int8(number),
+                },
+                Err(_) => Value::Numeric(number.to_string()),
+            },
+            SqlValue::SingleQuotedString(value)
+            | SqlValue::EscapedStringLiteral(value)
+            | SqlValue::UnicodeStringLiteral(value)
+            | SqlValue::NationalStringLiteral(value) => Value::Text(value.clone()),
+            SqlValue::DollarQuotedString(value) => Value::Text(value.value.clone()),
+            SqlValue::Boolean(value) => Value::Bool(*value),
+            SqlValue::Null => Value::Null,
+            SqlValue::Placeholder(placeholder) => {
+                let index = placeholder
+                    .strip_prefix('$')
+                    .and_then(|index| index.parse::<usize>().ok())
+                    .filter(|index| *index >= 1);
+                match index.and_then(|index| self.parameters.get(index - 1)) {
+                    Some(value) => value.clone(),
+                    None => {
+                        return Err(("42P02", format!("there is no parameter {}", placeholder)))
+                    }
+                }
+            }
+            value => return Err(unsupported(format!("literal {}", value))),
+        })
+    }
+
+    fn cast(&self, value: Value, data_type: &DataType) -> EvalResult<Value> {
+        let type_name = data_type.to_string().to_lowercase();
+        let type_name = type_name.strip_prefix("pg_catalog.").unwrap_or(&type_name);
+        if type_name.ends_with("[]") {
+            return Ok(match value {
+                Value::Text(text) => Value::Array(parse_text_array(&text)),
+                value => value,
+            });
+        }
+        if value.is_null() {
+            return Ok(Value::Null);
+        }
+        let invalid = |type_name: &str, value: &Value| {
+            (
+                "22P02",
+                format!(
+                    "invalid input syntax for type {}: \"{}\"",
+                    type_name,
+                    text(value).unwrap_or_default()
+                ),
+            )
+        };
+        Ok(match type_name {
+            "text" | "varchar" | "character varying" | "name" | "bpchar" | "char" | "character"
+            | "\"char\"" => Value::Text(text(&value).unwrap_or_default()),
+            "int2" | "smallint" | "int4" | "int" | "integer" | "int8" | "bigint" | "oid" => {
+                let number = match &value {
+                    Value::Text(text) => text.trim().parse::<i64>().ok(),
+                    value => as_number(value).map(|number| number as i64),
+                }
+                .ok_or_else(|| invalid(type_name, &value))?;
+                match type_name {
+                    "int2" | "smallint" => Value::Int2(number as i16),
+                    "int4" | "int" | "integer" => Value::Int4(number as i32),
+                    _ => Value::Int8(number),
+                }
+            }
+            "float4" | "real" | "float8" | "double precision" | "float" => {
+                Value::Float8(as_number(&value).ok_or_else(|| invalid(type_name, &value))?)
+            }
+            "numeric" | "decimal" => match &value {
+                Value::Numeric(_) => value,
+                value => Value::Numeric(
+                    as_number(value)
+                        .ok_or_else(|| invalid(type_name, value))?
+                        .to_string(),
+                ),
+            },
+            "bool" | "boolean" => bool_value(Some(
+                as_bool(&value).ok_or_else(|| invalid(type_name, &value))?,
+            )),
+            type_name => match self.database.cast(value.clone(), type_name)? {
+                Some(value) => value,
+                None => value,
+            },
+        })
+    }
+
+    fn function_arg(&self, arg: &FunctionArg, scope: &Scope) -> EvalResult<Value> {
+        match arg {
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(expr))
+            | FunctionArg::Named {
+                arg: FunctionArgExpr::Expr(expr),
+                ..
+            }
+            | FunctionArg::ExprNamed {
+                arg: FunctionArgExpr::Expr(expr),
+                ..
+            } => self.eval(expr, scope),
+            arg => Err(unsupported(format!("argument {}", arg))),
+        }
+    }
+
+    fn function(&self, function: &Function, scope: &Scope) -> EvalResult<Value> {
+        let (_, name) = object_name(&function.name);
+        let args: &[FunctionArg] = match &function.args {
+            FunctionArguments::None => &[],
+            FunctionArguments::List(list) => &list.args,
+            FunctionArguments::Subquery(_) => return Err(unsupported(function)),
+        };
+        if is_aggregate(&name) && function.over.is_none() {
+            let group = scope.group.ok_or_else(|| {
+                (
+                    "42803",
+                    format!("aggregate functions are not allowed here: {}", function),
+                )
+            })?;
+            return self.aggregate(&name, args, group, scope);
+        }
+        let args = args
+            .iter()
+            .map(|arg| self.function_arg(arg, scope))
+            .collect::<EvalResult<Vec<Value>>>()?;
+        Ok(match (name.as_str(), args.as_slice()) {
+            ("coalesce", args) => args
+                .iter()
+                .find(|value| !value.is_null())
+                .cloned()
+                .unwrap_or(Value::Null),
+            ("nullif", [left, right]) => {
+                if compare(left, right) == Some(Ordering::Equal) {
+                    Value::Null
+                } else {
+                    left.clone()
+                }
+            }
+            ("lower", [value]) => map_text(value, |text| text.to_lowercase()),
+            ("upper", [value]) => map_text(value, |text| text.to_uppercase()),
+            ("length" | "char_length", [value]) => match text(value) {
+                Some(text) => Value::Int4(text.chars().count() as i32),
+                None => Value::Null,
+            },
+            ("concat", args) => Value::Text(args.iter().filter_map(text).collect()),
+            ("array_to_string", [array, separator, ..]) => match (array, text(separator)) {
+                (Value::Array(elements), Some(separator)) => Value::Text(
+                    elements
+                        .iter()
+                        .filter_map(text)
+                        .collect::<Vec<String>>()
+                        .join(&separator),
+                ),
+                _ => Value::Null,
+            },
+            ("array_length" | "cardinality", [Value::Array(elements), ..]) => {
+                Value::Int4(elements.len() as i32)
+            }
+            ("array_upper", [Value::Array(elements), _]) if !elements.is_empty() => {
+                Value::Int4(elements.len() as i32)
+            }
+            _ => self.database.function(&name, &args)?.unwrap_or(Value::Null),
+        })
+    }
+
+    fn aggregate(
+        &self,
+        name: &str,
+        args: &[FunctionArg],
+        group: &[Vec<Value>],
+        scope: &Scope,
+    ) -> EvalResult<Value> {
+        let star = matches!(args, [FunctionArg::Unnamed(FunctionArgExpr::Wildcard)] | []);
+        if name == "count" && star {
+            return Ok(Value::Int8(group.len() as i64));
+        }
+        let mut values = Vec::with_capacity(group.len());
+        let mut extra = Vec::new();
+        for row in group {
+            let scope = Scope {
+                bindings: scope.bindings,
+                row,
+                group: None,
+                outer: scope.outer,
+            };
+            let mut args = args.iter();
+            let value = match args.next() {
+                Some(arg) => self.function_arg(arg, &scope)?,
+                None => Value::Null,
+            };
+            if extra.is_empty() {
+                extra = args
+                    .map(|arg| self.function_arg(arg, &scope))
+                    .collect::<EvalResult<Vec<Value>>>()?;
+            }
+            values.push(value);
+        }
+        let present = || values.iter().filter(|value| !value.is_null());
+        Ok(match name {
+            "count" => Value::Int8(present().count() as i64),
+            "max" => present()
+                .max_by(|a, b| compare(a, b).unwrap_or(Ordering::Equal))
+                .cloned()
+                .unwrap_or(Value::Null),
+            "min" => present()
+                .min_by(|a, b| compare(a, b).unwrap_or(Ordering::Equal))
+                .cloned()
+                .unwrap_or(Value::Null),
+            "sum" => match present().next() {
+                None => Value::Null,
+                Some(_) => number_value(present().filter_map(as_number).sum()),
+            },
+            "bool_and" | "every" => match present().next() {
+                None => Value::Null,
+                Some(_) => Value::Bool(present().all(|value| as_bool(value) == Some(true))),
+            },
+            "bool_or" => match present().next() {
+                None => Value::Null,
+                Some(_) => Value::Bool(present().any(|value| as_bool(value) == Some(true))),
+            },
+            "array_agg" => {
+                if values.is_empty() {
+                    Value::Null
+                } else {
+                    Value::Array(values.clone())
+                }
+            }
+            "string_agg" => match present().next() {
+                None => Value::Null,
+                Some(_) => Value::Text(
+                    present()
+                        .filter_map(text)
+                        .collect::<Vec<String>>()
+                        .join(&extra.first().and_then(text).unwrap_or_default()),
+                ),
+            },
+            name => return Err(unsupported(format!("aggregate {}", name))),
+        })
+    }
+}
+
+fn column(scope: &Scope, qualifier: Option<&str>, name: &str) -> EvalResult<Value> {
+    let mut current = Some(scope);
+    while let Some(scope) = current {
+        for binding in scope.bindings {
+            if qualifier.is_some_and(|qualifier| qualifier != binding.alias) {
+                continue;
+            }
+            if let Some(i) = binding.columns.iter().position(|column| column == name) {
+                return Ok(scope.row[binding.offset + i].clone());
+            }
+            if binding.open && qualifier.is_some() {
+                return Ok(Value::Null);
+            }
+        }
+        current = scope.outer;
+    }
+    let name = match qualifier {
+        Some(qualifier) => format!("{}.{}", qualifier, name),
+        None => name.to_string(),
+    };
+    Err(("42703", format!("column \"{}\" does not exist", name)))
+}
+
+fn qualified_binding<'b>(bindings: &'b [Binding], name: &ObjectName) -> EvalResult<&'b Binding> {
+    let (_, alias) = object_name(name);
+    bindings
+        .iter()
+        .find(|binding| binding.alias == alias)
+        .ok_or_else(|| {
+            (
+                "42P01",
+                format!("missing FROM-clause entry for table \"{}\"", alias),
+            )
+        })
+}
+
+fn table_function(name: &str, args: &[Value]) -> EvalResult<Relation> {
+    match (name, args) {
+        ("generate_series", [start, stop, step @ ..]) => {
+            let step = step.first().and_then(as_number).unwrap_or(1.0) as i64;
+            let rows = match (as_number(start), as_number(stop)) {
+                (Some(start), Some(stop)) if step != 0 => {
+                    let (start, stop) = (start as i64, stop as i64);
+                    let mut rows = Vec::new();
+                    let mut i = start;
+                    while (step > 0 && i <= stop) || (step < 0 && i >= stop) {
+                        rows.push(vec![Value::Int4(i as i32)]);
+                        i += step;
+                    }
+                    rows
+                }
+                _ => vec![],
+            };
+            Ok(Relation {
+                columns: vec![name.to_string()],
+                rows,
+            })
+        }
+        ("unnest", [Value::Array(elements)]) => Ok(Relation {
+            columns: vec![name.to_string()],
+            rows: elements.iter().map(|value| vec![value.clone()]).collect(),
+        }),
+        (name, _) => Err(unsupported(format!("function {} in FROM", name))),
+    }
+}
+
+fn is_aggregate(name: &str) -> bool {
+    matches!(
+        name,
+        "count"
+            | "max"
+            | "min"
+            | "sum"
+            | "bool_and"
+            | "bool_or"
+            | "every"
+            | "array_agg"
+            | "string_agg"
+    )
+}
+
+fn has_aggregate(expr: &Expr) -> bool {
+    match expr {
+        Expr::Function(function) => {
+            let (_, name) = object_name(&function.name);
+            (is_aggregate(&name) && function.over.is_none())
+                || match &function.args {
+                    FunctionArguments::List(list) => list.args.iter().any(|arg| match arg {
+                        FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => has_aggregate(expr),
+                        _ => false,
+                    }),
+                    _ => false,
+                }
+        }
+        Expr::Nested(expr) | Expr::Cast { expr, .. } | Expr::UnaryOp { expr, .. } => {
+            has_aggregate(expr)
+        }
+        Expr::BinaryOp { left, right, .. } => has_aggregate(left) || has_aggregate(right),
+        Expr::Case {
+            conditions,
+            results,
+            else_result,
+            ..
+        } => {
+            conditions.iter().chain(results).any(has_aggregate)
+                || else_result.as_deref().is_some_and(has_aggregate)
+        }
+        _ => false,
+    }
+}
+
+/// The name postgres gives to an unaliased output column.
+fn expr_name(expr: &Expr) -> String {
+    match expr {
+        Expr::Identifier(name) => ident(name),
+        Expr::CompoundIdentifier(names) => names.last().map(ident).unwrap_or_default(),
+        Expr::Function(function) => object_name(&function.name).1,
+        Expr::Cast {
+            expr, data_type, ..
+        } => match expr_name(expr).as_str() {
+            "?column?" => {
+                let type_name = data_type.to_string().to_lowercase();
+                type_name
+                    .rsplit('.')
+                    .next()
+                    .unwrap_or(&type_name)
+                    .to_string()
+            }
+            name => name.to_string(),
+        },
+        Expr::Nested(expr) | Expr::Collate { expr, .. } => expr_name(expr),
+        Expr::Case { .. } => "case".to_string(),
+        Expr::Exists { .. } => "exists".to_string(),
+        Expr::Array(_) => "array".to_string(),
+        _ => "?column?".to_string(),
+    }
+}
+
+/// Resolves an ORDER BY item that names an output column by position or alias.
+fn output_column(expr: &Expr, names: &[String]) -> Option<usize> {
+    match expr {
+        Expr::Value(SqlValue::Number(number, _)) => number
+            .parse::<usize>()
+            .ok()
+            .filter(|i| (1..=names.len()).contains(i))
+            .map(|i| i - 1),
+        Expr::Identifier(name) => {
+            let name = ident(name);
+            names.iter().position(|other| *other == name)
+        }
+        _ => None,
+    }
+}
+
+fn sort_rows(rows: &mut [(Vec<Value>, Vec<Value>)], order_by: &[OrderByExpr]) {
+    if order_by.is_empty() {
+        return;
+    }
+    rows.sort_by(|(_, left), (_, right)| {
+        for ((left, right), order) in left.iter().zip(right).zip(order_by) {
+            let descending = order.asc == Some(false);
+            let nulls_first = order.nulls_first.unwrap_or(descending);
+            let ordering = match (left.is_null(), right.is_null()) {
+                (true, true) => Ordering::Equal,
+                (true, false) if nulls_first => Ordering::Less,
+                (true, false) => Ordering::Greater,
+                (false, true) if nulls_first => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => {
+                    let ordering = compare(left, right).unwrap_or(Ordering::Equal);
+                    if descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    }
+                }
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    });
+}
+
+fn distinct(rows: Vec<Vec<Value>>) -> Vec<Vec<Value>> {
+    let mut result: Vec<Vec<Value>> = Vec::with_capacity(rows.len());
+    for row in rows {
+        if !result.iter().any(|other| rows_equal(other, &row)) {
+            result.push(row);
+        }
+    }
+    result
+}
+
+/// Row equality for grouping and set operations, where NULLs are equal.
+fn rows_equal(left: &[Value], right: &[Value]) -> bool {
+    left.len() == right.len()
+        && left.iter().zip(right).all(|(left, right)| {
+            (left.is_null() && right.is_null()) || compare(left, right) == Some(Ordering::Equal)
+        })
+}
+
+fn in_values(value: &Value, list: &[Value], negated: bool) -> Value {
+    if value.is_null() {
+        return Value::Null;
+    }
+    if list
+        .iter()
+        .any(|item| compare(value, item) == Some(Ordering::Equal))
+    {
+        Value::Bool(!negated)
+    } else if list.iter().any(Value::is_null) {
+        Value::Null
+    } else {
+        Value::Bool(negated)
+    }
+}
+
+fn binary_op(left: &Value, op: &BinaryOperator, right: &Value) -> EvalResult<Value> {
+    let comparison = |matches: fn(Ordering) -> bool| {
+        compare(left, right)
+            .map(|ordering| Value::Bool(matches(ordering)))
+            .unwrap_or(Value::Null)
+    };
+    Ok(match op {
+        BinaryOperator::Eq => comparison(|ordering| ordering == Ordering::Equal),
+        BinaryOperator::NotEq => comparison(|ordering| ordering != Ordering::Equal),
+        BinaryOperator::Lt => comparison(|ordering| ordering == Ordering::Less),
+        BinaryOperator::LtEq => comparison(|ordering| ordering != Ordering::Greater),
+        BinaryOperator::Gt => comparison(|ordering| ordering == Ordering::Greater),
+        BinaryOperator::GtEq => comparison(|ordering| ordering != Ordering::Less),
+        BinaryOperator::StringConcat => match (left, right) {
+            (Value::Null, _) | (_, Value::Null) => Value::Null,
+            (Value::Array(left), Value::Array(right)) => concat_arrays(left, right)?,
+            (Value::Array(left), right) if dimensions(left).len() == 1 => {
+                Value::Array(left.iter().chain([right]).cloned().collect())
+            }
+            (left, Value::Array(right)) if dimensions(right).len() == 1 => {
+                Value::Array([left].into_iter().chain(right).cloned().collect())
+            }
+            (Value::Array(_), _) | (_, Value::Array(_)) => return Err(incompatible_arrays()),
+            (left, right) => {
+                Value::Text(text(left).unwrap_or_default() + &text(right).unwrap_or_default())
+            }
+        },
+        BinaryOperator::Plus
+        | BinaryOperator::Minus
+        | BinaryOperator::Multiply
+        | BinaryOperator::Divide
+        | BinaryOperator::Modulo => arithmetic(left, op, right)?,
+        BinaryOperator::PGRegexMatch => regex_match(left, right, false, false)?,
+        BinaryOperator::PGRegexIMatch => regex_match(left, right, true, false)?,
+        BinaryOperator::PGRegexNotMatch => regex_match(left, right, false, true)?,
+        BinaryOperator::PGRegexNotIMatch => regex_match(left, right, true, true)?,
+        BinaryOperator::PGLikeMatch
+        | BinaryOperator::PGILikeMatch
+        | BinaryOperator::PGNotLikeMatch
+        | BinaryOperator::PGNotILikeMatch => match (text(left), text(right)) {
+            (Some(value), Some(pattern)) => {
+                let case_insensitive = matches!(
+                    op,
+                    BinaryOperator::PGILikeMatch | BinaryOperator::PGNotILikeMatch
+                );
+                let negated = matches!(
+                    op,
+                    BinaryOperator::PGNotLikeMatch | BinaryOperator::PGNotILikeMatch
+                );
+                Value::Bool(
+                    like_regex(&pattern, None, case_insensitive)?.is_match(&value) != negated,
+                )
+            }
+            _ => Value::Null,
+        },
+        BinaryOperator::PGCustomBinaryOperator(parts) => match parts.last().map(String::as_str) {
+            Some("=") => binary_op(left, &BinaryOperator::Eq, right)?,
+            Some("<>") => binary_op(left, &BinaryOperator::NotEq, right)?,
+            Some("~") => regex_match(left, right, false, false)?,
+            Some("~*") => regex_match(left, right, true, false)?,
+            Some("!~") => regex_match(left, right, false, true)?,
+            Some("!~*") => regex_match(left, right, true, true)?,
+            Some("~~") => binary_op(left, &BinaryOperator::PGLikeMatch, right)?,
+            Some("~~*") => binary_op(left, &BinaryOperator::PGILikeMatch, right)?,
+            _ => return Err(unsupported(format!("operator {}", op))),
+        },
+        op => return Err(unsupported(format!("operator {}", op))),
+    })
+}
+
+fn incompatible_arrays() -> EvalError {
+    (
+        "2202E",
+        "cannot concatenate incompatible arrays".to_string(),
+    )
+}
+
+/// The lengths of an array's dimensions, outermost first, read along its
+/// first elements.
+fn dimensions(elements: &[Value]) -> Vec<usize> {
+    let mut lengths = vec![elements.len()];
+    if let Some(Value::Array(inner)) = elements.first() {
+        lengths.extend(dimensions(inner));
+    }
+    lengths
+}
+
+/// `array || array` the way postgres does it: arrays of the same depth are
+/// joined along their first dimension, and an array one level shallower
+/// than the other becomes its first or last element.
+fn concat_arrays(left: &[Value], right: &[Value]) -> EvalResult<Value> {
+    if left.is_empty() || right.is_empty() {
+        return Ok(Value::Array(left.iter().chain(right).cloned().collect()));
+    }
+    let (left_dimensions, right_dimensions) = (dimensions(left), dimensions(right));
+    let elements = if left_dimensions[1..] == right_dimensions[1..] {
+        left.iter().chain(right).cloned().collect()
+    } else if left_dimensions[1..] == right_dimensions[..] {
+        let mut elements = left.to_vec();
+        elements.push(Value::Array(right.to_vec()));
+        elements
+    } else if right_dimensions[1..] == left_dimensions[..] {
+        [Value::Array(left.to_vec())]
+            .into_iter()
+            .chain(right.iter().cloned())
+            .collect()
+    } else {
+        return Err(incompatible_arrays());
+    };
+    Ok(Value::Array(elements))
+}
+
+fn arithmetic(left: &Value, op: &BinaryOperator, right: &Value) -> EvalResult<Value> {
+    if left.is_null() || right.is_null() {
+        return Ok(Value::Null);
+    }
+    let division_by_zero = || ("22012", "division by zero".to_string());
+    if let (Some(left), Some(right)) = (as_integer(left), as_integer(right)) {
+        let result = match op {
+            BinaryOperator::Plus => left.checked_add(right),
+            BinaryOperator::Minus => left.checked_sub(right),
+            BinaryOperator::Multiply => left.checked_mul(right),
+            BinaryOperator::Divide if right == 0 => return Err(division_by_zero()),
+            BinaryOperator::Divide => left.checked_div(right),
+            BinaryOperator::Modulo if right == 0 => return Err(division_by_zero()),
+            _ => left.checked_rem(right),
+        };
+        return match result {
+            Some(result) => Ok(match i32::try_from(result) {
+                Ok(result) => Value::Int4(result),
+                Err(_) => Value::Int8(result),
+            }),
+            None => Err(("22003", "integer out of range".to_string())),
+        };
+    }
+    match (as_number(left), as_number(right)) {
+        (Some(left), Some(right)) => Ok(Value::Float8(match op {
+            BinaryOperator::Plus => left + right,
+            BinaryOperator::Minus => left - right,
+            BinaryOperator::Multiply => left * right,
+            _ if right == 0.0 => return Err(division_by_zero()),
+            BinaryOperator::Divide => left / right,
+            _ => left % right,
+        })),
+        _ => Err((
+            "42883",
+            format!("operator does not exist: {:?} {} {:?}", left, op, right),
+        )),
+    }
+}
+
+fn regex_match(
+    value: &Value,
+    pattern: &Value,
+    case_insensitive: bool,
+    negated: bool,
+) -> EvalResult<Value> {
+    match (text(value), text(pattern)) {
+        (Some(value), Some(pattern)) => {
+            let regex = RegexBuilder::new(&pattern)
+                .case_insensitive(case_insensitive)
+                .build()
+                .map_err(|error| ("2201B", format!("invalid regular expression: {}", error)))?;
+            Ok(Value::Bool(regex.is_match(&value) != negated))
+        }
+        _ => Ok(Value::Null),
+    }
+}
+
+fn like_regex(pattern: &str, escape: Option<&str>, case_insensitive: bool) -> EvalResult<Regex> {
+    let escape = match escape {
+        Some(escape) => escape.chars().next(),
+        None => Some('\\'),
+    };
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if Some(c) == escape => {
+                if let Some(c) = chars.next() {
+                    regex.push_str(&regex::escape(&c.to_string()));
+                }
+            }
+            '%' => regex.push_str(".*"),
+            '_' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    RegexBuilder::new(&regex)
+        .case_insensitive(case_insensitive)
+        .dot_matches_new_line(true)
+        .build()
+        .map_err(|error| ("22025", format!("invalid LIKE pattern: {}", error)))
+}
+
+/// Compares two values, converting text to the type of the other side the
+/// way postgres resolves an unknown literal.
+pub(crate) fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Null, _) | (_, Value::Null) => None,
+        (Value::Text(left), Value::Text(right)) => Some(left.cmp(right)),
+        (Value::Bool(_), _) | (_, Value::Bool(_)) => Some(as_bool(left)?.cmp(&as_bool(right)?)),
+        (Value::Array(left), Value::Array(right)) => {
+            for (left, right) in left.iter().zip(right) {
+                match compare(left, right)? {
+                    Ordering::Equal => {}
+                    ordering => return Some(ordering),
+                }
+            }
+            Some(left.len().cmp(&right.len()))
+        }
+        _ => match (as_number(left), as_number(right)) {
+            (Some(left), Some(right)) => left.partial_cmp(&right),
+            _ => Some(text(left)?.cmp(&text(right)?)),
+        },
+    }
+}
+
+fn as_integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::Int2(value) => Some(*value as i64),
+        Value::Int4(value) => Some(*value as i64),
+        Value::Int8(value) => Some(*value),
+        Value::Text(value) => value.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int2(value) => Some(*value as f64),
+        Value::Int4(value) => Some(*value as f64),
+        Value::Int8(value) => Some(*value as f64),
+        Value::Float4(value) => Some(*value as f64),
+        Value::Float8(value) => Some(*value),
+        Value::Numeric(value) | Value::Text(value) => value.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn number_value(number: f64) -> Value {
+    if number.fract() == 0.0 && number.abs() < i64::MAX as f64 {
+        let number = number as i64;
+        match i32::try_from(number) {
+            Ok(number) => Value::Int4(number),
+            Err(_) => Value::Int8(number),
+        }
+    } else {
+        Value::Float8(number)
+    }
+}
+
+fn as_bool(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(value) => Some(*value),
+        Value::Text(value) => match value.trim().to_lowercase().as_str() {
+            "t" | "true" | "yes" | "y" | "on" | "1" => Some(true),
+            "f" | "false" | "no" | "n" | "off" | "0" => Some(false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn bool_value(value: Option<bool>) -> Value {
+    value.map(Value::Bool).unwrap_or(Value::Null)
+}
+
+/// The text representation of a value, `None` for NULL.
+pub(crate) fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Text(value) => Some(value.clone()),
+        value => {
+            let ty = value.sql_type().unwrap_or(Type::TEXT_ARRAY);
+            value
+                .as_str_value(&ty)
+                .ok()?
+                .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        }
+    }
+}
+
+fn map_text(value: &Value, f: impl Fn(&str) -> String) -> Value {
+    match text(value) {
+        Some(text) => Value::Text(f(&text)),
+        None => Value::Null,
+    }
+}
+
+/// Parses a one dimensional array literal such as `{a,b}` into text elements.
+fn parse_text_array(text: &str) -> Vec<Value> {
+    let inner = text.trim().trim_start_matches('{').trim_end_matches('}');
+    if inner.is_empty() {
+        return vec![];
+    }
+    inner
+        .split(',')
+        .map(|element| match element.trim() {
+            "NULL" => Value::Null,
+            element => Value::Text(element.trim_matches('"').to_string()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlparser::ast::Statement;
+    use sqlparser::dialect::PostgreSqlDialect;
+    use sqlparser::parser::Parser;
+
+    struct Empty;
+
+    impl Database for Empty {
+        fn table(&self, _schema: Option<&str>, _name: &str) -> Option<Relation> {
+            None
+        }
+
+        fn is_system_table(&self, _schema: Option<&str>, _name: &str) -> bool {
+            false
+        }
+
+        fn function(&self, _name: &str, _args: &[Value]) -> EvalResult<Option<Value>> {
+            Ok(None)
+        }
+
+        fn cast(&self, _value: Value, _type_name: &str) -> EvalResult<Option<Value>> {
+            Ok(None)
+        }
+    }
+
+    /// Evaluates `SELECT <expressions>` and returns its row as text.
+    fn select(expressions: &str) -> EvalResult<Vec<Option<String>>> {
+        let sql = format!("SELECT {}", expressions);
+        let query = match Parser::parse_sql(&PostgreSqlDialect {}, &sql)
+            .unwrap()
+            .pop()
+        {
+            Some(Statement::Query(query)) => query,
+            statement => panic!("not a query: {:?}", statement),
+        };
+        let mut result = execute(&Empty, &query, &[])?;
+        assert_eq!(result.rows.len(), 1);
+        Ok(result.rows.remove(0).iter().map(text).collect())
+    }
+
+    fn values(expressions: &str) -> Vec<Option<String>> {
+        select(expressions).unwrap()
+    }
+
+    fn code(expressions: &str) -> &'static str {
+        select(expressions).unwrap_err().0
+    }
+
+    /// The error of running `sql`, which must fail.
+    fn query_error(sql: &str) -> EvalError {
+        let query = match Parser::parse_sql(&PostgreSqlDialect {}, sql).unwrap().pop() {
+            Some(Statement::Query(query)) => query,
+            statement => panic!("not a query: {:?}", statement),
+        };
+        execute(&Empty, &query, &[]).unwrap_err()
+    }
+
+    fn some(values: &[&str]) -> Vec<Option<String>> {
+        values.iter().map(|value| Some(value.to_string())).collect()
+    }
+
+    #[test]
+    fn rejects_rows_of_different_widths() {
+        assert_eq!(
+            query_error("VALUES (NULL, NULL), (3) UNION ALL SELECT version(), version()"),
+            (
+                "42601",
+                "VALUES lists must all be the same length".to_string()
+            )
+        );
+        assert_eq!(
+            query_error("VALUES (1, 2) UNION ALL SELECT 3"),
+            (
+                "42601",
+                "each UNION query must have the same number of columns".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn evaluates_arithmetic() {
+        assert_eq!(
+            values("1 + 2, 7 - 10, 6 * 7, 7 / 2, 7 % 3, 1.5 * 2, '4' + 1"),
+            some(&["3", "-3", "42", "3", "1", "3", "5"])
+        );
+        assert_eq!(values("2147483647 + 1"), some(&["2147483648"]));
+        assert_eq!(code("9223372036854775807 + 1"), "22003");
+        assert_eq!(code("1 / 0"), "22012");
+        assert_eq!(code("1 % 0"), "22012");
+        assert_eq!(code("1.5 / 0"), "22012");
+        assert_eq!(code("true + 1"), "42883");
+    }
+
+    #[test]
+    fn evaluates_comparisons() {
+        assert_eq!(
+            values("1 < 2, 2 <= 1, 'b' > 'a', 3 >= 3, 1 = 1.0, 1 <> 2, '10' > 9"),
+            some(&["t", "f", "t", "t", "t", "t", "t"])
+        );
+        assert_eq!(
+            values("'abc' LIKE 'a%', 'ABC' ILIKE 'a_c', 'abc' ~ '^b', 'abc' !~* 'B'"),
+            some(&["t", "t", "f", "f"])
+        );
+        assert_eq!(
+            values("ARRAY[1, 2] < ARRAY[1, 3], 2 IN (1, 2), 3 NOT IN (1, 2)"),
+            some(&["t", "t", "t"])
+        );
+        assert_eq!(code("'a' ~ '('"), "2201B");
+    }
+
+    #[test]
+    fn concatenates_text_and_arrays() {
+        assert_eq!(
+            values("'a' || 'b', 'n' || 1, ARRAY[1, 2] || ARRAY[3], ARRAY[1] || 2, 0 || ARRAY[1]"),
+            some(&["ab", "n1", "{1,2,3}", "{1,2}", "{0,1}"])
+        );
+        assert_eq!(
+            values(
+                "ARRAY[[1, 2]] || ARRAY[[3, 4]], ARRAY[[1, 2]] || ARRAY[3, 4], \
+                 ARRAY[1, 2] || ARRAY[[3, 4]], ARRAY[]::int4[] || ARRAY[[1]]"
+            ),
+            some(&["{{1,2},{3,4}}", "{{1,2},{3,4}}", "{{1,2},{3,4}}", "{{1}}"])
+        );
+        assert_eq!(code("ARRAY[[1, 2]] || ARRAY[3]"), "2202E");
+        assert_eq!(code("ARRAY[[1, 2]] || ARRAY[[[3, 4, 5]]]"), "2202E");
+        assert_eq!(code("ARRAY[[1, 2]] || 3"), "2202E");
+        assert_eq!(code("3 || ARRAY[[1, 2]]"), "2202E");
+    }
+
+    #[test]
+    fn evaluates_casts() {
+        assert_eq!(
+            values(
+                "'42'::int4, ' 7 '::bigint, 2.5::float8, '2.50'::numeric, 'yes'::bool, 12::text"
+            ),
+            some(&["42", "7", "2.5", "2.5", "t", "12"])
+        );
+        assert_eq!(
+            values("'{a,NULL,\"b c\"}'::text[]"),
+            some(&["{a,NULL,\"b c\"}"])
+        );
+        assert_eq!(values("CAST(NULL AS integer)"), vec![None]);
+        assert_eq!(code("'x'::integer"), "22P02");
+        assert_eq!(code("'maybe'::boolean"), "22P02");
+    }
+
+    #[test]
+    fn follows_null_semantics() {
+        assert_eq!(
+            values("NULL + 1, NULL = NULL, NULL || 'a', 1 IN (2, NULL), NULL::text IS NULL"),
+            vec![None, None, None, None, Some("t".to_string())]
+        );
+        assert_eq!(
+            values("NULL AND false, NULL AND true, NULL OR true, NULL OR false, NOT NULL::bool"),
+            vec![
+                Some("f".to_string()),
+                None,
+                Some("t".to_string()),
+                None,
+                None
+            ]
+        );
+        assert_eq!(
+            values("coalesce(NULL, 2), CASE WHEN NULL THEN 1 ELSE 2 END, 1 IN (1, NULL)"),
+            some(&["2", "2", "t"])
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::client::{Client, QueryResult};
+use crate::client_message::StartupMessage;
+use crate::{PostgresShim, PostgressIntermediary, Type};
+
+/// Runs a shim in a [`PostgressIntermediary`] over an in-memory stream and
+/// talks to it with a [`Client`], for unit tests that need neither a socket
+/// nor a driver.
+///
+/// ```
+/// # use postgres_shim::{DefaultServerParameters, Harness, Router};
+/// # fn parameters() -> DefaultServerParameters {
+/// #     DefaultServerParameters {
+/// #         server_version: "14".to_string(),
+/// #         server_encoding: "UTF8".to_string(),
+/// #         client_encoding: "UTF8".to_string(),
+/// #         application_name: String::new(),
+/// #         default_transaction_read_only: "off".to_string(),
+/// #         in_hot_standby: "off".to_string(),
+/// #         is_superuser: "off".to_string(),
+/// #         session_authorization: "postgres".to_string(),
+/// #         date_style: "ISO, MDY".to_string(),
+/// #         interval_style: "postgres".to_string(),
+/// #         time_zone: "UTC".to_string(),
+/// #         integer_datetimes: "on".to_string(),
+/// #         standard_conforming_strings: "on".to_string(),
+/// #     }
+/// # }
+/// let mut harness = Harness::start(|| Router::new(parameters()))?;
+/// let result = harness.query("SHOW statement_timeout", &[])?;
+/// assert_eq!(result.values(), vec![vec![Some("0")]]);
+/// harness.finish()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Harness {
+    client: Client<DuplexStream>,
+    session: JoinHandle<Result<()>>,
+}
+
+impl Harness {
+    /// Starts a session as `postgres` with the shim `factory` makes. The
+    /// shim is made on the session's thread, so it does not need to be
+    /// `Send`.
+    pub fn start<Factory, Shim, PortalData>(factory: Factory) -> Result<Self>
+    where
+        Factory: FnOnce() -> Shim + Send + 'static,
+        Shim: PostgresShim<PortalData>,
+        PortalData: 'static,
+    {
+        Self::start_as(StartupMessage::new("postgres"), factory)
+    }
+
+    /// Starts a session with `startup_message`, for shims that look at the
+    /// user, database or parameters.
+    pub fn start_as<Factory, Shim, PortalData>(
+        startup_message: StartupMessage,
+        factory: Factory,
+    ) -> Result<Self>
+    where
+        Factory: FnOnce() -> Shim + Send + 'static,
+        Shim: PostgresShim<PortalData>,
+        PortalData: 'static,
+    {
+        let (client, server) = duplex();
+        let session = thread::spawn(move || PostgressIntermediary::new(factory(), server).run());
+        let client = Client::connect(client, startup_message, "")?;
+        Ok(Harness { client, session })
+    }
+
+    /// See [`Client::query`].
+    pub fn query(&mut self, query: &str, parameters: &[&str]) -> Result<QueryResult> {
+        self.client.query(query, parameters)
+    }
+
+    /// See [`Client::prepare`].
+    pub fn prepare(&mut self, name: &str, query: &str, parameter_types: &[Type]) -> Result<()> {
+        self.client.prepare(name, query, parameter_types)
+    }
+
+    /// See [`Client::execute`].
+    pub fn execute(&mut self, name: &str, parameters: &[&str]) -> Result<QueryResult> {
+        self.client.execute(name, parameters)
+    }
+
+    /// See [`Client::close`].
+    pub fn close(&mut self, name: &str) -> Result<()> {
+        self.client.close(name)
+    }
+
+    /// See [`Client::simple_query`].
+    pub fn simple_query(&mut self, query: &str) -> Result<Vec<QueryResult>> {
+        self.client.simple_query(query)
+    }
+
+    /// The client, for anything the harness does not wrap.
+    pub fn client(&mut self) -> &mut Client<DuplexStream> {
+        &mut self.client
+    }
+
+    /// Ends the session and returns how the intermediary finished, which
+    /// is an error if the shim failed.
+    pub fn finish(self) -> Result<()> {
+        // The session may already be over if the shim failed.
+        let _ = self.client.terminate();
+        self.session
+            .join()
+            .unwrap_or_else(|_| Err(Error::other("the session panicked")))
+    }
+}
+
+/// One end of an in-memory byte stream, see [`duplex`].
+pub struct DuplexStream {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+}
+
+/// Bytes written by one end and not read yet by the other.
+#[derive(Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    readable: Condvar,
+}
+
+#[derive(Default)]
+struct PipeState {
+    bytes: VecDeque<u8>,
+    closed: bool,
+}
+
+/// A pair of connected in-memory streams. Reads block until the other end
+/// writes, and return end of file once it is dropped.
+pub fn duplex() -> (DuplexStream, DuplexStream) {
+    let (a, b) = (Arc::new(Pipe::default()), Arc::new(Pipe::default()));
+    (
+        DuplexStream {
+            incoming: a.clone(),
+            outgoing: b.clone(),
+        },
+        DuplexStream {
+            incoming: b,
+            outgoing: a,
+        },
+    )
+}
+
+impl Read for DuplexStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut state = self.incoming.state.lock().unwrap();
+        while state.bytes.is_empty() && !state.closed {
+            state = self.incoming.readable.wait(state).unwrap();
+        }
+        let length = buf.len().min(state.bytes.len());
+        for (byte, read) in buf.iter_mut().zip(state.bytes.drain(..length)) {
+            *byte = read;
+        }
+        Ok(length)
+    }
+}
+
+impl Write for DuplexStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut state = self.outgoing.state.lock().unwrap();
+        if state.closed {
+            return Err(Error::new(
+                ErrorKind::BrokenPipe,
+                "the other end was dropped",
+            ));
+        }
+        state.bytes.extend(buf);
+        self.outgoing.readable.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        for pipe in [&self.incoming, &self.outgoing] {
+            pipe.state.lock().unwrap().closed = true;
+            pipe.readable.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Column, DefaultServerParameters, ErrorResponse, Limits, Response, Router, Value};
+
+    fn parameters() -> DefaultServerParameters {
+        DefaultServerParameters {
+            server_version: "14".to_string(),
+            server_encoding: "UTF8".to_string(),
+            client_encoding: "UTF8".to_string(),
+            application_name: String::new(),
+            default_transaction_read_only: "off".to_string(),
+            in_hot_standby: "off".to_string(),
+            is_superuser: "off".to_string(),
+            session_authorization: "postgres".to_string(),
+            date_style: "ISO, MDY".to_string(),
+            interval_style: "postgres".to_string(),
+            time_zone: "UTC".to_string(),
+            integer_datetimes: "on".to_string(),
+            standard_conforming_strings: "on".to_string(),
+        }
+    }
+
+    fn users() -> Router {
+        Router::new(parameters())
+            .select("users", |request| {
+                let id: i32 = request.capture("id").unwrap_or("0").parse().unwrap_or(0);
+                Ok(Response::rows(
+                    vec![
+                        Column::new("id", Type::INT4),
+                        Column::new("name", Type::TEXT),
+                    ],
+                    vec![vec![Value::Int4(id), Value::Text(format!("user {}", id))]],
+                ))
+            })
+            .delete("users", |_| Ok(Response::command("DELETE 2")))
+    }
+
+    #[test]
+    fn runs_queries_against_a_shim() {
+        let mut harness = Harness::start(users).unwrap();
+        let result = harness
+            .query("SELECT id, name FROM users WHERE id = $1", &["3"])
+            .unwrap();
+        assert_eq!(result.values(), vec![vec![Some("3"), Some("user 3")]]);
+
+        harness
+            .prepare("delete", "DELETE FROM users WHERE id > $1", &[Type::INT4])
+            .unwrap();
+        let result = harness.execute("delete", &["1"]).unwrap();
+        assert!(result.rows.is_empty());
+        assert_eq!(result.tag.as_deref(), Some("DELETE 2"));
+
+        let error = harness
+            .query("SET statement_timeout TO 'soon'", &[])
+            .unwrap_err();
+        assert_eq!(ErrorResponse::of(&error).unwrap().code, "22023");
+        let result = harness.query("SHOW statement_timeout", &[]).unwrap();
+        assert_eq!(result.values(), vec![vec![Some("0")]]);
+        harness.finish().unwrap();
+    }
+
+    #[test]
+    fn runs_simple_queries_against_a_shim() {
+        let mut harness = Harness::start(users).unwrap();
+        let results = harness
+            .simple_query("SELECT id, name FROM users WHERE id = 5")
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].values(), vec![vec![Some("5"), Some("user 5")]]);
+
+        let results = harness.simple_query("DELETE FROM users").unwrap();
+        assert_eq!(results[0].tag.as_deref(), Some("DELETE 2"));
+
+        let error = harness.simple_query("SELECT * FROM orders").unwrap_err();
+        assert_eq!(ErrorResponse::of(&error).unwrap().code, "0A000");
+        let results = harness
+            .simple_query("SET statement_timeout TO 100")
+            .unwrap();
+        assert_eq!(results[0].tag.as_deref(), Some("SET"));
+        harness.finish().unwrap();
+    }
+
+    #[test]
+    fn closed_statements_free_the_limit() {
+        let (client, server) = duplex();
+        let limits = Limits {
+            max_statements: 2,
+            ..Limits::default()
+        };
+        let session = thread::spawn(move || {
+            PostgressIntermediary::new(users(), server)
+                .with_limits(limits)
+                .run()
+        });
+        let mut client = Client::connect(client, StartupMessage::new("postgres"), "").unwrap();
+        let code = |result: Result<_>| {
+            ErrorResponse::of(&result.unwrap_err())
+                .unwrap()
+                .code
+                .clone()
+        };
+
+        // Statements that failed to prepare are not counted.
+        assert_eq!(
+            code(client.prepare("orders", "SELECT * FROM orders", &[])),
+            "0A000"
+        );
+        client
+            .prepare("user", "SELECT * FROM users WHERE id = $1", &[])
+            .unwrap();
+        client
+            .prepare("timeout", "SHOW statement_timeout", &[])
+            .unwrap();
+        assert_eq!(
+            code(client.prepare("users", "SELECT * FROM users", &[])),
+            "54000"
+        );
+
+        client.close("timeout").unwrap();
+        assert_eq!(code(client.execute("timeout", &[]).map(|_| ())), "26000");
+        client.prepare("users", "SELECT * FROM users", &[]).unwrap();
+        client.close("user").unwrap();
+        assert_eq!(code(client.execute("user", &["1"]).map(|_| ())), "26000");
+        let result = client.execute("users", &[]).unwrap();
+        assert_eq!(result.values(), vec![vec![Some("0"), Some("user 0")]]);
+
+        client.terminate().unwrap();
+        session.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn reports_shim_failures() {
+        let mut harness =
+            Harness::start(|| users().update("users", |_| Err(std::io::Error::other("disk full"))))
+                .unwrap();
+        let error = harness.query("SELECT * FROM orders", &[]).unwrap_err();
+        assert_eq!(ErrorResponse::of(&error).unwrap().code, "0A000");
+        let result = harness.query("SELECT * FROM users", &[]).unwrap();
+        assert_eq!(result.values(), vec![vec![Some("0"), Some("user 0")]]);
+
+        // Errors other than an ErrorResponse end the session.
+        let error = harness
+            .query("UPDATE users SET name = 'x'", &[])
+            .unwrap_err();
+        assert!(ErrorResponse::of(&error).is_none());
+        assert!(harness.finish().is_err());
+    }
+}
@@ -0,0 +1,577 @@
+//! The `information_schema` views BI tools use to discover tables, built
+//! from the same metadata as the emulated `pg_catalog`.
+
+use postgres_types::Kind;
+use std::collections::HashMap;
+
+use crate::catalog::{relation, Catalog, TableKind, BUILTIN_TYPES, CATALOG_SCHEMA};
+use crate::evaluate::Relation;
+use crate::{Column, Type, Value};
+
+pub(crate) fn views(catalog: &Catalog) -> HashMap<&'static str, Relation> {
+    HashMap::from([
+        ("schemata", schemata(catalog)),
+        ("tables", tables(catalog)),
+        ("columns", columns(catalog)),
+        ("views", views_view(catalog)),
+    ])
+}
+
+fn text(value: &str) -> Value {
+    Value::Text(value.to_string())
+}
+
+fn yes_or_no(value: bool) -> Value {
+    text(if value { "YES" } else { "NO" })
+}
+
+fn cardinal(value: Option<i32>) -> Value {
+    value.map(Value::Int4).unwrap_or(Value::Null)
+}
+
+fn schemata(catalog: &Catalog) -> Relation {
+    relation(
+        &[
+            "catalog_name",
+            "schema_name",
+            "schema_owner",
+            "default_character_set_catalog",
+            "default_character_set_schema",
+            "default_character_set_name",
+            "sql_path",
+        ],
+        catalog
+            .schemas()
+            .map(|schema| {
+                vec![
+                    text(catalog.database()),
+                    text(schema),
+                    text(catalog.user()),
+                    Value::Null,
+                    Value::Null,
+                    Value::Null,
+                    Value::Null,
+                ]
+            })
+            .collect(),
+    )
+}
+
+fn tables(catalog: &Catalog) -> Relation {
+    relation(
+        &[
+            "table_catalog",
+            "table_schema",
+            "table_name",
+            "table_type",
+            "self_referencing_column_name",
+            "reference_generation",
+            "user_defined_type_catalog",
+            "user_defined_type_schema",
+            "user_defined_type_name",
+            "is_insertable_into",
+            "is_typed",
+            "commit_action",
+        ],
+        catalog
+            .tables()
+            .iter()
+            .map(|table| {
+                vec![
+                    text(catalog.database()),
+                    text(&table.schema),
+                    text(&table.name),
+                    text(match table.kind {
+                        TableKind::Table => "BASE TABLE",
+                        TableKind::View => "VIEW",
+                    }),
+                    Value::Null,
+                    Value::Null,
+                    Value::Null,
+                    Value::Null,
+                    Value::Null,
+                    yes_or_no(table.kind == TableKind::Table),
+                    yes_or_no(false),
+                    Value::Null,
+                ]
+            })
+            .collect(),
+    )
+}
+
+fn views_view(catalog: &Catalog) -> Relation {
+    relation(
+        &[
+            "table_catalog",
+            "table_schema",
+            "table_name",
+            "view_definition",
+            "check_option",
+            "is_updatable",
+            "is_insertable_into",
+            "is_trigger_updatable",
+            "is_trigger_deletable",
+            "is_trigger_insertable_into",
+        ],
+        catalog
+            .tables()
+            .iter()
+            .filter(|table| table.kind == TableKind::View)
+            .map(|table| {
+                vec![
+                    text(catalog.database()),
+                    text(&table.schema),
+                    text(&table.name),
+                    Value::Null,
+                    text("NONE"),
+                    yes_or_no(false),
+                    yes_or_no(false),
+                    yes_or_no(false),
+                    yes_or_no(false),
+                    yes_or_no(false),
+                ]
+            })
+            .collect(),
+    )
+}
+
+fn columns(catalog: &Catalog) -> Relation {
+    relation(
+        &[
+            "table_catalog",
+            "table_schema",
+            "table_name",
+            "column_name",
+            "ordinal_position",
+            "column_default",
+            "is_nullable",
+            "data_type",
+            "character_maximum_length",
+            "character_octet_length",
+            "numeric_precision",
+            "numeric_precision_radix",
+            "numeric_scale",
+            "datetime_precision",
+            "interval_type",
+            "interval_precision",
+            "character_set_catalog",
+            "character_set_schema",
+            "character_set_name",
+            "collation_catalog",
+            "collation_schema",
+            "collation_name",
+            "domain_catalog",
+            "domain_schema",
+            "domain_name",
+            "udt_catalog",
+            "udt_schema",
+            "udt_name",
+            "scope_catalog",
+            "scope_schema",
+            "scope_name",
+            "maximum_cardinality",
+            "dtd_identifier",
+            "is_self_referencing",
+            "is_identity",
+            "identity_generation",
+            "identity_start",
+            "identity_increment",
+            "identity_maximum",
+            "identity_minimum",
+            "identity_cycle",
+            "is_generated",
+            "generation_expression",
+            "is_updatable",
+        ],
+        catalog
+            .tables()
+            .iter()
+            .flat_map(|table| {
+                table.columns.iter().enumerate().map(move |(i, column)| {
+                    let position = column.attribute_number.unwrap_or(i as i16 + 1);
+                    let ty = &column.column_type;
+                    let (domain, ty) = match ty.kind() {
+                        Kind::Domain(base) => (Some(ty), base),
+                        _ => (None, ty),
+                    };
+                    let domain_value = |value: &str| match domain {
+                        Some(_) => text(value),
+                        None => Value::Null,
+                    };
+                    let (precision, radix, scale) = numeric_precision(ty, column);
+                    let length = character_length(ty, column);
+                    vec![
+                        text(catalog.database()),
+                        text(&table.schema),
+                        text(&table.name),
+                        text(&column.name),
+                        Value::Int4(position as i32),
+                        Value::Null,
+                        yes_or_no(column.nullable),
+                        data_type(catalog, ty),
+                        cardinal(length),
+                        cardinal(length.map(|length| length * 4)),
+                        cardinal(precision),
+                        cardinal(radix),
+                        cardinal(scale),
+                        cardinal(datetime_precision(ty)),
+                        Value::Null,
+                        Value::Null,
+                        Value::Null,
+                        Value::Null,
+                        Value::Null,
+                        Value::Null,
+                        Value::Null,
+                        Value::Null,
+                        domain_value(catalog.database()),
+                        domain
+                            .map(|domain| text(domain.schema()))
+                            .unwrap_or(Value::Null),
+                        domain
+                            .map(|domain| text(domain.name()))
+                            .unwrap_or(Value::Null),
+                        text(catalog.database()),
+                        text(udt_schema(ty)),
+                        text(ty.name()),
+                        Value::Null,
+                        Value::Null,
+                        Value::Null,
+                        Value::Null,
+                        text(&position.to_string()),
+                        yes_or_no(false),
+                        yes_or_no(false),
+                        Value::Null,
+                        Value::Null,
+                        Value::Null,
+                        Value::Null,
+                        Value::Null,
+                        yes_or_no(false),
+                        text("NEVER"),
+                        Value::Null,
+                        yes_or_no(table.kind == TableKind::Table),
+                    ]
+                })
+            })
+            .collect(),
+    )
+}
+
+fn udt_schema(ty: &Type) -> &str {
+    if BUILTIN_TYPES.contains(ty) {
+        CATALOG_SCHEMA
+    } else {
+        ty.schema()
+    }
+}
+
+/// The standard spelling of a type, without modifiers.
+fn data_type(catalog: &Catalog, ty: &Type) -> Value {
+    match ty.kind() {
+        Kind::Array(_) => text("ARRAY"),
+        _ if !BUILTIN_TYPES.contains(ty) => text("USER-DEFINED"),
+        _ => text(
+            &catalog
+                .format_type(ty.oid(), -1)
+                .unwrap_or_else(|| ty.name().to_string()),
+        ),
+    }
+}
+
+fn character_length(ty: &Type, column: &Column) -> Option<i32> {
+    match *ty {
+        Type::VARCHAR | Type::BPCHAR if column.type_modifier() >= 4 => {
+            Some(column.type_modifier() - 4)
+        }
+        _ => None,
+    }
+}
+
+fn numeric_precision(ty: &Type, column: &Column) -> (Option<i32>, Option<i32>, Option<i32>) {
+    match *ty {
+        Type::INT2 => (Some(16), Some(2), Some(0)),
+        Type::INT4 => (Some(32), Some(2), Some(0)),
+        Type::INT8 => (Some(64), Some(2), Some(0)),
+        Type::FLOAT4 => (Some(24), Some(2), None),
+        Type::FLOAT8 => (Some(53), Some(2), None),
+        Type::NUMERIC if column.type_modifier() >= 4 => {
+            let modifier = column.type_modifier() - 4;
+            (Some(modifier >> 16), Some(10), Some(modifier & 0xffff))
+        }
+        Type::NUMERIC => (None, Some(10), None),
+        _ => (None, None, None),
+    }
+}
+
+fn datetime_precision(ty: &Type) -> Option<i32> {
+    match *ty {
+        Type::DATE => Some(0),
+        Type::TIME | Type::TIMESTAMP | Type::TIMESTAMPTZ | Type::INTERVAL => Some(6),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtin::SessionState;
+    use crate::catalog::{SchemaDescription, Table};
+    use crate::TypeRegistry;
+
+    struct Shop;
+
+    impl SchemaDescription for Shop {
+        fn tables(&self) -> Vec<Table> {
+            let quantity = Type::new(
+                "quantity".to_string(),
+                90000,
+                Kind::Domain(Type::INT4),
+                "shop".to_string(),
+            );
+            vec![
+                Table::new(16384, "orders").with_columns([
+                    Column::new("id", Type::INT8).not_null(),
+                    Column::new("note", Type::VARCHAR).with_max_length(20),
+                    Column::new("total", Type::NUMERIC).with_precision(10, 2),
+                    Column::new("placed", Type::TIMESTAMPTZ),
+                    Column::new("tags", Type::TEXT_ARRAY),
+                    Column::new("quantity", quantity),
+                ]),
+                Table::new(16390, "big_orders")
+                    .in_schema("reports")
+                    .view()
+                    .with_column(Column::new("id", Type::INT8)),
+            ]
+        }
+
+        fn schemas(&self) -> Vec<String> {
+            vec!["archive".to_string()]
+        }
+    }
+
+    fn view(name: &str) -> Relation {
+        let mut session = SessionState::new();
+        session.user = "alice".to_string();
+        session.database = "shop".to_string();
+        let catalog = Catalog::new(&Shop, &TypeRegistry::new(), &session);
+        views(&catalog).remove(name).unwrap()
+    }
+
+    /// The values of `columns` in each row, as text.
+    fn select(relation: &Relation, columns: &[&str]) -> Vec<Vec<Option<String>>> {
+        let indices: Vec<usize> = columns
+            .iter()
+            .map(|name| relation.columns.iter().position(|c| c == name).unwrap())
+            .collect();
+        relation
+            .rows
+            .iter()
+            .map(|row| {
+                assert_eq!(row.len(), relation.columns.len());
+                indices
+                    .iter()
+                    .map(|&index| crate::evaluate::text(&row[index]))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn row(values: &[Option<&str>]) -> Vec<Option<String>> {
+        values
+            .iter()
+            .map(|value| value.map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn lists_schemata() {
+        assert_eq!(
+            select(
+                &view("schemata"),
+                &["catalog_name", "schema_name", "schema_owner"]
+            ),
+            vec![
+                row(&[Some("shop"), Some("pg_catalog"), Some("alice")]),
+                row(&[Some("shop"), Some("public"), Some("alice")]),
+                row(&[Some("shop"), Some("information_schema"), Some("alice")]),
+                row(&[Some("shop"), Some("archive"), Some("alice")]),
+                row(&[Some("shop"), Some("reports"), Some("alice")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_tables_and_views() {
+        assert_eq!(
+            select(
+                &view("tables"),
+                &[
+                    "table_schema",
+                    "table_name",
+                    "table_type",
+                    "is_insertable_into"
+                ]
+            ),
+            vec![
+                row(&[
+                    Some("public"),
+                    Some("orders"),
+                    Some("BASE TABLE"),
+                    Some("YES")
+                ]),
+                row(&[
+                    Some("reports"),
+                    Some("big_orders"),
+                    Some("VIEW"),
+                    Some("NO")
+                ]),
+            ]
+        );
+        assert_eq!(
+            select(
+                &view("views"),
+                &[
+                    "table_schema",
+                    "table_name",
+                    "view_definition",
+                    "check_option"
+                ]
+            ),
+            vec![row(&[
+                Some("reports"),
+                Some("big_orders"),
+                None,
+                Some("NONE")
+            ])]
+        );
+    }
+
+    #[test]
+    fn describes_columns() {
+        let columns = view("columns");
+        assert_eq!(
+            select(
+                &columns,
+                &[
+                    "table_name",
+                    "column_name",
+                    "ordinal_position",
+                    "is_nullable",
+                    "data_type",
+                    "udt_name",
+                    "is_updatable",
+                ]
+            ),
+            vec![
+                row(&[
+                    Some("orders"),
+                    Some("id"),
+                    Some("1"),
+                    Some("NO"),
+                    Some("bigint"),
+                    Some("int8"),
+                    Some("YES")
+                ]),
+                row(&[
+                    Some("orders"),
+                    Some("note"),
+                    Some("2"),
+                    Some("YES"),
+                    Some("character varying"),
+                    Some("varchar"),
+                    Some("YES")
+                ]),
+                row(&[
+                    Some("orders"),
+                    Some("total"),
+                    Some("3"),
+                    Some("YES"),
+                    Some("numeric"),
+                    Some("numeric"),
+                    Some("YES")
+                ]),
+                row(&[
+                    Some("orders"),
+                    Some("placed"),
+                    Some("4"),
+                    Some("YES"),
+                    Some("timestamp with time zone"),
+                    Some("timestamptz"),
+                    Some("YES")
+                ]),
+                row(&[
+                    Some("orders"),
+                    Some("tags"),
+                    Some("5"),
+                    Some("YES"),
+                    Some("ARRAY"),
+                    Some("_text"),
+                    Some("YES")
+                ]),
+                row(&[
+                    Some("orders"),
+                    Some("quantity"),
+                    Some("6"),
+                    Some("YES"),
+                    Some("integer"),
+                    Some("int4"),
+                    Some("YES")
+                ]),
+                row(&[
+                    Some("big_orders"),
+                    Some("id"),
+                    Some("1"),
+                    Some("YES"),
+                    Some("bigint"),
+                    Some("int8"),
+                    Some("NO")
+                ]),
+            ]
+        );
+        assert_eq!(
+            select(
+                &columns,
+                &[
+                    "character_maximum_length",
+                    "character_octet_length",
+                    "numeric_precision",
+                    "numeric_precision_radix",
+                    "numeric_scale",
+                    "datetime_precision",
+                    "domain_schema",
+                    "domain_name",
+                ]
+            )[..4],
+            [
+                row(&[
+                    None,
+                    None,
+                    Some("64"),
+                    Some("2"),
+                    Some("0"),
+                    None,
+                    None,
+                    None
+                ]),
+                row(&[Some("20"), Some("80"), None, None, None, None, None, None]),
+                row(&[
+                    None,
+                    None,
+                    Some("10"),
+                    Some("10"),
+                    Some("2"),
+                    None,
+                    None,
+                    None
+                ]),
+                row(&[None, None, None, None, None, Some("6"), None, None]),
+            ]
+        );
+        assert_eq!(
+            select(
+                &columns,
+                &["domain_catalog", "domain_schema", "domain_name"]
+            )[5],
+            row(&[Some("shop"), Some("shop"), Some("quantity")])
+        );
+    }
+}
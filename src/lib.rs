@@ -1,22 +1,83 @@
 use bytes::BytesMut;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 pub use postgres_types::{FromSql, Type};
 use postgres_types::{IsNull, ToSql};
 use std::collections::HashMap;
-use std::fmt::Display;
-use std::io::{Read, Result, Write};
+use std::fmt::Write as _;
+use std::io::{ErrorKind, Read, Result, Write};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
 
-use client_message::{ClientMessage, Describe, FormatCode, PasswordMessage, StartupMessage};
-use server_message::{CommandCompleteTag, ServerMessage};
+use client_message::{report_fatal, ProtocolViolation, StartupRequest};
+use protocol::{describe_columns, Prepare, Protocol};
+use timeout::StatementTimeout;
 
+pub use array::{Array, ArrayDimension};
+#[cfg(feature = "tokio")]
+pub use async_intermediary::{AsyncPostgresShim, AsyncPostgressIntermediary};
+pub use backend_message::{BackendMessage, ErrorResponse, FieldDescription};
+pub use catalog::{SchemaDescription, Table, TableKind};
+pub use client::{Client, QueryResult, Row};
+pub use client_message::{
+    ClientMessage, Close, Describe, FormatCode, PasswordMessage, StartupMessage,
+};
+pub use codec::{ClientCodec, FrontendMessage, ServerCodec};
+pub use encoding::ClientEncoding;
+pub use harness::{duplex, DuplexStream, Harness};
+pub use limits::Limits;
+pub use postgres_shim_derive::PgRow;
+#[cfg(feature = "arrow")]
+pub use record_batch::columns_from_schema;
+pub use router::{Request, Response, Router, RouterPortal};
+pub use row::{PgRow, PgType};
+pub use serde_row::{infer_columns, to_row_values, SerializeError};
+pub use server::{PeerAddress, Server, SessionContext, ShutdownHandle, ShutdownMode};
+pub use server_message::{CommandCompleteTag, ServerMessage};
+pub use sqlparser;
+pub use statement::{ParsedStatement, Placeholder};
+pub use timeout::Timeouts;
+pub use types::TypeRegistry;
+pub use value::Value;
+
+mod array;
+#[cfg(feature = "tokio")]
+mod async_intermediary;
+mod backend_message;
+mod builtin;
+mod catalog;
+mod client;
 mod client_message;
+mod codec;
+mod encoding;
+mod evaluate;
+mod harness;
+mod information_schema;
+mod limits;
+mod protocol;
+#[cfg(feature = "arrow")]
+mod record_batch;
+mod router;
+mod row;
+mod serde_row;
+mod server;
 mod server_message;
+mod statement;
+mod timeout;
+mod types;
+mod value;
 
 pub struct PostgressIntermediary<Stream, Shim, PortalData> {
     stream: Stream,
     shim: Shim,
     portals: HashMap<String, Portal<PortalData>>,
+    protocol: Protocol,
+    set_read_timeout: Option<SetReadTimeout<Stream>>,
+    read_timeout: Option<Duration>,
 }
 
+/// Sets or clears the read timeout of a stream.
+pub type SetReadTimeout<Stream> = fn(&Stream, Option<Duration>) -> Result<()>;
+
 pub trait PostgresShim<PortalData> {
     fn prepare(
         &mut self,
@@ -36,6 +97,41 @@ pub trait PostgresShim<PortalData> {
     where
         S: Write;
     fn default_parameters(&mut self) -> DefaultServerParameters;
+    fn type_registry(&mut self) -> TypeRegistry {
+        TypeRegistry::new()
+    }
+    /// Tables the shim exposes. When set, queries that only read `pg_catalog`
+    /// or `information_schema` are answered by the intermediary and never
+    /// reach `prepare`.
+    fn schema_description(&mut self) -> Option<&dyn SchemaDescription> {
+        None
+    }
+    /// Opts into answering the queries drivers send while connecting, such
+    /// as `SELECT version()`, `SHOW transaction_isolation`, JDBC's
+    /// `SET extra_float_digits` and npgsql's type loading query, from the
+    /// default parameters and session state instead of `prepare`.
+    fn driver_compatibility(&mut self) -> bool {
+        false
+    }
+    /// Opts into parsing queries with the Postgres dialect before they are
+    /// prepared. Unparsable SQL is rejected with a 42601 syntax error and
+    /// `prepare_parsed` is called instead of `prepare`.
+    fn parse_queries(&mut self) -> bool {
+        false
+    }
+    /// Prepares a parsed query, only called when `parse_queries` is on.
+    /// Empty queries still go to `prepare`.
+    fn prepare_parsed(
+        &mut self,
+        query_name: String,
+        statement: ParsedStatement,
+        parameter_types: Vec<Type>,
+    ) -> Result<()> {
+        self.prepare(query_name, statement.query, parameter_types)
+    }
+    /// Called when the client closes the statement `query_name`, so the shim
+    /// can free what it prepared. Names the shim never prepared may come up.
+    fn close_statement(&mut self, _query_name: String) {}
 }
 
 pub struct Portal<PortalData> {
@@ -47,15 +143,21 @@ pub struct Portal<PortalData> {
 pub struct ResultWriter<'a, S> {
     stream: &'a mut S,
     result_format_codes: Vec<FormatCode>,
+    encoding: ClientEncoding,
+    /// When the statement timeout cancels the execution.
+    deadline: Option<Instant>,
 }
 
 pub struct RowWriter<'a, S> {
     stream: &'a mut S,
     result_format_codes: Vec<FormatCode>,
-    types: Vec<Type>,
+    columns: Vec<Column>,
+    encoding: ClientEncoding,
     row_count: u32,
+    deadline: Option<Instant>,
 }
 
+#[derive(Debug, Clone)]
 pub struct DefaultServerParameters {
     pub server_version: String,
     pub server_encoding: String,
@@ -72,20 +174,133 @@ pub struct DefaultServerParameters {
     pub standard_conforming_strings: String,
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct Column {
     pub name: String,
     pub column_type: Type,
+    /// OID of the table the column comes from, if it is a plain table column.
+    pub table_oid: Option<u32>,
+    /// Attribute number of the column within `table_oid`.
+    pub attribute_number: Option<i16>,
+    /// Size of the type as in `pg_type.typlen`, derived from the type when unset.
+    pub type_size: Option<i16>,
+    /// Type specific modifier as in `pg_attribute.atttypmod`, -1 when unset.
+    pub type_modifier: Option<i32>,
+    pub nullable: bool,
+}
+
+impl Column {
+    pub fn new(name: impl Into<String>, column_type: Type) -> Self {
+        Self {
+            name: name.into(),
+            column_type,
+            table_oid: None,
+            attribute_number: None,
+            type_size: None,
+            type_modifier: None,
+            nullable: true,
+        }
+    }
+
+    pub fn not_null(mut self) -> Self {
+        self.nullable = false;
+        self
+    }
+
+    pub fn with_table(mut self, table_oid: u32, attribute_number: i16) -> Self {
+        self.table_oid = Some(table_oid);
+        self.attribute_number = Some(attribute_number);
+        self
+    }
+
+    pub fn with_type_size(mut self, type_size: i16) -> Self {
+        self.type_size = Some(type_size);
+        self
+    }
+
+    pub fn with_type_modifier(mut self, type_modifier: i32) -> Self {
+        self.type_modifier = Some(type_modifier);
+        self
+    }
+
+    /// Type modifier for `varchar(n)` and `char(n)` columns.
+    pub fn with_max_length(self, length: i32) -> Self {
+        self.with_type_modifier(length + 4)
+    }
+
+    /// Type modifier for `numeric(precision, scale)` columns.
+    pub fn with_precision(self, precision: i32, scale: i32) -> Self {
+        self.with_type_modifier(((precision << 16) | (scale & 0xffff)) + 4)
+    }
+
+    pub(crate) fn table_oid(&self) -> u32 {
+        self.table_oid.unwrap_or(0)
+    }
+
+    pub(crate) fn attribute_number(&self) -> i16 {
+        self.attribute_number.unwrap_or(0)
+    }
+
+    pub(crate) fn type_size(&self) -> i16 {
+        self.type_size
+            .unwrap_or_else(|| type_size(&self.column_type))
+    }
+
+    pub(crate) fn type_modifier(&self) -> i32 {
+        self.type_modifier.unwrap_or(-1)
+    }
+}
+
+pub(crate) fn is_text_type(ty: &Type) -> bool {
+    match *ty {
+        Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN => true,
+        _ => match ty.kind() {
+            postgres_types::Kind::Enum(_) => true,
+            postgres_types::Kind::Domain(base) => is_text_type(base),
+            _ => false,
+        },
+    }
+}
+
+pub(crate) fn type_size(ty: &Type) -> i16 {
+    match *ty {
+        Type::BOOL | Type::CHAR => 1,
+        Type::INT2 => 2,
+        Type::INT4 | Type::OID | Type::FLOAT4 | Type::DATE | Type::REGPROC | Type::REGTYPE => 4,
+        Type::INT8 | Type::FLOAT8 | Type::TIME | Type::TIMESTAMP | Type::TIMESTAMPTZ => 8,
+        Type::MONEY => 8,
+        Type::TIMETZ => 12,
+        Type::UUID | Type::INTERVAL => 16,
+        Type::NAME => 64,
+        _ => match ty.kind() {
+            postgres_types::Kind::Enum(_) => 4,
+            postgres_types::Kind::Domain(base) => type_size(base),
+            _ => -1,
+        },
+    }
 }
 
 impl<'a, S> ResultWriter<'a, S> {
-    fn new(result_format_codes: Vec<FormatCode>, stream: &'a mut S) -> Self {
+    fn new(
+        result_format_codes: Vec<FormatCode>,
+        stream: &'a mut S,
+        encoding: ClientEncoding,
+    ) -> Self {
         Self {
             result_format_codes,
             stream,
+            encoding,
+            deadline: None,
         }
     }
 
+    /// Makes writing a row or completing the statement fail once `deadline`
+    /// has passed, which the intermediary reports as a cancelled statement.
+    fn with_deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
     pub fn start_writing<'b>(
         self,
         columns: impl IntoIterator<Item = &'b Column>,
@@ -94,63 +309,100 @@ impl<'a, S> ResultWriter<'a, S> {
         &'a mut S: Write,
     {
         let columns: Vec<Column> = columns.into_iter().cloned().collect();
-        let format_codes = format_codes(&columns, self.result_format_codes.clone());
-        Ok(RowWriter::new(
-            format_codes,
-            columns
-                .iter()
-                .map(|column| column.column_type.clone())
-                .collect(),
-            self.stream,
-        ))
+        let format_codes = format_codes(&columns, self.result_format_codes.clone())?;
+        let mut row_writer = RowWriter::new(format_codes, columns, self.stream, self.encoding);
+        row_writer.deadline = self.deadline;
+        Ok(row_writer)
+    }
+
+    /// Starts writing rows of `R` using the columns generated for it.
+    pub fn start_writing_pg_rows<R>(self) -> Result<RowWriter<'a, S>>
+    where
+        R: PgRow,
+        &'a mut S: Write,
+    {
+        self.start_writing(&R::columns())
     }
 
     pub fn empty_result(mut self) -> Result<()>
     where
         &'a mut S: Write,
     {
+        check_deadline(self.deadline)?;
         ServerMessage::EmptyQueryResponse.write(&mut self.stream)?;
         Ok(())
     }
+
+    /// Completes a statement that returns no rows with its command tag, such
+    /// as `INSERT 0 1` or `DELETE 3`.
+    pub fn command_complete(mut self, tag: &str) -> Result<()>
+    where
+        &'a mut S: Write,
+    {
+        check_deadline(self.deadline)?;
+        ServerMessage::CommandComplete(CommandCompleteTag::Other(tag.to_string()))
+            .write(&mut self.stream)?;
+        Ok(())
+    }
+}
+
+/// Fails with a [`StatementTimeout`] once `deadline` has passed.
+fn check_deadline(deadline: Option<Instant>) -> Result<()> {
+    match deadline {
+        Some(deadline) if Instant::now() >= deadline => Err(StatementTimeout::error()),
+        _ => Ok(()),
+    }
 }
 
 fn row_description(
-    columns: &Vec<Column>,
+    columns: &[Column],
     result_format_codes: Vec<FormatCode>,
-) -> Result<ServerMessage> {
+) -> Result<ServerMessage<'static>> {
     Ok(ServerMessage::RowDescription {
         fields: columns
             .iter()
             .zip(result_format_codes.clone())
-            .map(|(column, format_code)| {
-                (column.name.clone(), column.column_type.clone(), format_code)
-            })
+            .map(|(column, format_code)| (column.clone(), format_code))
             .collect(),
     })
 }
 
-fn format_codes(columns: &Vec<Column>, result_format_codes: Vec<FormatCode>) -> Vec<FormatCode> {
+fn format_codes(
+    columns: &[Column],
+    result_format_codes: Vec<FormatCode>,
+) -> Result<Vec<FormatCode>> {
     let format_codes = match result_format_codes.len() {
         0 => vec![FormatCode::Text; columns.len()],
         1 => vec![result_format_codes[0].clone(); columns.len()],
         _ => result_format_codes,
     };
     if format_codes.len() != columns.len() {
-        panic!("Invalid number of columns compared to expected result format codes");
+        return Err(ProtocolViolation::error(format!(
+            "bind message has {} result formats but query has {} columns",
+            format_codes.len(),
+            columns.len()
+        )));
     }
-    format_codes
+    Ok(format_codes)
 }
 
 impl<'a, S> RowWriter<'a, S>
 where
     &'a mut S: Write,
 {
-    fn new(result_format_codes: Vec<FormatCode>, types: Vec<Type>, stream: &'a mut S) -> Self {
+    fn new(
+        result_format_codes: Vec<FormatCode>,
+        columns: Vec<Column>,
+        stream: &'a mut S,
+        encoding: ClientEncoding,
+    ) -> Self {
         Self {
             result_format_codes,
             stream,
-            types,
+            columns,
+            encoding,
             row_count: 0,
+            deadline: None,
         }
     }
 
@@ -159,61 +411,258 @@ where
         I: IntoIterator<Item = E>,
         E: ToSqlValue,
     {
-        let fields: Vec<Option<BytesMut>> = rows
+        let fields = rows
             .into_iter()
             .zip(&self.result_format_codes)
-            .zip(&self.types)
-            .map(|((sql_value, format_code), ty)| match format_code {
-                FormatCode::Binary => sql_value.as_bin_value(ty),
-                FormatCode::Text => sql_value.as_str_value(ty),
+            .zip(&self.columns)
+            .map(|((sql_value, format_code), column)| match format_code {
+                FormatCode::Binary => sql_value.as_bin_value(&column.column_type),
+                FormatCode::Text => sql_value.as_str_value(&column.column_type),
             })
-            .collect();
+            .collect::<Result<Vec<Option<BytesMut>>>>()?;
+        self.write_data_row(fields)
+    }
+
+    /// Converts text to the client encoding and writes the row. Text the
+    /// client encoding cannot represent fails the statement with 22P05
+    /// `untranslatable_character`. SQL_ASCII clients get the bytes as they
+    /// are.
+    fn write_data_row(&mut self, mut fields: Vec<Option<BytesMut>>) -> Result<()> {
+        check_deadline(self.deadline)?;
+        if !matches!(
+            self.encoding,
+            ClientEncoding::Utf8 | ClientEncoding::SqlAscii
+        ) {
+            for ((field, format_code), column) in fields
+                .iter_mut()
+                .zip(&self.result_format_codes)
+                .zip(&self.columns)
+            {
+                let is_text = match format_code {
+                    FormatCode::Text => true,
+                    FormatCode::Binary => is_text_type(&column.column_type),
+                };
+                if let (Some(data), true) = (field.as_mut(), is_text) {
+                    let encoded = self
+                        .encoding
+                        .encode(data)
+                        .map_err(|message| ErrorResponse::error("22P05", message))?;
+                    if let std::borrow::Cow::Owned(encoded) = encoded {
+                        *data = BytesMut::from(&encoded[..]);
+                    }
+                }
+            }
+        }
         ServerMessage::DataRow { fields }.write(&mut self.stream)?;
         self.row_count += 1;
         Ok(())
     }
 
+    /// Writes a struct as a row, matching its fields to the columns by name.
+    pub fn write_pg_row<R>(&mut self, row: &R) -> Result<()>
+    where
+        R: PgRow,
+    {
+        let fields = self.pg_row_fields::<R>()?;
+        let values = row.values();
+        self.write_row(fields.iter().map(|i| values[*i]))
+    }
+
+    /// The field of `R` each column is written from.
+    fn pg_row_fields<R>(&self) -> Result<Vec<usize>>
+    where
+        R: PgRow,
+    {
+        let names: Vec<String> = R::columns().into_iter().map(|column| column.name).collect();
+        self.columns
+            .iter()
+            .map(|column| {
+                names
+                    .iter()
+                    .position(|name| *name == column.name)
+                    .ok_or_else(|| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!("column \"{}\" is not a field of the row", column.name),
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    /// Writes a `Serialize` struct or map as a row, matching its fields to the
+    /// columns by name. Fails with a [`SerializeError`] when a column has no
+    /// field or its field does not convert to the column type.
+    pub fn write_serialized<T>(&mut self, record: &T) -> Result<()>
+    where
+        T: serde::Serialize + ?Sized,
+    {
+        let values = serde_row::values_for_columns(record, &self.columns)?;
+        self.write_row(values)
+    }
+
+    /// Writes every row of an arrow batch. Columns are matched by position
+    /// and the batch is rejected before any row is written unless each arrow
+    /// type maps to its column type, as in [`columns_from_schema`].
+    #[cfg(feature = "arrow")]
+    pub fn write_record_batch(&mut self, batch: &arrow_array::RecordBatch) -> Result<()> {
+        let columns = self.columns.clone();
+        let format_codes = self.result_format_codes.clone();
+        record_batch::write_batch(batch, &columns, &format_codes, |fields| {
+            self.write_data_row(fields)
+        })
+    }
+
     pub fn finish(mut self) -> Result<()> {
-        self.complete_result()?;
+        self.complete_result(CommandCompleteTag::Select {
+            rows: self.row_count,
+        })?;
         Ok(())
     }
 
-    fn complete_result(&mut self) -> Result<()> {
-        ServerMessage::CommandComplete(CommandCompleteTag::Select {
-            rows: self.row_count,
-        })
-        .write(&mut self.stream)?;
+    fn complete_result(&mut self, tag: CommandCompleteTag) -> Result<()> {
+        check_deadline(self.deadline)?;
+        ServerMessage::CommandComplete(tag).write(&mut self.stream)?;
         Ok(())
     }
 }
 
+/// A value that can be written to a row in the text and binary formats.
+/// `Ok(None)` is NULL. Values that cannot be encoded as the column type fail
+/// with 42804 `datatype_mismatch`, see [`ErrorResponse::error`].
 pub trait ToSqlValue: std::fmt::Debug {
-    fn as_bin_value(&self, ty: &Type) -> Option<BytesMut>;
-    fn as_str_value(&self, ty: &Type) -> Option<BytesMut>;
+    fn as_bin_value(&self, ty: &Type) -> Result<Option<BytesMut>>;
+    fn as_str_value(&self, ty: &Type) -> Result<Option<BytesMut>>;
 }
 
-impl<T> ToSqlValue for T
+impl<T> ToSqlValue for Option<T>
 where
-    T: ToSql + Display,
+    T: ToSqlValue,
 {
-    fn as_bin_value(&self, ty: &Type) -> Option<BytesMut> {
-        let mut buffer = BytesMut::new();
-        match self.to_sql(ty, &mut buffer) {
-            Ok(IsNull::Yes) => None,
-            Ok(IsNull::No) => Some(buffer),
-            Err(_) => panic!("Error using type as bin postgres"),
+    fn as_bin_value(&self, ty: &Type) -> Result<Option<BytesMut>> {
+        match self {
+            Some(value) => value.as_bin_value(ty),
+            None => Ok(None),
         }
     }
 
-    fn as_str_value(&self, _: &Type) -> Option<BytesMut> {
-        let self_as_string = self.to_string();
-        match self_as_string.as_str() {
-            "NULL" => None,
-            _ => Some(self_as_string.as_bytes().into()),
+    fn as_str_value(&self, ty: &Type) -> Result<Option<BytesMut>> {
+        match self {
+            Some(value) => value.as_str_value(ty),
+            None => Ok(None),
         }
     }
 }
 
+impl<T> ToSqlValue for &T
+where
+    T: ToSqlValue + ?Sized,
+{
+    fn as_bin_value(&self, ty: &Type) -> Result<Option<BytesMut>> {
+        (**self).as_bin_value(ty)
+    }
+
+    fn as_str_value(&self, ty: &Type) -> Result<Option<BytesMut>> {
+        (**self).as_str_value(ty)
+    }
+}
+
+/// Writes any `ToSql + Display` type, in binary with `ToSql` and in text
+/// with `Display`. Types outside the ones this crate implements
+/// [`ToSqlValue`] for, such as `rust_decimal::Decimal`, can be written
+/// wrapped in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Displayed<T>(pub T);
+
+impl<T> ToSqlValue for Displayed<T>
+where
+    T: ToSql + std::fmt::Display + std::fmt::Debug,
+{
+    fn as_bin_value(&self, ty: &Type) -> Result<Option<BytesMut>> {
+        to_bin_value(&self.0, ty)
+    }
+
+    fn as_str_value(&self, _: &Type) -> Result<Option<BytesMut>> {
+        Ok(Some(BytesMut::from(self.0.to_string().as_bytes())))
+    }
+}
+
+fn to_bin_value<T>(value: &T, ty: &Type) -> Result<Option<BytesMut>>
+where
+    T: ToSql,
+{
+    // Domains are sent as their base type and enums as their label.
+    let ty = match ty.kind() {
+        postgres_types::Kind::Domain(base) => return to_bin_value(value, base),
+        postgres_types::Kind::Enum(_) => &Type::TEXT,
+        _ => ty,
+    };
+    let mut buffer = BytesMut::new();
+    match value.to_sql_checked(ty, &mut buffer) {
+        Ok(IsNull::Yes) => Ok(None),
+        Ok(IsNull::No) => Ok(Some(buffer)),
+        Err(error) => Err(ErrorResponse::error("42804", error.to_string())),
+    }
+}
+
+macro_rules! to_sql_value_impl {
+    ($ty:ty, |$value:ident| $text:expr) => {
+        impl ToSqlValue for $ty {
+            fn as_bin_value(&self, ty: &Type) -> Result<Option<BytesMut>> {
+                to_bin_value(&self, ty)
+            }
+
+            fn as_str_value(&self, _: &Type) -> Result<Option<BytesMut>> {
+                let $value = self;
+                Ok(Some(BytesMut::from($text.as_bytes())))
+            }
+        }
+    };
+    ($($ty:ty),*) => {
+        $(to_sql_value_impl!($ty, |value| value.to_string());)*
+    };
+}
+
+to_sql_value_impl!(i16, i32, i64, u32, String);
+to_sql_value_impl!(i8, |value| char_to_string(*value as u8));
+to_sql_value_impl!(str, |value| value);
+to_sql_value_impl!(bool, |value| if *value { "t" } else { "f" });
+to_sql_value_impl!(f32, |value| float_to_string(value.to_string()));
+to_sql_value_impl!(f64, |value| float_to_string(value.to_string()));
+to_sql_value_impl!([u8], |value| bytea_to_string(value));
+to_sql_value_impl!(Vec<u8>, |value| bytea_to_string(value));
+to_sql_value_impl!(NaiveDate, NaiveTime, NaiveDateTime, Uuid, serde_json::Value);
+to_sql_value_impl!(DateTime<Utc>, |value| value
+    .format("%Y-%m-%d %H:%M:%S%.f+00")
+    .to_string());
+
+fn float_to_string(value: String) -> String {
+    match value.as_str() {
+        "inf" => "Infinity".to_string(),
+        "-inf" => "-Infinity".to_string(),
+        _ => value,
+    }
+}
+
+/// `i8` is a `"char"`, whose text form is the byte itself, with bytes
+/// outside ASCII escaped in octal as postgres does.
+fn char_to_string(value: u8) -> String {
+    match value {
+        0 => String::new(),
+        1..=127 => (value as char).to_string(),
+        _ => format!("\\{:03o}", value),
+    }
+}
+
+fn bytea_to_string(value: &[u8]) -> String {
+    let mut result = String::with_capacity(2 + value.len() * 2);
+    result.push_str("\\x");
+    for byte in value {
+        let _ = write!(result, "{:02x}", byte);
+    }
+    result
+}
+
 impl<PortalData> Portal<PortalData> {
     pub fn new(portal_data: PortalData, result_format_codes: Vec<FormatCode>) -> Self {
         Self {
@@ -232,10 +681,11 @@ impl<PortalData> Portal<PortalData> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ParameterValue {
     Text(String),
     Binary(Vec<u8>),
+    Null,
 }
 
 impl<Stream, Shim, PortalData> PostgressIntermediary<Stream, Shim, PortalData> {
@@ -244,24 +694,115 @@ impl<Stream, Shim, PortalData> PostgressIntermediary<Stream, Shim, PortalData> {
             shim,
             stream,
             portals: HashMap::new(),
+            protocol: Protocol::new(),
+            set_read_timeout: None,
+            read_timeout: None,
         }
     }
 
+    /// Bounds what the client may send, see [`Limits`].
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.protocol.limits = limits;
+        self
+    }
+
+    /// Sets the default session timeouts. Idle timeouts also need
+    /// [`with_read_timeout`](Self::with_read_timeout).
+    ///
+    /// The statement timeout is cooperative here: it is only checked when the
+    /// shim writes a row or completes the statement, which then fails and
+    /// the statement is cancelled with 57014. A shim is not interrupted
+    /// while it runs. The async intermediary of the `tokio` feature drops
+    /// the execution future instead.
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.protocol.timeouts = timeouts;
+        self
+    }
+
+    /// How to bound waiting for the client, such as
+    /// `TcpStream::set_read_timeout`, which the idle timeouts rely on.
+    pub fn with_read_timeout(mut self, set_read_timeout: SetReadTimeout<Stream>) -> Self {
+        self.set_read_timeout = Some(set_read_timeout);
+        self
+    }
+
     pub fn run(mut self) -> std::io::Result<()>
     where
         Stream: Read + Write,
         Shim: PostgresShim<PortalData>,
     {
-        self.init()?;
+        match authenticate(&mut self.stream)? {
+            Some(startup_message) => self.serve(&startup_message),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs the session of a client that already went through
+    /// [`authenticate`].
+    pub(crate) fn serve(mut self, startup_message: &StartupMessage) -> std::io::Result<()>
+    where
+        Stream: Read + Write,
+        Shim: PostgresShim<PortalData>,
+    {
+        let result = self.session(startup_message);
+        if let Err(error) = &result {
+            let _ = report_fatal(error, &mut self.stream);
+        }
+        result
+    }
+
+    fn session(&mut self, startup_message: &StartupMessage) -> std::io::Result<()>
+    where
+        Stream: Read + Write,
+        Shim: PostgresShim<PortalData>,
+    {
+        self.init(startup_message)?;
         loop {
-            match ClientMessage::from_stream(&mut self.stream)? {
+            let idle_timeout = self.protocol.take_idle_timeout();
+            if let Some(set_read_timeout) = self.set_read_timeout {
+                let read_timeout = idle_timeout.map(|timeout| timeout.duration);
+                if read_timeout != self.read_timeout {
+                    set_read_timeout(&self.stream, read_timeout)?;
+                    self.read_timeout = read_timeout;
+                }
+            }
+            let message = match ClientMessage::from_stream_with_limits(
+                &mut self.stream,
+                &self.protocol.limits,
+            ) {
+                Ok(message) => message,
+                Err(error)
+                    if idle_timeout.is_some()
+                        && matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    return idle_timeout.unwrap().terminate(&mut self.stream);
+                }
+                Err(error) => return Err(error),
+            };
+            if self.protocol.skips(&message) {
+                continue;
+            }
+            match message {
                 ClientMessage::Parse {
                     name,
                     query,
-                    parameters_types,
+                    parameter_type_oids,
                 } => {
-                    self.shim.prepare(name, query, parameters_types)?;
-                    ServerMessage::ParseComplete.write(&mut self.stream)?;
+                    let prepare = self.protocol.parse(
+                        &mut self.stream,
+                        self.shim.schema_description(),
+                        name,
+                        query,
+                        parameter_type_oids,
+                    )?;
+                    if let Some(prepare) = prepare {
+                        let name = prepare.name().to_string();
+                        let result = self.prepare(prepare);
+                        if self.protocol.recover(&mut self.stream, result)?.is_some() {
+                            self.protocol.prepared(&name);
+                            ServerMessage::ParseComplete.write(&mut self.stream)?;
+                        }
+                    }
                 }
                 ClientMessage::Bind {
                     portal,
@@ -270,86 +811,94 @@ impl<Stream, Shim, PortalData> PostgressIntermediary<Stream, Shim, PortalData> {
                     parameters,
                     result_format_codes,
                 } => {
-                    let parameters = match parameter_format_codes.len() {
-                        0 => parameters
-                            .into_iter()
-                            .map(|data| {
-                                ParameterValue::Text(String::from_utf8_lossy(&data).to_string())
-                            })
-                            .collect(),
-                        1 => parameters
-                            .into_iter()
-                            .map(|data| match parameter_format_codes[0] {
-                                FormatCode::Text => {
-                                    ParameterValue::Text(String::from_utf8_lossy(&data).to_string())
-                                }
-                                FormatCode::Binary => ParameterValue::Binary(data),
-                            })
-                            .collect(),
-                        _ => parameters
-                            .into_iter()
-                            .zip(parameter_format_codes)
-                            .map(|(data, format_code)| match format_code {
-                                FormatCode::Text => {
-                                    ParameterValue::Text(String::from_utf8_lossy(&data).to_string())
-                                }
-                                FormatCode::Binary => ParameterValue::Binary(data),
-                            })
-                            .collect(),
-                    };
-                    self.portals.insert(
+                    let bind = self.protocol.bind(
+                        &mut self.stream,
+                        self.shim.schema_description(),
                         portal,
-                        Portal::new(self.shim.bind(name, parameters)?, result_format_codes),
-                    );
-                    ServerMessage::BindComplete.write(&mut self.stream)?;
+                        name,
+                        parameter_format_codes,
+                        parameters,
+                        result_format_codes,
+                    )?;
+                    if let Some(bind) = bind {
+                        let data = self.shim.bind(bind.name, bind.parameters);
+                        if let Some(data) = self.protocol.recover(&mut self.stream, data)? {
+                            self.protocol.bound(&bind.portal);
+                            self.portals
+                                .insert(bind.portal, Portal::new(data, bind.result_format_codes));
+                            ServerMessage::BindComplete.write(&mut self.stream)?;
+                        }
+                    }
                 }
                 ClientMessage::Execute { portal, max_rows } => match self.portals.remove(&portal) {
-                    Some(portal) => {
-                        let format_codes = portal.result_format_codes.clone();
-                        let (data, columns) = portal.data();
-                        self.shim.execute(
-                            data,
-                            max_rows,
-                            columns,
-                            ResultWriter::new(format_codes, &mut self.stream),
-                        )?
-                    }
-                    None => {
-                        ServerMessage::ErrorResponse {
-                            code: 'S' as u8,
-                            message: "Portal not found".to_string(),
+                    Some(shim_portal) => {
+                        self.protocol.close_portal(&portal);
+                        let format_codes = shim_portal.result_format_codes.clone();
+                        let (data, columns) = shim_portal.data();
+                        let result_writer = ResultWriter::new(
+                            format_codes,
+                            &mut self.stream,
+                            self.protocol.session.client_encoding,
+                        )
+                        .with_deadline(self.protocol.statement_deadline());
+                        let result = self.shim.execute(data, max_rows, columns, result_writer);
+                        if self.protocol.recover(&mut self.stream, result)?.is_some() {
+                            self.protocol.executed(&portal);
                         }
-                        .write(&mut self.stream)?;
                     }
+                    None => self.protocol.execute(&mut self.stream, &portal)?,
                 },
                 ClientMessage::Query { query } => {
-                    println!("{}", query)
+                    let query = self.protocol.simple_query(
+                        &mut self.stream,
+                        self.shim.schema_description(),
+                        query,
+                    )?;
+                    if let Some(prepare) = query {
+                        self.simple_query(prepare)?;
+                        self.protocol.sync(&mut self.stream)?;
+                    }
                 }
                 ClientMessage::Describe(describe) => match describe {
                     Describe::Portal { name } => {
-                        let portal = self.portals.get_mut(&name).unwrap();
-                        match self.shim.describe(&portal.portal_data)? {
-                            None => ServerMessage::NoData.write(&mut self.stream)?,
-                            Some(columns) => {
-                                row_description(
-                                    &columns,
-                                    format_codes(&columns, portal.result_format_codes.clone()),
-                                )?
-                                .write(&mut self.stream)?;
-                                portal.add_columns(Some(columns));
+                        if !self
+                            .protocol
+                            .describe_builtin_portal(&mut self.stream, &name)?
+                        {
+                            match self.portals.get_mut(&name) {
+                                Some(portal) => {
+                                    let columns = self.shim.describe(&portal.portal_data);
+                                    if let Some(columns) =
+                                        self.protocol.recover(&mut self.stream, columns)?
+                                    {
+                                        describe_columns(
+                                            &mut self.stream,
+                                            columns.as_deref(),
+                                            portal.result_format_codes.clone(),
+                                        )?;
+                                        portal.add_columns(columns);
+                                    }
+                                }
+                                None => self.protocol.unknown_portal(&mut self.stream, &name)?,
                             }
                         }
                     }
-                    Describe::Statement { name: _ } => {
-                        todo!("We need to handle statement describe")
-                    }
+                    Describe::Statement { name } => self.protocol.describe_statement(
+                        &mut self.stream,
+                        self.shim.schema_description(),
+                        &name,
+                    )?,
                 },
-                ClientMessage::Sync => {
-                    ServerMessage::ReadyForQuery {
-                        transaction_status: 'I' as u8,
+                ClientMessage::Close(close) => {
+                    match &close {
+                        Close::Statement { name } => self.shim.close_statement(name.clone()),
+                        Close::Portal { name } => {
+                            self.portals.remove(name);
+                        }
                     }
-                    .write(&mut self.stream)?;
+                    self.protocol.close(&mut self.stream, &close)?;
                 }
+                ClientMessage::Sync => self.protocol.sync(&mut self.stream)?,
                 ClientMessage::Terminate => {
                     return Ok(());
                 }
@@ -358,101 +907,654 @@ impl<Stream, Shim, PortalData> PostgressIntermediary<Stream, Shim, PortalData> {
         }
     }
 
-    fn init(&mut self) -> std::io::Result<()>
+    fn prepare(&mut self, prepare: Prepare) -> std::io::Result<()>
+    where
+        Shim: PostgresShim<PortalData>,
+    {
+        match prepare {
+            Prepare::Text {
+                name,
+                query,
+                parameter_types,
+            } => self.shim.prepare(name, query, parameter_types),
+            Prepare::Parsed {
+                name,
+                statement,
+                parameter_types,
+            } => self.shim.prepare_parsed(name, *statement, parameter_types),
+        }
+    }
+
+    /// Runs a simple query through the shim as the unnamed statement and
+    /// portal: Parse, Bind, Describe and Execute with text results.
+    fn simple_query(&mut self, prepare: Prepare) -> std::io::Result<()>
     where
         Stream: Read + Write,
         Shim: PostgresShim<PortalData>,
     {
-        let _ = StartupMessage::from_stream(&mut self.stream)?;
-        ServerMessage::AuthenticationCleartextPassword.write(&mut self.stream)?;
-        self.stream.flush()?;
-        let _ = PasswordMessage::from_stream(&mut self.stream)?;
-        ServerMessage::AuthenticationOk.write(&mut self.stream)?;
+        self.portals.remove("");
+        self.protocol.close_portal("");
+        let prepared = self.prepare(prepare);
+        if self.protocol.recover(&mut self.stream, prepared)?.is_none() {
+            return Ok(());
+        }
+        let data = self.shim.bind(String::new(), vec![]);
+        let Some(data) = self.protocol.recover(&mut self.stream, data)? else {
+            return Ok(());
+        };
+        let columns = self.shim.describe(&data);
+        let Some(columns) = self.protocol.recover(&mut self.stream, columns)? else {
+            return Ok(());
+        };
+        if let Some(columns) = &columns {
+            describe_columns(&mut self.stream, Some(columns), vec![])?;
+        }
+        let result_writer = ResultWriter::new(
+            vec![],
+            &mut self.stream,
+            self.protocol.session.client_encoding,
+        )
+        .with_deadline(self.protocol.statement_deadline());
+        let result = self.shim.execute(data, 0, columns, result_writer);
+        if self.protocol.recover(&mut self.stream, result)?.is_some() {
+            self.protocol.executed("");
+        }
+        Ok(())
+    }
+
+    fn init(&mut self, startup_message: &StartupMessage) -> std::io::Result<()>
+    where
+        Stream: Read + Write,
+        Shim: PostgresShim<PortalData>,
+    {
+        self.protocol.types = self.shim.type_registry();
+        self.protocol.driver_compatibility = self.shim.driver_compatibility();
+        self.protocol.parse_queries = self.shim.parse_queries();
         let default_parameters = self.shim.default_parameters();
-        ServerMessage::ParameterStatus {
-            name: "server_version",
-            value: &default_parameters.server_version,
-        }
-        .write(&mut self.stream)?;
-        ServerMessage::ParameterStatus {
-            name: "server_encoding",
-            value: &default_parameters.server_encoding,
-        }
-        .write(&mut self.stream)?;
-        ServerMessage::ParameterStatus {
-            name: "client_encoding",
-            value: &default_parameters.client_encoding,
-        }
-        .write(&mut self.stream)?;
-        ServerMessage::ParameterStatus {
-            name: "application_name",
-            value: &default_parameters.application_name,
-        }
-        .write(&mut self.stream)?;
-        ServerMessage::ParameterStatus {
-            name: "default_transaction_read_only",
-            value: &default_parameters.default_transaction_read_only,
-        }
-        .write(&mut self.stream)?;
-        ServerMessage::ParameterStatus {
-            name: "in_hot_standby",
-            value: &default_parameters.in_hot_standby,
-        }
-        .write(&mut self.stream)?;
-        ServerMessage::ParameterStatus {
-            name: "server_version",
-            value: &default_parameters.server_version,
-        }
-        .write(&mut self.stream)?;
-        ServerMessage::ParameterStatus {
-            name: "is_superuser",
-            value: &default_parameters.is_superuser,
-        }
-        .write(&mut self.stream)?;
-        ServerMessage::ParameterStatus {
-            name: "DateStyle",
-            value: &default_parameters.date_style,
-        }
-        .write(&mut self.stream)?;
-        ServerMessage::ParameterStatus {
-            name: "IntervalStyle",
-            value: &default_parameters.interval_style,
-        }
-        .write(&mut self.stream)?;
-        ServerMessage::ParameterStatus {
-            name: "TimeZone",
-            value: &default_parameters.time_zone,
-        }
-        .write(&mut self.stream)?;
-        ServerMessage::ParameterStatus {
-            name: "integer_datetimes",
-            value: &default_parameters.integer_datetimes,
-        }
-        .write(&mut self.stream)?;
-        ServerMessage::ParameterStatus {
-            name: "standard_conforming_strings",
-            value: &default_parameters.standard_conforming_strings,
-        }
-        .write(&mut self.stream)?;
-        ServerMessage::BackendKeyData {
-            process_id: 0,
-            secret_key: 0,
-        }
-        .write(&mut self.stream)?;
-        ServerMessage::ReadyForQuery {
-            transaction_status: 'I' as u8,
-        }
-        .write(&mut self.stream)?;
+        self.protocol
+            .startup(&mut self.stream, startup_message, &default_parameters)?;
         self.stream.flush()?;
         Ok(())
     }
 }
 
+/// Reads the startup message and asks for a password, which is accepted
+/// whatever it is. Returns `None` for a CancelRequest, which ends the
+/// connection.
+pub(crate) fn authenticate(stream: &mut (impl Read + Write)) -> Result<Option<StartupMessage>> {
+    let result = (|| {
+        let Some(startup_message) = read_startup(stream)? else {
+            return Ok(None);
+        };
+        ServerMessage::AuthenticationCleartextPassword.write(stream)?;
+        stream.flush()?;
+        let _ = PasswordMessage::from_stream(stream)?;
+        Ok(Some(startup_message))
+    })();
+    if let Err(error) = &result {
+        let _ = report_fatal(error, stream);
+    }
+    result
+}
+
+/// Reads the startup message, declining the encryption clients ask for
+/// first by default so they go on without it. Returns `None` for a
+/// CancelRequest, as there are no queries to cancel.
+pub(crate) fn read_startup(stream: &mut (impl Read + Write)) -> Result<Option<StartupMessage>> {
+    loop {
+        match StartupRequest::from_stream(stream)? {
+            StartupRequest::Startup(startup_message) => return Ok(Some(startup_message)),
+            StartupRequest::Encryption => {
+                stream.write_all(b"N")?;
+                stream.flush()?;
+            }
+            StartupRequest::Cancel => return Ok(None),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn it_works() {
         let result = 2 + 2;
         assert_eq!(result, 4);
     }
+
+    #[test]
+    fn none_is_null_in_both_formats() {
+        let value: Option<i32> = None;
+        assert_eq!(value.as_str_value(&Type::INT4).unwrap(), None);
+        assert_eq!(value.as_bin_value(&Type::INT4).unwrap(), None);
+    }
+
+    #[test]
+    fn some_delegates_to_inner_value() {
+        let value = Some(42i32);
+        assert_eq!(
+            value.as_str_value(&Type::INT4).unwrap(),
+            Some(BytesMut::from(&b"42"[..]))
+        );
+        assert_eq!(
+            value.as_bin_value(&Type::INT4).unwrap(),
+            Some(BytesMut::from(&42i32.to_be_bytes()[..]))
+        );
+    }
+
+    #[test]
+    fn null_string_is_plain_text() {
+        assert_eq!(
+            "NULL".as_str_value(&Type::TEXT).unwrap(),
+            Some(BytesMut::from(&b"NULL"[..]))
+        );
+    }
+
+    #[test]
+    fn text_format_uses_postgres_representation() {
+        assert_eq!(
+            true.as_str_value(&Type::BOOL).unwrap(),
+            Some(BytesMut::from(&b"t"[..]))
+        );
+        assert_eq!(
+            f64::INFINITY.as_str_value(&Type::FLOAT8).unwrap(),
+            Some(BytesMut::from(&b"Infinity"[..]))
+        );
+        assert_eq!(
+            vec![0xdeu8, 0xad].as_str_value(&Type::BYTEA).unwrap(),
+            Some(BytesMut::from(&b"\\xdead"[..]))
+        );
+        assert_eq!(
+            65i8.as_str_value(&Type::CHAR).unwrap(),
+            Some(BytesMut::from(&b"A"[..]))
+        );
+        assert_eq!(
+            65i8.as_bin_value(&Type::CHAR).unwrap(),
+            Some(BytesMut::from(&b"A"[..]))
+        );
+        assert_eq!(
+            (-56i8).as_str_value(&Type::CHAR).unwrap(),
+            Some(BytesMut::from(&b"\\310"[..]))
+        );
+    }
+
+    #[derive(Debug)]
+    struct Untransmittable;
+
+    impl std::fmt::Display for Untransmittable {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("untransmittable")
+        }
+    }
+
+    impl ToSql for Untransmittable {
+        fn to_sql(
+            &self,
+            _: &Type,
+            _: &mut BytesMut,
+        ) -> std::result::Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+            Err("value too large to transmit".into())
+        }
+
+        postgres_types::accepts!(TEXT);
+        postgres_types::to_sql_checked!();
+    }
+
+    #[test]
+    fn displayed_writes_to_sql_types() {
+        assert_eq!(
+            Displayed(Untransmittable)
+                .as_str_value(&Type::TEXT)
+                .unwrap(),
+            Some(BytesMut::from(&b"untransmittable"[..]))
+        );
+        let error = Displayed(Untransmittable)
+            .as_bin_value(&Type::TEXT)
+            .unwrap_err();
+        assert_eq!(ErrorResponse::of(&error).unwrap().code, "42804");
+
+        let mut stream = Vec::new();
+        let columns = vec![Column::new("a", Type::TEXT)];
+        let mut row_writer =
+            ResultWriter::new(vec![FormatCode::Binary], &mut stream, ClientEncoding::Utf8)
+                .start_writing(&columns)
+                .unwrap();
+        assert!(row_writer.write_row([Displayed(Untransmittable)]).is_err());
+        assert!(stream.is_empty());
+    }
+
+    #[test]
+    fn binary_values_check_the_column_type() {
+        let code = |result: Result<Option<BytesMut>>| {
+            ErrorResponse::of(&result.unwrap_err())
+                .unwrap()
+                .code
+                .clone()
+        };
+        assert_eq!(code(42i32.as_bin_value(&Type::TEXT)), "42804");
+        assert_eq!(code(42i32.as_bin_value(&Type::INT8)), "42804");
+        assert_eq!(code("42".as_bin_value(&Type::INT4)), "42804");
+        assert_eq!(
+            42i32.as_bin_value(&Type::INT4).unwrap(),
+            Some(BytesMut::from(&[0, 0, 0, 42][..]))
+        );
+
+        let mut types = TypeRegistry::new();
+        let mood = types.register_enum("mood", 90001, ["sad", "happy"]);
+        let id = types.register_domain("id", 90002, Type::INT4);
+        assert_eq!(
+            "happy".as_bin_value(&mood).unwrap(),
+            Some(BytesMut::from(&b"happy"[..]))
+        );
+        assert_eq!(code(42i32.as_bin_value(&mood)), "42804");
+        assert_eq!(
+            42i32.as_bin_value(&id).unwrap(),
+            Some(BytesMut::from(&[0, 0, 0, 42][..]))
+        );
+    }
+
+    #[derive(Debug)]
+    struct Pair {
+        id: i32,
+        name: &'static str,
+    }
+
+    impl PgRow for Pair {
+        fn columns() -> Vec<Column> {
+            vec![
+                Column::new("id", Type::INT4),
+                Column::new("name", Type::TEXT),
+            ]
+        }
+
+        fn values(&self) -> Vec<&dyn ToSqlValue> {
+            vec![&self.id, &self.name]
+        }
+    }
+
+    #[test]
+    fn write_pg_row_matches_columns_by_name() {
+        let mut stream = Vec::new();
+        let columns = vec![
+            Column::new("name", Type::TEXT),
+            Column::new("id", Type::INT4),
+        ];
+        let mut row_writer = ResultWriter::new(vec![], &mut stream, ClientEncoding::Utf8)
+            .start_writing(&columns)
+            .unwrap();
+        for (id, name) in [(1, "a"), (2, "b")] {
+            row_writer.write_pg_row(&Pair { id, name }).unwrap();
+        }
+        row_writer.finish().unwrap();
+        assert_eq!(
+            &stream[..34],
+            &[
+                b'D', 0, 0, 0, 16, 0, 2, 0, 0, 0, 1, b'a', 0, 0, 0, 1, b'1', //
+                b'D', 0, 0, 0, 16, 0, 2, 0, 0, 0, 1, b'b', 0, 0, 0, 1, b'2',
+            ][..]
+        );
+
+        // Rows of another type, borrowing their fields, are matched by their
+        // own columns.
+        #[derive(Debug)]
+        struct Renamed<'a> {
+            id: i32,
+            name: &'a str,
+        }
+        impl PgRow for Renamed<'_> {
+            fn columns() -> Vec<Column> {
+                vec![
+                    Column::new("name", Type::TEXT),
+                    Column::new("id", Type::INT4),
+                ]
+            }
+
+            fn values(&self) -> Vec<&dyn ToSqlValue> {
+                vec![&self.name, &self.id]
+            }
+        }
+        let mut stream = Vec::new();
+        let mut row_writer = ResultWriter::new(vec![], &mut stream, ClientEncoding::Utf8)
+            .start_writing(&columns)
+            .unwrap();
+        row_writer.write_pg_row(&Pair { id: 1, name: "a" }).unwrap();
+        let name = String::from("b");
+        row_writer
+            .write_pg_row(&Renamed { id: 2, name: &name })
+            .unwrap();
+        assert_eq!(
+            &stream[17..34],
+            &[b'D', 0, 0, 0, 16, 0, 2, 0, 0, 0, 1, b'b', 0, 0, 0, 1, b'2'][..]
+        );
+
+        let mut stream = Vec::new();
+        let columns = vec![Column::new("email", Type::TEXT)];
+        let mut row_writer = ResultWriter::new(vec![], &mut stream, ClientEncoding::Utf8)
+            .start_writing(&columns)
+            .unwrap();
+        assert!(row_writer.write_pg_row(&Pair { id: 1, name: "a" }).is_err());
+    }
+
+    #[test]
+    fn write_row_mixes_null_and_values() {
+        let mut stream = Vec::new();
+        let columns = vec![Column::new("a", Type::TEXT), Column::new("b", Type::TEXT)];
+        let mut row_writer = ResultWriter::new(vec![], &mut stream, ClientEncoding::Utf8)
+            .start_writing(&columns)
+            .unwrap();
+        row_writer.write_row([Some("x"), None]).unwrap();
+        row_writer.finish().unwrap();
+        assert_eq!(
+            &stream[..17],
+            &[b'D', 0, 0, 0, 15, 0, 2, 0, 0, 0, 1, b'x', 255, 255, 255, 255, b'C'][..]
+        );
+    }
+
+    #[test]
+    fn write_row_converts_text_to_client_encoding() {
+        let mut stream = Vec::new();
+        let columns = vec![Column::new("a", Type::TEXT)];
+        let mut row_writer = ResultWriter::new(vec![], &mut stream, ClientEncoding::Latin1)
+            .start_writing(&columns)
+            .unwrap();
+        row_writer.write_row(["é"]).unwrap();
+        let error = row_writer.write_row(["€"]).unwrap_err();
+        assert_eq!(ErrorResponse::of(&error).unwrap().code, "22P05");
+        assert_eq!(
+            &stream[..],
+            &[b'D', 0, 0, 0, 11, 0, 1, 0, 0, 0, 1, 0xe9][..]
+        );
+    }
+
+    /// Sleeps in `execute`, then writes a row if the query reads rows.
+    struct Slow;
+
+    impl PostgresShim<bool> for Slow {
+        fn prepare(&mut self, _: String, _: String, _: Vec<Type>) -> Result<()> {
+            Ok(())
+        }
+
+        fn bind(&mut self, name: String, _: Vec<ParameterValue>) -> Result<bool> {
+            Ok(name == "rows")
+        }
+
+        fn describe(&mut self, rows: &bool) -> Result<Option<Vec<Column>>> {
+            Ok(rows.then(|| vec![Column::new("id", Type::INT4)]))
+        }
+
+        fn execute<'a, S>(
+            &mut self,
+            rows: bool,
+            _: u32,
+            columns: Option<Vec<Column>>,
+            result_writer: ResultWriter<'a, S>,
+        ) -> Result<()>
+        where
+            S: Write,
+        {
+            std::thread::sleep(Duration::from_millis(50));
+            if !rows {
+                return result_writer.command_complete("UPDATE 1");
+            }
+            let mut row_writer = result_writer.start_writing(&columns.unwrap_or_default())?;
+            row_writer.write_row([Value::Int4(1)])?;
+            row_writer.finish()
+        }
+
+        fn default_parameters(&mut self) -> DefaultServerParameters {
+            DefaultServerParameters {
+                server_version: "14".to_string(),
+                server_encoding: "UTF8".to_string(),
+                client_encoding: "UTF8".to_string(),
+                application_name: String::new(),
+                default_transaction_read_only: "off".to_string(),
+                in_hot_standby: "off".to_string(),
+                is_superuser: "off".to_string(),
+                session_authorization: "postgres".to_string(),
+                date_style: "ISO, MDY".to_string(),
+                interval_style: "postgres".to_string(),
+                time_zone: "UTC".to_string(),
+                integer_datetimes: "on".to_string(),
+                standard_conforming_strings: "on".to_string(),
+            }
+        }
+    }
+
+    #[test]
+    fn statement_timeout_is_checked_when_results_are_written() {
+        let mut harness = Harness::start(|| Slow).unwrap();
+        harness.query("SET statement_timeout TO 10", &[]).unwrap();
+
+        // Completing without rows is checked as well.
+        harness
+            .prepare("command", "UPDATE jobs SET done = true", &[])
+            .unwrap();
+        let error = harness.execute("command", &[]).unwrap_err();
+        assert_eq!(ErrorResponse::of(&error).unwrap().code, "57014");
+
+        harness.prepare("rows", "SELECT id FROM jobs", &[]).unwrap();
+        let error = harness.execute("rows", &[]).unwrap_err();
+        assert_eq!(ErrorResponse::of(&error).unwrap().code, "57014");
+        harness.finish().unwrap();
+    }
+
+    #[test]
+    fn untranslatable_characters_fail_the_statement() {
+        let mut startup_message = StartupMessage::new("postgres");
+        startup_message
+            .parameters
+            .insert("client_encoding".to_string(), "LATIN1".to_string());
+        let mut harness = Harness::start_as(startup_message, || {
+            let parameters = DefaultServerParameters {
+                server_version: "14".to_string(),
+                server_encoding: "UTF8".to_string(),
+                client_encoding: "UTF8".to_string(),
+                application_name: String::new(),
+                default_transaction_read_only: "off".to_string(),
+                in_hot_standby: "off".to_string(),
+                is_superuser: "off".to_string(),
+                session_authorization: "postgres".to_string(),
+                date_style: "ISO, MDY".to_string(),
+                interval_style: "postgres".to_string(),
+                time_zone: "UTC".to_string(),
+                integer_datetimes: "on".to_string(),
+                standard_conforming_strings: "on".to_string(),
+            };
+            Router::new(parameters)
+                .select("prices", |_| {
+                    Ok(Response::rows(
+                        vec![Column::new("price", Type::TEXT)],
+                        vec![vec![Value::from("5 €")]],
+                    ))
+                })
+                .select("currencies", |_| {
+                    Ok(Response::rows(
+                        vec![Column::new("name", Type::TEXT)],
+                        vec![vec![Value::from("café")]],
+                    ))
+                })
+        })
+        .unwrap();
+        let error = harness.query("SELECT price FROM prices", &[]).unwrap_err();
+        assert_eq!(ErrorResponse::of(&error).unwrap().code, "22P05");
+        let result = harness.query("SELECT name FROM currencies", &[]).unwrap();
+        assert_eq!(result.rows[0].fields, vec![Some(b"caf\xe9".to_vec())]);
+        harness.finish().unwrap();
+    }
+
+    fn parameters() -> DefaultServerParameters {
+        DefaultServerParameters {
+            server_version: "14".to_string(),
+            server_encoding: "UTF8".to_string(),
+            client_encoding: "UTF8".to_string(),
+            application_name: String::new(),
+            default_transaction_read_only: "off".to_string(),
+            in_hot_standby: "off".to_string(),
+            is_superuser: "off".to_string(),
+            session_authorization: "postgres".to_string(),
+            date_style: "ISO, MDY".to_string(),
+            interval_style: "postgres".to_string(),
+            time_zone: "UTC".to_string(),
+            integer_datetimes: "on".to_string(),
+            standard_conforming_strings: "on".to_string(),
+        }
+    }
+
+    #[test]
+    fn binary_text_parameters_are_decoded_from_client_encoding() {
+        let (mut stream, server) = duplex();
+        let session = std::thread::spawn(move || {
+            let router = Router::new(parameters()).select("users", |request| {
+                Ok(Response::rows(
+                    vec![Column::new("name", Type::TEXT)],
+                    vec![vec![Value::from(
+                        request.capture("name").unwrap_or_default(),
+                    )]],
+                ))
+            });
+            PostgressIntermediary::new(router, server).run()
+        });
+        let mut startup_message = StartupMessage::new("postgres");
+        startup_message
+            .parameters
+            .insert("client_encoding".to_string(), "LATIN1".to_string());
+        Client::connect(&mut stream, startup_message, "").unwrap();
+
+        let mut messages = Vec::new();
+        for message in [
+            ClientMessage::Parse {
+                name: String::new(),
+                query: b"SELECT name FROM users WHERE name = $1".to_vec(),
+                parameter_type_oids: vec![Type::VARCHAR.oid()],
+            },
+            ClientMessage::Bind {
+                portal: String::new(),
+                name: String::new(),
+                parameter_format_codes: vec![FormatCode::Binary],
+                parameters: vec![Some(b"caf\xe9".to_vec())],
+                result_format_codes: vec![],
+            },
+            ClientMessage::Describe(Describe::Portal {
+                name: String::new(),
+            }),
+            ClientMessage::Execute {
+                portal: String::new(),
+                max_rows: 0,
+            },
+            ClientMessage::Sync,
+            ClientMessage::Terminate,
+        ] {
+            message.write(&mut messages).unwrap();
+        }
+        stream.write_all(&messages).unwrap();
+        let mut rows = vec![];
+        loop {
+            match BackendMessage::from_stream(&mut stream).unwrap() {
+                BackendMessage::DataRow { fields } => rows.push(fields),
+                BackendMessage::ErrorResponse(error) => panic!("{}", error.message),
+                BackendMessage::ReadyForQuery { .. } => break,
+                _ => {}
+            }
+        }
+        assert_eq!(rows, vec![vec![Some(b"caf\xe9".to_vec())]]);
+        session.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn describes_builtin_statements() {
+        let (mut stream, server) = duplex();
+        let session = std::thread::spawn(move || {
+            let router = Router::new(parameters()).with_driver_compatibility();
+            PostgressIntermediary::new(router, server).run()
+        });
+        Client::connect(&mut stream, StartupMessage::new("postgres"), "").unwrap();
+
+        let mut messages = Vec::new();
+        for (name, query) in [
+            ("version", "SELECT version()"),
+            ("type", "SELECT typname FROM pg_type WHERE oid = $1"),
+            ("set", "SET extra_float_digits = 3"),
+        ] {
+            ClientMessage::Parse {
+                name: name.to_string(),
+                query: query.as_bytes().to_vec(),
+                parameter_type_oids: vec![],
+            }
+            .write(&mut messages)
+            .unwrap();
+            ClientMessage::Describe(Describe::Statement {
+                name: name.to_string(),
+            })
+            .write(&mut messages)
+            .unwrap();
+        }
+        ClientMessage::Sync.write(&mut messages).unwrap();
+        ClientMessage::Terminate.write(&mut messages).unwrap();
+        stream.write_all(&messages).unwrap();
+        let mut replies = vec![];
+        loop {
+            match BackendMessage::from_stream(&mut stream).unwrap() {
+                BackendMessage::ReadyForQuery { .. } => break,
+                message => replies.push(message),
+            }
+        }
+        let described = replies
+            .chunks(3)
+            .map(|replies| match replies {
+                [BackendMessage::ParseComplete, BackendMessage::ParameterDescription { type_oids }, columns] => {
+                    let columns = match columns {
+                        BackendMessage::RowDescription { fields } => Some(fields.clone()),
+                        BackendMessage::NoData => None,
+                        message => panic!("unexpected message {:?}", message),
+                    };
+                    (type_oids.clone(), columns)
+                }
+                replies => panic!("unexpected replies {:?}", replies),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(described.len(), 3);
+        let (parameters, columns) = &described[0];
+        assert!(parameters.is_empty());
+        let columns = columns.as_ref().unwrap();
+        assert_eq!(columns[0].name, "version");
+        assert_eq!(columns[0].type_oid, Type::TEXT.oid());
+        let (parameters, columns) = &described[1];
+        assert_eq!(parameters, &[Type::TEXT.oid()]);
+        assert_eq!(columns.as_ref().unwrap()[0].name, "typname");
+        let (parameters, columns) = &described[2];
+        assert!(parameters.is_empty() && columns.is_none());
+        session.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn type_registry_resolves_custom_oids() {
+        let mut types = TypeRegistry::new();
+        let mood = types.register_enum("mood", 100_000, ["sad", "ok", "happy"]);
+        types.register_domain("positive_int", 100_001, Type::INT4);
+        assert_eq!(types.from_oid(0), Some(Type::UNKNOWN));
+        assert_eq!(types.from_oid(Type::INT4.oid()), Some(Type::INT4));
+        assert_eq!(types.from_oid(100_000), Some(mood));
+        assert_eq!(types.from_name("positive_int").unwrap().oid(), 100_001);
+        assert_eq!(types.from_oid(100_002), None);
+    }
+
+    #[test]
+    fn row_description_encodes_column_metadata() {
+        let columns = vec![Column::new("name", Type::VARCHAR)
+            .with_table(16384, 2)
+            .with_max_length(20)];
+        let mut stream = Vec::new();
+        row_description(&columns, format_codes(&columns, vec![]).unwrap())
+            .unwrap()
+            .write(&mut stream)
+            .unwrap();
+        let field = &stream[7..];
+        assert_eq!(&field[..5], b"name\0");
+        assert_eq!(&field[5..9], &16384u32.to_be_bytes());
+        assert_eq!(&field[9..11], &2u16.to_be_bytes());
+        assert_eq!(&field[11..15], &Type::VARCHAR.oid().to_be_bytes());
+        assert_eq!(&field[15..17], &(-1i16).to_be_bytes());
+        assert_eq!(&field[17..21], &24i32.to_be_bytes());
+    }
 }
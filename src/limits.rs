@@ -0,0 +1,64 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+/// Bounds on what a client may send, checked before anything is allocated
+/// for it. Oversized messages end the session with FATAL 54000
+/// `program_limit_exceeded`, since the rest of the message is never read.
+/// Too many statements or portals fail the message with ERROR 54000.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Length of a message, including its length field.
+    pub max_message_size: usize,
+    /// Parameters of a Bind and parameter types of a Parse.
+    pub max_parameters: usize,
+    /// Length of a single parameter value.
+    pub max_parameter_size: usize,
+    /// Length of the query of a Query or Parse.
+    pub max_query_length: usize,
+    /// Prepared statements open at once, the unnamed one included.
+    pub max_statements: usize,
+    /// Portals open at once, the unnamed one included.
+    pub max_portals: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_message_size: 64 << 20,
+            max_parameters: u16::MAX as usize,
+            max_parameter_size: 32 << 20,
+            max_query_length: 16 << 20,
+            max_statements: 1000,
+            max_portals: 1000,
+        }
+    }
+}
+
+/// Returned by the decoders when a message exceeds the [`Limits`].
+#[derive(Debug)]
+pub(crate) struct LimitExceeded(String);
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+impl LimitExceeded {
+    pub fn error(message: impl Into<String>) -> Error {
+        Error::new(ErrorKind::InvalidData, LimitExceeded(message.into()))
+    }
+
+    /// Fails unless `length` is at most `limit`.
+    pub fn check(what: &str, length: usize, limit: usize) -> std::io::Result<()> {
+        match length <= limit {
+            true => Ok(()),
+            false => Err(Self::error(format!(
+                "{} of {} exceeds the limit of {}",
+                what, length, limit
+            ))),
+        }
+    }
+}
@@ -0,0 +1,766 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::time::{Duration, Instant};
+
+use crate::builtin::{
+    self, BuiltinOptions, BuiltinPortal, BuiltinResult, BuiltinStatement, SessionAction,
+    SessionState,
+};
+use crate::catalog::{Catalog, NoTables};
+use crate::client_message::{ClientMessage, Close, FormatCode, StartupMessage};
+use crate::limits::Limits;
+use crate::server_message::ServerMessage;
+use crate::statement::{self, ParsedStatement, SyntaxError};
+use crate::timeout::{
+    IdleTimeout, StatementTimeout, Timeouts, IDLE_IN_TRANSACTION_SESSION_TIMEOUT,
+    IDLE_SESSION_TIMEOUT, STATEMENT_TIMEOUT,
+};
+use crate::{
+    format_codes, row_description, ClientEncoding, Column, DefaultServerParameters, ErrorResponse,
+    ParameterValue, ResultWriter, SchemaDescription, Type, TypeRegistry,
+};
+
+/// The part of a session that does not depend on how the shim is called:
+/// builtin statements, session state and error recovery. The blocking and
+/// the async intermediary drive it and only call the shim for what it
+/// returns.
+pub(crate) struct Protocol {
+    builtin_statements: HashMap<String, BuiltinStatement>,
+    builtin_portals: HashMap<String, BuiltinPortal>,
+    pub session: SessionState,
+    pub types: TypeRegistry,
+    ignore_till_sync: bool,
+    pub driver_compatibility: bool,
+    pub parse_queries: bool,
+    pub timeouts: Timeouts,
+    pub limits: Limits,
+    /// Names of the statements and portals open in the session, builtin or
+    /// not, counted against the limits. Names are added once Parse or Bind
+    /// succeeded and removed by Close.
+    statements: HashSet<String>,
+    portals: HashSet<String>,
+    transaction: TransactionStatus,
+    /// Whether ReadyForQuery was sent and no message has been read since.
+    ready: bool,
+    /// Transaction commands among the statements and portals of the shim.
+    statement_commands: HashMap<String, TransactionCommand>,
+    portal_commands: HashMap<String, TransactionCommand>,
+    /// Parameter types the statements were parsed with, to decode binary
+    /// text parameters from the client encoding.
+    statement_parameter_types: HashMap<String, Vec<Type>>,
+}
+
+/// The transaction status reported in ReadyForQuery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TransactionStatus {
+    Idle,
+    InBlock,
+    Failed,
+}
+
+/// A statement that starts or ends a transaction block, which the
+/// intermediary follows to report the transaction status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TransactionCommand {
+    Begin,
+    End,
+}
+
+/// A statement the shim has to prepare.
+pub(crate) enum Prepare {
+    Text {
+        name: String,
+        query: String,
+        parameter_types: Vec<Type>,
+    },
+    Parsed {
+        name: String,
+        statement: Box<ParsedStatement>,
+        parameter_types: Vec<Type>,
+    },
+}
+
+/// A portal the shim has to bind.
+pub(crate) struct Bind {
+    pub portal: String,
+    pub name: String,
+    pub parameters: Vec<ParameterValue>,
+    pub result_format_codes: Vec<FormatCode>,
+}
+
+impl Prepare {
+    pub fn name(&self) -> &str {
+        match self {
+            Prepare::Text { name, .. } | Prepare::Parsed { name, .. } => name,
+        }
+    }
+}
+
+impl Protocol {
+    pub fn new() -> Self {
+        Protocol {
+            builtin_statements: HashMap::new(),
+            builtin_portals: HashMap::new(),
+            session: SessionState::new(),
+            types: TypeRegistry::new(),
+            ignore_till_sync: false,
+            driver_compatibility: false,
+            parse_queries: false,
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            statements: HashSet::new(),
+            portals: HashSet::new(),
+            transaction: TransactionStatus::Idle,
+            ready: false,
+            statement_commands: HashMap::new(),
+            portal_commands: HashMap::new(),
+            statement_parameter_types: HashMap::new(),
+        }
+    }
+
+    /// Whether `message` is skipped because an earlier extended query
+    /// message failed and the client has not sent Sync yet.
+    pub fn skips(&self, message: &ClientMessage) -> bool {
+        self.ignore_till_sync && !matches!(message, ClientMessage::Sync | ClientMessage::Terminate)
+    }
+
+    /// Sets up the session once the client is authenticated and reports the
+    /// server parameters.
+    pub fn startup(
+        &mut self,
+        out: &mut impl Write,
+        startup_message: &StartupMessage,
+        default_parameters: &DefaultServerParameters,
+    ) -> Result<()> {
+        ServerMessage::AuthenticationOk.write(out)?;
+        self.session.user = startup_message.user.clone();
+        self.session.database = startup_message
+            .database
+            .clone()
+            .unwrap_or_else(|| startup_message.user.clone());
+        self.session.reset_settings(default_parameters);
+        for (name, value) in self.timeouts.settings() {
+            self.session.set_default(name, value);
+        }
+        self.session.default_client_encoding =
+            ClientEncoding::from_name(&default_parameters.client_encoding)
+                .unwrap_or(ClientEncoding::Utf8);
+        self.session.client_encoding = match startup_message.parameters.get("client_encoding") {
+            None => self.session.default_client_encoding,
+            Some(name) => match ClientEncoding::from_name(name) {
+                Some(encoding) => encoding,
+                None => {
+                    ServerMessage::ErrorResponse {
+                        severity: "FATAL",
+                        code: "22023",
+                        message: format!(
+                            "invalid value for parameter \"client_encoding\": \"{}\"",
+                            name
+                        ),
+                        position: None,
+                    }
+                    .write(out)?;
+                    out.flush()?;
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Unsupported client_encoding {}", name),
+                    ));
+                }
+            },
+        };
+        for (name, value) in [
+            ("server_version", default_parameters.server_version.as_str()),
+            ("server_encoding", &default_parameters.server_encoding),
+            ("client_encoding", self.session.client_encoding.name()),
+            ("application_name", &default_parameters.application_name),
+            (
+                "default_transaction_read_only",
+                &default_parameters.default_transaction_read_only,
+            ),
+            ("in_hot_standby", &default_parameters.in_hot_standby),
+            ("server_version", &default_parameters.server_version),
+            ("is_superuser", &default_parameters.is_superuser),
+            ("DateStyle", &default_parameters.date_style),
+            ("IntervalStyle", &default_parameters.interval_style),
+            ("TimeZone", &default_parameters.time_zone),
+            ("integer_datetimes", &default_parameters.integer_datetimes),
+            (
+                "standard_conforming_strings",
+                &default_parameters.standard_conforming_strings,
+            ),
+        ] {
+            ServerMessage::ParameterStatus { name, value }.write(out)?;
+        }
+        ServerMessage::BackendKeyData {
+            process_id: 0,
+            secret_key: 0,
+        }
+        .write(out)?;
+        self.ready_for_query(out)
+    }
+
+    /// Handles Parse. Builtin statements are answered here, anything else is
+    /// returned for the shim to prepare, after which the caller writes
+    /// ParseComplete.
+    pub fn parse(
+        &mut self,
+        out: &mut impl Write,
+        description: Option<&dyn SchemaDescription>,
+        name: String,
+        query: Vec<u8>,
+        parameter_type_oids: Vec<u32>,
+    ) -> Result<Option<Prepare>> {
+        if !self.statements.contains(&name) && self.statements.len() >= self.limits.max_statements {
+            self.error(
+                out,
+                "54000",
+                format!(
+                    "too many prepared statements, the limit is {}",
+                    self.limits.max_statements
+                ),
+            )?;
+            return Ok(None);
+        }
+        let query = match self.session.client_encoding.decode(&query) {
+            Ok(query) => query,
+            Err(message) => {
+                self.error(out, "22021", message)?;
+                return Ok(None);
+            }
+        };
+        let parameter_types = parameter_type_oids
+            .into_iter()
+            .map(|oid| self.types.from_oid(oid).ok_or(oid))
+            .collect::<std::result::Result<Vec<Type>, u32>>();
+        let parameter_types = match parameter_types {
+            Ok(parameter_types) => parameter_types,
+            Err(oid) => {
+                self.error(
+                    out,
+                    "42704",
+                    format!("type with OID {} does not exist", oid),
+                )?;
+                return Ok(None);
+            }
+        };
+        let options = self.builtin_options(description);
+        if let Some(statement) = BuiltinStatement::from_query(&query, &parameter_types, options) {
+            self.statements.insert(name.clone());
+            self.statement_parameter_types
+                .insert(name.clone(), statement.parameter_types().to_vec());
+            self.builtin_statements.insert(name, statement);
+            ServerMessage::ParseComplete.write(out)?;
+            return Ok(None);
+        }
+        match self.shim_statement(name, query, parameter_types) {
+            Ok(prepare) => Ok(Some(prepare)),
+            Err(error) => {
+                self.syntax_error(out, error)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// The statement the shim prepares for `query`, parsed when
+    /// `parse_queries` is on.
+    fn shim_statement(
+        &mut self,
+        name: String,
+        query: String,
+        parameter_types: Vec<Type>,
+    ) -> std::result::Result<Prepare, SyntaxError> {
+        self.builtin_statements.remove(&name);
+        match transaction_command(&query) {
+            Some(command) => self.statement_commands.insert(name.clone(), command),
+            None => self.statement_commands.remove(&name),
+        };
+        self.statement_parameter_types
+            .insert(name.clone(), parameter_types.clone());
+        if !self.parse_queries {
+            return Ok(Prepare::Text {
+                name,
+                query,
+                parameter_types,
+            });
+        }
+        Ok(match statement::parse(&query)? {
+            Some(statement) => Prepare::Parsed {
+                name,
+                statement: Box::new(statement),
+                parameter_types,
+            },
+            None => Prepare::Text {
+                name,
+                query,
+                parameter_types,
+            },
+        })
+    }
+
+    /// Handles Bind. Builtin portals are bound here, anything else is
+    /// returned for the shim to bind, after which the caller writes
+    /// BindComplete.
+    #[allow(clippy::too_many_arguments)]
+    pub fn bind(
+        &mut self,
+        out: &mut impl Write,
+        description: Option<&dyn SchemaDescription>,
+        portal: String,
+        name: String,
+        parameter_format_codes: Vec<FormatCode>,
+        parameters: Vec<Option<Vec<u8>>>,
+        result_format_codes: Vec<FormatCode>,
+    ) -> Result<Option<Bind>> {
+        if !self.portals.contains(&portal) && self.portals.len() >= self.limits.max_portals {
+            self.error(
+                out,
+                "54000",
+                format!("too many portals, the limit is {}", self.limits.max_portals),
+            )?;
+            return Ok(None);
+        }
+        let parameter_format_codes = match parameter_format_codes.len() {
+            0 => vec![FormatCode::Text; parameters.len()],
+            1 => vec![parameter_format_codes[0].clone(); parameters.len()],
+            count if count == parameters.len() => parameter_format_codes,
+            count => {
+                self.error(
+                    out,
+                    "08P01",
+                    format!(
+                        "bind message has {} parameter formats but {} parameters",
+                        count,
+                        parameters.len()
+                    ),
+                )?;
+                return Ok(None);
+            }
+        };
+        let encoding = self.session.client_encoding;
+        let parameter_types = self
+            .statement_parameter_types
+            .get(&name)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let parameters = parameters
+            .into_iter()
+            .zip(parameter_format_codes)
+            .enumerate()
+            .map(|(i, (data, format_code))| match (data, format_code) {
+                (None, _) => Ok(ParameterValue::Null),
+                (Some(data), FormatCode::Text) => encoding.decode(&data).map(ParameterValue::Text),
+                // The binary format of text types is the text itself.
+                (Some(data), FormatCode::Binary) if parameter_types.get(i).is_some_and(is_text) => {
+                    encoding
+                        .decode(&data)
+                        .map(|text| ParameterValue::Binary(text.into_bytes()))
+                }
+                (Some(data), FormatCode::Binary) => Ok(ParameterValue::Binary(data)),
+            })
+            .collect::<std::result::Result<Vec<ParameterValue>, String>>();
+        let parameters = match parameters {
+            Ok(parameters) => parameters,
+            Err(message) => {
+                self.error(out, "22021", message)?;
+                return Ok(None);
+            }
+        };
+        if let Some(statement) = self.builtin_statements.get(&name) {
+            let catalog = self.catalog(description);
+            match statement.bind(&self.session, catalog.as_ref(), parameters) {
+                Ok(result) => {
+                    self.portals.insert(portal.clone());
+                    self.builtin_portals.insert(
+                        portal,
+                        BuiltinPortal {
+                            result,
+                            result_format_codes,
+                        },
+                    );
+                    ServerMessage::BindComplete.write(out)?;
+                }
+                Err((code, message)) => self.error(out, code, message)?,
+            }
+            return Ok(None);
+        }
+        self.builtin_portals.remove(&portal);
+        match self.statement_commands.get(&name) {
+            Some(command) => self.portal_commands.insert(portal.clone(), *command),
+            None => self.portal_commands.remove(&portal),
+        };
+        Ok(Some(Bind {
+            portal,
+            name,
+            parameters,
+            result_format_codes,
+        }))
+    }
+
+    /// Executes a portal the shim does not know, either a builtin one or one
+    /// that does not exist.
+    pub fn execute(&mut self, out: &mut impl Write, portal: &str) -> Result<()> {
+        self.portals.remove(portal);
+        match self.builtin_portals.remove(portal) {
+            Some(portal) => self.execute_builtin(out, portal.result, portal.result_format_codes),
+            None => self.unknown_portal(out, portal),
+        }
+    }
+
+    pub fn unknown_portal(&mut self, out: &mut impl Write, portal: &str) -> Result<()> {
+        self.error(
+            out,
+            "34000",
+            format!("portal \"{}\" does not exist", portal),
+        )
+    }
+
+    /// Handles Describe of a statement. Builtin statements are described
+    /// here. Shims describe portals only, so describing one of their
+    /// statements is an error rather than a guess at its parameters.
+    pub fn describe_statement(
+        &mut self,
+        out: &mut impl Write,
+        description: Option<&dyn SchemaDescription>,
+        name: &str,
+    ) -> Result<()> {
+        let statement = match self.builtin_statements.get(name) {
+            Some(statement) => statement,
+            None => {
+                return self.error(
+                    out,
+                    "0A000",
+                    "describing prepared statements is not supported".to_string(),
+                )
+            }
+        };
+        let catalog = self.catalog(description);
+        match statement.describe(&self.session, catalog.as_ref()) {
+            Ok(columns) => {
+                ServerMessage::ParameterDescription {
+                    types: statement.parameter_types().to_vec(),
+                }
+                .write(out)?;
+                describe_columns(out, columns.as_deref(), vec![])
+            }
+            Err((code, message)) => self.error(out, code, message),
+        }
+    }
+
+    /// When a shim execution started now has to be cancelled.
+    pub fn statement_deadline(&self) -> Option<Instant> {
+        Some(Instant::now() + self.statement_timeout()?)
+    }
+
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        self.session.timeout(STATEMENT_TIMEOUT)
+    }
+
+    /// Counts a statement the shim prepared.
+    pub fn prepared(&mut self, name: &str) {
+        self.statements.insert(name.to_string());
+    }
+
+    /// Counts a portal the shim bound.
+    pub fn bound(&mut self, portal: &str) {
+        self.portals.insert(portal.to_string());
+    }
+
+    /// Forgets a portal of the shim, which is dropped once executed.
+    pub fn close_portal(&mut self, portal: &str) {
+        self.portals.remove(portal);
+    }
+
+    /// Handles Close, for builtin statements and portals as well as those of
+    /// the shim, which the caller drops. Closing a name that is not open is
+    /// not an error.
+    pub fn close(&mut self, out: &mut impl Write, close: &Close) -> Result<()> {
+        match close {
+            Close::Statement { name } => {
+                self.statements.remove(name);
+                self.builtin_statements.remove(name);
+                self.statement_commands.remove(name);
+                self.statement_parameter_types.remove(name);
+            }
+            Close::Portal { name } => {
+                self.portals.remove(name);
+                self.builtin_portals.remove(name);
+                self.portal_commands.remove(name);
+            }
+        }
+        ServerMessage::CloseComplete.write(out)
+    }
+
+    /// Follows the transaction status after the shim executed `portal`.
+    pub fn executed(&mut self, portal: &str) {
+        match self.portal_commands.get(portal) {
+            Some(TransactionCommand::Begin) if self.transaction == TransactionStatus::Idle => {
+                self.transaction = TransactionStatus::InBlock
+            }
+            Some(TransactionCommand::End) => self.transaction = TransactionStatus::Idle,
+            _ => {}
+        }
+    }
+
+    /// Passes on what a shim call returned. Statement timeouts and
+    /// [`ErrorResponse`] errors fail the message and are reported to the
+    /// client as `None`, any other error ends the session.
+    pub fn recover<T>(&mut self, out: &mut impl Write, result: Result<T>) -> Result<Option<T>> {
+        let error = match result {
+            Ok(value) => return Ok(Some(value)),
+            Err(error) => error,
+        };
+        if StatementTimeout::is(&error) {
+            self.error(out, "57014", StatementTimeout.to_string())?;
+            return Ok(None);
+        }
+        match ErrorResponse::of(&error) {
+            Some(response) => {
+                self.error(out, &response.code, response.message.clone())?;
+                Ok(None)
+            }
+            None => Err(error),
+        }
+    }
+
+    /// How long to wait for the next message before ending the session.
+    /// Only applies right after ReadyForQuery, so call it once before each
+    /// message is read.
+    pub fn take_idle_timeout(&mut self) -> Option<IdleTimeout> {
+        if !std::mem::take(&mut self.ready) {
+            return None;
+        }
+        let in_transaction = self.transaction != TransactionStatus::Idle;
+        let name = match in_transaction {
+            true => IDLE_IN_TRANSACTION_SESSION_TIMEOUT,
+            false => IDLE_SESSION_TIMEOUT,
+        };
+        Some(IdleTimeout::new(
+            self.session.timeout(name)?,
+            in_transaction,
+        ))
+    }
+
+    /// Describes a builtin portal, `false` if `name` is not one.
+    pub fn describe_builtin_portal(&mut self, out: &mut impl Write, name: &str) -> Result<bool> {
+        let portal = match self.builtin_portals.get(name) {
+            Some(portal) => portal,
+            None => return Ok(false),
+        };
+        describe_columns(
+            out,
+            portal.result.columns.as_deref(),
+            portal.result_format_codes.clone(),
+        )?;
+        Ok(true)
+    }
+
+    /// Handles a simple query. Builtin statements are answered here, anything
+    /// else is returned for the shim to run as the unnamed statement and
+    /// portal, after which the caller finishes with [`Protocol::sync`].
+    pub fn simple_query(
+        &mut self,
+        out: &mut impl Write,
+        description: Option<&dyn SchemaDescription>,
+        query: Vec<u8>,
+    ) -> Result<Option<Prepare>> {
+        let query = match self.session.client_encoding.decode(&query) {
+            Ok(query) => query,
+            Err(message) => {
+                self.error_response(out, "22021", message)?;
+                self.ready_for_query(out)?;
+                return Ok(None);
+            }
+        };
+        let options = self.builtin_options(description);
+        let statements = match BuiltinStatement::from_simple_query(&query, options) {
+            Some(statements) => statements,
+            None => {
+                return match self.shim_statement(String::new(), query, vec![]) {
+                    Ok(prepare) => {
+                        self.builtin_portals.remove("");
+                        match self.statement_commands.get("") {
+                            Some(command) => self.portal_commands.insert(String::new(), *command),
+                            None => self.portal_commands.remove(""),
+                        };
+                        Ok(Some(prepare))
+                    }
+                    Err(error) => {
+                        self.syntax_error(out, error)?;
+                        self.sync(out)?;
+                        Ok(None)
+                    }
+                };
+            }
+        };
+        for statement in statements {
+            let catalog = self.catalog(description);
+            match statement.bind(&self.session, catalog.as_ref(), vec![]) {
+                Ok(result) => {
+                    if let Some(columns) = &result.columns {
+                        row_description(columns, format_codes(columns, vec![])?)?.write(out)?;
+                    }
+                    self.execute_builtin(out, result, vec![])?;
+                }
+                Err((code, message)) => {
+                    self.error_response(out, code, message)?;
+                    break;
+                }
+            }
+        }
+        self.ready_for_query(out)?;
+        Ok(None)
+    }
+
+    pub fn sync(&mut self, out: &mut impl Write) -> Result<()> {
+        self.ignore_till_sync = false;
+        self.ready_for_query(out)
+    }
+
+    /// Reports an error to the client and skips the rest of the extended
+    /// query messages until the next Sync, as postgres does.
+    pub fn error(&mut self, out: &mut impl Write, code: &str, message: String) -> Result<()> {
+        self.error_response(out, code, message)?;
+        self.ignore_till_sync = true;
+        Ok(())
+    }
+
+    fn error_response(&mut self, out: &mut impl Write, code: &str, message: String) -> Result<()> {
+        if self.transaction == TransactionStatus::InBlock {
+            self.transaction = TransactionStatus::Failed;
+        }
+        ServerMessage::ErrorResponse {
+            severity: "ERROR",
+            code,
+            message,
+            position: None,
+        }
+        .write(out)
+    }
+
+    fn syntax_error(&mut self, out: &mut impl Write, error: SyntaxError) -> Result<()> {
+        if self.transaction == TransactionStatus::InBlock {
+            self.transaction = TransactionStatus::Failed;
+        }
+        ServerMessage::ErrorResponse {
+            severity: "ERROR",
+            code: "42601",
+            message: error.message,
+            position: error.position,
+        }
+        .write(out)?;
+        self.ignore_till_sync = true;
+        Ok(())
+    }
+
+    fn ready_for_query(&mut self, out: &mut impl Write) -> Result<()> {
+        self.ready = true;
+        ServerMessage::ReadyForQuery {
+            transaction_status: match self.transaction {
+                TransactionStatus::Idle => b'I',
+                TransactionStatus::InBlock => b'T',
+                TransactionStatus::Failed => b'E',
+            },
+        }
+        .write(out)
+    }
+
+    /// Writes the rows of a builtin statement and applies its session change.
+    fn execute_builtin(
+        &mut self,
+        out: &mut impl Write,
+        result: BuiltinResult,
+        result_format_codes: Vec<FormatCode>,
+    ) -> Result<()> {
+        match result.columns {
+            Some(columns) => {
+                let mut row_writer =
+                    ResultWriter::new(result_format_codes, out, self.session.client_encoding)
+                        .start_writing(&columns)?;
+                for row in result.rows {
+                    row_writer.write_row(row)?;
+                }
+                row_writer.complete_result(result.tag)?;
+            }
+            None => ServerMessage::CommandComplete(result.tag).write(out)?,
+        }
+        match result.action {
+            Some(SessionAction::SetClientEncoding(encoding)) => {
+                self.session.client_encoding = encoding;
+                ServerMessage::ParameterStatus {
+                    name: "client_encoding",
+                    value: encoding.name(),
+                }
+                .write(out)?;
+            }
+            Some(SessionAction::Set { name, value }) => {
+                if let Some(reported) = builtin::reported_name(&name) {
+                    ServerMessage::ParameterStatus {
+                        name: reported,
+                        value: &value,
+                    }
+                    .write(out)?;
+                }
+                self.session.settings.insert(name, value);
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn builtin_options(&self, description: Option<&dyn SchemaDescription>) -> BuiltinOptions {
+        BuiltinOptions {
+            catalog: self.driver_compatibility || description.is_some(),
+            bootstrap: self.driver_compatibility,
+        }
+    }
+
+    /// The catalogs builtin queries read, `None` when the shim neither
+    /// describes its schema nor answers bootstrap queries.
+    fn catalog(&self, description: Option<&dyn SchemaDescription>) -> Option<Catalog> {
+        match description {
+            Some(description) => Some(Catalog::new(description, &self.types, &self.session)),
+            None if self.driver_compatibility => {
+                Some(Catalog::new(&NoTables, &self.types, &self.session))
+            }
+            None => None,
+        }
+    }
+}
+
+/// Recognizes the statements that start and end transaction blocks.
+fn transaction_command(query: &str) -> Option<TransactionCommand> {
+    let mut words = query
+        .split(|c: char| c.is_whitespace() || c == ';')
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase);
+    match words.next()?.as_str() {
+        "begin" | "start" => Some(TransactionCommand::Begin),
+        "commit" | "end" | "abort" => Some(TransactionCommand::End),
+        // ROLLBACK TO SAVEPOINT stays in the transaction.
+        "rollback" if words.next().as_deref() != Some("to") => Some(TransactionCommand::End),
+        _ => None,
+    }
+}
+
+/// Whether binary parameters of `ty` are text in the client encoding.
+fn is_text(ty: &Type) -> bool {
+    match ty.kind() {
+        postgres_types::Kind::Domain(base) => is_text(base),
+        _ => matches!(*ty, Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME),
+    }
+}
+
+/// Answers Describe with a RowDescription, or NoData for statements without
+/// rows.
+pub(crate) fn describe_columns(
+    out: &mut impl Write,
+    columns: Option<&[Column]>,
+    result_format_codes: Vec<FormatCode>,
+) -> Result<()> {
+    match columns {
+        None => ServerMessage::NoData.write(out),
+        Some(columns) => {
+            row_description(columns, format_codes(columns, result_format_codes)?)?.write(out)
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Date32Type, Date64Type, Decimal128Type, Decimal256Type, Float32Type, Float64Type, Int16Type,
+    Int32Type, Int64Type, Int8Type, Time32MillisecondType, Time32SecondType, Time64MicrosecondType,
+    Time64NanosecondType, TimestampMicrosecondType, TimestampMillisecondType,
+    TimestampNanosecondType, TimestampSecondType, UInt16Type, UInt32Type, UInt64Type, UInt8Type,
+};
+use arrow_array::{Array, ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Schema, TimeUnit};
+use bytes::BytesMut;
+use chrono::{TimeZone, Utc};
+use std::io::{Error, ErrorKind, Result};
+
+use crate::client_message::FormatCode;
+use crate::{Column, ErrorResponse, ToSqlValue, Type, Value};
+
+/// Derives result columns from an arrow schema.
+pub fn columns_from_schema(schema: &Schema) -> Result<Vec<Column>> {
+    schema
+        .fields()
+        .iter()
+        .map(|field| {
+            let column = Column::new(field.name(), pg_type(field.data_type())?);
+            let column = match field.data_type() {
+                DataType::Decimal128(precision, scale) | DataType::Decimal256(precision, scale) => {
+                    column.with_precision(*precision as i32, *scale as i32)
+                }
+                _ => column,
+            };
+            Ok(if field.is_nullable() {
+                column
+            } else {
+                column.not_null()
+            })
+        })
+        .collect()
+}
+
+fn pg_type(data_type: &DataType) -> Result<Type> {
+    Ok(match data_type {
+        DataType::Boolean => Type::BOOL,
+        DataType::Int8 | DataType::Int16 | DataType::UInt8 => Type::INT2,
+        DataType::Int32 | DataType::UInt16 => Type::INT4,
+        DataType::Int64 | DataType::UInt32 => Type::INT8,
+        DataType::UInt64 | DataType::Decimal128(_, _) | DataType::Decimal256(_, _) => Type::NUMERIC,
+        DataType::Float32 => Type::FLOAT4,
+        DataType::Float64 => Type::FLOAT8,
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View => Type::TEXT,
+        DataType::Binary | DataType::LargeBinary | DataType::BinaryView => Type::BYTEA,
+        DataType::Date32 | DataType::Date64 => Type::DATE,
+        DataType::Time32(_) | DataType::Time64(_) => Type::TIME,
+        DataType::Timestamp(_, None) => Type::TIMESTAMP,
+        DataType::Timestamp(_, Some(_)) => Type::TIMESTAMPTZ,
+        data_type => return Err(unsupported(data_type)),
+    })
+}
+
+fn unsupported(data_type: &DataType) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("Unsupported arrow type {}", data_type),
+    )
+}
+
+fn encode_each<V, F>(
+    len: usize,
+    value: F,
+    ty: &Type,
+    format_code: &FormatCode,
+) -> Result<Vec<Option<BytesMut>>>
+where
+    V: ToSqlValue,
+    F: Fn(usize) -> Result<Option<V>>,
+{
+    (0..len)
+        .map(|i| match (value(i)?, format_code) {
+            (None, _) => Ok(None),
+            (Some(value), FormatCode::Binary) => value.as_bin_value(ty),
+            (Some(value), FormatCode::Text) => value.as_str_value(ty),
+        })
+        .collect()
+}
+
+/// Arrow dates and times chrono cannot represent fail the batch with 22008
+/// `datetime_field_overflow` rather than being written as NULL.
+fn out_of_range(ty: &Type) -> Error {
+    ErrorResponse::error("22008", format!("{} out of range", ty.name()))
+}
+
+/// Encodes every value of an arrow column. Each column is downcast once and
+/// encoded in a tight loop before the rows are assembled. `$value` is `None`
+/// when a value that is not null cannot be converted.
+pub(crate) fn encode_column(
+    array: &ArrayRef,
+    ty: &Type,
+    format_code: &FormatCode,
+) -> Result<Vec<Option<BytesMut>>> {
+    macro_rules! encode {
+        ($array:expr, |$array_ident:ident, $i:ident| $value:expr) => {{
+            let $array_ident = $array;
+            encode_each(
+                $array_ident.len(),
+                |$i| match $array_ident.is_null($i) {
+                    true => Ok(None),
+                    false => $value.map(Some).ok_or_else(|| out_of_range(ty)),
+                },
+                ty,
+                format_code,
+            )
+        }};
+    }
+
+    match array.data_type() {
+        DataType::Boolean => encode!(array.as_boolean(), |a, i| Some(a.value(i))),
+        DataType::Int8 => encode!(array.as_primitive::<Int8Type>(), |a, i| Some(
+            a.value(i) as i16
+        )),
+        DataType::Int16 => encode!(array.as_primitive::<Int16Type>(), |a, i| Some(a.value(i))),
+        DataType::Int32 => encode!(array.as_primitive::<Int32Type>(), |a, i| Some(a.value(i))),
+        DataType::Int64 => encode!(array.as_primitive::<Int64Type>(), |a, i| Some(a.value(i))),
+        DataType::UInt8 => encode!(array.as_primitive::<UInt8Type>(), |a, i| Some(
+            a.value(i) as i16
+        )),
+        DataType::UInt16 => encode!(array.as_primitive::<UInt16Type>(), |a, i| Some(
+            a.value(i) as i32
+        )),
+        DataType::UInt32 => encode!(array.as_primitive::<UInt32Type>(), |a, i| Some(
+            a.value(i) as i64
+        )),
+        DataType::UInt64 => encode!(array.as_primitive::<UInt64Type>(), |a, i| Some(
+            Value::Numeric(a.value(i).to_string())
+        )),
+        DataType::Float32 => encode!(array.as_primitive::<Float32Type>(), |a, i| Some(a.value(i))),
+        DataType::Float64 => encode!(array.as_primitive::<Float64Type>(), |a, i| Some(a.value(i))),
+        DataType::Decimal128(_, _) => encode!(array.as_primitive::<Decimal128Type>(), |a, i| {
+            Some(Value::Numeric(a.value_as_string(i)))
+        }),
+        DataType::Decimal256(_, _) => encode!(array.as_primitive::<Decimal256Type>(), |a, i| {
+            Some(Value::Numeric(a.value_as_string(i)))
+        }),
+        DataType::Utf8 => encode!(array.as_string::<i32>(), |a, i| Some(a.value(i))),
+        DataType::LargeUtf8 => encode!(array.as_string::<i64>(), |a, i| Some(a.value(i))),
+        DataType::Utf8View => encode!(array.as_string_view(), |a, i| Some(a.value(i))),
+        DataType::Binary => encode!(array.as_binary::<i32>(), |a, i| Some(a.value(i))),
+        DataType::LargeBinary => encode!(array.as_binary::<i64>(), |a, i| Some(a.value(i))),
+        DataType::BinaryView => encode!(array.as_binary_view(), |a, i| Some(a.value(i))),
+        DataType::Date32 => encode!(array.as_primitive::<Date32Type>(), |a, i| a
+            .value_as_date(i)),
+        DataType::Date64 => encode!(array.as_primitive::<Date64Type>(), |a, i| a
+            .value_as_date(i)),
+        DataType::Time32(TimeUnit::Second) => {
+            encode!(array.as_primitive::<Time32SecondType>(), |a, i| a
+                .value_as_time(i))
+        }
+        DataType::Time32(TimeUnit::Millisecond) => {
+            encode!(array.as_primitive::<Time32MillisecondType>(), |a, i| a
+                .value_as_time(i))
+        }
+        DataType::Time64(TimeUnit::Microsecond) => {
+            encode!(array.as_primitive::<Time64MicrosecondType>(), |a, i| a
+                .value_as_time(i))
+        }
+        DataType::Time64(TimeUnit::Nanosecond) => {
+            encode!(array.as_primitive::<Time64NanosecondType>(), |a, i| a
+                .value_as_time(i))
+        }
+        DataType::Timestamp(unit, time_zone) => {
+            let timestamps = match unit {
+                TimeUnit::Second => timestamps(array.as_primitive::<TimestampSecondType>(), ty),
+                TimeUnit::Millisecond => {
+                    timestamps(array.as_primitive::<TimestampMillisecondType>(), ty)
+                }
+                TimeUnit::Microsecond => {
+                    timestamps(array.as_primitive::<TimestampMicrosecondType>(), ty)
+                }
+                TimeUnit::Nanosecond => {
+                    timestamps(array.as_primitive::<TimestampNanosecondType>(), ty)
+                }
+            }?;
+            match time_zone {
+                None => encode_each(timestamps.len(), |i| Ok(timestamps[i]), ty, format_code),
+                Some(_) => encode_each(
+                    timestamps.len(),
+                    |i| Ok(timestamps[i].map(|value| Utc.from_utc_datetime(&value))),
+                    ty,
+                    format_code,
+                ),
+            }
+        }
+        data_type => Err(unsupported(data_type)),
+    }
+}
+
+fn timestamps<T>(
+    array: &arrow_array::PrimitiveArray<T>,
+    ty: &Type,
+) -> Result<Vec<Option<chrono::NaiveDateTime>>>
+where
+    T: arrow_array::types::ArrowTimestampType,
+    i64: From<T::Native>,
+{
+    (0..array.len())
+        .map(|i| match array.is_null(i) {
+            true => Ok(None),
+            false => array
+                .value_as_datetime(i)
+                .map(Some)
+                .ok_or_else(|| out_of_range(ty)),
+        })
+        .collect()
+}
+
+/// How many rows of a batch are encoded at a time.
+const ROWS_PER_SLICE: usize = 1024;
+
+/// Encodes the rows of a batch into DataRow fields and passes them to
+/// `write_row`. Columns are encoded a slice of rows at a time, so a large
+/// batch is never held encoded in full.
+pub(crate) fn write_batch(
+    batch: &RecordBatch,
+    columns: &[Column],
+    format_codes: &[FormatCode],
+    mut write_row: impl FnMut(Vec<Option<BytesMut>>) -> Result<()>,
+) -> Result<()> {
+    check_batch(batch, columns)?;
+    for offset in (0..batch.num_rows()).step_by(ROWS_PER_SLICE) {
+        let slice = batch.slice(offset, ROWS_PER_SLICE.min(batch.num_rows() - offset));
+        let encoded = slice
+            .columns()
+            .iter()
+            .zip(columns)
+            .zip(format_codes)
+            .map(|((array, column), format_code)| {
+                encode_column(array, &column.column_type, format_code)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut encoded: Vec<_> = encoded.into_iter().map(Vec::into_iter).collect();
+        for _ in 0..slice.num_rows() {
+            write_row(
+                encoded
+                    .iter_mut()
+                    .map(|column| column.next().flatten())
+                    .collect(),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the batch has the result's columns and that each array is
+/// of a type that is written as its column type.
+fn check_batch(batch: &RecordBatch, columns: &[Column]) -> Result<()> {
+    if batch.num_columns() != columns.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Record batch has {} columns but the result has {}",
+                batch.num_columns(),
+                columns.len()
+            ),
+        ));
+    }
+    for (field, column) in batch.schema().fields().iter().zip(columns) {
+        let data_type = field.data_type();
+        let matches = match pg_type(data_type)? {
+            Type::TEXT => matches!(
+                column.column_type,
+                Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN
+            ),
+            ty => ty == column.column_type,
+        };
+        if !matches {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Arrow type {} of column \"{}\" cannot be written as type {}",
+                    data_type,
+                    column.name,
+                    column.column_type.name()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Date64Array, Int32Array, StringArray, TimestampSecondArray};
+    use arrow_schema::Field;
+    use std::sync::Arc;
+
+    #[test]
+    fn encodes_batch_rows_with_nulls() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int32Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec![Some("a"), None])),
+            ],
+        )
+        .unwrap();
+        let columns = columns_from_schema(&schema).unwrap();
+        assert_eq!(columns[0].column_type, Type::INT4);
+        assert!(!columns[0].nullable);
+        let mut rows = vec![];
+        write_batch(
+            &batch,
+            &columns,
+            &[FormatCode::Binary, FormatCode::Text],
+            |row| {
+                rows.push(row);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    Some(BytesMut::from(&1i32.to_be_bytes()[..])),
+                    Some(BytesMut::from(&b"a"[..]))
+                ],
+                vec![Some(BytesMut::from(&2i32.to_be_bytes()[..])), None],
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_batches_not_matching_the_columns() {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int32, false)]));
+        let batch =
+            RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from(vec![1, 2]))]).unwrap();
+        let write = |columns: &[Column]| {
+            write_batch(&batch, columns, &[FormatCode::Binary], |_| {
+                panic!("no row is written")
+            })
+        };
+        assert!(write(&[Column::new("id", Type::INT8)]).is_err());
+        assert!(write(&[Column::new("id", Type::TEXT)]).is_err());
+        assert!(write(&[]).is_err());
+        let id = Column::new("id", Type::INT4);
+        assert!(write(&[id.clone(), id]).is_err());
+    }
+
+    #[test]
+    fn rejects_timestamps_out_of_range() {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "at",
+            DataType::Timestamp(TimeUnit::Second, None),
+            true,
+        )]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(TimestampSecondArray::from(vec![
+                None,
+                Some(i64::MAX),
+            ]))],
+        )
+        .unwrap();
+        let columns = columns_from_schema(&schema).unwrap();
+        let error = write_batch(&batch, &columns, &[FormatCode::Text], |_| Ok(())).unwrap_err();
+        assert_eq!(ErrorResponse::of(&error).unwrap().code, "22008");
+
+        let schema = Arc::new(Schema::new(vec![Field::new("on", DataType::Date64, true)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Date64Array::from(vec![None, Some(i64::MAX)]))],
+        )
+        .unwrap();
+        let columns = columns_from_schema(&schema).unwrap();
+        let error = write_batch(&batch, &columns, &[FormatCode::Binary], |_| Ok(())).unwrap_err();
+        assert_eq!(ErrorResponse::of(&error).unwrap().code, "22008");
+    }
+
+    #[test]
+    fn writes_batches_in_slices() {
+        let schema = Arc::new(Schema::new(vec![Field::new("name", DataType::Utf8, true)]));
+        let names: Vec<String> = (0..ROWS_PER_SLICE * 2 + 1).map(|i| i.to_string()).collect();
+        let batch =
+            RecordBatch::try_new(schema, vec![Arc::new(StringArray::from(names.clone()))]).unwrap();
+        let mut written = vec![];
+        write_batch(
+            &batch,
+            &[Column::new("name", Type::VARCHAR)],
+            &[FormatCode::Text],
+            |row| {
+                written.push(String::from_utf8(row[0].as_ref().unwrap().to_vec()).unwrap());
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(written, names);
+    }
+}
@@ -0,0 +1,871 @@
+use regex::Regex;
+use sqlparser::ast::{
+    visit_relations, AssignmentTarget, BinaryOperator, Expr, FromTable, ObjectName, SetExpr,
+    Statement, TableFactor, UnaryOperator,
+};
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::io::{Error, Result, Write};
+use std::ops::ControlFlow;
+use std::rc::Rc;
+
+use crate::{
+    evaluate, statement, Column, DefaultServerParameters, ErrorResponse, ParameterValue,
+    PostgresShim, ResultWriter, Type, TypeRegistry, Value,
+};
+
+type Handler = Box<dyn FnMut(&Request) -> Result<Response>>;
+
+/// A shim that dispatches each prepared statement to the first registered
+/// handler whose route matches it, instead of one `match` over query strings.
+///
+/// Statements no route matches fail to prepare with `0A000`. Handlers run
+/// when the portal is executed, or when it is described before that since
+/// the description needs the response's columns. Returning an
+/// [`ErrorResponse::error`] from a handler fails the statement, any other
+/// error ends the session.
+///
+/// ```no_run
+/// # use postgres_shim::{Column, DefaultServerParameters, Response, Router, Type, Value};
+/// # fn routes(parameters: DefaultServerParameters) -> Router {
+/// Router::new(parameters)
+///     .select("orders", |request| {
+///         let id: i32 = request.capture("id").unwrap_or("0").parse().unwrap_or(0);
+///         Ok(Response::rows(
+///             vec![Column::new("id", Type::INT4)],
+///             vec![vec![Value::Int4(id)]],
+///         ))
+///     })
+///     .pattern(r"(?i)^LISTEN (?P<channel>\w+)", |_| Ok(Response::command("LISTEN")))
+/// # }
+/// ```
+pub struct Router {
+    routes: Vec<(Route, Handler)>,
+    fallback: Option<Handler>,
+    statements: HashMap<String, Rc<Prepared>>,
+    parameters: DefaultServerParameters,
+    types: TypeRegistry,
+    driver_compatibility: bool,
+}
+
+enum Route {
+    Pattern(Regex),
+    Table(StatementKind, String),
+    Statement(Box<dyn Fn(&Statement) -> bool>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StatementKind {
+    Select,
+    Insert,
+    Update,
+    Delete,
+}
+
+/// A value a route takes from the statement.
+#[derive(Debug, Clone)]
+enum Captured {
+    Literal(String),
+    Parameter(usize),
+    Null,
+}
+
+struct Prepared {
+    /// Index into `routes`, `None` for the fallback.
+    route: Option<usize>,
+    query: String,
+    statement: Option<Statement>,
+    parameter_types: Vec<Type>,
+    captures: HashMap<String, Captured>,
+}
+
+/// A bound statement, waiting for its handler to run.
+pub struct RouterPortal {
+    prepared: Rc<Prepared>,
+    parameters: Vec<ParameterValue>,
+    captures: HashMap<String, String>,
+    /// The response of a portal described before it was executed.
+    response: OnceCell<Response>,
+}
+
+/// What a handler sees of the statement it was routed.
+pub struct Request<'a> {
+    pub query: &'a str,
+    /// The parsed statement, `None` when the query only matched a pattern
+    /// and could not be parsed.
+    pub statement: Option<&'a Statement>,
+    pub parameters: &'a [ParameterValue],
+    pub parameter_types: &'a [Type],
+    captures: HashMap<String, String>,
+}
+
+impl Request<'_> {
+    /// A value the route extracted: a named group of a pattern, or a
+    /// `column = value` of the statement's `WHERE`, `SET` or single `VALUES`
+    /// row, with `$n` placeholders replaced by the bound parameters. Column
+    /// names are lowercase, `NULL` values are absent.
+    pub fn capture(&self, name: &str) -> Option<&str> {
+        self.captures.get(name).map(String::as_str)
+    }
+}
+
+/// The result of a handler.
+#[derive(Debug, Clone)]
+pub struct Response {
+    columns: Option<Vec<Column>>,
+    rows: Vec<Vec<Value>>,
+    tag: Option<String>,
+}
+
+impl Response {
+    pub fn rows(columns: Vec<Column>, rows: Vec<Vec<Value>>) -> Self {
+        Response {
+            columns: Some(columns),
+            rows,
+            tag: None,
+        }
+    }
+
+    /// A statement without rows, completed with `tag` such as `INSERT 0 1`.
+    pub fn command(tag: impl Into<String>) -> Self {
+        Response {
+            columns: None,
+            rows: vec![],
+            tag: Some(tag.into()),
+        }
+    }
+
+    pub fn empty() -> Self {
+        Response {
+            columns: None,
+            rows: vec![],
+            tag: None,
+        }
+    }
+}
+
+impl Router {
+    pub fn new(parameters: DefaultServerParameters) -> Self {
+        Router {
+            routes: vec![],
+            fallback: None,
+            statements: HashMap::new(),
+            parameters,
+            types: TypeRegistry::new(),
+            driver_compatibility: false,
+        }
+    }
+
+    pub fn with_type_registry(mut self, types: TypeRegistry) -> Self {
+        self.types = types;
+        self
+    }
+
+    /// See [`PostgresShim::driver_compatibility`].
+    pub fn with_driver_compatibility(mut self) -> Self {
+        self.driver_compatibility = true;
+        self
+    }
+
+    /// Routes queries reading `table`.
+    pub fn select<F>(self, table: &str, handler: F) -> Self
+    where
+        F: FnMut(&Request) -> Result<Response> + 'static,
+    {
+        self.route(
+            Route::Table(StatementKind::Select, table.to_lowercase()),
+            handler,
+        )
+    }
+
+    pub fn insert<F>(self, table: &str, handler: F) -> Self
+    where
+        F: FnMut(&Request) -> Result<Response> + 'static,
+    {
+        self.route(
+            Route::Table(StatementKind::Insert, table.to_lowercase()),
+            handler,
+        )
+    }
+
+    pub fn update<F>(self, table: &str, handler: F) -> Self
+    where
+        F: FnMut(&Request) -> Result<Response> + 'static,
+    {
+        self.route(
+            Route::Table(StatementKind::Update, table.to_lowercase()),
+            handler,
+        )
+    }
+
+    pub fn delete<F>(self, table: &str, handler: F) -> Self
+    where
+        F: FnMut(&Request) -> Result<Response> + 'static,
+    {
+        self.route(
+            Route::Table(StatementKind::Delete, table.to_lowercase()),
+            handler,
+        )
+    }
+
+    /// Routes queries whose text matches `pattern`. Named groups become
+    /// captures.
+    ///
+    /// # Panics
+    ///
+    /// If `pattern` is not a valid regular expression.
+    pub fn pattern<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: FnMut(&Request) -> Result<Response> + 'static,
+    {
+        let pattern = Regex::new(pattern)
+            .unwrap_or_else(|error| panic!("invalid route pattern {:?}: {}", pattern, error));
+        self.route(Route::Pattern(pattern), handler)
+    }
+
+    /// Routes statements the predicate accepts, for matching on the shape of
+    /// the AST.
+    pub fn statement<P, F>(self, predicate: P, handler: F) -> Self
+    where
+        P: Fn(&Statement) -> bool + 'static,
+        F: FnMut(&Request) -> Result<Response> + 'static,
+    {
+        self.route(Route::Statement(Box::new(predicate)), handler)
+    }
+
+    /// Handles statements no route matches. Without one they are rejected.
+    pub fn fallback<F>(mut self, handler: F) -> Self
+    where
+        F: FnMut(&Request) -> Result<Response> + 'static,
+    {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    fn route<F>(mut self, route: Route, handler: F) -> Self
+    where
+        F: FnMut(&Request) -> Result<Response> + 'static,
+    {
+        self.routes.push((route, Box::new(handler)));
+        self
+    }
+
+    /// Runs the handler of a bound statement.
+    fn respond(&mut self, portal: &RouterPortal) -> Result<Response> {
+        let prepared = &portal.prepared;
+        let request = Request {
+            query: &prepared.query,
+            statement: prepared.statement.as_ref(),
+            parameters: &portal.parameters,
+            parameter_types: &prepared.parameter_types,
+            captures: portal.captures.clone(),
+        };
+        let handler = match prepared.route {
+            Some(index) => &mut self.routes[index].1,
+            None => self
+                .fallback
+                .as_mut()
+                .expect("statements without a route are only prepared with a fallback"),
+        };
+        handler(&request)
+    }
+}
+
+impl Route {
+    fn captures(
+        &self,
+        query: &str,
+        statement: Option<&Statement>,
+    ) -> Option<HashMap<String, Captured>> {
+        match self {
+            Route::Pattern(pattern) => {
+                let found = pattern.captures(query.trim())?;
+                Some(
+                    pattern
+                        .capture_names()
+                        .flatten()
+                        .filter_map(|name| {
+                            let value = found.name(name)?.as_str().to_string();
+                            Some((name.to_string(), Captured::Literal(value)))
+                        })
+                        .collect(),
+                )
+            }
+            Route::Table(kind, table) => {
+                let statement = statement?;
+                if statement_kind(statement) != Some(*kind) || !reads_table(statement, *kind, table)
+                {
+                    return None;
+                }
+                Some(statement_captures(statement))
+            }
+            Route::Statement(predicate) => {
+                let statement = statement?;
+                predicate(statement).then(|| statement_captures(statement))
+            }
+        }
+    }
+}
+
+fn statement_kind(statement: &Statement) -> Option<StatementKind> {
+    match statement {
+        Statement::Query(_) => Some(StatementKind::Select),
+        Statement::Insert(_) => Some(StatementKind::Insert),
+        Statement::Update { .. } => Some(StatementKind::Update),
+        Statement::Delete(_) => Some(StatementKind::Delete),
+        _ => None,
+    }
+}
+
+fn table_matches(name: &ObjectName, table: &str) -> bool {
+    name.0
+        .last()
+        .is_some_and(|part| part.value.to_lowercase() == table)
+}
+
+/// Whether the statement reads `table`, or for changes, targets it.
+fn reads_table(statement: &Statement, kind: StatementKind, table: &str) -> bool {
+    let target = |factor: &TableFactor| match factor {
+        TableFactor::Table { name, .. } => table_matches(name, table),
+        _ => false,
+    };
+    match (kind, statement) {
+        (StatementKind::Select, _) => visit_relations(statement, |name| {
+            if table_matches(name, table) {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .is_break(),
+        (_, Statement::Insert(insert)) => table_matches(&insert.table_name, table),
+        (_, Statement::Update { table: update, .. }) => target(&update.relation),
+        (_, Statement::Delete(delete)) => match &delete.from {
+            FromTable::WithFromKeyword(from) | FromTable::WithoutKeyword(from) => {
+                from.first().is_some_and(|from| target(&from.relation))
+            }
+        },
+        _ => false,
+    }
+}
+
+/// The `column = value` pairs of a statement. `WHERE` equalities take
+/// precedence over `SET` assignments of the same column.
+fn statement_captures(statement: &Statement) -> HashMap<String, Captured> {
+    let mut captures = HashMap::new();
+    match statement {
+        Statement::Query(query) => {
+            if let SetExpr::Select(select) = query.body.as_ref() {
+                if let Some(selection) = &select.selection {
+                    equalities(selection, &mut captures);
+                }
+            }
+        }
+        Statement::Insert(insert) => {
+            if let Some(SetExpr::Values(values)) =
+                insert.source.as_ref().map(|source| source.body.as_ref())
+            {
+                if let [row] = values.rows.as_slice() {
+                    for (column, value) in insert.columns.iter().zip(row) {
+                        if let Some(value) = captured(value) {
+                            captures.insert(column.value.to_lowercase(), value);
+                        }
+                    }
+                }
+            }
+        }
+        Statement::Update {
+            assignments,
+            selection,
+            ..
+        } => {
+            for assignment in assignments {
+                if let (AssignmentTarget::ColumnName(name), Some(value)) =
+                    (&assignment.target, captured(&assignment.value))
+                {
+                    if let Some(column) = name.0.last() {
+                        captures.insert(column.value.to_lowercase(), value);
+                    }
+                }
+            }
+            if let Some(selection) = selection {
+                equalities(selection, &mut captures);
+            }
+        }
+        Statement::Delete(delete) => {
+            if let Some(selection) = &delete.selection {
+                equalities(selection, &mut captures);
+            }
+        }
+        _ => {}
+    }
+    captures
+}
+
+fn equalities(expr: &Expr, captures: &mut HashMap<String, Captured>) {
+    match expr {
+        Expr::Nested(expr) => equalities(expr, captures),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            equalities(left, captures);
+            equalities(right, captures);
+        }
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Eq,
+            right,
+        } => {
+            let column = match left.as_ref() {
+                Expr::Identifier(ident) => Some(ident),
+                Expr::CompoundIdentifier(parts) => parts.last(),
+                _ => None,
+            };
+            if let (Some(column), Some(value)) = (column, captured(right)) {
+                captures.insert(column.value.to_lowercase(), value);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn captured(expr: &Expr) -> Option<Captured> {
+    use sqlparser::ast::Value as Literal;
+    Some(match expr {
+        Expr::Nested(expr) | Expr::Cast { expr, .. } => return captured(expr),
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => match captured(expr)? {
+            Captured::Literal(number) => Captured::Literal(format!("-{}", number)),
+            _ => return None,
+        },
+        Expr::Value(Literal::Placeholder(name)) => {
+            Captured::Parameter(name.strip_prefix('$')?.parse().ok()?)
+        }
+        Expr::Value(Literal::Number(number, _)) => Captured::Literal(number.clone()),
+        Expr::Value(Literal::SingleQuotedString(text)) => Captured::Literal(text.clone()),
+        Expr::Value(Literal::Boolean(value)) => Captured::Literal(value.to_string()),
+        Expr::Value(Literal::Null) => Captured::Null,
+        _ => return None,
+    })
+}
+
+impl Prepared {
+    /// Replaces placeholders in the captures with the bound parameters.
+    fn resolve(&self, parameters: &[ParameterValue]) -> Result<HashMap<String, String>> {
+        let mut resolved = HashMap::new();
+        for (name, captured) in &self.captures {
+            let value = match captured {
+                Captured::Literal(value) => value.clone(),
+                Captured::Null => continue,
+                Captured::Parameter(number) => {
+                    let parameter = parameters.get(number - 1).ok_or_else(|| {
+                        ErrorResponse::error("42P02", format!("there is no parameter ${}", number))
+                    })?;
+                    match parameter {
+                        ParameterValue::Null => continue,
+                        ParameterValue::Text(text) => text.clone(),
+                        ParameterValue::Binary(_) => {
+                            let ty = self
+                                .parameter_types
+                                .get(number - 1)
+                                .filter(|ty| **ty != Type::UNKNOWN)
+                                .ok_or_else(|| {
+                                    ErrorResponse::error(
+                                        "42P18",
+                                        format!(
+                                            "binary parameter ${} needs a declared type",
+                                            number
+                                        ),
+                                    )
+                                })?;
+                            let value = Value::from_parameter(parameter.clone(), ty)
+                                .map_err(|error| invalid_binary_parameter(*number, &error))?;
+                            match evaluate::text(&value) {
+                                Some(text) => text,
+                                None => continue,
+                            }
+                        }
+                    }
+                }
+            };
+            resolved.insert(name.clone(), value);
+        }
+        Ok(resolved)
+    }
+}
+
+/// Fails the Bind with 22P03 `invalid_binary_representation`, which the
+/// client recovers from at the next Sync.
+fn invalid_binary_parameter(number: usize, error: &Error) -> Error {
+    let detail =
+        ErrorResponse::of(error).map_or_else(|| error.to_string(), |error| error.message.clone());
+    ErrorResponse::error(
+        "22P03",
+        format!(
+            "incorrect binary data format in bind parameter ${}: {}",
+            number, detail
+        ),
+    )
+}
+
+impl PostgresShim<RouterPortal> for Router {
+    fn prepare(
+        &mut self,
+        query_name: String,
+        query: String,
+        parameter_types: Vec<Type>,
+    ) -> Result<()> {
+        let statement = statement::parse(&query)
+            .ok()
+            .flatten()
+            .map(|parsed| parsed.statement);
+        let matched = self
+            .routes
+            .iter()
+            .enumerate()
+            .find_map(|(index, (route, _))| {
+                route
+                    .captures(&query, statement.as_ref())
+                    .map(|captures| (Some(index), captures))
+            });
+        let (route, captures) = match matched {
+            Some(matched) => matched,
+            None if self.fallback.is_some() => (None, HashMap::new()),
+            None => {
+                return Err(ErrorResponse::error(
+                    "0A000",
+                    format!("no route matches the query: {}", query),
+                ))
+            }
+        };
+        self.statements.insert(
+            query_name,
+            Rc::new(Prepared {
+                route,
+                query,
+                statement,
+                parameter_types,
+                captures,
+            }),
+        );
+        Ok(())
+    }
+
+    fn bind(
+        &mut self,
+        query_name: String,
+        parameters: Vec<ParameterValue>,
+    ) -> Result<RouterPortal> {
+        let prepared = self.statements.get(&query_name).ok_or_else(|| {
+            ErrorResponse::error(
+                "26000",
+                format!("prepared statement \"{}\" does not exist", query_name),
+            )
+        })?;
+        Ok(RouterPortal {
+            prepared: prepared.clone(),
+            captures: prepared.resolve(&parameters)?,
+            parameters,
+            response: OnceCell::new(),
+        })
+    }
+
+    fn describe(&mut self, portal: &RouterPortal) -> Result<Option<Vec<Column>>> {
+        if portal.response.get().is_none() {
+            let response = self.respond(portal)?;
+            let _ = portal.response.set(response);
+        }
+        Ok(portal
+            .response
+            .get()
+            .and_then(|response| response.columns.clone()))
+    }
+
+    /// Fetching a portal in parts is not supported, a `max_rows` below the
+    /// number of rows the handler returns fails the statement.
+    fn execute<'a, S>(
+        &mut self,
+        mut portal: RouterPortal,
+        max_rows: u32,
+        _columns: Option<Vec<Column>>,
+        result_writer: ResultWriter<'a, S>,
+    ) -> Result<()>
+    where
+        S: Write,
+    {
+        let response = match portal.response.take() {
+            Some(response) => response,
+            None => self.respond(&portal)?,
+        };
+        if max_rows > 0 && response.rows.len() > max_rows as usize {
+            return Err(ErrorResponse::error(
+                "0A000",
+                format!(
+                    "fetching {} of {} rows is not supported",
+                    max_rows,
+                    response.rows.len()
+                ),
+            ));
+        }
+        match (response.columns, response.tag) {
+            (Some(columns), _) => {
+                let mut row_writer = result_writer.start_writing(&columns)?;
+                for row in response.rows {
+                    row_writer.write_row(row)?;
+                }
+                row_writer.finish()
+            }
+            (None, Some(tag)) => result_writer.command_complete(&tag),
+            (None, None) => result_writer.empty_result(),
+        }
+    }
+
+    fn close_statement(&mut self, query_name: String) {
+        self.statements.remove(&query_name);
+    }
+
+    fn default_parameters(&mut self) -> DefaultServerParameters {
+        self.parameters.clone()
+    }
+
+    fn type_registry(&mut self) -> TypeRegistry {
+        self.types.clone()
+    }
+
+    fn driver_compatibility(&mut self) -> bool {
+        self.driver_compatibility
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientEncoding, ErrorResponse};
+    use std::cell::{Cell, RefCell};
+
+    fn parameters() -> DefaultServerParameters {
+        DefaultServerParameters {
+            server_version: "14".to_string(),
+            server_encoding: "UTF8".to_string(),
+            client_encoding: "UTF8".to_string(),
+            application_name: String::new(),
+            default_transaction_read_only: "off".to_string(),
+            in_hot_standby: "off".to_string(),
+            is_superuser: "off".to_string(),
+            session_authorization: "alice".to_string(),
+            date_style: "ISO, MDY".to_string(),
+            interval_style: "postgres".to_string(),
+            time_zone: "UTC".to_string(),
+            integer_datetimes: "on".to_string(),
+            standard_conforming_strings: "on".to_string(),
+        }
+    }
+
+    fn routed(
+        router: &mut Router,
+        query: &str,
+        parameters: Vec<ParameterValue>,
+    ) -> Result<Response> {
+        router.prepare("s".to_string(), query.to_string(), vec![])?;
+        let portal = router.bind("s".to_string(), parameters)?;
+        router.respond(&portal)
+    }
+
+    fn code(result: Result<Response>) -> String {
+        ErrorResponse::of(&result.unwrap_err())
+            .unwrap()
+            .code
+            .clone()
+    }
+
+    fn execute(router: &mut Router, portal: RouterPortal, max_rows: u32) -> Result<Vec<u8>> {
+        let mut stream = vec![];
+        let writer = ResultWriter::new(vec![], &mut stream, ClientEncoding::Utf8);
+        router.execute(portal, max_rows, None, writer)?;
+        Ok(stream)
+    }
+
+    #[test]
+    fn routes_by_table_with_captures() {
+        let seen = Rc::new(RefCell::new(vec![]));
+        let log = seen.clone();
+        let mut router = Router::new(parameters())
+            .select("orders", move |request| {
+                log.borrow_mut()
+                    .push(("select", request.capture("id").map(str::to_string)));
+                Ok(Response::rows(vec![Column::new("id", Type::INT4)], vec![]))
+            })
+            .update("orders", |request| {
+                assert_eq!(request.capture("note"), Some("paid"));
+                assert_eq!(request.capture("id"), Some("7"));
+                Ok(Response::command("UPDATE 1"))
+            })
+            .insert("orders", |request| {
+                assert_eq!(request.capture("id"), Some("-3"));
+                assert_eq!(request.capture("note"), None);
+                Ok(Response::command("INSERT 0 1"))
+            });
+
+        routed(
+            &mut router,
+            "SELECT * FROM public.orders o WHERE o.id = $1 AND true",
+            vec![ParameterValue::Text("42".to_string())],
+        )
+        .unwrap();
+        assert_eq!(*seen.borrow(), vec![("select", Some("42".to_string()))]);
+
+        let response = routed(
+            &mut router,
+            "UPDATE orders SET note = $1 WHERE id = 7",
+            vec![ParameterValue::Text("paid".to_string())],
+        )
+        .unwrap();
+        assert_eq!(response.tag.as_deref(), Some("UPDATE 1"));
+
+        routed(
+            &mut router,
+            "INSERT INTO orders (id, note) VALUES (-3, NULL)",
+            vec![],
+        )
+        .unwrap();
+
+        assert_eq!(
+            code(routed(&mut router, "DELETE FROM orders", vec![])),
+            "0A000"
+        );
+        assert_eq!(
+            code(routed(
+                &mut router,
+                "SELECT * FROM orders WHERE id = $2",
+                vec![]
+            )),
+            "42P02"
+        );
+        routed(
+            &mut router,
+            "SELECT * FROM orders WHERE id = $1",
+            vec![ParameterValue::Null],
+        )
+        .unwrap();
+        assert_eq!(seen.borrow()[1], ("select", None));
+
+        router
+            .prepare(
+                "s".to_string(),
+                "SELECT * FROM orders WHERE id = $1".to_string(),
+                vec![Type::INT4],
+            )
+            .unwrap();
+        let bound = router.bind("s".to_string(), vec![ParameterValue::Binary(vec![0, 42])]);
+        assert_eq!(code(bound.map(|_| Response::command("SELECT 0"))), "22P03");
+    }
+
+    #[test]
+    fn routes_deletes_and_joins() {
+        let mut router = Router::new(parameters())
+            .delete("orders", |request| {
+                assert_eq!(request.capture("id"), Some("9"));
+                assert_eq!(request.capture("status"), Some("open"));
+                Ok(Response::command("DELETE 1"))
+            })
+            .select("customers", |_| Ok(Response::command("SELECT 0")));
+
+        let response = routed(
+            &mut router,
+            "DELETE FROM shop.orders WHERE (id = 9 AND status = 'open')",
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(response.tag.as_deref(), Some("DELETE 1"));
+        // Selects are routed by any table they read.
+        let response = routed(
+            &mut router,
+            "SELECT o.id FROM orders o JOIN Customers c ON c.id = o.customer",
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(response.tag.as_deref(), Some("SELECT 0"));
+        assert_eq!(
+            code(routed(
+                &mut router,
+                "UPDATE customers SET name = 'x'",
+                vec![]
+            )),
+            "0A000"
+        );
+    }
+
+    #[test]
+    fn runs_handlers_on_execute() {
+        let calls = Rc::new(Cell::new(0));
+        let counted = calls.clone();
+        let mut router = Router::new(parameters()).select("orders", move |_| {
+            counted.set(counted.get() + 1);
+            Ok(Response::rows(
+                vec![Column::new("id", Type::INT4)],
+                vec![vec![Value::Int4(1)], vec![Value::Int4(2)]],
+            ))
+        });
+        router
+            .prepare("s".to_string(), "SELECT id FROM orders".to_string(), vec![])
+            .unwrap();
+
+        let portal = router.bind("s".to_string(), vec![]).unwrap();
+        assert_eq!(calls.get(), 0);
+        let stream = execute(&mut router, portal, 0).unwrap();
+        assert_eq!(calls.get(), 1);
+        assert_eq!(stream.iter().filter(|byte| **byte == b'D').count(), 2);
+
+        // A described portal runs its handler once, when described.
+        let portal = router.bind("s".to_string(), vec![]).unwrap();
+        let columns = router.describe(&portal).unwrap().unwrap();
+        assert_eq!(columns[0].name, "id");
+        assert_eq!(calls.get(), 2);
+        execute(&mut router, portal, 2).unwrap();
+        assert_eq!(calls.get(), 2);
+
+        let portal = router.bind("s".to_string(), vec![]).unwrap();
+        let error = execute(&mut router, portal, 1).unwrap_err();
+        assert_eq!(ErrorResponse::of(&error).unwrap().code, "0A000");
+
+        assert_eq!(
+            code(
+                router
+                    .bind("t".to_string(), vec![])
+                    .map(|_| Response::empty())
+            ),
+            "26000"
+        );
+    }
+
+    #[test]
+    fn routes_patterns_statements_and_fallback() {
+        let mut router = Router::new(parameters())
+            .pattern(r"(?i)^LISTEN (?P<channel>\w+)", |request| {
+                Ok(Response::command(format!(
+                    "LISTEN {}",
+                    request.capture("channel").unwrap()
+                )))
+            })
+            .statement(
+                |statement| matches!(statement, Statement::StartTransaction { .. }),
+                |_| Ok(Response::command("BEGIN")),
+            )
+            .fallback(|_| Ok(Response::empty()));
+
+        let tag = |response: Response| response.tag;
+        assert_eq!(
+            tag(routed(&mut router, "listen jobs", vec![]).unwrap()),
+            Some("LISTEN jobs".to_string())
+        );
+        assert_eq!(
+            tag(routed(&mut router, "BEGIN", vec![]).unwrap()),
+            Some("BEGIN".to_string())
+        );
+        assert_eq!(tag(routed(&mut router, "VACUUM", vec![]).unwrap()), None);
+    }
+}
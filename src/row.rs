@@ -0,0 +1,94 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use postgres_types::Type;
+use uuid::Uuid;
+
+use crate::{Column, ToSqlValue};
+
+/// A struct that can be written as a result row, usually through `#[derive(PgRow)]`.
+pub trait PgRow {
+    fn columns() -> Vec<Column>;
+    /// Values in the same order as `columns`.
+    fn values(&self) -> Vec<&dyn ToSqlValue>;
+}
+
+/// Postgres type of a rust type, used by `#[derive(PgRow)]` to build columns.
+pub trait PgType {
+    const NULLABLE: bool = false;
+    fn pg_type() -> Type;
+}
+
+macro_rules! pg_type_impl {
+    ($($ty:ty => $pg_type:ident),* $(,)?) => {
+        $(
+            impl PgType for $ty {
+                fn pg_type() -> Type {
+                    Type::$pg_type
+                }
+            }
+        )*
+    };
+}
+
+pg_type_impl!(
+    bool => BOOL,
+    i8 => CHAR,
+    i16 => INT2,
+    i32 => INT4,
+    i64 => INT8,
+    u32 => OID,
+    f32 => FLOAT4,
+    f64 => FLOAT8,
+    String => TEXT,
+    &str => TEXT,
+    Vec<u8> => BYTEA,
+    &[u8] => BYTEA,
+    NaiveDate => DATE,
+    NaiveTime => TIME,
+    NaiveDateTime => TIMESTAMP,
+    DateTime<Utc> => TIMESTAMPTZ,
+    Uuid => UUID,
+    serde_json::Value => JSONB,
+);
+
+macro_rules! pg_array_type_impl {
+    ($($ty:ty => $pg_type:ident),* $(,)?) => {
+        $(
+            impl PgType for Vec<$ty> {
+                fn pg_type() -> Type {
+                    Type::$pg_type
+                }
+            }
+
+            impl PgType for Vec<Option<$ty>> {
+                fn pg_type() -> Type {
+                    Type::$pg_type
+                }
+            }
+        )*
+    };
+}
+
+pg_array_type_impl!(
+    bool => BOOL_ARRAY,
+    i16 => INT2_ARRAY,
+    i32 => INT4_ARRAY,
+    i64 => INT8_ARRAY,
+    f32 => FLOAT4_ARRAY,
+    f64 => FLOAT8_ARRAY,
+    String => TEXT_ARRAY,
+    NaiveDate => DATE_ARRAY,
+    NaiveDateTime => TIMESTAMP_ARRAY,
+    DateTime<Utc> => TIMESTAMPTZ_ARRAY,
+    Uuid => UUID_ARRAY,
+);
+
+impl<T> PgType for Option<T>
+where
+    T: PgType,
+{
+    const NULLABLE: bool = true;
+
+    fn pg_type() -> Type {
+        T::pg_type()
+    }
+}
@@ -0,0 +1,789 @@
+use serde::ser::{
+    self, Impossible, Serialize, SerializeMap, SerializeSeq, SerializeStruct,
+    SerializeStructVariant, SerializeTuple, SerializeTupleStruct, SerializeTupleVariant,
+    Serializer,
+};
+use serde_json::value::Serializer as JsonSerializer;
+use std::fmt::Display;
+use std::io::{Error, ErrorKind, Result};
+
+use postgres_types::Kind;
+
+use crate::{Column, ParameterValue, ToSqlValue, Type, Value};
+
+/// Serializes a struct or a map into its fields, in declaration order.
+pub fn to_row_values<T>(record: &T) -> Result<Vec<(String, Value)>>
+where
+    T: Serialize + ?Sized,
+{
+    record.serialize(RowSerializer).map_err(Into::into)
+}
+
+/// Infers the columns of a result from one record. Fields that are `None` in
+/// the record are typed as `text`.
+pub fn infer_columns<T>(record: &T) -> Result<Vec<Column>>
+where
+    T: Serialize + ?Sized,
+{
+    Ok(to_row_values(record)?
+        .into_iter()
+        .map(|(name, value)| Column::new(name, value.sql_type().unwrap_or(Type::TEXT)))
+        .collect())
+}
+
+/// Picks the value of each column by name from a serialized record, converting
+/// it to the column type. Every column needs a field of a type that converts
+/// to the column type.
+pub(crate) fn values_for_columns<T>(record: &T, columns: &[Column]) -> Result<Vec<Value>>
+where
+    T: Serialize + ?Sized,
+{
+    let mut fields = to_row_values(record)?;
+    columns
+        .iter()
+        .map(
+            |column| match fields.iter().position(|(name, _)| *name == column.name) {
+                Some(i) => coerce(fields.swap_remove(i).1, &column.column_type).map_err(|error| {
+                    SerializeError(format!("column \"{}\": {}", column.name, error)).into()
+                }),
+                None => Err(SerializeError(format!(
+                    "column \"{}\" is not a field of the record",
+                    column.name
+                ))
+                .into()),
+            },
+        )
+        .collect()
+}
+
+/// Serde maps everything that is not a number, bool or bytes to strings or
+/// sequences. Convert those to the column type so, for example, a timestamp
+/// serialized as a string is sent as a timestamp.
+fn coerce(value: Value, ty: &Type) -> Result<Value> {
+    if value.is_null() || value.sql_type().as_ref() == Some(ty) {
+        return Ok(value);
+    }
+    let text = match value {
+        Value::Text(text) => text,
+        Value::Array(values) => {
+            return match ty.kind() {
+                Kind::Array(element) => coerce_elements(values, element),
+                _ => Err(mismatch("a sequence", ty)),
+            }
+        }
+        Value::Json(_) if matches!(*ty, Type::JSON | Type::JSONB) => return Ok(value),
+        Value::Json(_) => return Err(mismatch("a map", ty)),
+        Value::Bytea(_) => return Err(mismatch("bytes", ty)),
+        value => match value.as_str_value(ty)? {
+            Some(text) => String::from_utf8_lossy(&text).to_string(),
+            None => return Ok(Value::Null),
+        },
+    };
+    Value::from_parameter(ParameterValue::Text(text), ty)
+}
+
+/// Converts the elements of a possibly nested sequence to `element`.
+fn coerce_elements(values: Vec<Value>, element: &Type) -> Result<Value> {
+    values
+        .into_iter()
+        .map(|value| match value {
+            Value::Array(values) => coerce_elements(values, element),
+            value => coerce(value, element),
+        })
+        .collect::<Result<_>>()
+        .map(Value::Array)
+}
+
+fn mismatch(what: &str, ty: &Type) -> Error {
+    SerializeError(format!("{} cannot be written as type {}", what, ty.name())).into()
+}
+
+#[derive(Debug)]
+pub struct SerializeError(String);
+
+impl Display for SerializeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for SerializeError {}
+
+impl ser::Error for SerializeError {
+    fn custom<T: Display>(msg: T) -> Self {
+        SerializeError(msg.to_string())
+    }
+}
+
+impl From<SerializeError> for Error {
+    fn from(error: SerializeError) -> Self {
+        Error::new(ErrorKind::InvalidData, error)
+    }
+}
+
+impl From<serde_json::Error> for SerializeError {
+    fn from(error: serde_json::Error) -> Self {
+        SerializeError(error.to_string())
+    }
+}
+
+fn not_a_record<T>() -> std::result::Result<T, SerializeError> {
+    Err(SerializeError(
+        "A row can only be serialized from a struct or a map".to_string(),
+    ))
+}
+
+macro_rules! reject_non_records {
+    ($($method:ident($($arg:ty),*)),* $(,)?) => {
+        $(
+            fn $method(self, $(_: $arg),*) -> std::result::Result<Self::Ok, Self::Error> {
+                not_a_record()
+            }
+        )*
+    };
+}
+
+struct RowSerializer;
+
+impl Serializer for RowSerializer {
+    type Ok = Vec<(String, Value)>;
+    type Error = SerializeError;
+    type SerializeSeq = Impossible<Self::Ok, Self::Error>;
+    type SerializeTuple = Impossible<Self::Ok, Self::Error>;
+    type SerializeTupleStruct = Impossible<Self::Ok, Self::Error>;
+    type SerializeTupleVariant = Impossible<Self::Ok, Self::Error>;
+    type SerializeMap = RecordSerializer;
+    type SerializeStruct = RecordSerializer;
+    type SerializeStructVariant = Impossible<Self::Ok, Self::Error>;
+
+    reject_non_records!(
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_none(),
+        serialize_unit(),
+        serialize_unit_struct(&'static str),
+        serialize_unit_variant(&'static str, u32, &'static str),
+    );
+
+    fn serialize_some<T>(self, value: &T) -> std::result::Result<Self::Ok, Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> std::result::Result<Self::Ok, Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> std::result::Result<Self::Ok, Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        not_a_record()
+    }
+
+    fn serialize_seq(
+        self,
+        _: Option<usize>,
+    ) -> std::result::Result<Self::SerializeSeq, Self::Error> {
+        not_a_record()
+    }
+
+    fn serialize_tuple(self, _: usize) -> std::result::Result<Self::SerializeTuple, Self::Error> {
+        not_a_record()
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> std::result::Result<Self::SerializeTupleStruct, Self::Error> {
+        not_a_record()
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> std::result::Result<Self::SerializeTupleVariant, Self::Error> {
+        not_a_record()
+    }
+
+    fn serialize_map(
+        self,
+        len: Option<usize>,
+    ) -> std::result::Result<Self::SerializeMap, Self::Error> {
+        Ok(RecordSerializer::new(len.unwrap_or(0)))
+    }
+
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        len: usize,
+    ) -> std::result::Result<Self::SerializeStruct, Self::Error> {
+        Ok(RecordSerializer::new(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> std::result::Result<Self::SerializeStructVariant, Self::Error> {
+        not_a_record()
+    }
+}
+
+struct RecordSerializer {
+    fields: Vec<(String, Value)>,
+    next_key: Option<String>,
+}
+
+impl RecordSerializer {
+    fn new(len: usize) -> Self {
+        Self {
+            fields: Vec::with_capacity(len),
+            next_key: None,
+        }
+    }
+}
+
+impl SerializeStruct for RecordSerializer {
+    type Ok = Vec<(String, Value)>;
+    type Error = SerializeError;
+
+    fn serialize_field<T>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> std::result::Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.fields
+            .push((key.to_string(), value.serialize(ValueSerializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> std::result::Result<Self::Ok, Self::Error> {
+        Ok(self.fields)
+    }
+}
+
+impl SerializeMap for RecordSerializer {
+    type Ok = Vec<(String, Value)>;
+    type Error = SerializeError;
+
+    fn serialize_key<T>(&mut self, key: &T) -> std::result::Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        match key.serialize(ValueSerializer)? {
+            Value::Text(key) => {
+                self.next_key = Some(key);
+                Ok(())
+            }
+            _ => Err(SerializeError("Row keys must be strings".to_string())),
+        }
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> std::result::Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let key = self
+            .next_key
+            .take()
+            .ok_or_else(|| SerializeError("Value serialized before its key".to_string()))?;
+        self.fields.push((key, value.serialize(ValueSerializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> std::result::Result<Self::Ok, Self::Error> {
+        Ok(self.fields)
+    }
+}
+
+/// Serializes a single field. Nested structs, maps and enum variants with
+/// data become JSON.
+struct ValueSerializer;
+
+impl Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = SerializeError;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant =
+        JsonCompound<<JsonSerializer as Serializer>::SerializeTupleVariant>;
+    type SerializeMap = JsonCompound<<JsonSerializer as Serializer>::SerializeMap>;
+    type SerializeStruct = JsonCompound<<JsonSerializer as Serializer>::SerializeStruct>;
+    type SerializeStructVariant =
+        JsonCompound<<JsonSerializer as Serializer>::SerializeStructVariant>;
+
+    fn serialize_bool(self, v: bool) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Int2(v as i16))
+    }
+
+    fn serialize_i16(self, v: i16) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Int2(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Int4(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Int8(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Numeric(v.to_string()))
+    }
+
+    fn serialize_u8(self, v: u8) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Int2(v as i16))
+    }
+
+    fn serialize_u16(self, v: u16) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Int4(v as i32))
+    }
+
+    fn serialize_u32(self, v: u32) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Int8(v as i64))
+    }
+
+    fn serialize_u64(self, v: u64) -> std::result::Result<Value, Self::Error> {
+        Ok(match i64::try_from(v) {
+            Ok(v) => Value::Int8(v),
+            Err(_) => Value::Numeric(v.to_string()),
+        })
+    }
+
+    fn serialize_u128(self, v: u128) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Numeric(v.to_string()))
+    }
+
+    fn serialize_f32(self, v: f32) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Float4(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Float8(v))
+    }
+
+    fn serialize_char(self, v: char) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Text(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Text(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Bytea(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T>(self, value: &T) -> std::result::Result<Value, Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Text(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> std::result::Result<Value, Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> std::result::Result<Value, Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        Ok(Value::Json(JsonSerializer.serialize_newtype_variant(
+            name,
+            variant_index,
+            variant,
+            value,
+        )?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> std::result::Result<SeqSerializer, Self::Error> {
+        Ok(SeqSerializer(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> std::result::Result<SeqSerializer, Self::Error> {
+        Ok(SeqSerializer(Vec::with_capacity(len)))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        len: usize,
+    ) -> std::result::Result<SeqSerializer, Self::Error> {
+        Ok(SeqSerializer(Vec::with_capacity(len)))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> std::result::Result<Self::SerializeTupleVariant, Self::Error> {
+        Ok(JsonCompound(JsonSerializer.serialize_tuple_variant(
+            name,
+            variant_index,
+            variant,
+            len,
+        )?))
+    }
+
+    fn serialize_map(
+        self,
+        len: Option<usize>,
+    ) -> std::result::Result<Self::SerializeMap, Self::Error> {
+        Ok(JsonCompound(JsonSerializer.serialize_map(len)?))
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> std::result::Result<Self::SerializeStruct, Self::Error> {
+        Ok(JsonCompound(JsonSerializer.serialize_struct(name, len)?))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> std::result::Result<Self::SerializeStructVariant, Self::Error> {
+        Ok(JsonCompound(JsonSerializer.serialize_struct_variant(
+            name,
+            variant_index,
+            variant,
+            len,
+        )?))
+    }
+}
+
+struct SeqSerializer(Vec<Value>);
+
+impl SerializeSeq for SeqSerializer {
+    type Ok = Value;
+    type Error = SerializeError;
+
+    fn serialize_element<T>(&mut self, value: &T) -> std::result::Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.0.push(value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Array(self.0))
+    }
+}
+
+impl SerializeTuple for SeqSerializer {
+    type Ok = Value;
+    type Error = SerializeError;
+
+    fn serialize_element<T>(&mut self, value: &T) -> std::result::Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> std::result::Result<Value, Self::Error> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl SerializeTupleStruct for SeqSerializer {
+    type Ok = Value;
+    type Error = SerializeError;
+
+    fn serialize_field<T>(&mut self, value: &T) -> std::result::Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> std::result::Result<Value, Self::Error> {
+        SerializeSeq::end(self)
+    }
+}
+
+struct JsonCompound<C>(C);
+
+impl<C> SerializeTupleVariant for JsonCompound<C>
+where
+    C: SerializeTupleVariant<Ok = serde_json::Value, Error = serde_json::Error>,
+{
+    type Ok = Value;
+    type Error = SerializeError;
+
+    fn serialize_field<T>(&mut self, value: &T) -> std::result::Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        Ok(self.0.serialize_field(value)?)
+    }
+
+    fn end(self) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Json(self.0.end()?))
+    }
+}
+
+impl<C> SerializeMap for JsonCompound<C>
+where
+    C: SerializeMap<Ok = serde_json::Value, Error = serde_json::Error>,
+{
+    type Ok = Value;
+    type Error = SerializeError;
+
+    fn serialize_key<T>(&mut self, key: &T) -> std::result::Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        Ok(self.0.serialize_key(key)?)
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> std::result::Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        Ok(self.0.serialize_value(value)?)
+    }
+
+    fn end(self) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Json(self.0.end()?))
+    }
+}
+
+impl<C> SerializeStruct for JsonCompound<C>
+where
+    C: SerializeStruct<Ok = serde_json::Value, Error = serde_json::Error>,
+{
+    type Ok = Value;
+    type Error = SerializeError;
+
+    fn serialize_field<T>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> std::result::Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        Ok(self.0.serialize_field(key, value)?)
+    }
+
+    fn end(self) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Json(self.0.end()?))
+    }
+}
+
+impl<C> SerializeStructVariant for JsonCompound<C>
+where
+    C: SerializeStructVariant<Ok = serde_json::Value, Error = serde_json::Error>,
+{
+    type Ok = Value;
+    type Error = SerializeError;
+
+    fn serialize_field<T>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> std::result::Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        Ok(self.0.serialize_field(key, value)?)
+    }
+
+    fn end(self) -> std::result::Result<Value, Self::Error> {
+        Ok(Value::Json(self.0.end()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize)]
+    struct Record {
+        id: u32,
+        name: String,
+        created_at: String,
+        tags: Vec<String>,
+        extra: Option<BTreeMap<String, i32>>,
+    }
+
+    struct Bytes;
+
+    impl Serialize for Bytes {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serializer.serialize_bytes(&[1])
+        }
+    }
+
+    fn record() -> Record {
+        Record {
+            id: 7,
+            name: "ana".to_string(),
+            created_at: "2024-01-02 03:04:05".to_string(),
+            tags: vec!["a".to_string()],
+            extra: None,
+        }
+    }
+
+    #[test]
+    fn infers_columns_from_record() {
+        let columns: Vec<(String, Type)> = infer_columns(&record())
+            .unwrap()
+            .into_iter()
+            .map(|column| (column.name, column.column_type))
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                ("id".to_string(), Type::INT8),
+                ("name".to_string(), Type::TEXT),
+                ("created_at".to_string(), Type::TEXT),
+                ("tags".to_string(), Type::TEXT_ARRAY),
+                ("extra".to_string(), Type::TEXT),
+            ]
+        );
+    }
+
+    #[test]
+    fn values_follow_columns_and_types() {
+        let columns = vec![
+            Column::new("created_at", Type::TIMESTAMP),
+            Column::new("extra", Type::JSONB),
+            Column::new("id", Type::INT4),
+        ];
+        let values = values_for_columns(&record(), &columns).unwrap();
+        assert_eq!(
+            values,
+            vec![
+                Value::Timestamp(
+                    chrono::NaiveDate::from_ymd_opt(2024, 1, 2)
+                        .unwrap()
+                        .and_hms_opt(3, 4, 5)
+                        .unwrap()
+                ),
+                Value::Null,
+                Value::Int4(7),
+            ]
+        );
+    }
+
+    #[test]
+    fn sequences_are_converted_element_wise() {
+        let record = BTreeMap::from([("ids", vec![vec!["1", "2"], vec!["3", "4"]])]);
+        let values = values_for_columns(&record, &[Column::new("ids", Type::INT4_ARRAY)]).unwrap();
+        let row = |values: [i32; 2]| Value::Array(values.map(Value::Int4).to_vec());
+        assert_eq!(values, vec![Value::Array(vec![row([1, 2]), row([3, 4])])]);
+    }
+
+    #[test]
+    fn rejects_fields_not_matching_columns() {
+        for column in [
+            Column::new("missing", Type::TEXT),
+            Column::new("tags", Type::TEXT),
+            Column::new("tags", Type::INT4_ARRAY),
+        ] {
+            let error = values_for_columns(&record(), &[column]).unwrap_err();
+            assert!(error.get_ref().unwrap().is::<SerializeError>());
+        }
+        let record = BTreeMap::from([("data", Bytes)]);
+        assert!(values_for_columns(&record, &[Column::new("data", Type::INT4)]).is_err());
+        let record = BTreeMap::from([("extra", BTreeMap::from([("a", 1)]))]);
+        assert!(values_for_columns(&record, &[Column::new("extra", Type::TEXT)]).is_err());
+    }
+
+    #[test]
+    fn rejects_non_records() {
+        assert!(to_row_values(&5).is_err());
+    }
+}
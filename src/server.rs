@@ -0,0 +1,963 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::ToSocketAddrs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::server_message::ServerMessage;
+use crate::{authenticate, read_startup, Limits, PostgresShim, PostgressIntermediary, Timeouts};
+
+/// The client a [`Server`] creates a shim for, taken from its startup
+/// message.
+#[derive(Debug, Clone)]
+pub struct SessionContext {
+    pub peer_address: PeerAddress,
+    pub user: String,
+    pub database: Option<String>,
+    /// Other startup parameters, such as `application_name`.
+    pub parameters: HashMap<String, String>,
+}
+
+/// Where a client connected from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerAddress {
+    Tcp(SocketAddr),
+    /// A connection over a Unix domain socket.
+    Local,
+}
+
+impl fmt::Display for PeerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => address.fmt(f),
+            Self::Local => f.write_str("[local]"),
+        }
+    }
+}
+
+/// How [`ShutdownHandle::shutdown`] treats open sessions. In every mode
+/// the server stops accepting connections right away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ShutdownMode {
+    /// Waits for clients to disconnect on their own.
+    Smart,
+    /// Ends each session with FATAL 57P01 `admin_shutdown` once the request
+    /// it is running, if any, completes.
+    Fast,
+    /// Closes every connection at once without telling the clients.
+    Immediate,
+}
+
+type ErrorHook = dyn Fn(PeerAddress, Error) + Send + Sync;
+
+/// Clients turned away at once when `max_connections` is reached, any more
+/// are disconnected without an error message.
+const MAX_REJECTING: usize = 16;
+/// How long to wait before accepting again after accepting failed, as
+/// errors such as running out of file descriptors last a while.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The open connections of a server, shared with its sessions and
+/// shutdown handles.
+#[derive(Default)]
+struct Shared {
+    connections: Mutex<Connections>,
+    closed: Condvar,
+}
+
+#[derive(Default)]
+struct Connections {
+    next_id: u64,
+    open: HashMap<u64, Socket>,
+    /// Clients being turned away, see [`MAX_REJECTING`].
+    rejecting: usize,
+    shutdown: Option<ShutdownMode>,
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixSocket),
+}
+
+/// Accepts TCP connections and serves each on its own thread with a shim
+/// made by the factory for that session.
+///
+/// ```no_run
+/// use postgres_shim::{DefaultServerParameters, Router, Server};
+///
+/// # fn parameters() -> DefaultServerParameters { unimplemented!() }
+/// Server::bind("127.0.0.1:5432", |_| Ok(Router::new(parameters())))?
+///     .on_error(|peer, error| eprintln!("{}: {}", peer, error))
+///     .run()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Server<Factory> {
+    listener: Listener,
+    factory: Arc<Factory>,
+    on_error: Arc<ErrorHook>,
+    max_connections: usize,
+    authentication_timeout: Duration,
+    timeouts: Timeouts,
+    limits: Limits,
+    shared: Arc<Shared>,
+}
+
+/// Shuts a [`Server`] down from another thread.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shared: Arc<Shared>,
+    listener: ListenerAddress,
+}
+
+/// Where to connect to wake up a server blocked accepting connections.
+#[derive(Clone)]
+enum ListenerAddress {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl<Factory> Server<Factory> {
+    pub fn bind<Shim>(address: impl ToSocketAddrs, factory: Factory) -> Result<Self>
+    where
+        Factory: Fn(&SessionContext) -> Result<Shim> + Send + Sync + 'static,
+    {
+        Ok(Self::new(
+            Listener::Tcp(TcpListener::bind(address)?),
+            factory,
+        ))
+    }
+
+    /// Listens on `.s.PGSQL.<port>` in `directory`, the socket libpq
+    /// connects to for `host=<directory> port=<port>`. The socket is
+    /// guarded by a `.s.PGSQL.<port>.lock` file and both are removed when
+    /// the server is dropped.
+    #[cfg(unix)]
+    pub fn bind_unix<Shim>(directory: impl AsRef<Path>, port: u16, factory: Factory) -> Result<Self>
+    where
+        Factory: Fn(&SessionContext) -> Result<Shim> + Send + Sync + 'static,
+    {
+        Ok(Self::new(
+            Listener::Unix(UnixSocket::bind(directory.as_ref(), port)?),
+            factory,
+        ))
+    }
+
+    fn new(listener: Listener, factory: Factory) -> Self {
+        Self {
+            listener,
+            factory: Arc::new(factory),
+            on_error: Arc::new(|peer, error| eprintln!("connection from {}: {}", peer, error)),
+            max_connections: 100,
+            authentication_timeout: Duration::from_secs(60),
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            shared: Arc::default(),
+        }
+    }
+
+    /// Clients connecting while this many sessions are open are turned away
+    /// with 53300 `too_many_connections`. Defaults to 100, as in Postgres.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// How long a client may take to send its startup and password
+    /// messages before it is disconnected. Defaults to a minute, as in
+    /// Postgres.
+    pub fn authentication_timeout(mut self, timeout: Duration) -> Self {
+        self.authentication_timeout = timeout;
+        self
+    }
+
+    /// Called with the error that ended a connection, from the connection's
+    /// thread. Errors are printed to stderr by default. Failing to accept a
+    /// connection is reported with the address the server listens on.
+    pub fn on_error<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(PeerAddress, Error) + Send + Sync + 'static,
+    {
+        self.on_error = Arc::new(hook);
+        self
+    }
+
+    /// The address of a TCP server.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        match &self.listener {
+            Listener::Tcp(listener) => listener.local_addr(),
+            #[cfg(unix)]
+            Listener::Unix(_) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the server listens on a Unix socket",
+            )),
+        }
+    }
+
+    /// The default session timeouts, which clients may change with `SET`.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Bounds what clients may send, see [`Limits`].
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn shutdown_handle(&self) -> Result<ShutdownHandle> {
+        let listener = match &self.listener {
+            Listener::Tcp(listener) => {
+                let mut address = listener.local_addr()?;
+                if address.ip().is_unspecified() {
+                    address.set_ip(match address.ip() {
+                        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                    });
+                }
+                ListenerAddress::Tcp(address)
+            }
+            #[cfg(unix)]
+            Listener::Unix(socket) => ListenerAddress::Unix(socket.path.clone()),
+        };
+        Ok(ShutdownHandle {
+            shared: self.shared.clone(),
+            listener,
+        })
+    }
+
+    /// The socket path of a server bound with [`Server::bind_unix`].
+    #[cfg(unix)]
+    pub fn socket_path(&self) -> Option<&Path> {
+        match &self.listener {
+            Listener::Tcp(_) => None,
+            Listener::Unix(socket) => Some(&socket.path),
+        }
+    }
+
+    /// Accepts connections until the server is shut down. After a smart or
+    /// fast shutdown it returns once every session has ended.
+    pub fn run<Shim, PortalData>(self) -> Result<()>
+    where
+        Factory: Fn(&SessionContext) -> Result<Shim> + Send + Sync + 'static,
+        Shim: PostgresShim<PortalData>,
+    {
+        let address = match &self.listener {
+            Listener::Tcp(listener) => PeerAddress::Tcp(listener.local_addr()?),
+            #[cfg(unix)]
+            Listener::Unix(_) => PeerAddress::Local,
+        };
+        loop {
+            let accepted = self
+                .listener
+                .accept()
+                .and_then(|(socket, peer)| Ok((socket.try_clone()?, socket, peer)));
+            let mut connections = self.shared.connections.lock().unwrap();
+            if connections.shutdown.is_some() {
+                break;
+            }
+            let (registered, socket, peer) = match accepted {
+                Ok(accepted) => accepted,
+                Err(error) => {
+                    drop(connections);
+                    (self.on_error)(address, error);
+                    thread::sleep(ACCEPT_RETRY_DELAY);
+                    continue;
+                }
+            };
+            if connections.open.len() >= self.max_connections {
+                if connections.rejecting < MAX_REJECTING {
+                    connections.rejecting += 1;
+                    let shared = self.shared.clone();
+                    let timeout = self.authentication_timeout;
+                    thread::spawn(move || {
+                        reject(socket, timeout);
+                        shared.connections.lock().unwrap().rejecting -= 1;
+                    });
+                }
+                continue;
+            }
+            let id = connections.next_id;
+            connections.next_id += 1;
+            connections.open.insert(id, registered);
+            drop(connections);
+            self.spawn(id, socket, peer);
+        }
+        drop(self.listener);
+        let mut connections = self.shared.connections.lock().unwrap();
+        while connections.shutdown != Some(ShutdownMode::Immediate) && !connections.open.is_empty()
+        {
+            connections = self.shared.closed.wait(connections).unwrap();
+        }
+        Ok(())
+    }
+
+    fn spawn<Shim, PortalData>(&self, id: u64, socket: Socket, peer: PeerAddress)
+    where
+        Factory: Fn(&SessionContext) -> Result<Shim> + Send + Sync + 'static,
+        Shim: PostgresShim<PortalData>,
+    {
+        let factory = self.factory.clone();
+        let on_error = self.on_error.clone();
+        let authentication_timeout = self.authentication_timeout;
+        let timeouts = self.timeouts;
+        let limits = self.limits;
+        let registration = Registration {
+            shared: self.shared.clone(),
+            id,
+        };
+        thread::spawn(move || {
+            let writer = socket.try_clone();
+            let result = serve_connection(
+                socket,
+                peer,
+                &*factory,
+                authentication_timeout,
+                timeouts,
+                limits,
+            );
+            let shutdown = registration.shared.connections.lock().unwrap().shutdown;
+            match (result, shutdown) {
+                (Ok(()), _) | (Err(_), Some(ShutdownMode::Immediate)) => {}
+                (Err(_), Some(ShutdownMode::Fast)) => {
+                    if let Ok(mut writer) = writer {
+                        let _ = write_fatal(
+                            &mut writer,
+                            "57P01",
+                            "terminating connection due to administrator command",
+                        );
+                    }
+                }
+                (Err(error), _) => on_error(peer, error),
+            }
+            drop(registration);
+        });
+    }
+}
+
+impl Listener {
+    fn accept(&self) -> Result<(Socket, PeerAddress)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, address) = listener.accept()?;
+                stream.set_nodelay(true)?;
+                Ok((Socket::Tcp(stream), PeerAddress::Tcp(address)))
+            }
+            #[cfg(unix)]
+            Self::Unix(socket) => {
+                let (stream, _) = socket.listener.accept()?;
+                Ok((Socket::Unix(stream), PeerAddress::Local))
+            }
+        }
+    }
+}
+
+impl ShutdownHandle {
+    /// Stops the server accepting connections and ends sessions as `mode`
+    /// says. A later call may escalate to a faster mode.
+    pub fn shutdown(&self, mode: ShutdownMode) {
+        let mut connections = self.shared.connections.lock().unwrap();
+        let mode = connections
+            .shutdown
+            .map_or(mode, |current| current.max(mode));
+        connections.shutdown = Some(mode);
+        for socket in connections.open.values() {
+            // Sessions blocked reading the next message see the end of the
+            // stream, the write half stays open for the FATAL message.
+            let _ = match mode {
+                ShutdownMode::Smart => Ok(()),
+                ShutdownMode::Fast => socket.shutdown(Shutdown::Read),
+                ShutdownMode::Immediate => socket.shutdown(Shutdown::Both),
+            };
+        }
+        drop(connections);
+        self.shared.closed.notify_all();
+        // Wakes the accept loop up so it sees the shutdown.
+        let _ = match &self.listener {
+            ListenerAddress::Tcp(address) => TcpStream::connect(address).map(drop),
+            #[cfg(unix)]
+            ListenerAddress::Unix(path) => UnixStream::connect(path).map(drop),
+        };
+    }
+}
+
+/// Removes a session from the open connections when its thread ends.
+struct Registration {
+    shared: Arc<Shared>,
+    id: u64,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut connections = self.shared.connections.lock().unwrap();
+        connections.open.remove(&self.id);
+        self.shared.closed.notify_all();
+    }
+}
+
+/// An accepted connection.
+enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Socket {
+    fn try_clone(&self) -> Result<Self> {
+        match self {
+            Self::Tcp(stream) => stream.try_clone().map(Self::Tcp),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.try_clone().map(Self::Unix),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    fn shutdown(&self, how: Shutdown) -> Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.shutdown(how),
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A listening Unix socket and its lock file, removed on drop.
+#[cfg(unix)]
+struct UnixSocket {
+    listener: UnixListener,
+    path: PathBuf,
+    lock_path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    fn bind(directory: &Path, port: u16) -> Result<Self> {
+        use std::fs::{self, OpenOptions, Permissions};
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = directory.join(format!(".s.PGSQL.{}", port));
+        let lock_path = directory.join(format!(".s.PGSQL.{}.lock", port));
+        let mut lock_file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(lock_file) => lock_file,
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                // Like Postgres, a lock whose socket no one answers on is
+                // left over from a server that did not shut down cleanly.
+                if UnixStream::connect(&path).is_ok() {
+                    return Err(Error::new(
+                        ErrorKind::AddrInUse,
+                        format!("another server is listening on {}", path.display()),
+                    ));
+                }
+                fs::remove_file(&lock_path)?;
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&lock_path)?
+            }
+            Err(error) => return Err(error),
+        };
+        writeln!(lock_file, "{}", std::process::id())?;
+        writeln!(lock_file, "{}", directory.display())?;
+        writeln!(lock_file, "{}", port)?;
+        // The lock is ours, so a socket file left behind is stale.
+        let listener = match fs::remove_file(&path) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error),
+            _ => UnixListener::bind(&path),
+        };
+        let socket = match listener {
+            Ok(listener) => Self {
+                listener,
+                path,
+                lock_path,
+            },
+            Err(error) => {
+                let _ = fs::remove_file(&lock_path);
+                return Err(error);
+            }
+        };
+        // Any local user may connect, as with Postgres' default
+        // unix_socket_permissions.
+        fs::set_permissions(&socket.path, Permissions::from_mode(0o777))?;
+        Ok(socket)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+        let _ = std::fs::remove_file(&self.lock_path);
+    }
+}
+
+fn serve_connection<Factory, Shim, PortalData>(
+    mut stream: Socket,
+    peer_address: PeerAddress,
+    factory: &Factory,
+    authentication_timeout: Duration,
+    timeouts: Timeouts,
+    limits: Limits,
+) -> Result<()>
+where
+    Factory: Fn(&SessionContext) -> Result<Shim>,
+    Shim: PostgresShim<PortalData>,
+{
+    stream.set_read_timeout(Some(authentication_timeout))?;
+    let Some(startup_message) = authenticate(&mut stream)? else {
+        return Ok(());
+    };
+    stream.set_read_timeout(None)?;
+    let context = SessionContext {
+        peer_address,
+        user: startup_message.user.clone(),
+        database: startup_message.database.clone(),
+        parameters: startup_message.parameters.clone(),
+    };
+    let shim = match factory(&context) {
+        Ok(shim) => shim,
+        Err(error) => {
+            write_fatal(&mut stream, "08004", &error.to_string())?;
+            return Err(error);
+        }
+    };
+    PostgressIntermediary::new(shim, stream)
+        .with_timeouts(timeouts)
+        .with_limits(limits)
+        .with_read_timeout(Socket::set_read_timeout)
+        .serve(&startup_message)
+}
+
+/// Turns a client away once it sent its startup message, as Postgres does
+/// when `max_connections` is reached.
+fn reject(mut socket: Socket, timeout: Duration) {
+    if socket.set_read_timeout(Some(timeout)).is_err() {
+        return;
+    }
+    if let Ok(Some(_)) = read_startup(&mut socket) {
+        let _ = write_fatal(&mut socket, "53300", "sorry, too many clients already");
+    }
+}
+
+fn write_fatal(stream: &mut impl Write, code: &str, message: &str) -> Result<()> {
+    ServerMessage::ErrorResponse {
+        severity: "FATAL",
+        code,
+        message: message.to_string(),
+        position: None,
+    }
+    .write(stream)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Column, DefaultServerParameters, Response, Router, Type, Value};
+    use std::net::TcpStream;
+    use std::sync::mpsc;
+
+    fn parameters() -> DefaultServerParameters {
+        DefaultServerParameters {
+            server_version: "14".to_string(),
+            server_encoding: "UTF8".to_string(),
+            client_encoding: "UTF8".to_string(),
+            application_name: String::new(),
+            default_transaction_read_only: "off".to_string(),
+            in_hot_standby: "off".to_string(),
+            is_superuser: "off".to_string(),
+            session_authorization: "postgres".to_string(),
+            date_style: "ISO, MDY".to_string(),
+            interval_style: "postgres".to_string(),
+            time_zone: "UTC".to_string(),
+            integer_datetimes: "on".to_string(),
+            standard_conforming_strings: "on".to_string(),
+        }
+    }
+
+    fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![tag];
+        message.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+        message.extend_from_slice(body);
+        message
+    }
+
+    /// Sends the startup message for `user`.
+    fn send_startup<S: Write>(mut stream: S, user: &str) -> S {
+        let mut startup = 196608_i32.to_be_bytes().to_vec();
+        startup.extend_from_slice(format!("user\0{}\0\0", user).as_bytes());
+        stream
+            .write_all(&((startup.len() + 4) as i32).to_be_bytes())
+            .unwrap();
+        stream.write_all(&startup).unwrap();
+        stream
+    }
+
+    /// Sends the startup and password messages for `user`.
+    fn start<S: Write>(stream: S, user: &str) -> S {
+        let mut stream = send_startup(stream, user);
+        stream.write_all(&message(b'p', b"secret\0")).unwrap();
+        stream
+    }
+
+    /// Runs `SELECT name FROM users` with the extended protocol and
+    /// terminates, returning everything the server sent.
+    fn select_name(mut stream: impl Read + Write) -> Vec<u8> {
+        stream
+            .write_all(&message(b'P', b"\0SELECT name FROM users\0\0\0"))
+            .unwrap();
+        stream
+            .write_all(&message(b'B', b"\0\0\0\0\0\0\0\0"))
+            .unwrap();
+        stream.write_all(&message(b'D', b"P\0")).unwrap();
+        stream.write_all(&message(b'E', b"\0\0\0\0\0")).unwrap();
+        stream.write_all(&message(b'S', b"")).unwrap();
+        stream.write_all(&message(b'X', b"")).unwrap();
+        let mut received = vec![];
+        stream.read_to_end(&mut received).unwrap();
+        received
+    }
+
+    #[test]
+    fn creates_a_shim_per_session() {
+        let (errors, failures) = mpsc::channel();
+        let server = Server::bind("127.0.0.1:0", |context| {
+            if context.user == "mallory" {
+                return Err(Error::new(ErrorKind::PermissionDenied, "go away"));
+            }
+            let user = context.user.clone();
+            Ok(Router::new(parameters()).select("users", move |_| {
+                Ok(Response::rows(
+                    vec![Column::new("name", Type::TEXT)],
+                    vec![vec![Value::Text(user.clone())]],
+                ))
+            }))
+        })
+        .unwrap()
+        .on_error(move |_, error| errors.send(error.kind()).unwrap());
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let connect = move || TcpStream::connect(address).unwrap();
+        let alice = thread::spawn(move || select_name(start(connect(), "alice")));
+        let bob = select_name(start(connect(), "bob"));
+        assert!(bob.windows(3).any(|window| window == b"bob"));
+        let alice = alice.join().unwrap();
+        assert!(alice.windows(5).any(|window| window == b"alice"));
+        assert!(!alice.windows(3).any(|window| window == b"bob"));
+
+        let mut mallory = vec![];
+        start(connect(), "mallory")
+            .read_to_end(&mut mallory)
+            .unwrap();
+        assert!(mallory.windows(7).any(|window| window == b"go away"));
+        assert_eq!(failures.recv().unwrap(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn declines_ssl_and_closes_on_cancel() {
+        let server = Server::bind("127.0.0.1:0", |_| Ok(Router::new(parameters()))).unwrap();
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        // libpq asks for TLS first with sslmode=prefer.
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"\0\0\0\x08\x04\xd2\x16\x2f").unwrap();
+        let mut answer = [0];
+        stream.read_exact(&mut answer).unwrap();
+        assert_eq!(&answer, b"N");
+        wait_until_ready(&mut start(stream, "alice"));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"\0\0\0\x10\x04\xd2\x16\x2e\0\0\0\x01\0\0\0\x02")
+            .unwrap();
+        let mut received = vec![];
+        stream.read_to_end(&mut received).unwrap();
+        assert!(received.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn listens_on_a_libpq_socket() {
+        let directory = std::env::temp_dir().join(format!("postgres-shim-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let factory = |_: &SessionContext| -> Result<Router> {
+            Ok(Router::new(parameters()).select("users", |_| {
+                Ok(Response::rows(
+                    vec![Column::new("name", Type::TEXT)],
+                    vec![vec![Value::Text("local".to_string())]],
+                ))
+            }))
+        };
+
+        // A lock without a live socket is stale and taken over.
+        std::fs::write(directory.join(".s.PGSQL.5433.lock"), "1\n").unwrap();
+        let server = Server::bind_unix(&directory, 5433, factory).unwrap();
+        let path = server.socket_path().unwrap().to_path_buf();
+        assert_eq!(path, directory.join(".s.PGSQL.5433"));
+        let lock = std::fs::read_to_string(directory.join(".s.PGSQL.5433.lock")).unwrap();
+        assert_eq!(lock.lines().next(), Some(&*std::process::id().to_string()));
+        thread::spawn(move || server.run());
+
+        let error = Server::bind_unix(&directory, 5433, factory).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::AddrInUse);
+
+        let received = select_name(start(UnixStream::connect(&path).unwrap(), "alice"));
+        assert!(received.windows(5).any(|window| window == b"local"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    /// Reads backend messages until the session is ready for a query.
+    fn wait_until_ready(stream: &mut impl Read) {
+        loop {
+            let mut header = [0; 5];
+            stream.read_exact(&mut header).unwrap();
+            let length = i32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+            let mut body = vec![0; length as usize - 4];
+            stream.read_exact(&mut body).unwrap();
+            if header[0] == b'Z' {
+                return;
+            }
+        }
+    }
+
+    fn contains(received: &[u8], text: &str) -> bool {
+        received
+            .windows(text.len())
+            .any(|window| window == text.as_bytes())
+    }
+
+    #[test]
+    fn limits_connections_and_shuts_down_fast() {
+        let server = Server::bind("127.0.0.1:0", |_| Ok(Router::new(parameters())))
+            .unwrap()
+            .max_connections(1);
+        let address = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let running = thread::spawn(move || server.run());
+
+        let mut alice = start(TcpStream::connect(address).unwrap(), "alice");
+        wait_until_ready(&mut alice);
+        let mut bob = vec![];
+        // Turned away before authentication.
+        send_startup(TcpStream::connect(address).unwrap(), "bob")
+            .read_to_end(&mut bob)
+            .unwrap();
+        assert!(contains(&bob, "53300"));
+
+        handle.shutdown(ShutdownMode::Fast);
+        let mut received = vec![];
+        alice.read_to_end(&mut received).unwrap();
+        assert!(contains(&received, "57P01"));
+        running.join().unwrap().unwrap();
+        assert!(TcpStream::connect(address).is_err());
+    }
+
+    #[test]
+    fn disconnects_clients_slow_to_authenticate() {
+        let (errors, failures) = mpsc::channel();
+        let server = Server::bind("127.0.0.1:0", |_| Ok(Router::new(parameters())))
+            .unwrap()
+            .max_connections(1)
+            .authentication_timeout(Duration::from_millis(50))
+            .on_error(move |_, error| errors.send(error.kind()).unwrap());
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut silent = TcpStream::connect(address).unwrap();
+        let mut received = vec![];
+        silent.read_to_end(&mut received).unwrap();
+        assert!(received.is_empty());
+        assert!(matches!(
+            failures.recv().unwrap(),
+            ErrorKind::WouldBlock | ErrorKind::TimedOut
+        ));
+
+        // The slot is free again, and clients turned away are not waited
+        // for either.
+        let mut alice = start(TcpStream::connect(address).unwrap(), "alice");
+        wait_until_ready(&mut alice);
+        let mut rejected = TcpStream::connect(address).unwrap();
+        rejected.read_to_end(&mut received).unwrap();
+        assert!(received.is_empty());
+    }
+
+    #[test]
+    fn smart_shutdown_waits_for_sessions() {
+        let server = Server::bind("127.0.0.1:0", |_| {
+            Ok(Router::new(parameters()).select("users", |_| {
+                Ok(Response::rows(
+                    vec![Column::new("name", Type::TEXT)],
+                    vec![vec![Value::Text("still here".to_string())]],
+                ))
+            }))
+        })
+        .unwrap();
+        let address = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let running = thread::spawn(move || server.run());
+
+        let mut alice = start(TcpStream::connect(address).unwrap(), "alice");
+        wait_until_ready(&mut alice);
+        handle.shutdown(ShutdownMode::Smart);
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!running.is_finished());
+
+        let received = select_name(alice);
+        assert!(contains(&received, "still here"));
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn ends_idle_sessions() {
+        let server = Server::bind("127.0.0.1:0", |_| {
+            Ok(Router::new(parameters()).pattern("(?i)^begin", |_| Ok(Response::command("BEGIN"))))
+        })
+        .unwrap()
+        .timeouts(Timeouts {
+            idle_session: Some(Duration::from_millis(50)),
+            idle_in_transaction_session: Some(Duration::from_millis(50)),
+            ..Timeouts::default()
+        });
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut idle = vec![];
+        start(TcpStream::connect(address).unwrap(), "alice")
+            .read_to_end(&mut idle)
+            .unwrap();
+        assert!(contains(&idle, "57P05"));
+
+        let mut stream = start(TcpStream::connect(address).unwrap(), "bob");
+        wait_until_ready(&mut stream);
+        stream.write_all(&message(b'P', b"\0BEGIN\0\0\0")).unwrap();
+        stream
+            .write_all(&message(b'B', b"\0\0\0\0\0\0\0\0"))
+            .unwrap();
+        stream.write_all(&message(b'E', b"\0\0\0\0\0")).unwrap();
+        stream.write_all(&message(b'S', b"")).unwrap();
+        let mut in_transaction = vec![];
+        stream.read_to_end(&mut in_transaction).unwrap();
+        assert!(contains(&in_transaction, "Z\0\0\0\x05T"));
+        assert!(contains(&in_transaction, "25P03"));
+    }
+
+    #[test]
+    fn closes_sessions_breaking_the_protocol() {
+        let (errors, failures) = mpsc::channel();
+        let server = Server::bind("127.0.0.1:0", |_| Ok(Router::new(parameters())))
+            .unwrap()
+            .on_error(move |_, error| errors.send(error.kind()).unwrap());
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut stream = start(TcpStream::connect(address).unwrap(), "alice");
+        wait_until_ready(&mut stream);
+        stream.write_all(&message(b'z', b"")).unwrap();
+        let mut received = vec![];
+        stream.read_to_end(&mut received).unwrap();
+        assert!(contains(&received, "08P01"));
+        assert_eq!(failures.recv().unwrap(), ErrorKind::InvalidData);
+
+        // A Bind with two parameter formats and one parameter fails, but the
+        // session goes on.
+        let mut stream = start(TcpStream::connect(address).unwrap(), "bob");
+        wait_until_ready(&mut stream);
+        stream
+            .write_all(&message(b'P', b"\0SHOW statement_timeout\0\0\0"))
+            .unwrap();
+        stream
+            .write_all(&message(b'B', b"\0\0\0\x02\0\0\0\0\0\x01\0\0\0\x011\0\0"))
+            .unwrap();
+        stream.write_all(&message(b'S', b"")).unwrap();
+        let received = select_name(stream);
+        assert!(contains(&received, "2 parameter formats but 1 parameters"));
+        assert!(contains(&received, "08P01"));
+        assert!(contains(&received, "Z\0\0\0\x05I"));
+
+        // A hostile message must not take the server down with it.
+        wait_until_ready(&mut start(TcpStream::connect(address).unwrap(), "bob"));
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let server = Server::bind("127.0.0.1:0", |_| Ok(Router::new(parameters())))
+            .unwrap()
+            .limits(Limits {
+                max_message_size: 1024,
+                max_statements: 1,
+                ..Limits::default()
+            });
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut stream = start(TcpStream::connect(address).unwrap(), "alice");
+        wait_until_ready(&mut stream);
+        stream
+            .write_all(&message(b'P', b"a\0SET statement_timeout TO 0\0\0\0"))
+            .unwrap();
+        stream.write_all(&message(b'S', b"")).unwrap();
+        wait_until_ready(&mut stream);
+        stream
+            .write_all(&message(b'P', b"b\0SET statement_timeout TO 0\0\0\0"))
+            .unwrap();
+        stream.write_all(&message(b'S', b"")).unwrap();
+        stream.write_all(b"Q\x7f\xff\xff\xff").unwrap();
+        let mut received = vec![];
+        stream.read_to_end(&mut received).unwrap();
+        // The statement fails but the session goes on, until a message that
+        // is too long ends it.
+        assert!(contains(&received, "too many prepared statements"));
+        assert!(contains(&received, "Z\0\0\0\x05I"));
+        assert!(contains(&received, "FATAL"));
+        assert!(contains(&received, "message length"));
+    }
+}
@@ -1,8 +1,8 @@
-use bytes::BytesMut;
-use postgres_types::Type;
+use bytes::{BufMut, BytesMut};
 use std::io::{Cursor, Result, Write};
 
 use crate::client_message::FormatCode;
+use crate::{Column, Type};
 
 #[derive(Debug)]
 pub enum ServerMessage<'a> {
@@ -13,16 +13,23 @@ pub enum ServerMessage<'a> {
         secret_key: i32,
     },
     BindComplete,
+    CloseComplete,
     CommandComplete(CommandCompleteTag),
     DataRow {
         fields: Vec<Option<BytesMut>>,
     },
     ErrorResponse {
-        code: u8,
+        severity: &'a str,
+        code: &'a str,
         message: String,
+        /// 1-based character position in the query the error refers to.
+        position: Option<usize>,
     },
     EmptyQueryResponse,
     NoData,
+    ParameterDescription {
+        types: Vec<Type>,
+    },
     ParameterStatus {
         name: &'a str,
         value: &'a str,
@@ -32,20 +39,28 @@ pub enum ServerMessage<'a> {
         transaction_status: u8,
     },
     RowDescription {
-        fields: Vec<(String, Type, FormatCode)>,
+        fields: Vec<(Column, FormatCode)>,
     },
 }
 
 #[derive(Debug)]
 pub enum CommandCompleteTag {
     Select { rows: u32 },
+    Set,
+    Show,
+    Other(String),
 }
 
 impl<'a> ServerMessage<'a> {
+    /// Appends the message to `buffer`.
+    pub fn encode(self, buffer: &mut BytesMut) -> Result<()> {
+        self.write(&mut buffer.writer())
+    }
+
     pub fn write(self, stream: &mut impl WritePostgresExt) -> Result<()> {
         match self {
             Self::AuthenticationOk => {
-                stream.write(&['R' as u8])?;
+                stream.write_byte(b'R')?;
                 stream.write_int32(8)?;
                 stream.write_int32(0)?;
             }
@@ -53,71 +68,96 @@ impl<'a> ServerMessage<'a> {
                 process_id,
                 secret_key,
             } => {
-                stream.write_byte('K' as u8)?;
+                stream.write_byte(b'K')?;
                 stream.write_int32(12)?;
                 stream.write_int32(process_id)?;
                 stream.write_int32(secret_key)?;
             }
             Self::ReadyForQuery { transaction_status } => {
-                stream.write_byte('Z' as u8)?;
+                stream.write_byte(b'Z')?;
                 stream.write_int32(5)?;
-                stream.write(&[transaction_status])?;
+                stream.write_all(&[transaction_status])?;
             }
             Self::AuthenticationCleartextPassword => {
-                stream.write_byte('R' as u8)?;
+                stream.write_byte(b'R')?;
                 stream.write_int32(8)?;
                 stream.write_int32(3)?;
             }
             Self::ParameterStatus { name, value } => {
-                stream.write_byte('S' as u8)?;
+                stream.write_byte(b'S')?;
                 stream.write_int32((4 + name.len() + 1 + value.len() + 1) as i32)?;
-                stream.write_all(&name.as_bytes())?;
+                stream.write_all(name.as_bytes())?;
                 stream.write_byte(0)?;
-                stream.write_all(&value.as_bytes())?;
+                stream.write_all(value.as_bytes())?;
                 stream.write_byte(0)?;
             }
             Self::ParseComplete => {
-                stream.write_byte('1' as u8)?;
+                stream.write_byte(b'1')?;
                 stream.write_int32(4)?;
             }
             Self::BindComplete => {
-                stream.write_byte('2' as u8)?;
+                stream.write_byte(b'2')?;
                 stream.write_int32(4)?;
             }
-            Self::ErrorResponse { code, message } => {
-                stream.write_byte('E' as u8)?;
-                stream.write_int32((message.len() + 4 + 1 + 1) as i32)?;
-                stream.write_byte(code)?;
-                stream.write_all(&message.as_bytes())?;
-                stream.write_byte(0)?;
+            Self::CloseComplete => {
+                stream.write_byte(b'3')?;
+                stream.write_int32(4)?;
+            }
+            Self::ErrorResponse {
+                severity,
+                code,
+                message,
+                position,
+            } => {
+                stream.write_byte(b'E')?;
+                let mut buffer: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+                let position = position.map(|position| position.to_string());
+                let fields = [
+                    (b'S', Some(severity)),
+                    (b'V', Some(severity)),
+                    (b'C', Some(code)),
+                    (b'M', Some(message.as_str())),
+                    (b'P', position.as_deref()),
+                ];
+                for (field_type, value) in fields
+                    .into_iter()
+                    .filter_map(|(field_type, value)| Some((field_type, value?)))
+                {
+                    buffer.write_byte(field_type)?;
+                    buffer.write_all(value.as_bytes())?;
+                    buffer.write_byte(0)?;
+                }
+                buffer.write_byte(0)?;
+                let buffer = buffer.into_inner();
+                stream.write_int32(buffer.len() as i32 + 4)?;
+                stream.write_all(&buffer)?;
             }
             Self::RowDescription { fields } => {
-                stream.write_byte('T' as u8)?;
+                stream.write_byte(b'T')?;
                 let mut buffer: Cursor<Vec<u8>> = Cursor::new(Vec::new());
                 buffer.write_int16(fields.len() as u16)?;
                 fields
                     .iter()
-                    .map(|(field_name, field_type, field_format)| -> Result<()> {
-                        buffer.write_all(&field_name.as_bytes())?;
+                    .try_for_each(|(column, field_format)| -> Result<()> {
+                        buffer.write_all(column.name.as_bytes())?;
                         buffer.write_byte(0)?;
-                        buffer.write_int32(0)?;
-                        buffer.write_int16(0)?;
-                        buffer.write_int32(field_type.oid() as i32)?;
-                        buffer.write_int16(0)?;
-                        buffer.write_int32(0)?;
+                        buffer.write_int32(column.table_oid() as i32)?;
+                        buffer.write_int16(column.attribute_number() as u16)?;
+                        buffer.write_int32(column.column_type.oid() as i32)?;
+                        buffer.write_int16(column.type_size() as u16)?;
+                        buffer.write_int32(column.type_modifier())?;
                         buffer.write_int16(match field_format {
                             FormatCode::Text => 0,
                             FormatCode::Binary => 1,
                         })?;
                         Ok(())
-                    })
-                    .collect::<Result<()>>()?;
+                    })?;
                 let buffer = buffer.into_inner();
                 stream.write_int32(buffer.len() as i32 + 4)?;
                 stream.write_all(&buffer)?;
             }
             Self::DataRow { fields } => {
-                stream.write_byte('D' as u8)?;
+                stream.write_byte(b'D')?;
                 let mut buffer: Cursor<Vec<u8>> = Cursor::new(Vec::new());
                 buffer.write_int16(fields.len() as u16)?;
                 for field in fields {
@@ -134,12 +174,21 @@ impl<'a> ServerMessage<'a> {
                 stream.write_all(&buffer)?;
             }
             Self::CommandComplete(command_complete) => {
-                stream.write_byte('C' as u8)?;
+                stream.write_byte(b'C')?;
                 let mut buffer: Cursor<Vec<u8>> = Cursor::new(Vec::new());
                 match command_complete {
                     CommandCompleteTag::Select { rows } => {
                         buffer.write_all(format!("SELECT {}", rows).as_bytes())?;
                     }
+                    CommandCompleteTag::Set => {
+                        buffer.write_all(b"SET")?;
+                    }
+                    CommandCompleteTag::Show => {
+                        buffer.write_all(b"SHOW")?;
+                    }
+                    CommandCompleteTag::Other(tag) => {
+                        buffer.write_all(tag.as_bytes())?;
+                    }
                 }
                 buffer.write_byte(0)?;
                 let buffer = buffer.into_inner();
@@ -147,11 +196,19 @@ impl<'a> ServerMessage<'a> {
                 stream.write_all(&buffer)?;
             }
             Self::NoData => {
-                stream.write_byte('n' as u8)?;
+                stream.write_byte(b'n')?;
                 stream.write_int32(4)?;
             }
+            Self::ParameterDescription { types } => {
+                stream.write_byte(b't')?;
+                stream.write_int32(types.len() as i32 * 4 + 6)?;
+                stream.write_int16(types.len() as u16)?;
+                for ty in types {
+                    stream.write_int32(ty.oid() as i32)?;
+                }
+            }
             Self::EmptyQueryResponse => {
-                stream.write_byte('I' as u8)?;
+                stream.write_byte(b'I')?;
                 stream.write_int32(4)?;
             }
         }
@@ -171,8 +228,14 @@ pub trait WritePostgresExt: Write {
     }
 
     fn write_byte(&mut self, byte: u8) -> Result<()> {
-        self.write(&[byte])?;
+        self.write_all(&[byte])?;
         Ok(())
     }
+
+    /// Writes a null terminated string.
+    fn write_string(&mut self, string: &str) -> Result<()> {
+        self.write_all(string.as_bytes())?;
+        self.write_byte(0)
+    }
 }
 impl<T> WritePostgresExt for T where T: Write {}
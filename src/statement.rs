@@ -0,0 +1,171 @@
+use sqlparser::ast::Statement;
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use sqlparser::tokenizer::{Location, Token, Tokenizer};
+use std::ops::Range;
+
+/// A query parsed with the Postgres dialect, handed to
+/// [`PostgresShim::prepare_parsed`](crate::PostgresShim::prepare_parsed).
+#[derive(Debug, Clone)]
+pub struct ParsedStatement {
+    /// The query text as sent by the client.
+    pub query: String,
+    pub statement: Statement,
+    /// The `$n` placeholders in the order they appear in `query`.
+    pub placeholders: Vec<Placeholder>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placeholder {
+    /// The `n` of `$n`, starting at 1.
+    pub number: usize,
+    /// Byte range of the placeholder within the query text.
+    pub span: Range<usize>,
+}
+
+impl ParsedStatement {
+    /// The number of parameters the statement takes, the highest `$n` used.
+    pub fn parameter_count(&self) -> usize {
+        self.placeholders
+            .iter()
+            .map(|placeholder| placeholder.number)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// A query that failed to parse, reported as SQLSTATE 42601.
+#[derive(Debug)]
+pub(crate) struct SyntaxError {
+    pub message: String,
+    /// 1-based character position in the query, as in the `P` field of
+    /// `ErrorResponse`.
+    pub position: Option<usize>,
+}
+
+/// Parses a query sent with `Parse`. An empty query is `None`, several
+/// statements are an error as they are for Postgres.
+pub(crate) fn parse(query: &str) -> Result<Option<ParsedStatement>, SyntaxError> {
+    let dialect = PostgreSqlDialect {};
+    let tokens = Tokenizer::new(&dialect, query)
+        .tokenize_with_location()
+        .map_err(|error| SyntaxError {
+            message: format!("syntax error: {}", error.message),
+            position: Some(position(query, error.location)),
+        })?;
+    let placeholders = tokens
+        .iter()
+        .filter_map(|token| match &token.token {
+            Token::Placeholder(name) => Some(Placeholder {
+                number: name.strip_prefix('$')?.parse().ok()?,
+                span: offset(query, token.span.start)..offset(query, token.span.end),
+            }),
+            _ => None,
+        })
+        .collect();
+    let mut statements = Parser::new(&dialect)
+        .with_tokens_with_locations(tokens)
+        .parse_statements()
+        .map_err(|error| syntax_error(query, error.to_string()))?;
+    match statements.len() {
+        0 => Ok(None),
+        1 => Ok(Some(ParsedStatement {
+            query: query.to_string(),
+            statement: statements.remove(0),
+            placeholders,
+        })),
+        _ => Err(SyntaxError {
+            message: "cannot insert multiple commands into a prepared statement".to_string(),
+            position: None,
+        }),
+    }
+}
+
+/// The parser reports locations only as a ` at Line: l, Column: c` suffix of
+/// its message. Errors without one are at the end of the input.
+fn syntax_error(query: &str, message: String) -> SyntaxError {
+    let message = message.trim_start_matches("sql parser error: ").to_string();
+    let located = message
+        .rsplit_once(" at Line: ")
+        .and_then(|(message, location)| {
+            let (line, column) = location.split_once(", Column: ")?;
+            let location = Location::new(line.parse().ok()?, column.parse().ok()?);
+            Some((message.to_string(), position(query, location)))
+        });
+    match located {
+        Some((message, position)) => SyntaxError {
+            message: format!("syntax error: {}", message),
+            position: Some(position),
+        },
+        None => SyntaxError {
+            message: format!("syntax error at end of input: {}", message),
+            position: Some(query.chars().count() + 1),
+        },
+    }
+}
+
+/// Byte offset of a 1-based line and column.
+fn offset(query: &str, location: Location) -> usize {
+    let mut line = 1;
+    let mut column = 1;
+    for (offset, c) in query.char_indices() {
+        if line == location.line && column == location.column {
+            return offset;
+        }
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    query.len()
+}
+
+fn position(query: &str, location: Location) -> usize {
+    query[..offset(query, location)].chars().count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locates_placeholders() {
+        let query = "SELECT * FROM orders WHERE id = $2 AND note = $1";
+        let statement = parse(query).unwrap().unwrap();
+        assert_eq!(
+            statement.placeholders,
+            vec![
+                Placeholder {
+                    number: 2,
+                    span: 32..34
+                },
+                Placeholder {
+                    number: 1,
+                    span: 46..48
+                },
+            ]
+        );
+        assert_eq!(&query[statement.placeholders[0].span.clone()], "$2");
+        assert_eq!(statement.parameter_count(), 2);
+        assert!(matches!(statement.statement, Statement::Query(_)));
+    }
+
+    #[test]
+    fn reports_syntax_error_position() {
+        let error = parse("SELECT id\nFROM orders WHER id = 1").unwrap_err();
+        assert_eq!(error.position, Some(28));
+        assert!(
+            error.message.starts_with("syntax error"),
+            "{}",
+            error.message
+        );
+
+        let error = parse("SELECT * FROM").unwrap_err();
+        assert_eq!(error.position, Some(14));
+
+        assert!(parse("").unwrap().is_none());
+        assert!(parse("SELECT 1; SELECT 2").unwrap_err().position.is_none());
+    }
+}
@@ -0,0 +1,172 @@
+use std::fmt;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::time::Duration;
+
+use crate::server_message::ServerMessage;
+
+/// Server defaults of the `statement_timeout`, `idle_session_timeout` and
+/// `idle_in_transaction_session_timeout` parameters, which clients may
+/// change with `SET`. `None` disables a timeout, as 0 does in Postgres.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Cancels a shim execution with 57014 `query_canceled`. The blocking
+    /// intermediary only checks it when a row is written or the statement
+    /// completes, see
+    /// [`PostgressIntermediary::with_timeouts`](crate::PostgressIntermediary::with_timeouts).
+    pub statement: Option<Duration>,
+    /// Ends a session idle outside a transaction with FATAL 57P05.
+    pub idle_session: Option<Duration>,
+    /// Ends a session idle inside a transaction with FATAL 25P03.
+    pub idle_in_transaction_session: Option<Duration>,
+}
+
+pub(crate) const STATEMENT_TIMEOUT: &str = "statement_timeout";
+pub(crate) const IDLE_SESSION_TIMEOUT: &str = "idle_session_timeout";
+pub(crate) const IDLE_IN_TRANSACTION_SESSION_TIMEOUT: &str = "idle_in_transaction_session_timeout";
+
+/// The session parameters holding timeouts.
+pub(crate) const TIMEOUT_SETTINGS: &[&str] = &[
+    STATEMENT_TIMEOUT,
+    IDLE_SESSION_TIMEOUT,
+    IDLE_IN_TRANSACTION_SESSION_TIMEOUT,
+];
+
+impl Timeouts {
+    /// The settings with their default values.
+    pub(crate) fn settings(&self) -> [(&'static str, String); 3] {
+        [
+            (STATEMENT_TIMEOUT, format_timeout(self.statement)),
+            (IDLE_SESSION_TIMEOUT, format_timeout(self.idle_session)),
+            (
+                IDLE_IN_TRANSACTION_SESSION_TIMEOUT,
+                format_timeout(self.idle_in_transaction_session),
+            ),
+        ]
+    }
+}
+
+/// Parses a timeout setting such as `5000`, `'5s'` or `1min`. Plain numbers
+/// are milliseconds, 0 disables the timeout. `None` if `value` is invalid.
+pub(crate) fn parse_timeout(value: &str) -> Option<Option<Duration>> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let milliseconds = match unit.trim() {
+        "" | "ms" => number,
+        "us" => number / 1000.0,
+        "s" => number * 1000.0,
+        "min" => number * 60_000.0,
+        "h" => number * 3_600_000.0,
+        "d" => number * 86_400_000.0,
+        _ => return None,
+    };
+    if !milliseconds.is_finite() || milliseconds > i32::MAX as f64 {
+        return None;
+    }
+    Some(match milliseconds.round() as u64 {
+        0 => None,
+        milliseconds => Some(Duration::from_millis(milliseconds)),
+    })
+}
+
+/// Formats a timeout the way `SHOW` prints it.
+pub(crate) fn format_timeout(timeout: Option<Duration>) -> String {
+    let milliseconds = timeout.map_or(0, |timeout| timeout.as_millis());
+    match milliseconds {
+        0 => "0".to_string(),
+        _ if milliseconds.is_multiple_of(60_000) => format!("{}min", milliseconds / 60_000),
+        _ if milliseconds.is_multiple_of(1000) => format!("{}s", milliseconds / 1000),
+        _ => format!("{}ms", milliseconds),
+    }
+}
+
+/// Returned by row writers once the statement timeout has passed, so the
+/// intermediary can cancel the statement instead of ending the session.
+#[derive(Debug)]
+pub(crate) struct StatementTimeout;
+
+impl fmt::Display for StatementTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("canceling statement due to statement timeout")
+    }
+}
+
+impl std::error::Error for StatementTimeout {}
+
+impl StatementTimeout {
+    pub fn error() -> Error {
+        Error::new(ErrorKind::TimedOut, StatementTimeout)
+    }
+
+    pub fn is(error: &Error) -> bool {
+        error
+            .get_ref()
+            .is_some_and(|error| error.is::<StatementTimeout>())
+    }
+}
+
+/// How long a session may wait for the client's next message.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IdleTimeout {
+    pub duration: Duration,
+    in_transaction: bool,
+}
+
+impl IdleTimeout {
+    pub fn new(duration: Duration, in_transaction: bool) -> Self {
+        IdleTimeout {
+            duration,
+            in_transaction,
+        }
+    }
+
+    /// Tells the client why the session ends.
+    pub fn terminate(&self, out: &mut impl Write) -> Result<()> {
+        let (code, message) = match self.in_transaction {
+            true => (
+                "25P03",
+                "terminating connection due to idle-in-transaction timeout",
+            ),
+            false => (
+                "57P05",
+                "terminating connection due to idle-session timeout",
+            ),
+        };
+        ServerMessage::ErrorResponse {
+            severity: "FATAL",
+            code,
+            message: message.to_string(),
+            position: None,
+        }
+        .write(out)?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_timeouts_like_postgres() {
+        assert_eq!(parse_timeout("0"), Some(None));
+        assert_eq!(parse_timeout("250"), Some(Some(Duration::from_millis(250))));
+        assert_eq!(parse_timeout("5s"), Some(Some(Duration::from_secs(5))));
+        assert_eq!(parse_timeout("2 min"), Some(Some(Duration::from_secs(120))));
+        assert_eq!(
+            parse_timeout("1.5s"),
+            Some(Some(Duration::from_millis(1500)))
+        );
+        assert_eq!(parse_timeout("soon"), None);
+        assert_eq!(parse_timeout("-1"), None);
+        assert_eq!(parse_timeout("5 fortnights"), None);
+
+        assert_eq!(format_timeout(None), "0");
+        assert_eq!(format_timeout(Some(Duration::from_millis(1500))), "1500ms");
+        assert_eq!(format_timeout(Some(Duration::from_secs(5))), "5s");
+        assert_eq!(format_timeout(Some(Duration::from_secs(120))), "2min");
+    }
+}
@@ -0,0 +1,91 @@
+use postgres_types::{Field, Kind, Type};
+use std::collections::HashMap;
+
+/// Types declared by a shim on top of the postgres builtins. Used to resolve
+/// parameter OIDs sent in Parse messages and to describe custom types to
+/// clients that look them up.
+#[derive(Debug, Clone, Default)]
+pub struct TypeRegistry {
+    types: HashMap<u32, Type>,
+}
+
+const DEFAULT_SCHEMA: &str = "public";
+
+impl TypeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, ty: Type) -> Type {
+        self.types.insert(ty.oid(), ty.clone());
+        ty
+    }
+
+    pub fn register_enum<L>(&mut self, name: &str, oid: u32, labels: L) -> Type
+    where
+        L: IntoIterator,
+        L::Item: Into<String>,
+    {
+        self.register(Type::new(
+            name.to_string(),
+            oid,
+            Kind::Enum(labels.into_iter().map(Into::into).collect()),
+            DEFAULT_SCHEMA.to_string(),
+        ))
+    }
+
+    pub fn register_composite<F, N>(&mut self, name: &str, oid: u32, fields: F) -> Type
+    where
+        F: IntoIterator<Item = (N, Type)>,
+        N: Into<String>,
+    {
+        self.register(Type::new(
+            name.to_string(),
+            oid,
+            Kind::Composite(
+                fields
+                    .into_iter()
+                    .map(|(name, ty)| Field::new(name.into(), ty))
+                    .collect(),
+            ),
+            DEFAULT_SCHEMA.to_string(),
+        ))
+    }
+
+    pub fn register_domain(&mut self, name: &str, oid: u32, base: Type) -> Type {
+        self.register(Type::new(
+            name.to_string(),
+            oid,
+            Kind::Domain(base),
+            DEFAULT_SCHEMA.to_string(),
+        ))
+    }
+
+    /// Registers the array type of `element`, named `_<element>` like postgres does.
+    pub fn register_array(&mut self, oid: u32, element: Type) -> Type {
+        self.register(Type::new(
+            format!("_{}", element.name()),
+            oid,
+            Kind::Array(element.clone()),
+            element.schema().to_string(),
+        ))
+    }
+
+    /// Resolves a builtin or registered type. OID 0 means the client left the
+    /// type unspecified and resolves to `unknown`.
+    pub fn from_oid(&self, oid: u32) -> Option<Type> {
+        if oid == 0 {
+            return Some(Type::UNKNOWN);
+        }
+        Type::from_oid(oid).or_else(|| self.types.get(&oid).cloned())
+    }
+
+    pub fn from_name(&self, name: &str) -> Option<Type> {
+        self.types.values().find(|ty| ty.name() == name).cloned()
+    }
+
+    /// Registered custom types, builtins are not included.
+    pub fn types(&self) -> impl Iterator<Item = &Type> {
+        self.types.values()
+    }
+}
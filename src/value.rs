@@ -0,0 +1,624 @@
+use bytes::{BufMut, BytesMut};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use postgres_types::{accepts, to_sql_checked, FromSql, IsNull, Kind, ToSql, Type};
+use std::io::{Error, Result};
+use uuid::Uuid;
+
+use crate::{to_bin_value, Array, ErrorResponse, ParameterValue, ToSqlValue};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int2(i16),
+    Int4(i32),
+    Int8(i64),
+    Float4(f32),
+    Float8(f64),
+    Numeric(String),
+    Text(String),
+    Bytea(Vec<u8>),
+    Date(NaiveDate),
+    Time(NaiveTime),
+    Timestamp(NaiveDateTime),
+    TimestampTz(DateTime<Utc>),
+    Uuid(Uuid),
+    Json(serde_json::Value),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// The postgres type this value maps to, `None` for `Null` and empty arrays.
+    pub fn sql_type(&self) -> Option<Type> {
+        Some(match self {
+            Value::Null => return None,
+            Value::Bool(_) => Type::BOOL,
+            Value::Int2(_) => Type::INT2,
+            Value::Int4(_) => Type::INT4,
+            Value::Int8(_) => Type::INT8,
+            Value::Float4(_) => Type::FLOAT4,
+            Value::Float8(_) => Type::FLOAT8,
+            Value::Numeric(_) => Type::NUMERIC,
+            Value::Text(_) => Type::TEXT,
+            Value::Bytea(_) => Type::BYTEA,
+            Value::Date(_) => Type::DATE,
+            Value::Time(_) => Type::TIME,
+            Value::Timestamp(_) => Type::TIMESTAMP,
+            Value::TimestampTz(_) => Type::TIMESTAMPTZ,
+            Value::Uuid(_) => Type::UUID,
+            Value::Json(_) => Type::JSONB,
+            Value::Array(values) => {
+                return values
+                    .iter()
+                    .find_map(Value::sql_type)
+                    .and_then(|ty| array_type(&ty))
+            }
+        })
+    }
+
+    /// Decodes a bind parameter according to the type it was prepared with.
+    /// Types without a dedicated variant are kept as `Text` or `Bytea`.
+    pub fn from_parameter(parameter: ParameterValue, ty: &Type) -> Result<Self> {
+        if let ParameterValue::Null = parameter {
+            return Ok(Value::Null);
+        }
+        match ty.kind() {
+            Kind::Array(_) => return Ok(Array::from_parameter(parameter, ty)?.into_nested()),
+            Kind::Domain(base) => return Self::from_parameter(parameter, base),
+            Kind::Enum(_) => {
+                if let ParameterValue::Binary(data) = parameter {
+                    return String::from_utf8(data)
+                        .map(Value::Text)
+                        .map_err(|error| invalid_binary(ty, error));
+                }
+            }
+            _ => {}
+        }
+        match parameter {
+            ParameterValue::Text(text) => Self::from_text(text, ty),
+            ParameterValue::Binary(data) => Self::from_binary(&data, ty),
+            ParameterValue::Null => Ok(Value::Null),
+        }
+    }
+
+    fn from_text(text: String, ty: &Type) -> Result<Self> {
+        Ok(match *ty {
+            Type::BOOL => match text.to_lowercase().as_str() {
+                "t" | "true" | "y" | "yes" | "on" | "1" => Value::Bool(true),
+                "f" | "false" | "n" | "no" | "off" | "0" => Value::Bool(false),
+                _ => return Err(invalid_text(&text, ty)),
+            },
+            Type::INT2 => Value::Int2(text.trim().parse().map_err(|_| invalid_text(&text, ty))?),
+            Type::INT4 => Value::Int4(text.trim().parse().map_err(|_| invalid_text(&text, ty))?),
+            Type::INT8 => Value::Int8(text.trim().parse().map_err(|_| invalid_text(&text, ty))?),
+            Type::FLOAT4 => {
+                Value::Float4(parse_float(&text).ok_or_else(|| invalid_text(&text, ty))? as f32)
+            }
+            Type::FLOAT8 => {
+                Value::Float8(parse_float(&text).ok_or_else(|| invalid_text(&text, ty))?)
+            }
+            Type::NUMERIC => {
+                numeric_to_bin(&text).ok_or_else(|| invalid_text(&text, ty))?;
+                Value::Numeric(text.trim().to_string())
+            }
+            Type::BYTEA => Value::Bytea(parse_bytea(&text).ok_or_else(|| invalid_text(&text, ty))?),
+            Type::DATE => Value::Date(
+                NaiveDate::parse_from_str(&text, "%Y-%m-%d")
+                    .map_err(|_| invalid_text(&text, ty))?,
+            ),
+            Type::TIME => Value::Time(
+                NaiveTime::parse_from_str(&text, "%H:%M:%S%.f")
+                    .map_err(|_| invalid_text(&text, ty))?,
+            ),
+            Type::TIMESTAMP => Value::Timestamp(
+                NaiveDateTime::parse_from_str(&text, "%Y-%m-%d %H:%M:%S%.f")
+                    .or_else(|_| NaiveDateTime::parse_from_str(&text, "%Y-%m-%dT%H:%M:%S%.f"))
+                    .map_err(|_| invalid_text(&text, ty))?,
+            ),
+            Type::TIMESTAMPTZ => Value::TimestampTz(
+                DateTime::parse_from_str(&text, "%Y-%m-%d %H:%M:%S%.f%#z")
+                    .or_else(|_| DateTime::parse_from_rfc3339(&text))
+                    .map_err(|_| invalid_text(&text, ty))?
+                    .with_timezone(&Utc),
+            ),
+            Type::UUID => Value::Uuid(Uuid::parse_str(&text).map_err(|_| invalid_text(&text, ty))?),
+            Type::JSON | Type::JSONB => {
+                Value::Json(serde_json::from_str(&text).map_err(|_| invalid_text(&text, ty))?)
+            }
+            _ => Value::Text(text),
+        })
+    }
+
+    fn from_binary(data: &[u8], ty: &Type) -> Result<Self> {
+        Ok(match *ty {
+            Type::BOOL => Value::Bool(from_sql(ty, data)?),
+            Type::INT2 => Value::Int2(from_sql(ty, data)?),
+            Type::INT4 => Value::Int4(from_sql(ty, data)?),
+            Type::INT8 => Value::Int8(from_sql(ty, data)?),
+            Type::FLOAT4 => Value::Float4(from_sql(ty, data)?),
+            Type::FLOAT8 => Value::Float8(from_sql(ty, data)?),
+            Type::NUMERIC => Value::Numeric(
+                numeric_from_bin(data).ok_or_else(|| invalid_binary(ty, "invalid numeric"))?,
+            ),
+            Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN => {
+                Value::Text(from_sql(ty, data)?)
+            }
+            Type::DATE => Value::Date(from_sql(ty, data)?),
+            Type::TIME => Value::Time(from_sql(ty, data)?),
+            Type::TIMESTAMP => Value::Timestamp(from_sql(ty, data)?),
+            Type::TIMESTAMPTZ => Value::TimestampTz(from_sql(ty, data)?),
+            Type::UUID => Value::Uuid(from_sql(ty, data)?),
+            Type::JSON | Type::JSONB => Value::Json(from_sql(ty, data)?),
+            _ => Value::Bytea(data.to_vec()),
+        })
+    }
+}
+
+impl ToSqlValue for Value {
+    fn as_bin_value(&self, ty: &Type) -> Result<Option<BytesMut>> {
+        match self {
+            Value::Null => Ok(None),
+            Value::Bool(value) => value.as_bin_value(ty),
+            Value::Int2(value) => value.as_bin_value(ty),
+            Value::Int4(value) => value.as_bin_value(ty),
+            Value::Int8(value) => value.as_bin_value(ty),
+            Value::Float4(value) => value.as_bin_value(ty),
+            Value::Float8(value) => value.as_bin_value(ty),
+            Value::Numeric(value) => {
+                let encoded = numeric_to_bin(value).ok_or_else(|| invalid_numeric(value))?;
+                to_bin_value(&BinaryNumeric(encoded), ty)
+            }
+            Value::Text(value) => value.as_bin_value(ty),
+            Value::Bytea(value) => value.as_bin_value(ty),
+            Value::Date(value) => value.as_bin_value(ty),
+            Value::Time(value) => value.as_bin_value(ty),
+            Value::Timestamp(value) => value.as_bin_value(ty),
+            Value::TimestampTz(value) => value.as_bin_value(ty),
+            Value::Uuid(value) => value.as_bin_value(ty),
+            Value::Json(value) => value.as_bin_value(ty),
+            Value::Array(values) => nested_array(values)?.as_bin_value(ty),
+        }
+    }
+
+    fn as_str_value(&self, ty: &Type) -> Result<Option<BytesMut>> {
+        match self {
+            Value::Null => Ok(None),
+            Value::Bool(value) => value.as_str_value(ty),
+            Value::Int2(value) => value.as_str_value(ty),
+            Value::Int4(value) => value.as_str_value(ty),
+            Value::Int8(value) => value.as_str_value(ty),
+            Value::Float4(value) => value.as_str_value(ty),
+            Value::Float8(value) => value.as_str_value(ty),
+            Value::Numeric(value) => match numeric_to_bin(value) {
+                Some(_) => value.trim().as_str_value(ty),
+                None => Err(invalid_numeric(value)),
+            },
+            Value::Text(value) => value.as_str_value(ty),
+            Value::Bytea(value) => value.as_str_value(ty),
+            Value::Date(value) => value.as_str_value(ty),
+            Value::Time(value) => value.as_str_value(ty),
+            Value::Timestamp(value) => value.as_str_value(ty),
+            Value::TimestampTz(value) => value.as_str_value(ty),
+            Value::Uuid(value) => value.as_str_value(ty),
+            Value::Json(value) => value.as_str_value(ty),
+            Value::Array(values) => nested_array(values)?.as_str_value(ty),
+        }
+    }
+}
+
+macro_rules! value_from_impl {
+    ($($ty:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$ty> for Value {
+                fn from(value: $ty) -> Self {
+                    Value::$variant(value.into())
+                }
+            }
+        )*
+    };
+}
+
+value_from_impl!(
+    bool => Bool,
+    i16 => Int2,
+    i32 => Int4,
+    i64 => Int8,
+    f32 => Float4,
+    f64 => Float8,
+    String => Text,
+    &str => Text,
+    Vec<u8> => Bytea,
+    &[u8] => Bytea,
+    NaiveDate => Date,
+    NaiveTime => Time,
+    NaiveDateTime => Timestamp,
+    DateTime<Utc> => TimestampTz,
+    Uuid => Uuid,
+    serde_json::Value => Json,
+);
+
+impl<T> From<Option<T>> for Value
+where
+    T: Into<Value>,
+{
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Value::Null)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        Value::Array(values)
+    }
+}
+
+/// A numeric already in its binary form, written through [`to_bin_value`]
+/// so the column type is checked as for any other value.
+#[derive(Debug)]
+struct BinaryNumeric(BytesMut);
+
+impl ToSql for BinaryNumeric {
+    fn to_sql(
+        &self,
+        _: &Type,
+        out: &mut BytesMut,
+    ) -> std::result::Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        out.extend_from_slice(&self.0);
+        Ok(IsNull::No)
+    }
+
+    accepts!(NUMERIC);
+    to_sql_checked!();
+}
+
+/// `Value::Array`s can be ragged, which fails the row with 2202E
+/// `array_subscript_error` as postgres does for such array expressions.
+fn nested_array(values: &[Value]) -> Result<Array<Value>> {
+    Array::from_nested(values).map_err(|error| ErrorResponse::error("2202E", error.to_string()))
+}
+
+fn from_sql<'a, T>(ty: &Type, data: &'a [u8]) -> Result<T>
+where
+    T: FromSql<'a>,
+{
+    T::from_sql(ty, data).map_err(|error| invalid_binary(ty, error))
+}
+
+/// Parameters that fail to decode fail the Bind with 22P02
+/// `invalid_text_representation`, as postgres does.
+fn invalid_text(text: &str, ty: &Type) -> Error {
+    ErrorResponse::error(
+        "22P02",
+        format!("invalid input syntax for type {}: \"{}\"", ty.name(), text),
+    )
+}
+
+/// Binary parameters that fail to decode fail the Bind with 22P03
+/// `invalid_binary_representation`.
+fn invalid_binary(ty: &Type, error: impl std::fmt::Display) -> Error {
+    ErrorResponse::error(
+        "22P03",
+        format!(
+            "incorrect binary data format for type {}: {}",
+            ty.name(),
+            error
+        ),
+    )
+}
+
+/// Numeric values are strings the shim built, so they are only checked
+/// when written.
+fn invalid_numeric(text: &str) -> Error {
+    ErrorResponse::error(
+        "22P02",
+        format!("invalid input syntax for type numeric: \"{}\"", text),
+    )
+}
+
+fn parse_float(text: &str) -> Option<f64> {
+    match text.trim().to_lowercase().as_str() {
+        "infinity" | "+infinity" | "inf" => Some(f64::INFINITY),
+        "-infinity" | "-inf" => Some(f64::NEG_INFINITY),
+        "nan" => Some(f64::NAN),
+        text => text.parse().ok(),
+    }
+}
+
+fn parse_bytea(text: &str) -> Option<Vec<u8>> {
+    match text.strip_prefix("\\x") {
+        Some(hex) => {
+            if hex.len() % 2 != 0 {
+                return None;
+            }
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                .collect()
+        }
+        None => {
+            let bytes = text.as_bytes();
+            let mut result = Vec::with_capacity(bytes.len());
+            let mut i = 0;
+            while i < bytes.len() {
+                if bytes[i] != b'\\' {
+                    result.push(bytes[i]);
+                    i += 1;
+                } else if bytes.get(i + 1) == Some(&b'\\') {
+                    result.push(b'\\');
+                    i += 2;
+                } else {
+                    let octal = std::str::from_utf8(bytes.get(i + 1..i + 4)?).ok()?;
+                    result.push(u8::from_str_radix(octal, 8).ok()?);
+                    i += 4;
+                }
+            }
+            Some(result)
+        }
+    }
+}
+
+const NUMERIC_POS: u16 = 0x0000;
+const NUMERIC_NEG: u16 = 0x4000;
+const NUMERIC_NAN: u16 = 0xC000;
+const NUMERIC_PINF: u16 = 0xD000;
+const NUMERIC_NINF: u16 = 0xF000;
+
+/// Encodes a decimal string in the base 10000 binary numeric format.
+fn numeric_to_bin(text: &str) -> Option<BytesMut> {
+    let text = text.trim();
+    let mut buffer = BytesMut::new();
+    let special = match text.to_lowercase().as_str() {
+        "nan" => Some(NUMERIC_NAN),
+        "infinity" | "+infinity" => Some(NUMERIC_PINF),
+        "-infinity" => Some(NUMERIC_NINF),
+        _ => None,
+    };
+    if let Some(sign) = special {
+        buffer.put_i16(0);
+        buffer.put_i16(0);
+        buffer.put_u16(sign);
+        buffer.put_u16(0);
+        return Some(buffer);
+    }
+
+    let (sign, unsigned) = match text.strip_prefix('-') {
+        Some(unsigned) => (NUMERIC_NEG, unsigned),
+        None => (NUMERIC_POS, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (integer, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    if integer.is_empty() && fraction.is_empty()
+        || !integer
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let integer = integer.trim_start_matches('0');
+    let scale = fraction.len() as u16;
+
+    let integer_padding = (4 - integer.len() % 4) % 4;
+    let fraction_padding = (4 - fraction.len() % 4) % 4;
+    let digits_text = format!(
+        "{}{}{}{}",
+        "0".repeat(integer_padding),
+        integer,
+        fraction,
+        "0".repeat(fraction_padding)
+    );
+    let mut digits: Vec<i16> = digits_text
+        .as_bytes()
+        .chunks(4)
+        .map(|chunk| std::str::from_utf8(chunk).unwrap().parse().unwrap())
+        .collect();
+    let mut weight = ((integer.len() + integer_padding) / 4) as i16 - 1;
+    while digits.first() == Some(&0) {
+        digits.remove(0);
+        weight -= 1;
+    }
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+    if digits.is_empty() {
+        weight = 0;
+    }
+
+    buffer.put_i16(digits.len() as i16);
+    buffer.put_i16(weight);
+    buffer.put_u16(if digits.is_empty() { NUMERIC_POS } else { sign });
+    buffer.put_u16(scale);
+    for digit in digits {
+        buffer.put_i16(digit);
+    }
+    Some(buffer)
+}
+
+/// Decodes the binary numeric format back to its decimal string.
+fn numeric_from_bin(data: &[u8]) -> Option<String> {
+    let read_u16 = |i: usize| -> Option<u16> {
+        Some(u16::from_be_bytes(data.get(i..i + 2)?.try_into().ok()?))
+    };
+    let ndigits = read_u16(0)? as usize;
+    let weight = read_u16(2)? as i16 as i32;
+    let sign = read_u16(4)?;
+    let scale = read_u16(6)? as usize;
+    match sign {
+        NUMERIC_NAN => return Some("NaN".to_string()),
+        NUMERIC_PINF => return Some("Infinity".to_string()),
+        NUMERIC_NINF => return Some("-Infinity".to_string()),
+        NUMERIC_POS | NUMERIC_NEG => {}
+        _ => return None,
+    }
+    let digits = (0..ndigits)
+        .map(|i| read_u16(8 + i * 2))
+        .collect::<Option<Vec<u16>>>()?;
+    let digit = |position: i32| -> u16 {
+        usize::try_from(position)
+            .ok()
+            .and_then(|position| digits.get(position).copied())
+            .unwrap_or(0)
+    };
+
+    let mut result = String::new();
+    if sign == NUMERIC_NEG {
+        result.push('-');
+    }
+    if weight < 0 {
+        result.push('0');
+    } else {
+        result.push_str(&digit(0).to_string());
+        for position in 1..=weight {
+            result.push_str(&format!("{:04}", digit(position)));
+        }
+    }
+    if scale > 0 {
+        let mut fraction = String::new();
+        let mut position = weight + 1;
+        while fraction.len() < scale {
+            fraction.push_str(&format!("{:04}", digit(position)));
+            position += 1;
+        }
+        result.push('.');
+        result.push_str(&fraction[..scale]);
+    }
+    Some(result)
+}
+
+fn array_type(element: &Type) -> Option<Type> {
+    Some(match *element {
+        Type::BOOL => Type::BOOL_ARRAY,
+        Type::INT2 => Type::INT2_ARRAY,
+        Type::INT4 => Type::INT4_ARRAY,
+        Type::INT8 => Type::INT8_ARRAY,
+        Type::FLOAT4 => Type::FLOAT4_ARRAY,
+        Type::FLOAT8 => Type::FLOAT8_ARRAY,
+        Type::NUMERIC => Type::NUMERIC_ARRAY,
+        Type::TEXT => Type::TEXT_ARRAY,
+        Type::BYTEA => Type::BYTEA_ARRAY,
+        Type::DATE => Type::DATE_ARRAY,
+        Type::TIME => Type::TIME_ARRAY,
+        Type::TIMESTAMP => Type::TIMESTAMP_ARRAY,
+        Type::TIMESTAMPTZ => Type::TIMESTAMPTZ_ARRAY,
+        Type::UUID => Type::UUID_ARRAY,
+        Type::JSONB => Type::JSONB_ARRAY,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numeric_binary_round_trip() {
+        for text in [
+            "0",
+            "1",
+            "-12345.678",
+            "0.0001",
+            "100000000",
+            "3.14159",
+            "NaN",
+        ] {
+            let bin = numeric_to_bin(text).unwrap();
+            assert_eq!(numeric_from_bin(&bin).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn invalid_numerics_fail_in_both_formats() {
+        let value = Value::Numeric(" 1.50".to_string());
+        assert_eq!(
+            value.as_str_value(&Type::NUMERIC).unwrap(),
+            Some(BytesMut::from(&b"1.50"[..]))
+        );
+        assert!(value.as_bin_value(&Type::NUMERIC).unwrap().is_some());
+        for (value, ty) in [
+            (value.clone(), Type::INT4),
+            (Value::Int4(1), Type::INT8),
+            (Value::Text("1".to_string()), Type::INT4),
+        ] {
+            let error = value.as_bin_value(&ty).unwrap_err();
+            assert_eq!(ErrorResponse::of(&error).unwrap().code, "42804");
+        }
+        let value = Value::Numeric("1,5".to_string());
+        for result in [
+            value.as_bin_value(&Type::NUMERIC),
+            value.as_str_value(&Type::NUMERIC),
+        ] {
+            assert_eq!(
+                ErrorResponse::of(&result.unwrap_err()).unwrap().code,
+                "22P02"
+            );
+        }
+    }
+
+    #[test]
+    fn ragged_arrays_fail_in_both_formats() {
+        let value = Value::Array(vec![Value::Array(vec![Value::Int4(1)]), Value::Int4(2)]);
+        for result in [
+            value.as_str_value(&Type::INT4_ARRAY),
+            value.as_bin_value(&Type::INT4_ARRAY),
+        ] {
+            assert_eq!(
+                ErrorResponse::of(&result.unwrap_err()).unwrap().code,
+                "2202E"
+            );
+        }
+        let value = Value::Array(vec![
+            Value::Array(vec![Value::Int4(1), Value::Int4(2)]),
+            Value::Array(vec![Value::Int4(3)]),
+        ]);
+        assert!(value.as_str_value(&Type::INT4_ARRAY).is_err());
+    }
+
+    #[test]
+    fn parameters_are_decoded_by_type() {
+        assert_eq!(
+            Value::from_parameter(ParameterValue::Text("42".to_string()), &Type::INT4).unwrap(),
+            Value::Int4(42)
+        );
+        assert_eq!(
+            Value::from_parameter(ParameterValue::Binary(vec![0, 0, 0, 42]), &Type::INT4).unwrap(),
+            Value::Int4(42)
+        );
+        let code = |parameter, ty| {
+            let error = Value::from_parameter(parameter, ty).unwrap_err();
+            ErrorResponse::of(&error).unwrap().code.clone()
+        };
+        assert_eq!(
+            code(ParameterValue::Text("abc".to_string()), &Type::INT4),
+            "22P02"
+        );
+        assert_eq!(
+            code(ParameterValue::Binary(vec![0, 42]), &Type::INT4),
+            "22P03"
+        );
+        assert_eq!(
+            code(ParameterValue::Binary(vec![0]), &Type::NUMERIC),
+            "22P03"
+        );
+        assert_eq!(
+            Value::from_parameter(ParameterValue::Null, &Type::INT4_ARRAY).unwrap(),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn array_text_quotes_elements() {
+        let value = Value::Array(vec![
+            Value::from("a b"),
+            Value::Null,
+            Value::from("NULL"),
+            Value::from("x"),
+        ]);
+        assert_eq!(
+            value.as_str_value(&Type::TEXT_ARRAY).unwrap().unwrap(),
+            BytesMut::from(&br#"{"a b",NULL,"NULL",x}"#[..])
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use postgres_shim::{Array, PgRow, Type, Value};
+
+#[derive(PgRow)]
+struct User {
+    id: i64,
+    #[pg(rename = "user_name")]
+    name: String,
+    email: Option<String>,
+    #[pg(column_type = Type::VARCHAR)]
+    country: String,
+    #[pg(skip)]
+    #[allow(dead_code)]
+    password_hash: String,
+    #[pg(column_type = Type::INT4, nullable)]
+    score: Value,
+    #[pg(column_type = Type::INT4_ARRAY)]
+    ranks: Array<i32>,
+    #[pg(column_type = Type::NUMERIC)]
+    balance: Option<Value>,
+}
+
+#[test]
+fn derive_generates_columns() {
+    let columns = User::columns();
+    let columns: Vec<(&str, Type, bool)> = columns
+        .iter()
+        .map(|column| {
+            (
+                column.name.as_str(),
+                column.column_type.clone(),
+                column.nullable,
+            )
+        })
+        .collect();
+    assert_eq!(
+        columns,
+        vec![
+            ("id", Type::INT8, false),
+            ("user_name", Type::TEXT, false),
+            ("email", Type::TEXT, true),
+            ("country", Type::VARCHAR, false),
+            ("score", Type::INT4, true),
+            ("ranks", Type::INT4_ARRAY, false),
+            ("balance", Type::NUMERIC, true),
+        ]
+    );
+}
+
+#[test]
+fn derive_values_follow_column_order() {
+    let user = User {
+        id: 1,
+        name: "ana".to_string(),
+        email: None,
+        country: "BR".to_string(),
+        password_hash: "secret".to_string(),
+        score: Value::Null,
+        ranks: Array::from_elements(vec![1, 2]),
+        balance: Some(Value::Numeric("1.50".to_string())),
+    };
+    let values: Vec<Option<Vec<u8>>> = user
+        .values()
+        .iter()
+        .zip(User::columns())
+        .map(|(value, column)| {
+            value
+                .as_str_value(&column.column_type)
+                .unwrap()
+                .map(|value| value.to_vec())
+        })
+        .collect();
+    assert_eq!(
+        values,
+        vec![
+            Some(b"1".to_vec()),
+            Some(b"ana".to_vec()),
+            None,
+            Some(b"BR".to_vec()),
+            None,
+            Some(b"{1,2}".to_vec()),
+            Some(b"1.50".to_vec()),
+        ]
+    );
+}
+
+#[derive(PgRow)]
+struct Borrowed<'a> {
+    name: &'a str,
+    tags: Option<&'a str>,
+}
+
+#[test]
+fn derive_accepts_borrowed_fields() {
+    let name = String::from("ana");
+    let row = Borrowed {
+        name: &name,
+        tags: None,
+    };
+    let values: Vec<Option<Vec<u8>>> = row
+        .values()
+        .iter()
+        .zip(Borrowed::columns())
+        .map(|(value, column)| {
+            value
+                .as_str_value(&column.column_type)
+                .unwrap()
+                .map(|value| value.to_vec())
+        })
+        .collect();
+    assert_eq!(values, vec![Some(b"ana".to_vec()), None]);
+}